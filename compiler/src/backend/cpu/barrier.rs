@@ -0,0 +1,206 @@
+//! Barriers for the threads of a thread block and of its subgroups.
+use std::iter;
+use std::sync::{Arc, Condvar, Mutex, PoisonError};
+
+/// Like [`std::sync::Barrier`], except that threads can leave it. Once a
+/// thread has returned or trapped, the others stop waiting for it, so a
+/// barrier that only some threads of a block reach, which is undefined on
+/// devices, cannot deadlock the launch.
+pub(super) struct BlockBarrier {
+    state: Mutex<State>,
+    released: Condvar,
+}
+
+struct State {
+    /// Threads that have not left yet.
+    live: u32,
+    /// Threads waiting for the current generation to be released.
+    waiting: u32,
+    generation: u64,
+}
+
+impl State {
+    /// Releases the waiting threads once every live thread has arrived.
+    fn release_if_complete(&mut self, released: &Condvar) -> bool {
+        if self.waiting < self.live {
+            return false;
+        }
+        self.waiting = 0;
+        self.generation += 1;
+        released.notify_all();
+        true
+    }
+}
+
+impl BlockBarrier {
+    pub(super) fn new(num_threads: u32) -> Self {
+        Self {
+            state: Mutex::new(State {
+                live: num_threads,
+                waiting: 0,
+                generation: 0,
+            }),
+            released: Condvar::new(),
+        }
+    }
+
+    /// Blocks until every live thread has called `wait`. Memory accesses
+    /// made before the barrier happen before those made after it.
+    pub(super) fn wait(&self) {
+        let mut state = self.state.lock().unwrap_or_else(PoisonError::into_inner);
+        state.waiting += 1;
+        if state.release_if_complete(&self.released) {
+            return;
+        }
+        let generation = state.generation;
+        let _state = self
+            .released
+            .wait_while(state, |state| state.generation == generation)
+            .unwrap_or_else(PoisonError::into_inner);
+    }
+
+    /// Stops the barrier from waiting for the calling thread.
+    pub(super) fn leave(&self) {
+        let mut state = self.state.lock().unwrap_or_else(PoisonError::into_inner);
+        state.live -= 1;
+        if state.waiting > 0 {
+            state.release_if_complete(&self.released);
+        }
+    }
+
+    /// Number of times the barrier has released its threads.
+    pub(super) fn generation(&self) -> u64 {
+        self.state
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .generation
+    }
+}
+
+/// Exchanges the values the threads of a subgroup pass to their subgroup
+/// operations. Only the threads active at an operation take part in it: an
+/// exchange completes once every live thread has either reached a subgroup
+/// operation or is waiting at the block barrier, and each operation only
+/// sees the values of the threads that reached it. Threads can leave it
+/// like a [`BlockBarrier`].
+pub(super) struct SubgroupExchange<T> {
+    state: Mutex<ExchangeState<T>>,
+    released: Condvar,
+}
+
+/// A value passed to the subgroup operation identified by `op`.
+type Passed<T> = Option<(usize, T)>;
+
+struct ExchangeState<T> {
+    live: u32,
+    arrived: u32,
+    /// Values passed in the current generation, by lane.
+    values: Vec<Passed<T>>,
+    /// The block barrier generation each lane is waiting for, if any.
+    parked: Vec<Option<u64>>,
+    /// Values of the last released generation.
+    last: Arc<[Passed<T>]>,
+    generation: u64,
+}
+
+impl<T> ExchangeState<T> {
+    /// Releases the waiting threads once no live thread can still reach a
+    /// subgroup operation: threads waiting at the barrier are inactive
+    /// until the barrier's generation moves on.
+    fn release_if_complete(
+        &mut self,
+        barrier_generation: u64,
+        released: &Condvar,
+    ) -> Option<Arc<[Passed<T>]>> {
+        let parked = self
+            .parked
+            .iter()
+            .filter(|&&parked| parked == Some(barrier_generation))
+            .count() as u32;
+        if self.arrived == 0 || self.arrived + parked < self.live {
+            return None;
+        }
+        let values = iter::repeat_with(|| None).take(self.values.len()).collect();
+        self.last = std::mem::replace(&mut self.values, values).into();
+        self.arrived = 0;
+        self.generation += 1;
+        released.notify_all();
+        Some(self.last.clone())
+    }
+}
+
+impl<T: Clone> SubgroupExchange<T> {
+    pub(super) fn new(num_lanes: u32) -> Self {
+        Self {
+            state: Mutex::new(ExchangeState {
+                live: num_lanes,
+                arrived: 0,
+                values: iter::repeat_with(|| None)
+                    .take(num_lanes as usize)
+                    .collect(),
+                parked: vec![None; num_lanes as usize],
+                last: Arc::new([]),
+                generation: 0,
+            }),
+            released: Condvar::new(),
+        }
+    }
+
+    /// Blocks until every active thread of the subgroup has reached a
+    /// subgroup operation, and returns the values passed to operation `op`
+    /// by lane. The lanes of threads that are inactive, or that reached
+    /// another operation, are `None`.
+    pub(super) fn exchange(
+        &self,
+        lane: u32,
+        op: usize,
+        value: T,
+        barrier: &BlockBarrier,
+    ) -> Vec<Option<T>> {
+        let mut state = self.state.lock().unwrap_or_else(PoisonError::into_inner);
+        state.values[lane as usize] = Some((op, value));
+        state.parked[lane as usize] = None;
+        state.arrived += 1;
+        let values = match state.release_if_complete(barrier.generation(), &self.released) {
+            Some(values) => values,
+            None => {
+                // The values cannot be replaced before this thread reads
+                // them, as the next generation waits for it.
+                let generation = state.generation;
+                self.released
+                    .wait_while(state, |state| state.generation == generation)
+                    .unwrap_or_else(PoisonError::into_inner)
+                    .last
+                    .clone()
+            }
+        };
+        values
+            .iter()
+            .map(|passed| match passed {
+                Some((passed_op, value)) if *passed_op == op => Some(value.clone()),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Marks the calling thread as inactive until `barrier` releases it.
+    /// Must be called right before waiting at the barrier.
+    pub(super) fn park(&self, lane: u32, barrier: &BlockBarrier) {
+        let mut state = self.state.lock().unwrap_or_else(PoisonError::into_inner);
+        let generation = barrier.generation();
+        state.parked[lane as usize] = Some(generation);
+        if state.arrived > 0 {
+            state.release_if_complete(generation, &self.released);
+        }
+    }
+
+    /// Stops the exchange from waiting for the calling thread.
+    pub(super) fn leave(&self, lane: u32, barrier: &BlockBarrier) {
+        let mut state = self.state.lock().unwrap_or_else(PoisonError::into_inner);
+        state.live -= 1;
+        state.parked[lane as usize] = None;
+        if state.arrived > 0 {
+            state.release_if_complete(barrier.generation(), &self.released);
+        }
+    }
+}
@@ -0,0 +1,1114 @@
+//! Tree-walking interpreter for type-checked kernel IR.
+//!
+//! Vectors are composites of their components, and matrices composites of
+//! their columns.
+use std::sync::atomic::{self, AtomicU32, Ordering};
+use std::thread;
+
+use shared_type::f16;
+use shared_type::ir::{
+    layout, AtomicOp, BinaryOp, Block, Builtin, Expr, Function, Kernel, Literal, Place, ScalarType,
+    Stmt, SubgroupOp, SyncOp, Type, UnaryOp,
+};
+
+use super::barrier::{BlockBarrier, SubgroupExchange};
+use super::CpuMemory;
+use crate::backend::error::{Result, RyclError};
+
+/// A buffer range bound to a kernel argument.
+pub(super) struct Binding<'m> {
+    pub(super) memory: &'m CpuMemory,
+    pub(super) offset: u64,
+    pub(super) size: u64,
+}
+
+/// Everything shared by the threads of a launch.
+pub(super) struct Launch<'a> {
+    pub(super) kernel: &'a Kernel,
+    pub(super) buffers: &'a [Binding<'a>],
+    pub(super) num_thread_blocks: u32,
+    pub(super) thread_block_size: u32,
+    pub(super) subgroup_size: u32,
+}
+
+/// Everything shared by the threads of a thread block.
+struct ThreadBlock {
+    shared: Vec<CpuMemory>,
+    barrier: BlockBarrier,
+    subgroups: Vec<SubgroupExchange<Value>>,
+}
+
+impl Launch<'_> {
+    /// Runs every thread of block `block_id`, each on its own OS thread.
+    pub(super) fn run_block(&self, block_id: u32) -> Result<()> {
+        let block = &ThreadBlock {
+            // Shared arrays start zeroed, though kernels must not rely on it.
+            shared: self
+                .kernel
+                .shared
+                .iter()
+                .map(|s| CpuMemory::new(&vec![0; layout::std430(&s.ty).size as usize]))
+                .collect(),
+            barrier: BlockBarrier::new(self.thread_block_size),
+            // The last subgroup is partial if the block size is not a
+            // multiple of the subgroup size.
+            subgroups: (0..self.thread_block_size)
+                .step_by(self.subgroup_size as usize)
+                .map(|first| {
+                    SubgroupExchange::new(self.subgroup_size.min(self.thread_block_size - first))
+                })
+                .collect(),
+        };
+        if self.thread_block_size == 1 {
+            return self.run_thread(block_id, 0, block);
+        }
+        // The worker runs the first thread itself.
+        thread::scope(|s| {
+            let threads: Vec<_> = (1..self.thread_block_size)
+                .map(|local_id| s.spawn(move || self.run_thread(block_id, local_id, block)))
+                .collect();
+            let first = self.run_thread(block_id, 0, block);
+            threads
+                .into_iter()
+                .map(|t| t.join().unwrap_or_else(|_| Err(super::worker_panicked())))
+                .fold(first, Result::and)
+        })
+    }
+
+    fn run_thread(&self, block_id: u32, local_id: u32, block: &ThreadBlock) -> Result<()> {
+        // Leaves the barriers however the thread stops, panics included.
+        struct Leave<'b>(&'b BlockBarrier, &'b SubgroupExchange<Value>, u32);
+
+        impl Drop for Leave<'_> {
+            fn drop(&mut self) {
+                self.0.leave();
+                self.1.leave(self.2, self.0);
+            }
+        }
+
+        let subgroup = &block.subgroups[(local_id / self.subgroup_size) as usize];
+        let _leave = Leave(&block.barrier, subgroup, local_id % self.subgroup_size);
+        let mut thread = Thread {
+            launch: self,
+            block,
+            block_id,
+            local_id,
+            locals: vec![None; self.kernel.locals.len()],
+        };
+        thread.block(&self.kernel.body).map_err(|trap| {
+            RyclError::DispatchFailed(format!("kernel `{}`: {}", self.kernel.name, trap.0))
+        })?;
+        Ok(())
+    }
+}
+
+/// An error that stops a kernel thread, such as an out-of-bounds index.
+struct Trap(String);
+
+type Exec<T> = std::result::Result<T, Trap>;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Scalar {
+    Bool(bool),
+    U8(u8),
+    I8(i8),
+    U16(u16),
+    I16(i16),
+    U32(u32),
+    I32(i32),
+    U64(u64),
+    I64(i64),
+    F16(f16),
+    F32(f32),
+    F64(f64),
+}
+
+/// Evaluates `$body` with `$v` bound to the value of an integer scalar, and
+/// `$other` for the remaining variants.
+macro_rules! each_int {
+    ($value:expr, $v:ident => $body:expr, $rest:ident => $other:expr) => {
+        match $value {
+            Scalar::U8($v) => $body,
+            Scalar::I8($v) => $body,
+            Scalar::U16($v) => $body,
+            Scalar::I16($v) => $body,
+            Scalar::U32($v) => $body,
+            Scalar::I32($v) => $body,
+            Scalar::U64($v) => $body,
+            Scalar::I64($v) => $body,
+            $rest => $other,
+        }
+    };
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Value {
+    Scalar(Scalar),
+    /// Elements of an array, fields of a struct, components of a vector or
+    /// columns of a matrix.
+    Composite(Vec<Value>),
+}
+
+impl Value {
+    fn zero(ty: &Type) -> Value {
+        match ty {
+            Type::Scalar(s) => Value::Scalar(Scalar::from_bits(*s, 0)),
+            Type::Array(elem, len) => Value::Composite(vec![Value::zero(elem); *len as usize]),
+            Type::Struct(s) => {
+                Value::Composite(s.fields.iter().map(|f| Value::zero(&f.ty)).collect())
+            }
+            Type::Vector(..) | Type::Matrix(..) => {
+                let (elem, _) = element(ty);
+                Value::Composite(vec![Value::zero(&elem); vector_len(ty) as usize])
+            }
+            Type::Slice(_) => unreachable!("slices are only kernel arguments"),
+            Type::Named(name) => unreachable!("unresolved type `{name}`"),
+        }
+    }
+
+    fn scalar(&self) -> Scalar {
+        match self {
+            Value::Scalar(s) => *s,
+            Value::Composite(_) => unreachable!("expected a scalar"),
+        }
+    }
+
+    fn items(self) -> Vec<Value> {
+        match self {
+            Value::Composite(items) => items,
+            Value::Scalar(s) => unreachable!("expected a composite, got {s:?}"),
+        }
+    }
+}
+
+impl Scalar {
+    /// The scalar of type `ty` whose memory representation is the low
+    /// `ty.size()` bytes of `bits`.
+    fn from_bits(ty: ScalarType, bits: u64) -> Scalar {
+        match ty {
+            ScalarType::Bool => Scalar::Bool(bits != 0),
+            ScalarType::U8 => Scalar::U8(bits as u8),
+            ScalarType::I8 => Scalar::I8(bits as i8),
+            ScalarType::U16 => Scalar::U16(bits as u16),
+            ScalarType::I16 => Scalar::I16(bits as i16),
+            ScalarType::U32 => Scalar::U32(bits as u32),
+            ScalarType::I32 => Scalar::I32(bits as i32),
+            ScalarType::U64 => Scalar::U64(bits),
+            ScalarType::I64 => Scalar::I64(bits as i64),
+            ScalarType::F16 => Scalar::F16(f16::from_bits(bits as u16)),
+            ScalarType::F32 => Scalar::F32(f32::from_bits(bits as u32)),
+            ScalarType::F64 => Scalar::F64(f64::from_bits(bits)),
+        }
+    }
+
+    /// The float of type `ty` nearest to `value`.
+    fn from_f64(ty: ScalarType, value: f64) -> Scalar {
+        match ty {
+            ScalarType::F16 => Scalar::F16(f16::from_f64(value)),
+            ScalarType::F32 => Scalar::F32(value as f32),
+            ScalarType::F64 => Scalar::F64(value),
+            ty => unreachable!("{ty:?} is not a float type"),
+        }
+    }
+
+    /// The memory representation of the value, extended to 64 bits. Signed
+    /// integers are sign-extended.
+    fn to_bits(self) -> u64 {
+        match self {
+            Scalar::Bool(b) => u64::from(b),
+            Scalar::F16(v) => u64::from(v.to_bits()),
+            Scalar::F32(v) => u64::from(v.to_bits()),
+            Scalar::F64(v) => v.to_bits(),
+            value => value.as_i64() as u64,
+        }
+    }
+
+    fn as_bool(self) -> bool {
+        match self {
+            Scalar::Bool(b) => b,
+            _ => unreachable!("expected a bool"),
+        }
+    }
+
+    /// The value of an integer, sign- or zero-extended to 64 bits.
+    fn as_i64(self) -> i64 {
+        each_int!(self, v => v as i64, value => unreachable!("expected an integer, got {value:?}"))
+    }
+
+    fn as_f64(self) -> f64 {
+        match self {
+            Scalar::F16(v) => v.to_f64(),
+            Scalar::F32(v) => f64::from(v),
+            Scalar::F64(v) => v,
+            value => unreachable!("expected a float, got {value:?}"),
+        }
+    }
+
+    /// The value as an index or shift amount.
+    fn as_u32(self) -> u32 {
+        self.as_i64() as u32
+    }
+}
+
+/// Where a place lives: at a byte offset in a buffer, or along a path of
+/// element/field indices in a local.
+enum Location {
+    Buffer { buffer: Buffer, offset: u64 },
+    Local { local: usize, path: Vec<usize> },
+}
+
+/// An argument buffer, or the memory of a shared array of the thread block.
+#[derive(Clone, Copy)]
+enum Buffer {
+    Param(usize),
+    Shared(usize),
+}
+
+enum Flow {
+    Normal,
+    Break,
+    Continue,
+    Return,
+}
+
+struct Thread<'a> {
+    launch: &'a Launch<'a>,
+    block: &'a ThreadBlock,
+    block_id: u32,
+    local_id: u32,
+    locals: Vec<Option<Value>>,
+}
+
+impl Thread<'_> {
+    fn block(&mut self, block: &Block) -> Exec<Flow> {
+        for stmt in block {
+            match self.stmt(stmt)? {
+                Flow::Normal => {}
+                flow => return Ok(flow),
+            }
+        }
+        Ok(Flow::Normal)
+    }
+
+    fn stmt(&mut self, stmt: &Stmt) -> Exec<Flow> {
+        match stmt {
+            Stmt::Let { local, init } => {
+                let value = match init {
+                    Some(init) => self.expr(init)?,
+                    None => Value::zero(self.local_type(local.0 as usize)),
+                };
+                self.locals[local.0 as usize] = Some(value);
+            }
+            Stmt::Assign { place, value } => {
+                let value = self.expr(value)?;
+                let (location, ty) = self.locate(place)?;
+                self.store(&location, &ty, value)?;
+            }
+            Stmt::If {
+                cond,
+                then_block,
+                else_block,
+            } => {
+                return if self.expr(cond)?.scalar().as_bool() {
+                    self.block(then_block)
+                } else {
+                    self.block(else_block)
+                };
+            }
+            Stmt::For {
+                var,
+                start,
+                end,
+                inclusive,
+                body,
+            } => {
+                let var = var.0 as usize;
+                let start = self.expr(start)?.scalar();
+                let end = self.expr(end)?.scalar();
+                let mut current = start;
+                loop {
+                    let in_range = if *inclusive {
+                        compare(BinaryOp::Le, current, end)
+                    } else {
+                        compare(BinaryOp::Lt, current, end)
+                    };
+                    if !in_range {
+                        break;
+                    }
+                    self.locals[var] = Some(Value::Scalar(current));
+                    match self.block(body)? {
+                        Flow::Break => break,
+                        Flow::Return => return Ok(Flow::Return),
+                        Flow::Normal | Flow::Continue => {}
+                    }
+                    // `start..=end` must stop at `end` even when it is the
+                    // largest value of its type.
+                    if *inclusive && current == end {
+                        break;
+                    }
+                    current = arith(
+                        BinaryOp::Add,
+                        current,
+                        Scalar::from_bits(scalar_type(current), 1),
+                    )?;
+                }
+            }
+            Stmt::While { cond, body } => {
+                while self.expr(cond)?.scalar().as_bool() {
+                    match self.block(body)? {
+                        Flow::Break => break,
+                        Flow::Return => return Ok(Flow::Return),
+                        Flow::Normal | Flow::Continue => {}
+                    }
+                }
+            }
+            Stmt::Break => return Ok(Flow::Break),
+            Stmt::Continue => return Ok(Flow::Continue),
+            Stmt::Return => return Ok(Flow::Return),
+            // Memory is accessed with relaxed atomics, which fences order.
+            Stmt::Sync(SyncOp::BlockBarrier) => {
+                // Subgroup operations of the other threads go on without
+                // the threads waiting here.
+                let subgroup_size = self.launch.subgroup_size;
+                self.block.subgroups[(self.local_id / subgroup_size) as usize]
+                    .park(self.local_id % subgroup_size, &self.block.barrier);
+                self.block.barrier.wait();
+            }
+            Stmt::Sync(SyncOp::BlockFence | SyncOp::DeviceFence) => atomic::fence(Ordering::SeqCst),
+            Stmt::Expr(expr) => {
+                self.expr(expr)?;
+            }
+        }
+        Ok(Flow::Normal)
+    }
+
+    fn expr(&mut self, expr: &Expr) -> Exec<Value> {
+        Ok(Value::Scalar(match expr {
+            Expr::Literal(lit) => literal(lit),
+            Expr::Load(place) => {
+                let (location, ty) = self.locate(place)?;
+                return self.load(&location, &ty);
+            }
+            Expr::Len(place) => {
+                let (location, ty) = self.locate(place)?;
+                Scalar::U32(self.len(&location, &ty))
+            }
+            Expr::Builtin(Builtin::NumThreadBlocks) => Scalar::U32(self.launch.num_thread_blocks),
+            Expr::Builtin(Builtin::ThreadBlockSize) => Scalar::U32(self.launch.thread_block_size),
+            Expr::Builtin(Builtin::GlobalId) => {
+                // Wraps like the 32-bit global invocation id of a device.
+                Scalar::U32(
+                    self.block_id
+                        .wrapping_mul(self.launch.thread_block_size)
+                        .wrapping_add(self.local_id),
+                )
+            }
+            Expr::Builtin(Builtin::LocalId) => Scalar::U32(self.local_id),
+            Expr::Builtin(Builtin::BlockId) => Scalar::U32(self.block_id),
+            Expr::Builtin(Builtin::SubgroupSize) => Scalar::U32(self.launch.subgroup_size),
+            Expr::Builtin(Builtin::SubgroupId) => {
+                Scalar::U32(self.local_id / self.launch.subgroup_size)
+            }
+            Expr::Builtin(Builtin::SubgroupLocalId) => {
+                Scalar::U32(self.local_id % self.launch.subgroup_size)
+            }
+            Expr::Unary(op, operand) => return Ok(unary(*op, self.expr(operand)?)),
+            Expr::Binary(BinaryOp::And, lhs, rhs) => Scalar::Bool(
+                self.expr(lhs)?.scalar().as_bool() && self.expr(rhs)?.scalar().as_bool(),
+            ),
+            Expr::Binary(BinaryOp::Or, lhs, rhs) => Scalar::Bool(
+                self.expr(lhs)?.scalar().as_bool() || self.expr(rhs)?.scalar().as_bool(),
+            ),
+            Expr::Binary(op, lhs, rhs) => {
+                let lhs = self.expr(lhs)?;
+                let rhs = self.expr(rhs)?;
+                return binary(*op, lhs, rhs);
+            }
+            Expr::Cast(operand, to) => cast(self.expr(operand)?.scalar(), *to),
+            Expr::Select(cond, then_expr, else_expr) => {
+                // Both sides are evaluated, as in the generated `OpSelect`.
+                let cond = self.expr(cond)?.scalar().as_bool();
+                let then_value = self.expr(then_expr)?;
+                let else_value = self.expr(else_expr)?;
+                return Ok(if cond { then_value } else { else_value });
+            }
+            Expr::Construct(ty, args) => {
+                let mut items = Vec::with_capacity(args.len());
+                for arg in args {
+                    match (ty, self.expr(arg)?) {
+                        // Vectors take the components of smaller vectors.
+                        (Type::Vector(..), Value::Composite(components)) => {
+                            items.extend(components)
+                        }
+                        (_, value) => items.push(value),
+                    }
+                }
+                if let (Type::Vector(_, len), [value]) = (ty, items.as_slice()) {
+                    items = vec![value.clone(); *len as usize];
+                }
+                return Ok(Value::Composite(items));
+            }
+            Expr::Swizzle(operand, indices) => {
+                let components = self.expr(operand)?.items();
+                return Ok(match indices.as_slice() {
+                    [index] => components[*index as usize].clone(),
+                    _ => Value::Composite(
+                        indices
+                            .iter()
+                            .map(|&i| components[i as usize].clone())
+                            .collect(),
+                    ),
+                });
+            }
+            Expr::Call(function, args) => {
+                let args = args
+                    .iter()
+                    .map(|arg| self.expr(arg))
+                    .collect::<Exec<Vec<_>>>()?;
+                return call(*function, args);
+            }
+            Expr::Atomic(op, place, args) => {
+                let (location, ty) = self.locate(place)?;
+                let args = args
+                    .iter()
+                    .map(|arg| Ok(self.expr(arg)?.scalar().to_bits() as u32))
+                    .collect::<Exec<Vec<_>>>()?;
+                let (Location::Buffer { buffer, offset }, Type::Scalar(ty)) = (location, ty) else {
+                    unreachable!("atomics update scalars in buffers");
+                };
+                let (memory, at) = self.buffer_scalar(buffer, offset, ty)?;
+                let word = memory.word(at).ok_or_else(out_of_memory)?;
+                Scalar::from_bits(ty, u64::from(atomic(*op, ty, word, &args)))
+            }
+            Expr::Subgroup(op, args) => return self.subgroup(*op, args),
+        }))
+    }
+
+    fn subgroup(&mut self, op: SubgroupOp, args: &[Expr]) -> Exec<Value> {
+        let value = self.expr(&args[0])?;
+        let source = match op {
+            SubgroupOp::Broadcast | SubgroupOp::Shuffle => self.expr(&args[1])?.scalar().as_u32(),
+            _ => 0,
+        };
+        let subgroup_size = self.launch.subgroup_size;
+        let lane = self.local_id % subgroup_size;
+        // Threads in other branches reach other operations, which are told
+        // apart by the address of their arguments.
+        let values = self.block.subgroups[(self.local_id / subgroup_size) as usize].exchange(
+            lane,
+            args.as_ptr() as usize,
+            value.clone(),
+            &self.block.barrier,
+        );
+        let scalars = |lanes: &[Option<Value>]| {
+            lanes
+                .iter()
+                .flatten()
+                .map(Value::scalar)
+                .collect::<Vec<_>>()
+        };
+        let sum = |lanes: &[Option<Value>]| {
+            let zero = Scalar::from_bits(scalar_type(value.scalar()), 0);
+            scalars(lanes)
+                .into_iter()
+                .try_fold(zero, |sum, x| arith(BinaryOp::Add, sum, x))
+        };
+        let pick = |keep: BinaryOp| {
+            scalars(&values)
+                .into_iter()
+                .reduce(|best, x| if compare(keep, x, best) { x } else { best })
+                .expect("the calling thread passed a value")
+        };
+        Ok(Value::Scalar(match op {
+            SubgroupOp::ReduceAdd => sum(&values)?,
+            SubgroupOp::InclusiveAdd => sum(&values[..=lane as usize])?,
+            SubgroupOp::ExclusiveAdd => sum(&values[..lane as usize])?,
+            SubgroupOp::ReduceMin => pick(BinaryOp::Lt),
+            SubgroupOp::ReduceMax => pick(BinaryOp::Gt),
+            SubgroupOp::Broadcast | SubgroupOp::Shuffle => {
+                return values
+                    .get(source as usize)
+                    .cloned()
+                    .flatten()
+                    .ok_or_else(|| Trap(format!("subgroup lane {source} is not active")));
+            }
+            SubgroupOp::Ballot => Scalar::U32(
+                values
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, value)| value.as_ref().is_some_and(|v| v.scalar().as_bool()))
+                    .fold(0, |mask, (lane, _)| mask | 1 << lane),
+            ),
+        }))
+    }
+
+    fn local_type(&self, local: usize) -> &Type {
+        self.launch.kernel.locals[local]
+            .ty
+            .as_ref()
+            .expect("kernel has not been type checked")
+    }
+
+    /// Resolves `place` to a location and the type stored there.
+    fn locate(&mut self, place: &Place) -> Exec<(Location, Type)> {
+        match place {
+            Place::Param(i) => Ok((
+                Location::Buffer {
+                    buffer: Buffer::Param(*i),
+                    offset: 0,
+                },
+                self.launch.kernel.params[*i].ty.clone(),
+            )),
+            Place::Shared(i) => Ok((
+                Location::Buffer {
+                    buffer: Buffer::Shared(*i),
+                    offset: 0,
+                },
+                self.launch.kernel.shared[*i].ty.clone(),
+            )),
+            Place::Local(id) => Ok((
+                Location::Local {
+                    local: id.0 as usize,
+                    path: Vec::new(),
+                },
+                self.local_type(id.0 as usize).clone(),
+            )),
+            Place::Index(base, index) => {
+                let (location, ty) = self.locate(base)?;
+                let index = self.expr(index)?.scalar().as_u32();
+                let len = self.len(&location, &ty);
+                let (elem, stride) = element(&ty);
+                if index >= len {
+                    return Err(Trap(format!(
+                        "index out of bounds: the len is {len} but the index is {index}"
+                    )));
+                }
+                let location = match location {
+                    Location::Buffer { buffer, offset } => Location::Buffer {
+                        buffer,
+                        offset: offset + u64::from(index * stride),
+                    },
+                    Location::Local { local, mut path } => {
+                        path.push(index as usize);
+                        Location::Local { local, path }
+                    }
+                };
+                Ok((location, elem))
+            }
+            Place::Field(base, name) => {
+                let (location, ty) = self.locate(base)?;
+                let (field, field_ty) = ty.member(name).expect("unknown field");
+                let field_offset = match &ty {
+                    Type::Struct(s) => layout::struct_offsets(s)[field],
+                    ty => field as u32 * element(ty).1,
+                };
+                let location = match location {
+                    Location::Buffer { buffer, offset } => Location::Buffer {
+                        buffer,
+                        offset: offset + u64::from(field_offset),
+                    },
+                    Location::Local { local, mut path } => {
+                        path.push(field);
+                        Location::Local { local, path }
+                    }
+                };
+                Ok((location, field_ty))
+            }
+        }
+    }
+
+    /// Number of elements of the array, slice, vector or matrix at
+    /// `location`.
+    fn len(&self, location: &Location, ty: &Type) -> u32 {
+        match (ty, location) {
+            (Type::Array(_, len) | Type::Vector(_, len) | Type::Matrix(_, len), _) => *len,
+            // A slice spans its whole argument buffer.
+            (
+                Type::Slice(elem),
+                Location::Buffer {
+                    buffer: Buffer::Param(param),
+                    ..
+                },
+            ) => {
+                let size = self.launch.buffers[*param].size;
+                (size / u64::from(layout::array_stride(elem))) as u32
+            }
+            (ty, _) => unreachable!("length of non-array type {ty:?}"),
+        }
+    }
+
+    fn load(&self, location: &Location, ty: &Type) -> Exec<Value> {
+        match location {
+            Location::Buffer { buffer, offset } => self.load_buffer(*buffer, *offset, ty),
+            Location::Local { local, path } => {
+                let mut value = self.locals[*local]
+                    .as_ref()
+                    .expect("local read before it was initialized");
+                for &i in path {
+                    let Value::Composite(items) = value else {
+                        unreachable!("path into a scalar");
+                    };
+                    value = &items[i];
+                }
+                Ok(value.clone())
+            }
+        }
+    }
+
+    fn store(&mut self, location: &Location, ty: &Type, value: Value) -> Exec<()> {
+        match location {
+            Location::Buffer { buffer, offset } => self.store_buffer(*buffer, *offset, ty, &value),
+            Location::Local { local, path } => {
+                let ty = self.local_type(*local).clone();
+                let mut slot = self.locals[*local].get_or_insert_with(|| Value::zero(&ty));
+                for &i in path {
+                    let Value::Composite(items) = slot else {
+                        unreachable!("path into a scalar");
+                    };
+                    slot = &mut items[i];
+                }
+                *slot = value;
+                Ok(())
+            }
+        }
+    }
+
+    fn load_buffer(&self, buffer: Buffer, offset: u64, ty: &Type) -> Exec<Value> {
+        Ok(match ty {
+            Type::Scalar(s) => {
+                let (memory, at) = self.buffer_scalar(buffer, offset, *s)?;
+                let bits = memory.load_scalar(at, s.size()).ok_or_else(out_of_memory)?;
+                Value::Scalar(Scalar::from_bits(*s, bits))
+            }
+            Type::Array(_, len) | Type::Vector(_, len) | Type::Matrix(_, len) => {
+                let (elem, stride) = element(ty);
+                let stride = u64::from(stride);
+                Value::Composite(
+                    (0..u64::from(*len))
+                        .map(|i| self.load_buffer(buffer, offset + i * stride, &elem))
+                        .collect::<Exec<_>>()?,
+                )
+            }
+            Type::Struct(s) => Value::Composite(
+                s.fields
+                    .iter()
+                    .zip(layout::struct_offsets(s))
+                    .map(|(f, field_offset)| {
+                        self.load_buffer(buffer, offset + u64::from(field_offset), &f.ty)
+                    })
+                    .collect::<Exec<_>>()?,
+            ),
+            Type::Slice(_) => unreachable!("slices are only loaded an element at a time"),
+            Type::Named(name) => unreachable!("unresolved type `{name}`"),
+        })
+    }
+
+    fn store_buffer(&self, buffer: Buffer, offset: u64, ty: &Type, value: &Value) -> Exec<()> {
+        match (ty, value) {
+            (Type::Scalar(ty), Value::Scalar(s)) => {
+                let (memory, at) = self.buffer_scalar(buffer, offset, *ty)?;
+                memory
+                    .store_scalar(at, ty.size(), s.to_bits())
+                    .ok_or_else(out_of_memory)
+            }
+            (Type::Array(..) | Type::Vector(..) | Type::Matrix(..), Value::Composite(items)) => {
+                let (elem, stride) = element(ty);
+                let stride = u64::from(stride);
+                for (i, item) in items.iter().enumerate() {
+                    self.store_buffer(buffer, offset + i as u64 * stride, &elem, item)?;
+                }
+                Ok(())
+            }
+            (Type::Struct(s), Value::Composite(items)) => {
+                for ((field, field_offset), item) in
+                    s.fields.iter().zip(layout::struct_offsets(s)).zip(items)
+                {
+                    self.store_buffer(buffer, offset + u64::from(field_offset), &field.ty, item)?;
+                }
+                Ok(())
+            }
+            (ty, value) => unreachable!("storing {value:?} as {ty:?}"),
+        }
+    }
+
+    /// The memory of `buffer`, and the absolute byte offset of the scalar of
+    /// type `ty` at `offset` in it.
+    fn buffer_scalar(
+        &self,
+        buffer: Buffer,
+        offset: u64,
+        ty: ScalarType,
+    ) -> Exec<(&CpuMemory, u64)> {
+        let (memory, start, size) = match buffer {
+            Buffer::Param(param) => {
+                let binding = &self.launch.buffers[param];
+                (binding.memory, binding.offset, binding.size)
+            }
+            Buffer::Shared(i) => (&self.block.shared[i], 0, self.block.shared[i].size),
+        };
+        if offset + u64::from(ty.size()) > size {
+            return Err(out_of_memory());
+        }
+        Ok((memory, start + offset))
+    }
+}
+
+/// Element type and buffer stride of an array or slice, of the components
+/// of a vector, or of the columns of a matrix.
+fn element(ty: &Type) -> (Type, u32) {
+    match ty {
+        Type::Array(elem, _) | Type::Slice(elem) => ((**elem).clone(), layout::array_stride(elem)),
+        Type::Vector(scalar, _) => (Type::Scalar(*scalar), scalar.size()),
+        Type::Matrix(scalar, len) => {
+            let column = Type::Vector(*scalar, *len);
+            let stride = layout::array_stride(&column);
+            (column, stride)
+        }
+        ty => unreachable!("{ty:?} has no elements"),
+    }
+}
+
+fn vector_len(ty: &Type) -> u32 {
+    match ty {
+        Type::Vector(_, len) | Type::Matrix(_, len) => *len,
+        ty => unreachable!("{ty:?} is not a vector or matrix"),
+    }
+}
+
+fn out_of_memory() -> Trap {
+    Trap("access past the end of an argument buffer".to_string())
+}
+
+fn literal(lit: &Literal) -> Scalar {
+    match *lit {
+        Literal::Bool(b) => Scalar::Bool(b),
+        Literal::Int(value, ty) => Scalar::from_bits(ty.unwrap_or(ScalarType::I32), value),
+        Literal::Float(value, ty) => Scalar::from_f64(ty.unwrap_or(ScalarType::F32), value),
+    }
+}
+
+fn scalar_type(value: Scalar) -> ScalarType {
+    match value {
+        Scalar::Bool(_) => ScalarType::Bool,
+        Scalar::U8(_) => ScalarType::U8,
+        Scalar::I8(_) => ScalarType::I8,
+        Scalar::U16(_) => ScalarType::U16,
+        Scalar::I16(_) => ScalarType::I16,
+        Scalar::U32(_) => ScalarType::U32,
+        Scalar::I32(_) => ScalarType::I32,
+        Scalar::U64(_) => ScalarType::U64,
+        Scalar::I64(_) => ScalarType::I64,
+        Scalar::F16(_) => ScalarType::F16,
+        Scalar::F32(_) => ScalarType::F32,
+        Scalar::F64(_) => ScalarType::F64,
+    }
+}
+
+fn unary(op: UnaryOp, value: Value) -> Value {
+    let value = match value {
+        Value::Scalar(value) => value,
+        Value::Composite(items) => {
+            return Value::Composite(items.into_iter().map(|item| unary(op, item)).collect())
+        }
+    };
+    let ty = scalar_type(value);
+    Value::Scalar(match (op, value) {
+        (UnaryOp::Not, Scalar::Bool(v)) => Scalar::Bool(!v),
+        (UnaryOp::Neg, value) if ty.is_float() => Scalar::from_f64(ty, -value.as_f64()),
+        (UnaryOp::Neg, value) => Scalar::from_bits(ty, value.as_i64().wrapping_neg() as u64),
+        (UnaryOp::Not, value) if ty.is_int() => Scalar::from_bits(ty, !value.to_bits()),
+        (op, value) => unreachable!("{op:?} applied to {value:?}"),
+    })
+}
+
+/// `lhs op rhs` on scalars, vectors and matrices. Composites combine item
+/// by item, a scalar operand applying to every item, except for the
+/// matrix products.
+fn binary(op: BinaryOp, lhs: Value, rhs: Value) -> Exec<Value> {
+    let is_matrix = |items: &[Value]| matches!(items.first(), Some(Value::Composite(_)));
+    Ok(match (lhs, rhs) {
+        (Value::Scalar(lhs), Value::Scalar(rhs)) if op.is_comparison() => {
+            Value::Scalar(Scalar::Bool(compare(op, lhs, rhs)))
+        }
+        (Value::Scalar(lhs), Value::Scalar(rhs)) => Value::Scalar(arith(op, lhs, rhs)?),
+        // The columns of the matrix, scaled by the components of the vector
+        // and summed.
+        (Value::Composite(m), Value::Composite(v))
+            if op == BinaryOp::Mul && is_matrix(&m) && !is_matrix(&v) =>
+        {
+            let mut products = m.into_iter().zip(v).map(|(c, s)| binary(op, c, s));
+            let first = products.next().expect("matrices have columns")?;
+            products.try_fold(first, |sum, product| binary(BinaryOp::Add, sum, product?))?
+        }
+        (Value::Composite(m), Value::Composite(n)) if op == BinaryOp::Mul && is_matrix(&m) => {
+            Value::Composite(
+                n.into_iter()
+                    .map(|column| binary(op, Value::Composite(m.clone()), column))
+                    .collect::<Exec<_>>()?,
+            )
+        }
+        // Composites are equal when all their items are.
+        (Value::Composite(lhs), Value::Composite(rhs)) if op.is_comparison() => {
+            let mut equal = true;
+            for (lhs, rhs) in lhs.into_iter().zip(rhs) {
+                equal &= binary(BinaryOp::Eq, lhs, rhs)?.scalar().as_bool();
+            }
+            Value::Scalar(Scalar::Bool(equal == (op == BinaryOp::Eq)))
+        }
+        (Value::Composite(lhs), Value::Composite(rhs)) => Value::Composite(
+            lhs.into_iter()
+                .zip(rhs)
+                .map(|(lhs, rhs)| binary(op, lhs, rhs))
+                .collect::<Exec<_>>()?,
+        ),
+        (Value::Composite(lhs), rhs) => Value::Composite(
+            lhs.into_iter()
+                .map(|lhs| binary(op, lhs, rhs.clone()))
+                .collect::<Exec<_>>()?,
+        ),
+        (lhs, Value::Composite(rhs)) => Value::Composite(
+            rhs.into_iter()
+                .map(|rhs| binary(op, lhs.clone(), rhs))
+                .collect::<Exec<_>>()?,
+        ),
+    })
+}
+
+fn call(function: Function, mut args: Vec<Value>) -> Exec<Value> {
+    let dot = |a: &Value, b: &Value| -> Exec<Value> {
+        let product = binary(BinaryOp::Mul, a.clone(), b.clone())?.items();
+        let mut products = product.into_iter();
+        let first = products.next().expect("vectors have components");
+        products.try_fold(first, |sum, product| binary(BinaryOp::Add, sum, product))
+    };
+    let length = |v: &Value| -> Exec<Value> {
+        let squared = dot(v, v)?.scalar();
+        let length = squared.as_f64().sqrt();
+        Ok(Value::Scalar(Scalar::from_f64(
+            scalar_type(squared),
+            length,
+        )))
+    };
+    Ok(match function {
+        Function::Dot => dot(&args[0], &args[1])?,
+        Function::Length => length(&args[0])?,
+        Function::Normalize => {
+            let length = length(&args[0])?;
+            binary(BinaryOp::Div, args.swap_remove(0), length)?
+        }
+        Function::Cross => {
+            let b = args.pop().expect("cross takes two vectors").items();
+            let a = args.pop().expect("cross takes two vectors").items();
+            let component = |i: usize, j: usize| -> Exec<Value> {
+                let lhs = binary(BinaryOp::Mul, a[i].clone(), b[j].clone())?;
+                let rhs = binary(BinaryOp::Mul, a[j].clone(), b[i].clone())?;
+                binary(BinaryOp::Sub, lhs, rhs)
+            };
+            Value::Composite(vec![component(1, 2)?, component(2, 0)?, component(0, 1)?])
+        }
+        Function::Transpose => {
+            let columns: Vec<Vec<Value>> = args
+                .swap_remove(0)
+                .items()
+                .into_iter()
+                .map(Value::items)
+                .collect();
+            Value::Composite(
+                (0..columns.len())
+                    .map(|row| Value::Composite(columns.iter().map(|c| c[row].clone()).collect()))
+                    .collect(),
+            )
+        }
+        function => {
+            let args: Vec<_> = args.iter().map(Value::scalar).collect();
+            Value::Scalar(math(function, &args))
+        }
+    })
+}
+
+/// Applies math `function` to `args` with the methods of `std`.
+fn math(function: Function, args: &[Scalar]) -> Scalar {
+    match (function, args) {
+        (Function::Sqrt, &[Scalar::F32(x)]) => Scalar::F32(x.sqrt()),
+        (Function::Exp, &[Scalar::F32(x)]) => Scalar::F32(x.exp()),
+        (Function::Log, &[Scalar::F32(x)]) => Scalar::F32(x.ln()),
+        (Function::Pow, &[Scalar::F32(x), Scalar::F32(y)]) => Scalar::F32(x.powf(y)),
+        (Function::Sin, &[Scalar::F32(x)]) => Scalar::F32(x.sin()),
+        (Function::Cos, &[Scalar::F32(x)]) => Scalar::F32(x.cos()),
+        (Function::Tan, &[Scalar::F32(x)]) => Scalar::F32(x.tan()),
+        (Function::Floor, &[Scalar::F32(x)]) => Scalar::F32(x.floor()),
+        (Function::Ceil, &[Scalar::F32(x)]) => Scalar::F32(x.ceil()),
+        (Function::Round, &[Scalar::F32(x)]) => Scalar::F32(x.round()),
+        (Function::Fma, &[Scalar::F32(a), Scalar::F32(b), Scalar::F32(c)]) => {
+            Scalar::F32(a.mul_add(b, c))
+        }
+        (Function::Mix, &[Scalar::F32(a), Scalar::F32(b), Scalar::F32(t)]) => {
+            Scalar::F32(a * (1.0 - t) + b * t)
+        }
+        (Function::Abs, &[Scalar::F32(x)]) => Scalar::F32(x.abs()),
+        (Function::Abs, &[Scalar::I32(x)]) => Scalar::I32(x.wrapping_abs()),
+        (Function::Min, &[Scalar::F32(x), Scalar::F32(y)]) => Scalar::F32(x.min(y)),
+        (Function::Min, &[Scalar::U32(x), Scalar::U32(y)]) => Scalar::U32(x.min(y)),
+        (Function::Min, &[Scalar::I32(x), Scalar::I32(y)]) => Scalar::I32(x.min(y)),
+        (Function::Max, &[Scalar::F32(x), Scalar::F32(y)]) => Scalar::F32(x.max(y)),
+        (Function::Max, &[Scalar::U32(x), Scalar::U32(y)]) => Scalar::U32(x.max(y)),
+        (Function::Max, &[Scalar::I32(x), Scalar::I32(y)]) => Scalar::I32(x.max(y)),
+        // `clamp` of `std` panics on an empty range, whose result is
+        // undefined on devices.
+        (Function::Clamp, &[Scalar::F32(x), Scalar::F32(lo), Scalar::F32(hi)]) => {
+            Scalar::F32(x.max(lo).min(hi))
+        }
+        (Function::Clamp, &[Scalar::U32(x), Scalar::U32(lo), Scalar::U32(hi)]) => {
+            Scalar::U32(x.max(lo).min(hi))
+        }
+        (Function::Clamp, &[Scalar::I32(x), Scalar::I32(lo), Scalar::I32(hi)]) => {
+            Scalar::I32(x.max(lo).min(hi))
+        }
+        (function, args) => unreachable!("`{function:?}` applied to {args:?}"),
+    }
+}
+
+/// Applies atomic `op` to `word`, holding a value of type `ty`, with the
+/// bits of `args`, and returns the bits it held before.
+fn atomic(op: AtomicOp, ty: ScalarType, word: &AtomicU32, args: &[u32]) -> u32 {
+    let order = Ordering::Relaxed;
+    let update = |f: &dyn Fn(u32) -> u32| {
+        word.fetch_update(order, order, |bits| Some(f(bits)))
+            .unwrap_or_else(|bits| bits)
+    };
+    let signed = ty == ScalarType::I32;
+    match op {
+        AtomicOp::Add if ty == ScalarType::F32 => {
+            update(&|bits| (f32::from_bits(bits) + f32::from_bits(args[0])).to_bits())
+        }
+        // Two's complement addition is the same for both signednesses.
+        AtomicOp::Add => word.fetch_add(args[0], order),
+        AtomicOp::Min if signed => update(&|bits| (bits as i32).min(args[0] as i32) as u32),
+        AtomicOp::Min => word.fetch_min(args[0], order),
+        AtomicOp::Max if signed => update(&|bits| (bits as i32).max(args[0] as i32) as u32),
+        AtomicOp::Max => word.fetch_max(args[0], order),
+        AtomicOp::And => word.fetch_and(args[0], order),
+        AtomicOp::Or => word.fetch_or(args[0], order),
+        AtomicOp::Xor => word.fetch_xor(args[0], order),
+        AtomicOp::Exchange => word.swap(args[0], order),
+        AtomicOp::CompareExchange => word
+            .compare_exchange(args[0], args[1], order, order)
+            .unwrap_or_else(|bits| bits),
+    }
+}
+
+fn compare(op: BinaryOp, lhs: Scalar, rhs: Scalar) -> bool {
+    let ordering = match (lhs, rhs) {
+        (Scalar::Bool(a), Scalar::Bool(b)) => a.partial_cmp(&b),
+        (lhs, rhs) if scalar_type(lhs).is_float() => lhs.as_f64().partial_cmp(&rhs.as_f64()),
+        (lhs, rhs) if scalar_type(lhs).is_signed() => lhs.as_i64().partial_cmp(&rhs.as_i64()),
+        (lhs, rhs) => lhs.to_bits().partial_cmp(&rhs.to_bits()),
+    };
+    match op {
+        BinaryOp::Eq => ordering.is_some_and(|o| o.is_eq()),
+        // NaN compares unequal to everything, itself included.
+        BinaryOp::Ne => ordering.is_none_or(|o| o.is_ne()),
+        BinaryOp::Lt => ordering.is_some_and(|o| o.is_lt()),
+        BinaryOp::Le => ordering.is_some_and(|o| o.is_le()),
+        BinaryOp::Gt => ordering.is_some_and(|o| o.is_gt()),
+        BinaryOp::Ge => ordering.is_some_and(|o| o.is_ge()),
+        op => unreachable!("{op:?} is not a comparison"),
+    }
+}
+
+/// Integer arithmetic wraps, as it does on the device. Integers are
+/// computed on 64 bits and truncated to their type, and floats on `f64` and
+/// rounded to theirs, which gives the same results.
+fn arith(op: BinaryOp, lhs: Scalar, rhs: Scalar) -> Exec<Scalar> {
+    let ty = scalar_type(lhs);
+    if let (Scalar::Bool(a), Scalar::Bool(b)) = (lhs, rhs) {
+        return Ok(Scalar::Bool(match op {
+            BinaryOp::BitAnd => a & b,
+            BinaryOp::BitOr => a | b,
+            BinaryOp::BitXor => a ^ b,
+            op => unreachable!("{op:?} on bools"),
+        }));
+    }
+    if ty.is_float() {
+        let (a, b) = (lhs.as_f64(), rhs.as_f64());
+        return Ok(Scalar::from_f64(
+            ty,
+            match op {
+                BinaryOp::Add => a + b,
+                BinaryOp::Sub => a - b,
+                BinaryOp::Mul => a * b,
+                BinaryOp::Div => a / b,
+                BinaryOp::Rem => a % b,
+                op => unreachable!("{op:?} on floats"),
+            },
+        ));
+    }
+    let a = lhs.as_i64();
+    let bits = ty.size() * 8;
+    let result = if op.is_shift() {
+        let amount = rhs.as_u32() % bits;
+        match op {
+            BinaryOp::Shl => a.wrapping_shl(amount),
+            // `a` is sign-extended for signed types, and zero-extended
+            // otherwise.
+            BinaryOp::Shr if ty.is_signed() => a >> amount,
+            BinaryOp::Shr => ((a as u64) >> amount) as i64,
+            op => unreachable!("{op:?} is not a shift"),
+        }
+    } else {
+        let b = rhs.as_i64();
+        match op {
+            BinaryOp::Add => a.wrapping_add(b),
+            BinaryOp::Sub => a.wrapping_sub(b),
+            BinaryOp::Mul => a.wrapping_mul(b),
+            BinaryOp::Div | BinaryOp::Rem if b == 0 => {
+                return Err(Trap("attempt to divide by zero".to_string()))
+            }
+            BinaryOp::Div if ty.is_signed() => a.wrapping_div(b),
+            BinaryOp::Div => ((a as u64) / (b as u64)) as i64,
+            BinaryOp::Rem if ty.is_signed() => a.wrapping_rem(b),
+            BinaryOp::Rem => ((a as u64) % (b as u64)) as i64,
+            BinaryOp::BitAnd => a & b,
+            BinaryOp::BitOr => a | b,
+            BinaryOp::BitXor => a ^ b,
+            op => unreachable!("{op:?} on integers"),
+        }
+    };
+    Ok(Scalar::from_bits(ty, result as u64))
+}
+
+/// Follows the semantics of Rust's `as`.
+fn cast(value: Scalar, to: ScalarType) -> Scalar {
+    let from = scalar_type(value);
+    match value {
+        Scalar::Bool(b) if to.is_float() => Scalar::from_f64(to, f64::from(u8::from(b))),
+        Scalar::Bool(b) => Scalar::from_bits(to, u64::from(b)),
+        _ if to == ScalarType::Bool => Scalar::Bool(value.to_bits() != 0),
+        // Casts from floats saturate, and map NaN to 0.
+        _ if from.is_float() => {
+            let v = value.as_f64();
+            match to {
+                ScalarType::U8 => Scalar::U8(v as u8),
+                ScalarType::I8 => Scalar::I8(v as i8),
+                ScalarType::U16 => Scalar::U16(v as u16),
+                ScalarType::I16 => Scalar::I16(v as i16),
+                ScalarType::U32 => Scalar::U32(v as u32),
+                ScalarType::I32 => Scalar::I32(v as i32),
+                ScalarType::U64 => Scalar::U64(v as u64),
+                ScalarType::I64 => Scalar::I64(v as i64),
+                to => Scalar::from_f64(to, v),
+            }
+        }
+        // Conversions to `f32` round once, from the integer itself.
+        _ if to == ScalarType::F32 => Scalar::F32(each_int!(
+            value, v => v as f32,
+            value => unreachable!("{value:?} is not an integer")
+        )),
+        _ if to.is_float() => Scalar::from_f64(
+            to,
+            each_int!(value, v => v as f64, value => unreachable!("{value:?} is not an integer")),
+        ),
+        // Truncates, after extending the sign of signed values.
+        _ => Scalar::from_bits(to, value.to_bits()),
+    }
+}
@@ -0,0 +1,333 @@
+//! Reference backend that runs kernels on host threads.
+//!
+//! Kernels are executed by interpreting their IR. Every thread block is run
+//! by its own set of OS threads, one per kernel thread, and blocks are
+//! spread over a pool of workers, small enough that at most
+//! [`MAX_KERNEL_THREADS`] OS threads run kernel threads at once. Memory is
+//! stored as atomic 32-bit words so that threads can share buffers without
+//! data races, kernel atomics map to the read-modify-write operations of
+//! those words, and a block barrier parks the OS threads of its block until
+//! all of them arrive. Subgroups are emulated the same way: each subgroup
+//! operation waits for the threads of the subgroup that are active, those
+//! not waiting at the block barrier or at another operation, and hands
+//! every one of them all their values.
+mod barrier;
+mod interp;
+
+use std::any::Any;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::Arc;
+use std::thread;
+
+use shared_type::ir::Kernel;
+
+use super::device_ctx::{
+    BufferRange, DeviceCtx, DeviceFeatures, DeviceLimits, DeviceMemory, Module,
+};
+use super::error::{Result, RyclError};
+use crate::buffer::BufferUsage;
+
+/// Same value as `VK_PHYSICAL_DEVICE_TYPE_CPU`.
+const DEVICE_TYPE_CPU: i32 = 4;
+
+/// Subgroup size of new backends, that of most GPUs.
+const DEFAULT_SUBGROUP_SIZE: u32 = 32;
+
+/// Most OS threads a launch runs kernel threads on at once, which is also
+/// the largest thread block.
+const MAX_KERNEL_THREADS: u32 = 1024;
+
+/// Runs kernels on the host.
+pub struct Cpu {
+    num_workers: usize,
+    subgroup_size: u32,
+}
+
+impl Cpu {
+    /// A backend using one worker per available core.
+    pub fn new() -> Self {
+        Self::with_workers(thread::available_parallelism().map_or(1, |n| n.get()))
+    }
+
+    /// A backend running at most `num_workers` thread blocks at a time. Fewer
+    /// blocks run at once if they would need more than 1024 OS threads
+    /// together.
+    pub fn with_workers(num_workers: usize) -> Self {
+        Self {
+            num_workers: num_workers.max(1),
+            subgroup_size: DEFAULT_SUBGROUP_SIZE,
+        }
+    }
+
+    /// Emulates subgroups of `subgroup_size` threads instead of 32, to
+    /// check kernels against the subgroup size of another device. The size
+    /// must be a power of two between 1 and 32, the sizes for which ballots
+    /// can report every lane.
+    pub fn with_subgroup_size(mut self, subgroup_size: u32) -> Result<Self> {
+        if !subgroup_size.is_power_of_two() || subgroup_size > 32 {
+            return Err(RyclError::InvalidArgument(format!(
+                "subgroup size must be a power of two between 1 and 32, got {subgroup_size}"
+            )));
+        }
+        self.subgroup_size = subgroup_size;
+        Ok(self)
+    }
+}
+
+impl Default for Cpu {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+struct CpuModule {
+    kernel: Kernel,
+    thread_block_size: u32,
+}
+
+pub(crate) struct CpuMemory {
+    words: Vec<AtomicU32>,
+    size: u64,
+}
+
+impl CpuMemory {
+    fn new(bytes: &[u8]) -> Self {
+        let memory = Self {
+            words: (0..bytes.len().div_ceil(4))
+                .map(|_| AtomicU32::new(0))
+                .collect(),
+            size: bytes.len() as u64,
+        };
+        memory.store_bytes(0, bytes);
+        memory
+    }
+
+    /// The 32-bit word at `byte_offset`, which is aligned to 4 bytes, for
+    /// atomic updates.
+    pub(crate) fn word(&self, byte_offset: u64) -> Option<&AtomicU32> {
+        self.words.get((byte_offset / 4) as usize)
+    }
+
+    /// Loads the `size`-byte scalar at `byte_offset`, which is aligned to
+    /// its size, as the low bytes of the result.
+    pub(crate) fn load_scalar(&self, byte_offset: u64, size: u32) -> Option<u64> {
+        let index = (byte_offset / 4) as usize;
+        let word = self.words.get(index)?.load(Ordering::Relaxed);
+        Some(match size {
+            8 => {
+                let high = self.words.get(index + 1)?.load(Ordering::Relaxed);
+                let mut bytes = [0; 8];
+                bytes[..4].copy_from_slice(&word.to_ne_bytes());
+                bytes[4..].copy_from_slice(&high.to_ne_bytes());
+                u64::from_ne_bytes(bytes)
+            }
+            size => {
+                let mut bytes = [0; 8];
+                let at = (byte_offset % 4) as usize;
+                bytes[..size as usize].copy_from_slice(&word.to_ne_bytes()[at..at + size as usize]);
+                u64::from_ne_bytes(bytes)
+            }
+        })
+    }
+
+    /// Stores the low `size` bytes of `bits` at `byte_offset`, which is
+    /// aligned to `size`. Scalars narrower than a word are updated in place,
+    /// so that threads writing neighbouring bytes do not race.
+    pub(crate) fn store_scalar(&self, byte_offset: u64, size: u32, bits: u64) -> Option<()> {
+        let index = (byte_offset / 4) as usize;
+        let bytes = bits.to_ne_bytes();
+        match size {
+            8 => {
+                let (low, high) = (self.words.get(index)?, self.words.get(index + 1)?);
+                low.store(
+                    u32::from_ne_bytes(bytes[..4].try_into().unwrap()),
+                    Ordering::Relaxed,
+                );
+                high.store(
+                    u32::from_ne_bytes(bytes[4..].try_into().unwrap()),
+                    Ordering::Relaxed,
+                );
+            }
+            4 => {
+                let word = self.words.get(index)?;
+                word.store(
+                    u32::from_ne_bytes(bytes[..4].try_into().unwrap()),
+                    Ordering::Relaxed,
+                );
+            }
+            size => {
+                let at = (byte_offset % 4) as usize;
+                let size = size as usize;
+                let word = self.words.get(index)?;
+                let _ = word.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |word| {
+                    let mut word = word.to_ne_bytes();
+                    word[at..at + size].copy_from_slice(&bytes[..size]);
+                    Some(u32::from_ne_bytes(word))
+                });
+            }
+        }
+        Some(())
+    }
+
+    fn load_bytes(&self, offset: u64, out: &mut [u8]) {
+        for (i, byte) in out.iter_mut().enumerate() {
+            let at = offset as usize + i;
+            let word = self.words[at / 4].load(Ordering::Relaxed);
+            *byte = word.to_ne_bytes()[at % 4];
+        }
+    }
+
+    fn store_bytes(&self, offset: u64, data: &[u8]) {
+        for (i, &byte) in data.iter().enumerate() {
+            let at = offset as usize + i;
+            let word = &self.words[at / 4];
+            let mut bytes = word.load(Ordering::Relaxed).to_ne_bytes();
+            bytes[at % 4] = byte;
+            word.store(u32::from_ne_bytes(bytes), Ordering::Relaxed);
+        }
+    }
+}
+
+impl DeviceMemory for CpuMemory {
+    fn size(&self) -> u64 {
+        self.size
+    }
+
+    fn read(&self, offset: u64, out: &mut [u8]) -> Result<()> {
+        check_range(self.size, offset, out.len())?;
+        self.load_bytes(offset, out);
+        Ok(())
+    }
+
+    fn write(&self, offset: u64, data: &[u8]) -> Result<()> {
+        check_range(self.size, offset, data.len())?;
+        self.store_bytes(offset, data);
+        Ok(())
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+fn check_range(size: u64, offset: u64, len: usize) -> Result<()> {
+    if offset + len as u64 > size {
+        return Err(RyclError::InvalidArgument(format!(
+            "range {offset}..{} is out of bounds for {size} bytes of memory",
+            offset + len as u64
+        )));
+    }
+    Ok(())
+}
+
+impl DeviceCtx for Cpu {
+    fn device_id(&self) -> i32 {
+        0
+    }
+
+    fn device_type(&self) -> i32 {
+        DEVICE_TYPE_CPU
+    }
+
+    fn limits(&self) -> DeviceLimits {
+        DeviceLimits {
+            max_thread_block_size: MAX_KERNEL_THREADS,
+            max_thread_blocks: u32::MAX,
+            max_buffer_size: u64::from(u32::MAX),
+            max_kernel_args: 64,
+            min_buffer_offset_alignment: 4,
+            subgroup_size: self.subgroup_size,
+        }
+    }
+
+    fn features(&self) -> DeviceFeatures {
+        DeviceFeatures::ALL
+    }
+
+    fn compile(&self, kernel: &Kernel, thread_block_size: u32) -> Result<Module> {
+        Ok(Module::new(CpuModule {
+            kernel: kernel.clone(),
+            thread_block_size,
+        }))
+    }
+
+    fn allocate(&self, bytes: &[u8], _usage: BufferUsage) -> Result<Arc<dyn DeviceMemory>> {
+        Ok(Arc::new(CpuMemory::new(bytes)))
+    }
+
+    /// Runs every thread block to completion before returning.
+    fn launch(
+        &self,
+        module: &Module,
+        buffers: &[BufferRange],
+        num_thread_blocks: u32,
+    ) -> Result<()> {
+        let module = module
+            .downcast_ref::<CpuModule>()
+            .ok_or_else(|| foreign("module"))?;
+        let buffers = buffers
+            .iter()
+            .map(|range| {
+                let memory = range
+                    .memory
+                    .as_any()
+                    .downcast_ref::<CpuMemory>()
+                    .ok_or_else(|| foreign("buffer"))?;
+                if range.offset % 4 != 0 {
+                    return Err(RyclError::InvalidArgument(format!(
+                        "buffer offset {} is not aligned to 4 bytes",
+                        range.offset
+                    )));
+                }
+                Ok(interp::Binding {
+                    memory,
+                    offset: range.offset,
+                    size: range.size,
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        let launch = interp::Launch {
+            kernel: &module.kernel,
+            buffers: &buffers,
+            num_thread_blocks,
+            thread_block_size: module.thread_block_size,
+            subgroup_size: self.subgroup_size,
+        };
+        let next_block = AtomicU32::new(0);
+        let max_blocks_at_once = MAX_KERNEL_THREADS / module.thread_block_size.max(1);
+        let num_workers = self
+            .num_workers
+            .min(num_thread_blocks as usize)
+            .min(max_blocks_at_once.max(1) as usize);
+        thread::scope(|s| {
+            let workers: Vec<_> = (0..num_workers)
+                .map(|_| {
+                    s.spawn(|| loop {
+                        let block_id = next_block.fetch_add(1, Ordering::Relaxed);
+                        if block_id >= num_thread_blocks {
+                            return Ok(());
+                        }
+                        launch.run_block(block_id)?;
+                    })
+                })
+                .collect();
+            workers
+                .into_iter()
+                .try_for_each(|worker| worker.join().unwrap_or_else(|_| Err(worker_panicked())))
+        })
+    }
+
+    fn synchronize(&self) -> Result<()> {
+        // `launch` only returns once every block has finished.
+        Ok(())
+    }
+}
+
+fn worker_panicked() -> RyclError {
+    RyclError::DispatchFailed("a kernel thread panicked".to_string())
+}
+
+fn foreign(what: &str) -> RyclError {
+    RyclError::InvalidArgument(format!("{what} was created by another backend"))
+}
@@ -1,6 +1,164 @@
-pub(crate) trait DeviceCtx {
+//! The interface every backend implements.
+use std::any::Any;
+use std::sync::Arc;
+
+use shared_type::ir::{Kernel, ScalarType};
+
+use super::error::Result;
+use crate::buffer::BufferUsage;
+
+/// A device that kernels can be compiled for and launched on.
+///
+/// Backends are used through `Arc<dyn DeviceCtx>`, so buffers and compiled
+/// kernels are handed back as type-erased [`DeviceMemory`] and [`Module`]
+/// values, which the backend downcasts to its own types.
+pub trait DeviceCtx: Send + Sync {
     fn device_type(&self) -> i32;
     fn device_id(&self) -> i32;
-    fn entry_point(&self) -> &str;
-    // Additional methods specific to the backend behavior
+
+    fn limits(&self) -> DeviceLimits;
+
+    fn features(&self) -> DeviceFeatures;
+
+    /// Compiles a type-checked `kernel` for thread blocks of
+    /// `thread_block_size` threads, with an entry point named after the
+    /// kernel.
+    fn compile(&self, kernel: &Kernel, thread_block_size: u32) -> Result<Module>;
+
+    /// Loads `spirv`, the module of the type-checked `kernel` generated
+    /// ahead of time for thread blocks of `thread_block_size` threads.
+    /// Backends that do not run SPIR-V compile `kernel` instead.
+    fn load_spirv(&self, kernel: &Kernel, spirv: &[u32], thread_block_size: u32) -> Result<Module> {
+        let _ = spirv;
+        self.compile(kernel, thread_block_size)
+    }
+
+    /// Allocates device memory initialized with `bytes`.
+    fn allocate(&self, bytes: &[u8], usage: BufferUsage) -> Result<Arc<dyn DeviceMemory>>;
+
+    /// Runs `num_thread_blocks` thread blocks of `module`, with `buffers[i]`
+    /// bound to kernel argument `i`.
+    fn launch(
+        &self,
+        module: &Module,
+        buffers: &[BufferRange],
+        num_thread_blocks: u32,
+    ) -> Result<()>;
+
+    /// Waits until every launch has finished and its writes are visible to
+    /// the host.
+    fn synchronize(&self) -> Result<()>;
+}
+
+/// Launch limits of a device.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct DeviceLimits {
+    pub max_thread_block_size: u32,
+    pub max_thread_blocks: u32,
+    /// Largest buffer that can be bound to a kernel argument, in bytes.
+    pub max_buffer_size: u64,
+    /// Most arguments a kernel can take.
+    pub max_kernel_args: u32,
+    /// Required alignment, in bytes, of the start of a bound buffer range.
+    pub min_buffer_offset_alignment: u64,
+    /// Number of threads in a subgroup, 1 if the device has no subgroups.
+    pub subgroup_size: u32,
+}
+
+/// Optional scalar types a device supports, in arithmetic and in buffers,
+/// and optional operations on them.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct DeviceFeatures {
+    pub float16: bool,
+    pub float64: bool,
+    pub int8: bool,
+    pub int16: bool,
+    pub int64: bool,
+    /// Atomic additions to `f32` values in buffers and shared arrays.
+    pub float32_atomic_add: bool,
+    /// Subgroup builtins, reductions, scans, broadcasts, shuffles and
+    /// ballots in kernels.
+    pub subgroups: bool,
+}
+
+impl DeviceFeatures {
+    pub const ALL: Self = Self {
+        float16: true,
+        float64: true,
+        int8: true,
+        int16: true,
+        int64: true,
+        float32_atomic_add: true,
+        subgroups: true,
+    };
+
+    /// Whether kernels using `scalar` can run on the device.
+    pub fn supports(&self, scalar: ScalarType) -> bool {
+        match scalar {
+            ScalarType::F16 => self.float16,
+            ScalarType::F64 => self.float64,
+            ScalarType::U8 | ScalarType::I8 => self.int8,
+            ScalarType::U16 | ScalarType::I16 => self.int16,
+            ScalarType::U64 | ScalarType::I64 => self.int64,
+            ScalarType::Bool | ScalarType::U32 | ScalarType::I32 | ScalarType::F32 => true,
+        }
+    }
+}
+
+/// Memory allocated by a backend.
+pub trait DeviceMemory: Send + Sync {
+    /// Size in bytes.
+    fn size(&self) -> u64;
+    /// Copies the bytes starting at `offset` into `out`.
+    fn read(&self, offset: u64, out: &mut [u8]) -> Result<()>;
+    /// Copies `data` into the memory starting at `offset`.
+    fn write(&self, offset: u64, data: &[u8]) -> Result<()>;
+    fn as_any(&self) -> &dyn Any;
+}
+
+/// A byte range of device memory bound to a kernel argument.
+#[derive(Clone)]
+pub struct BufferRange {
+    pub memory: Arc<dyn DeviceMemory>,
+    pub offset: u64,
+    pub size: u64,
+}
+
+impl BufferRange {
+    /// The whole of `memory`.
+    pub fn new(memory: Arc<dyn DeviceMemory>) -> Self {
+        let size = memory.size();
+        Self {
+            memory,
+            offset: 0,
+            size,
+        }
+    }
+
+    pub fn read(&self, out: &mut [u8]) -> Result<()> {
+        self.memory
+            .read(self.offset, &mut out[..self.size as usize])
+    }
+
+    pub fn write(&self, data: &[u8]) -> Result<()> {
+        self.memory.write(self.offset, &data[..self.size as usize])
+    }
+}
+
+/// A kernel compiled by a backend.
+#[derive(Clone)]
+pub struct Module {
+    inner: Arc<dyn Any + Send + Sync>,
+}
+
+impl Module {
+    pub fn new<M: Any + Send + Sync>(module: M) -> Self {
+        Self {
+            inner: Arc::new(module),
+        }
+    }
+
+    pub fn downcast_ref<M: Any>(&self) -> Option<&M> {
+        self.inner.downcast_ref()
+    }
 }
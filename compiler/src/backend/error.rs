@@ -1 +1,66 @@
+use std::fmt;
 
+use shared_type::ir::typeck::TypeError;
+
+/// Errors returned by the host API.
+#[derive(Debug)]
+pub enum RyclError {
+    /// The Vulkan loader is missing, or no instance could be created.
+    NoVulkanLoader(String),
+    /// No device has a queue that supports compute.
+    NoComputeDevice,
+    /// No device matches the requested selector.
+    DeviceNotFound(String),
+    /// The kernel could not be type checked or translated.
+    InvalidKernel(String),
+    /// The kernel uses a type the device does not support.
+    UnsupportedType(String),
+    /// The driver rejected the generated module.
+    InvalidSpirv(String),
+    /// The module has no entry point with this name.
+    EntryPointNotFound(String),
+    OutOfDeviceMemory(String),
+    /// Recording, submitting or waiting for the dispatch failed.
+    DispatchFailed(String),
+    /// The arguments do not match the kernel or the buffer.
+    InvalidArgument(String),
+}
+
+pub type Result<T> = std::result::Result<T, RyclError>;
+
+impl fmt::Display for RyclError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RyclError::NoVulkanLoader(err) => write!(f, "failed to load Vulkan: {err}"),
+            RyclError::NoComputeDevice => write!(f, "no compute-capable device found"),
+            RyclError::DeviceNotFound(selector) => {
+                write!(f, "no device matches selector {selector}")
+            }
+            RyclError::InvalidKernel(err) => write!(f, "invalid kernel: {err}"),
+            RyclError::UnsupportedType(err) => write!(f, "unsupported type: {err}"),
+            RyclError::InvalidSpirv(err) => write!(f, "invalid SPIR-V module: {err}"),
+            RyclError::EntryPointNotFound(name) => {
+                write!(f, "entry point `{name}` not found in module")
+            }
+            RyclError::OutOfDeviceMemory(err) => {
+                write!(f, "failed to allocate device memory: {err}")
+            }
+            RyclError::DispatchFailed(err) => write!(f, "kernel dispatch failed: {err}"),
+            RyclError::InvalidArgument(err) => write!(f, "invalid argument: {err}"),
+        }
+    }
+}
+
+impl std::error::Error for RyclError {}
+
+impl From<TypeError> for RyclError {
+    fn from(err: TypeError) -> Self {
+        RyclError::InvalidKernel(err.0)
+    }
+}
+
+impl From<rspirv::dr::Error> for RyclError {
+    fn from(err: rspirv::dr::Error) -> Self {
+        RyclError::InvalidKernel(err.to_string())
+    }
+}
@@ -1,3 +1,6 @@
+pub(crate) mod cpu;
 pub(crate) mod device_ctx;
 pub(crate) mod error;
+#[cfg(test)]
+pub(crate) mod spirv_interp;
 pub(crate) mod vulkan;
@@ -0,0 +1,2598 @@
+//! Interpreter for the subset of SPIR-V emitted by [`spirv_codegen`].
+//!
+//! Generated modules can otherwise only be checked by handing them to a
+//! driver. The interpreter runs a compute module on host memory instead, so
+//! tests can assert on the numbers a generated binary computes.
+//!
+//! Storage buffers are plain byte vectors indexed by their binding, and are
+//! read and written through the `Offset` and `ArrayStride` decorations of
+//! the module, as a driver would. `Workgroup` variables are kept as values,
+//! zeroed at the start of each workgroup. Each invocation keeps its own
+//! program counter and SSA values and is stepped one instruction at a time.
+//! The invocations of a workgroup run in turn from one `OpControlBarrier` to
+//! the next, so memory barriers have nothing left to order, and atomics are
+//! a plain load and store. Subgroups are [`SUBGROUP_SIZE`] consecutive
+//! invocations; invocations stop at group operations as at barriers, and
+//! once all have stopped, each subgroup's operations are evaluated together.
+//!
+//! Integers and floats of every width are kept as bit patterns in a `u64`,
+//! zero-extended from their width, which is looked up from the type of the
+//! instruction producing them. Instructions on vectors apply to each
+//! component in turn.
+use std::collections::HashMap;
+
+use rspirv::dr::{self, Instruction, Operand};
+use rspirv::spirv::{self, Op, Word};
+use shared_type::f16;
+
+use super::error::{Result, RyclError};
+
+/// Instructions an invocation may execute before it is assumed to loop
+/// forever.
+const STEP_LIMIT: u64 = 1 << 24;
+
+/// Invocations per subgroup, small so that tests can cover several
+/// subgroups per workgroup.
+const SUBGROUP_SIZE: u32 = 4;
+
+/// A compute module prepared for interpretation.
+pub(crate) struct Interpreter {
+    types: HashMap<Word, Ty>,
+    /// Result type by result id, for the globals and instructions that have
+    /// one.
+    value_types: HashMap<Word, Word>,
+    constants: HashMap<Word, Value>,
+    globals: HashMap<Word, Global>,
+    blocks: Vec<dr::Block>,
+    /// Block index by label id.
+    labels: HashMap<Word, usize>,
+    local_size: [u32; 3],
+    /// The `GLSL.std.450` import, if the module has one.
+    glsl: Option<Word>,
+}
+
+#[derive(Clone, Debug)]
+enum Ty {
+    Void,
+    Bool,
+    /// An integer of this width in bits. Signedness is up to the
+    /// instructions using it.
+    Int(u32),
+    Float(u32),
+    Vector(Word, u32),
+    Matrix {
+        column: Word,
+        columns: u32,
+    },
+    Array {
+        elem: Word,
+        len: u32,
+        stride: u32,
+    },
+    RuntimeArray {
+        elem: Word,
+        stride: u32,
+    },
+    Struct {
+        members: Vec<Word>,
+        offsets: Vec<u32>,
+    },
+    Pointer(Word),
+    Function,
+}
+
+enum Global {
+    Builtin(spirv::BuiltIn),
+    Buffer { binding: u32, pointee: Word },
+    Workgroup { pointee: Word },
+}
+
+/// A runtime value. Integers and floats are kept as their bit patterns and
+/// interpreted by the instruction using them.
+#[derive(Clone, Debug, PartialEq)]
+enum Value {
+    Bool(bool),
+    Bits(u64),
+    Composite(Vec<Value>),
+    Pointer(Pointer),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Pointer {
+    Buffer {
+        binding: u32,
+        offset: u64,
+        pointee: Word,
+    },
+    Variable {
+        root: Root,
+        path: Vec<u32>,
+        pointee: Word,
+    },
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Root {
+    Function(usize),
+    Builtin(spirv::BuiltIn),
+    Workgroup(Word),
+}
+
+/// The memory invocations share: storage buffers, and the `Workgroup`
+/// variables of the workgroup being run.
+struct Memory<'b> {
+    buffers: &'b mut [Vec<u8>],
+    workgroup: HashMap<Word, Value>,
+}
+
+impl Interpreter {
+    /// Loads `words` and prepares entry point `entry_point` for running.
+    pub(crate) fn new(words: &[u32], entry_point: &str) -> Result<Self> {
+        let module = dr::load_words(words).map_err(|err| invalid(err.to_string()))?;
+
+        let entry = module
+            .entry_points
+            .iter()
+            .find(|inst| inst.operands[2].unwrap_literal_string() == entry_point)
+            .ok_or_else(|| RyclError::EntryPointNotFound(entry_point.to_string()))?;
+        let function_id = entry.operands[1].unwrap_id_ref();
+        let function = module
+            .functions
+            .iter()
+            .find(|f| f.def_id() == Some(function_id))
+            .ok_or_else(|| invalid("entry point has no function"))?;
+        let local_size = module
+            .execution_modes
+            .iter()
+            .find(|inst| {
+                inst.operands[0].unwrap_id_ref() == function_id
+                    && inst.operands[1] == Operand::ExecutionMode(spirv::ExecutionMode::LocalSize)
+            })
+            .map(|inst| {
+                let size = |i: usize| inst.operands[i].unwrap_literal_bit32();
+                [size(2), size(3), size(4)]
+            })
+            .ok_or_else(|| invalid("entry point has no local size"))?;
+
+        let mut interpreter = Self {
+            types: HashMap::new(),
+            value_types: function
+                .blocks
+                .iter()
+                .flat_map(|block| &block.instructions)
+                .filter_map(|inst| Some((inst.result_id?, inst.result_type?)))
+                .collect(),
+            constants: HashMap::new(),
+            globals: HashMap::new(),
+            labels: function
+                .blocks
+                .iter()
+                .enumerate()
+                .map(|(i, block)| (block.label_id().unwrap(), i))
+                .collect(),
+            blocks: function.blocks.clone(),
+            local_size,
+            glsl: module
+                .ext_inst_imports
+                .iter()
+                .find(|inst| inst.operands[0].unwrap_literal_string() == "GLSL.std.450")
+                .and_then(|inst| inst.result_id),
+        };
+        let decorations = Decorations::new(&module.annotations);
+        for inst in &module.types_global_values {
+            interpreter.declare(inst, &decorations)?;
+        }
+        Ok(interpreter)
+    }
+
+    fn declare(&mut self, inst: &Instruction, decorations: &Decorations) -> Result<()> {
+        let id = inst.result_id.unwrap();
+        if let Some(ty) = inst.result_type {
+            self.value_types.insert(id, ty);
+        }
+        let operand = |i: usize| &inst.operands[i];
+        match inst.class.opcode {
+            Op::TypeVoid => {
+                self.types.insert(id, Ty::Void);
+            }
+            Op::TypeBool => {
+                self.types.insert(id, Ty::Bool);
+            }
+            Op::TypeInt => {
+                self.types
+                    .insert(id, Ty::Int(operand(0).unwrap_literal_bit32()));
+            }
+            Op::TypeFloat => {
+                self.types
+                    .insert(id, Ty::Float(operand(0).unwrap_literal_bit32()));
+            }
+            Op::TypeVector => {
+                let elem = operand(0).unwrap_id_ref();
+                self.types
+                    .insert(id, Ty::Vector(elem, operand(1).unwrap_literal_bit32()));
+            }
+            Op::TypeMatrix => {
+                let column = operand(0).unwrap_id_ref();
+                let columns = operand(1).unwrap_literal_bit32();
+                self.types.insert(id, Ty::Matrix { column, columns });
+            }
+            Op::TypeArray => {
+                let elem = operand(0).unwrap_id_ref();
+                let Some(Value::Bits(len)) = self.constants.get(&operand(1).unwrap_id_ref()) else {
+                    return Err(invalid("array length is not a constant"));
+                };
+                let stride = decorations
+                    .get(id, None, spirv::Decoration::ArrayStride)
+                    .ok_or_else(|| invalid("array type has no stride"))?;
+                let ty = Ty::Array {
+                    elem,
+                    len: *len as u32,
+                    stride,
+                };
+                self.types.insert(id, ty);
+            }
+            Op::TypeRuntimeArray => {
+                let elem = operand(0).unwrap_id_ref();
+                let stride = decorations
+                    .get(id, None, spirv::Decoration::ArrayStride)
+                    .ok_or_else(|| invalid("runtime array type has no stride"))?;
+                self.types.insert(id, Ty::RuntimeArray { elem, stride });
+            }
+            Op::TypeStruct => {
+                let members: Vec<Word> = inst.operands.iter().map(Operand::unwrap_id_ref).collect();
+                let offsets = (0..members.len() as u32)
+                    .map(|i| {
+                        decorations
+                            .get(id, Some(i), spirv::Decoration::Offset)
+                            .unwrap_or(0)
+                    })
+                    .collect();
+                self.types.insert(id, Ty::Struct { members, offsets });
+            }
+            Op::TypePointer => {
+                self.types
+                    .insert(id, Ty::Pointer(operand(1).unwrap_id_ref()));
+            }
+            Op::TypeFunction => {
+                self.types.insert(id, Ty::Function);
+            }
+            Op::Constant => {
+                let bits = match operand(0) {
+                    Operand::LiteralBit32(bits) => u64::from(*bits),
+                    Operand::LiteralBit64(bits) => *bits,
+                    operand => return Err(invalid(format!("unsupported constant {operand:?}"))),
+                };
+                let width = self.width(inst.result_type.unwrap());
+                self.constants.insert(id, Value::Bits(mask(bits, width)));
+            }
+            Op::ConstantTrue => {
+                self.constants.insert(id, Value::Bool(true));
+            }
+            Op::ConstantFalse => {
+                self.constants.insert(id, Value::Bool(false));
+            }
+            Op::Variable => {
+                let pointee = self.pointee(inst.result_type.unwrap());
+                let global = match operand(0).unwrap_storage_class() {
+                    spirv::StorageClass::Input => Global::Builtin(
+                        decorations
+                            .builtin(id)
+                            .ok_or_else(|| invalid("input variable is not a builtin"))?,
+                    ),
+                    spirv::StorageClass::StorageBuffer => Global::Buffer {
+                        binding: decorations
+                            .get(id, None, spirv::Decoration::Binding)
+                            .ok_or_else(|| invalid("storage buffer has no binding"))?,
+                        pointee,
+                    },
+                    spirv::StorageClass::Workgroup => Global::Workgroup { pointee },
+                    class => return Err(invalid(format!("unsupported storage class {class:?}"))),
+                };
+                self.globals.insert(id, global);
+            }
+            op => return Err(invalid(format!("unsupported global instruction {op:?}"))),
+        }
+        Ok(())
+    }
+
+    /// Runs `num_workgroups` workgroups, with `buffers[i]` bound to binding
+    /// `i` of descriptor set 0.
+    pub(crate) fn run(&self, buffers: &mut [Vec<u8>], num_workgroups: u32) -> Result<()> {
+        let mut memory = Memory {
+            buffers,
+            workgroup: HashMap::new(),
+        };
+        for workgroup_id in 0..num_workgroups {
+            memory.workgroup = self
+                .globals
+                .iter()
+                .filter_map(|(&id, global)| match global {
+                    Global::Workgroup { pointee } => Some((id, self.zero(*pointee))),
+                    _ => None,
+                })
+                .collect();
+            let mut running: Vec<_> = (0..self.local_size[0])
+                .map(|local_id| Invocation {
+                    module: self,
+                    builtins: Builtins {
+                        num_workgroups,
+                        workgroup_id,
+                        local_id,
+                        global_id: workgroup_id * self.local_size[0] + local_id,
+                    },
+                    subgroup: local_id / SUBGROUP_SIZE,
+                    values: HashMap::new(),
+                    variables: Vec::new(),
+                    block: 0,
+                    previous_block: None,
+                    pc: 0,
+                    steps: 0,
+                })
+                .collect();
+            // Invocations that return stop being waited for, as on the CPU
+            // backend, rather than hanging the workgroup.
+            let mut at_barrier = Vec::new();
+            while !running.is_empty() {
+                let mut at_group_op = Vec::new();
+                for mut invocation in running.drain(..) {
+                    loop {
+                        match invocation.step(&mut memory)? {
+                            Step::Running => {}
+                            Step::Barrier => {
+                                at_barrier.push(invocation);
+                                break;
+                            }
+                            Step::GroupOp => {
+                                at_group_op.push(invocation);
+                                break;
+                            }
+                            Step::Returned => break,
+                        }
+                        invocation.steps += 1;
+                        if invocation.steps > STEP_LIMIT {
+                            return Err(trap("invocation did not terminate"));
+                        }
+                    }
+                }
+                // Invocations at a barrier wait for those that resume from
+                // group operations.
+                running = if at_group_op.is_empty() {
+                    std::mem::take(&mut at_barrier)
+                } else {
+                    self.group_ops(&mut at_group_op)?;
+                    at_group_op
+                };
+            }
+        }
+        Ok(())
+    }
+
+    /// Completes the group operations `invocations` stopped at, the
+    /// invocations of each subgroup operating together.
+    fn group_ops(&self, invocations: &mut [Invocation]) -> Result<()> {
+        let mut operands = Vec::with_capacity(invocations.len());
+        for invocation in invocations.iter() {
+            let inst = invocation.current();
+            // Every group operation is scoped to the subgroup, the first
+            // operand; arithmetic operations then name the operation.
+            let args = match inst.class.opcode {
+                Op::GroupNonUniformBroadcast
+                | Op::GroupNonUniformShuffle
+                | Op::GroupNonUniformBallot => invocation.args(&inst.operands[1..])?,
+                _ => invocation.args(&inst.operands[2..3])?,
+            };
+            operands.push(args);
+        }
+        let mut results = Vec::with_capacity(invocations.len());
+        for (invocation, args) in invocations.iter().zip(&operands) {
+            let inst = invocation.current();
+            // The values of the lanes of the invocation's subgroup.
+            let mut lanes: Vec<Option<&Arg>> = vec![None; SUBGROUP_SIZE as usize];
+            for (other, other_args) in invocations.iter().zip(&operands) {
+                if other.subgroup == invocation.subgroup {
+                    lanes[(other.builtins.local_id % SUBGROUP_SIZE) as usize] =
+                        Some(&other_args[0]);
+                }
+            }
+            let lane = (invocation.builtins.local_id % SUBGROUP_SIZE) as usize;
+            let result = match inst.class.opcode {
+                Op::GroupNonUniformBroadcast | Op::GroupNonUniformShuffle => {
+                    let source = args[1].bits()? as usize;
+                    lanes
+                        .get(source)
+                        .copied()
+                        .flatten()
+                        .ok_or_else(|| trap(format!("subgroup lane {source} is not active")))?
+                        .value
+                        .clone()
+                }
+                Op::GroupNonUniformBallot => {
+                    let mut mask = 0;
+                    for (i, arg) in lanes.iter().enumerate() {
+                        if let Some(arg) = arg {
+                            mask |= u64::from(arg.bool()?) << i;
+                        }
+                    }
+                    Value::Composite(vec![
+                        Value::Bits(mask),
+                        Value::Bits(0),
+                        Value::Bits(0),
+                        Value::Bits(0),
+                    ])
+                }
+                op => {
+                    let lanes = match inst.operands[1] {
+                        Operand::GroupOperation(spirv::GroupOperation::Reduce) => &lanes[..],
+                        Operand::GroupOperation(spirv::GroupOperation::InclusiveScan) => {
+                            &lanes[..=lane]
+                        }
+                        Operand::GroupOperation(spirv::GroupOperation::ExclusiveScan) => {
+                            &lanes[..lane]
+                        }
+                        ref operation => return Err(invalid(format!("unsupported {operation:?}"))),
+                    };
+                    let mut combined: Option<Arg> = None;
+                    for &arg in lanes.iter().flatten() {
+                        combined = Some(match combined {
+                            None => arg.clone(),
+                            Some(previous) => Arg {
+                                value: self.combine(op, &previous, arg)?,
+                                ty: arg.ty,
+                            },
+                        });
+                    }
+                    // An exclusive scan in the first lane is the identity,
+                    // which is zero for the additions kernels scan with.
+                    combined.map_or(Value::Bits(0), |arg| arg.value)
+                }
+            };
+            results.push(result);
+        }
+        for (invocation, result) in invocations.iter_mut().zip(results) {
+            let id = invocation.current().result_id.unwrap();
+            invocation.values.insert(id, result);
+        }
+        Ok(())
+    }
+
+    fn ty(&self, id: Word) -> &Ty {
+        &self.types[&id]
+    }
+
+    /// Width in bits of scalar type `ty`.
+    fn width(&self, ty: Word) -> u32 {
+        match self.ty(ty) {
+            Ty::Int(width) | Ty::Float(width) => *width,
+            Ty::Bool => 1,
+            ty => panic!("{ty:?} is not a scalar type"),
+        }
+    }
+
+    fn pointee(&self, pointer_ty: Word) -> Word {
+        match self.ty(pointer_ty) {
+            Ty::Pointer(pointee) => *pointee,
+            ty => panic!("{ty:?} is not a pointer type"),
+        }
+    }
+
+    /// The type of member `index` of composite type `ty`, and its byte
+    /// offset in buffer memory. Runtime arrays are not bounds checked, as
+    /// their length is only known from the buffer they are read from.
+    fn member(&self, ty: Word, index: u32) -> Result<(Word, u64)> {
+        let (elem, len, stride) = match self.ty(ty) {
+            Ty::RuntimeArray { elem, stride } => {
+                return Ok((*elem, u64::from(index) * u64::from(*stride)));
+            }
+            Ty::Vector(elem, len) => (*elem, *len, self.scalar_size(*elem) as u32),
+            // Matrices are only laid out as the code generator lays them
+            // out, with each column aligned like a vector of its length.
+            Ty::Matrix { column, columns } => {
+                let Ty::Vector(elem, len) = self.ty(*column) else {
+                    return Err(invalid("matrix column is not a vector"));
+                };
+                let aligned_len = if *len == 2 { 2 } else { 4 };
+                let stride = aligned_len * self.scalar_size(*elem) as u32;
+                (*column, *columns, stride)
+            }
+            Ty::Array { elem, len, stride } => (*elem, *len, *stride),
+            Ty::Struct { members, offsets } => {
+                return match members.get(index as usize) {
+                    Some(&member) => Ok((member, u64::from(offsets[index as usize]))),
+                    None => Err(invalid(format!("struct has no member {index}"))),
+                };
+            }
+            ty => return Err(invalid(format!("cannot index into {ty:?}"))),
+        };
+        if index >= len {
+            return Err(trap(format!(
+                "index {index} is out of bounds for a composite of length {len}"
+            )));
+        }
+        Ok((elem, u64::from(index) * u64::from(stride)))
+    }
+
+    fn zero(&self, ty: Word) -> Value {
+        match self.ty(ty) {
+            Ty::Bool => Value::Bool(false),
+            Ty::Vector(elem, len) => Value::Composite(vec![self.zero(*elem); *len as usize]),
+            Ty::Matrix { column, columns } => {
+                Value::Composite(vec![self.zero(*column); *columns as usize])
+            }
+            Ty::Array { elem, len, .. } => Value::Composite(vec![self.zero(*elem); *len as usize]),
+            Ty::Struct { members, .. } => {
+                Value::Composite(members.iter().map(|&m| self.zero(m)).collect())
+            }
+            _ => Value::Bits(0),
+        }
+    }
+
+    fn read(&self, bytes: &[u8], offset: u64, ty: Word) -> Result<Value> {
+        Ok(match self.ty(ty) {
+            Ty::Bool | Ty::Int(_) | Ty::Float(_) => {
+                let at = offset as usize;
+                let size = self.scalar_size(ty);
+                let scalar = bytes
+                    .get(at..at + size)
+                    .ok_or_else(|| trap(format!("read past the end of a buffer at {offset}")))?;
+                let mut word = [0; 8];
+                word[..size].copy_from_slice(scalar);
+                let bits = u64::from_ne_bytes(word);
+                match self.ty(ty) {
+                    Ty::Bool => Value::Bool(bits != 0),
+                    _ => Value::Bits(bits),
+                }
+            }
+            Ty::Vector(_, len) | Ty::Matrix { columns: len, .. } | Ty::Array { len, .. } => {
+                Value::Composite(
+                    (0..*len)
+                        .map(|i| {
+                            let (elem, member_offset) = self.member(ty, i)?;
+                            self.read(bytes, offset + member_offset, elem)
+                        })
+                        .collect::<Result<_>>()?,
+                )
+            }
+            Ty::Struct { members, .. } => Value::Composite(
+                (0..members.len() as u32)
+                    .map(|i| {
+                        let (member, member_offset) = self.member(ty, i)?;
+                        self.read(bytes, offset + member_offset, member)
+                    })
+                    .collect::<Result<_>>()?,
+            ),
+            ty => return Err(invalid(format!("cannot load {ty:?} from a buffer"))),
+        })
+    }
+
+    fn write(&self, bytes: &mut [u8], offset: u64, ty: Word, value: &Value) -> Result<()> {
+        match value {
+            Value::Bool(_) | Value::Bits(_) => {
+                let bits = match value {
+                    Value::Bool(b) => u64::from(*b),
+                    Value::Bits(bits) => *bits,
+                    _ => unreachable!(),
+                };
+                let at = offset as usize;
+                let size = self.scalar_size(ty);
+                bytes
+                    .get_mut(at..at + size)
+                    .ok_or_else(|| trap(format!("write past the end of a buffer at {offset}")))?
+                    .copy_from_slice(&bits.to_ne_bytes()[..size]);
+            }
+            Value::Composite(items) => {
+                for (i, item) in items.iter().enumerate() {
+                    let (member, member_offset) = self.member(ty, i as u32)?;
+                    self.write(bytes, offset + member_offset, member, item)?;
+                }
+            }
+            Value::Pointer(_) => return Err(invalid("cannot store a pointer in a buffer")),
+        }
+        Ok(())
+    }
+
+    /// Size in bytes of scalar type `ty` in buffer memory.
+    fn scalar_size(&self, ty: Word) -> usize {
+        match self.ty(ty) {
+            Ty::Bool => 4,
+            _ => self.width(ty) as usize / 8,
+        }
+    }
+
+    /// Component `i` of a vector argument, or column `i` of a matrix one.
+    /// Scalars apply to every component.
+    fn lane(&self, arg: &Arg, i: u32) -> Arg {
+        match (&arg.value, self.ty(arg.ty)) {
+            (Value::Composite(items), Ty::Vector(elem, _)) => Arg {
+                value: items[i as usize].clone(),
+                ty: *elem,
+            },
+            (Value::Composite(items), Ty::Matrix { column, .. }) => Arg {
+                value: items[i as usize].clone(),
+                ty: *column,
+            },
+            _ => arg.clone(),
+        }
+    }
+
+    /// Applies `op` to `args`, component by component when its result is a
+    /// vector or matrix of type `ty`.
+    fn lanes(&self, op: Op, ty: Word, args: &[Arg]) -> Result<Value> {
+        let (lane_ty, len) = match self.ty(ty) {
+            Ty::Vector(elem, len) => (*elem, *len),
+            Ty::Matrix { column, columns } => (*column, *columns),
+            _ => return self.scalar_op(op, ty, args),
+        };
+        Ok(Value::Composite(
+            (0..len)
+                .map(|i| {
+                    let lane_args: Vec<Arg> = args.iter().map(|arg| self.lane(arg, i)).collect();
+                    self.lanes(op, lane_ty, &lane_args)
+                })
+                .collect::<Result<_>>()?,
+        ))
+    }
+
+    /// Applies `op` to scalar `args`, giving a value of type `ty`.
+    fn scalar_op(&self, op: Op, ty: Word, args: &[Arg]) -> Result<Value> {
+        // Selects between composites that are not vectors pick them whole.
+        if op == Op::Select {
+            return Ok(if args[0].bool()? {
+                args[1].value.clone()
+            } else {
+                args[2].value.clone()
+            });
+        }
+        let width = self.width(ty);
+        let arg = &args[0];
+        Ok(match op {
+            Op::LogicalNot => Value::Bool(!arg.bool()?),
+            Op::Not => Value::Bits(mask(!arg.bits()?, width)),
+            Op::SNegate => Value::Bits(mask(arg.bits()?.wrapping_neg(), width)),
+            Op::FNegate => Value::Bits(from_f64(-self.float(arg)?, width)),
+            Op::Bitcast | Op::UConvert => Value::Bits(mask(arg.bits()?, width)),
+            Op::SConvert => Value::Bits(mask(self.signed(arg)? as u64, width)),
+            Op::FConvert => Value::Bits(from_f64(self.float(arg)?, width)),
+            Op::ConvertSToF => Value::Bits(from_f64(self.signed(arg)? as f64, width)),
+            Op::ConvertUToF => Value::Bits(from_f64(arg.bits()? as f64, width)),
+            Op::ConvertFToS => Value::Bits(mask(self.float(arg)? as i64 as u64, width)),
+            Op::ConvertFToU => Value::Bits(mask(self.float(arg)? as u64, width)),
+            op => self.binary(op, arg, &args[1])?,
+        })
+    }
+
+    fn binary(&self, op: Op, lhs: &Arg, rhs: &Arg) -> Result<Value> {
+        if let Some(result) = logical(op) {
+            return Ok(Value::Bool(result(lhs.bool()?, rhs.bool()?)));
+        }
+        if let Some(result) = float_op(op) {
+            return Ok(match result(self.float(lhs)?, self.float(rhs)?) {
+                Float::Value(value) => Value::Bits(from_f64(value, self.width(lhs.ty))),
+                Float::Bool(b) => Value::Bool(b),
+            });
+        }
+        let width = self.width(lhs.ty);
+        let (a, b) = (lhs.bits()?, rhs.bits()?);
+        let (sa, sb) = (self.signed(lhs)?, self.signed(rhs)?);
+        let divisor = || {
+            if b == 0 {
+                Err(trap("division by zero"))
+            } else {
+                Ok(b)
+            }
+        };
+        let shift = || (b % u64::from(width)) as u32;
+        let bits = match op {
+            Op::IAdd => a.wrapping_add(b),
+            Op::ISub => a.wrapping_sub(b),
+            Op::IMul => a.wrapping_mul(b),
+            Op::UDiv => a / divisor()?,
+            Op::UMod => a % divisor()?,
+            Op::SDiv => sa.wrapping_div(divisor().map(|_| sb)?) as u64,
+            Op::SRem => sa.wrapping_rem(divisor().map(|_| sb)?) as u64,
+            Op::BitwiseAnd => a & b,
+            Op::BitwiseOr => a | b,
+            Op::BitwiseXor => a ^ b,
+            Op::ShiftLeftLogical => a << shift(),
+            Op::ShiftRightLogical => a >> shift(),
+            Op::ShiftRightArithmetic => (sa >> shift()) as u64,
+            op => {
+                return Ok(Value::Bool(match op {
+                    Op::IEqual => a == b,
+                    Op::INotEqual => a != b,
+                    Op::ULessThan => a < b,
+                    Op::ULessThanEqual => a <= b,
+                    Op::UGreaterThan => a > b,
+                    Op::UGreaterThanEqual => a >= b,
+                    Op::SLessThan => sa < sb,
+                    Op::SLessThanEqual => sa <= sb,
+                    Op::SGreaterThan => sa > sb,
+                    Op::SGreaterThanEqual => sa >= sb,
+                    op => return Err(invalid(format!("unsupported instruction {op:?}"))),
+                }))
+            }
+        };
+        Ok(Value::Bits(mask(bits, width)))
+    }
+
+    /// The value atomic or group operation `op` combines `previous` and
+    /// operand `value` into.
+    fn combine(&self, op: Op, previous: &Arg, value: &Arg) -> Result<Value> {
+        let arith = match op {
+            Op::AtomicIAdd | Op::GroupNonUniformIAdd => Op::IAdd,
+            Op::AtomicFAddEXT | Op::GroupNonUniformFAdd => Op::FAdd,
+            Op::AtomicAnd => Op::BitwiseAnd,
+            Op::AtomicOr => Op::BitwiseOr,
+            Op::AtomicXor => Op::BitwiseXor,
+            Op::AtomicExchange => return Ok(value.value.clone()),
+            // Minimums and maximums keep the previous value if it wins.
+            op => {
+                let wins = match op {
+                    Op::AtomicSMin | Op::GroupNonUniformSMin => Op::SLessThan,
+                    Op::AtomicUMin | Op::GroupNonUniformUMin => Op::ULessThan,
+                    Op::GroupNonUniformFMin => Op::FOrdLessThan,
+                    Op::AtomicSMax | Op::GroupNonUniformSMax => Op::SGreaterThan,
+                    Op::AtomicUMax | Op::GroupNonUniformUMax => Op::UGreaterThan,
+                    Op::GroupNonUniformFMax => Op::FOrdGreaterThan,
+                    op => return Err(invalid(format!("unsupported operation {op:?}"))),
+                };
+                let kept = self.binary(wins, previous, value)? == Value::Bool(true);
+                return Ok(if kept { previous } else { value }.value.clone());
+            }
+        };
+        self.binary(arith, previous, value)
+    }
+
+    /// The integer `arg`, sign-extended from its width.
+    fn signed(&self, arg: &Arg) -> Result<i64> {
+        let shift = 64 - self.width(arg.ty);
+        Ok(((arg.bits()? << shift) as i64) >> shift)
+    }
+
+    fn float(&self, arg: &Arg) -> Result<f64> {
+        let bits = arg.bits()?;
+        Ok(match self.width(arg.ty) {
+            16 => f16::from_bits(bits as u16).to_f64(),
+            32 => f64::from(f32::from_bits(bits as u32)),
+            _ => f64::from_bits(bits),
+        })
+    }
+
+    /// The sum of the columns of matrix `m`, of type `ty`, scaled by the
+    /// components of vector `v`.
+    fn matrix_times_vector(&self, ty: Word, m: &Arg, v: &Arg) -> Result<Value> {
+        let Ty::Matrix { column, columns } = *self.ty(m.ty) else {
+            return Err(invalid("matrix product of a non-matrix"));
+        };
+        let mut sum = None;
+        for i in 0..columns {
+            let product = self.lanes(Op::FMul, column, &[self.lane(m, i), self.lane(v, i)])?;
+            sum = Some(match sum {
+                None => product,
+                Some(sum) => {
+                    let args = [Arg { value: sum, ty }, Arg { value: product, ty }];
+                    self.lanes(Op::FAdd, ty, &args)?
+                }
+            });
+        }
+        sum.ok_or_else(|| invalid("matrix has no columns"))
+    }
+
+    /// Sum of the products of the components of vectors `a` and `b`.
+    fn dot(&self, a: &Arg, b: &Arg) -> Result<Value> {
+        let Ty::Vector(elem, len) = *self.ty(a.ty) else {
+            return Err(invalid("dot product of a non-vector"));
+        };
+        let mut sum = None;
+        for i in 0..len {
+            let product = self.lanes(Op::FMul, elem, &[self.lane(a, i), self.lane(b, i)])?;
+            sum = Some(match sum {
+                None => product,
+                Some(sum) => {
+                    let args = [
+                        Arg {
+                            value: sum,
+                            ty: elem,
+                        },
+                        Arg {
+                            value: product,
+                            ty: elem,
+                        },
+                    ];
+                    self.lanes(Op::FAdd, elem, &args)?
+                }
+            });
+        }
+        sum.ok_or_else(|| invalid("vector has no components"))
+    }
+
+    /// Applies `GLSL.std.450` instruction `inst` to `args`, giving a value
+    /// of type `ty`.
+    fn glsl(&self, inst: u32, ty: Word, args: &[Arg]) -> Result<Value> {
+        match inst {
+            GLSL_LENGTH => {
+                let squared = self.dot(&args[0], &args[0])?;
+                let squared = self.float(&Arg { value: squared, ty })?;
+                Ok(Value::Bits(from_f64(squared.sqrt(), self.width(ty))))
+            }
+            GLSL_NORMALIZE => {
+                let Ty::Vector(elem, _) = *self.ty(ty) else {
+                    return Err(invalid("normalize of a non-vector"));
+                };
+                let length = self.glsl(GLSL_LENGTH, elem, args)?;
+                let length = Arg {
+                    value: length,
+                    ty: elem,
+                };
+                self.lanes(Op::FDiv, ty, &[args[0].clone(), length])
+            }
+            GLSL_CROSS => {
+                let Ty::Vector(elem, _) = *self.ty(ty) else {
+                    return Err(invalid("cross product of non-vectors"));
+                };
+                let (a, b) = (&args[0], &args[1]);
+                let component = |i: u32, j: u32| -> Result<Value> {
+                    let lhs = self.lanes(Op::FMul, elem, &[self.lane(a, i), self.lane(b, j)])?;
+                    let rhs = self.lanes(Op::FMul, elem, &[self.lane(a, j), self.lane(b, i)])?;
+                    let args = [
+                        Arg {
+                            value: lhs,
+                            ty: elem,
+                        },
+                        Arg {
+                            value: rhs,
+                            ty: elem,
+                        },
+                    ];
+                    self.lanes(Op::FSub, elem, &args)
+                };
+                Ok(Value::Composite(vec![
+                    component(1, 2)?,
+                    component(2, 0)?,
+                    component(0, 1)?,
+                ]))
+            }
+            // Float math is computed in `f64` and rounded to the width of
+            // `ty`.
+            GLSL_ROUND | GLSL_FABS | GLSL_FLOOR | GLSL_CEIL | GLSL_SIN | GLSL_COS | GLSL_TAN
+            | GLSL_POW | GLSL_EXP | GLSL_LOG | GLSL_SQRT | GLSL_FMIN | GLSL_FMAX | GLSL_FCLAMP
+            | GLSL_FMIX | GLSL_FMA => {
+                let a: Vec<f64> = args
+                    .iter()
+                    .map(|arg| self.float(arg))
+                    .collect::<Result<_>>()?;
+                let value = match inst {
+                    GLSL_ROUND => a[0].round(),
+                    GLSL_FABS => a[0].abs(),
+                    GLSL_FLOOR => a[0].floor(),
+                    GLSL_CEIL => a[0].ceil(),
+                    GLSL_SIN => a[0].sin(),
+                    GLSL_COS => a[0].cos(),
+                    GLSL_TAN => a[0].tan(),
+                    GLSL_POW => a[0].powf(a[1]),
+                    GLSL_EXP => a[0].exp(),
+                    GLSL_LOG => a[0].ln(),
+                    GLSL_SQRT => a[0].sqrt(),
+                    GLSL_FMIN => a[0].min(a[1]),
+                    GLSL_FMAX => a[0].max(a[1]),
+                    GLSL_FCLAMP => a[0].max(a[1]).min(a[2]),
+                    GLSL_FMIX => a[0] * (1.0 - a[2]) + a[1] * a[2],
+                    _ => a[0].mul_add(a[1], a[2]),
+                };
+                Ok(Value::Bits(from_f64(value, self.width(ty))))
+            }
+            GLSL_UMIN | GLSL_UMAX | GLSL_UCLAMP => {
+                let a: Vec<u64> = args.iter().map(|arg| arg.bits()).collect::<Result<_>>()?;
+                Ok(Value::Bits(match inst {
+                    GLSL_UMIN => a[0].min(a[1]),
+                    GLSL_UMAX => a[0].max(a[1]),
+                    _ => a[0].max(a[1]).min(a[2]),
+                }))
+            }
+            GLSL_SABS | GLSL_SMIN | GLSL_SMAX | GLSL_SCLAMP => {
+                let a: Vec<i64> = args
+                    .iter()
+                    .map(|arg| self.signed(arg))
+                    .collect::<Result<_>>()?;
+                let value = match inst {
+                    GLSL_SABS => a[0].wrapping_abs(),
+                    GLSL_SMIN => a[0].min(a[1]),
+                    GLSL_SMAX => a[0].max(a[1]),
+                    _ => a[0].max(a[1]).min(a[2]),
+                };
+                Ok(Value::Bits(mask(value as u64, self.width(ty))))
+            }
+            inst => Err(invalid(format!(
+                "unsupported GLSL.std.450 instruction {inst}"
+            ))),
+        }
+    }
+}
+
+/// `GLSL.std.450` extended instruction numbers.
+const GLSL_ROUND: u32 = 1;
+const GLSL_FABS: u32 = 4;
+const GLSL_SABS: u32 = 5;
+const GLSL_FLOOR: u32 = 8;
+const GLSL_CEIL: u32 = 9;
+const GLSL_SIN: u32 = 13;
+const GLSL_COS: u32 = 14;
+const GLSL_TAN: u32 = 15;
+const GLSL_POW: u32 = 26;
+const GLSL_EXP: u32 = 27;
+const GLSL_LOG: u32 = 28;
+const GLSL_SQRT: u32 = 31;
+const GLSL_FMIN: u32 = 37;
+const GLSL_UMIN: u32 = 38;
+const GLSL_SMIN: u32 = 39;
+const GLSL_FMAX: u32 = 40;
+const GLSL_UMAX: u32 = 41;
+const GLSL_SMAX: u32 = 42;
+const GLSL_FCLAMP: u32 = 43;
+const GLSL_UCLAMP: u32 = 44;
+const GLSL_SCLAMP: u32 = 45;
+const GLSL_FMIX: u32 = 46;
+const GLSL_FMA: u32 = 50;
+const GLSL_LENGTH: u32 = 66;
+const GLSL_CROSS: u32 = 68;
+const GLSL_NORMALIZE: u32 = 69;
+
+/// An instruction operand, with its type.
+#[derive(Clone, Debug)]
+struct Arg {
+    value: Value,
+    ty: Word,
+}
+
+impl Arg {
+    fn bool(&self) -> Result<bool> {
+        match self.value {
+            Value::Bool(b) => Ok(b),
+            ref value => Err(invalid(format!("expected a bool, found {value:?}"))),
+        }
+    }
+
+    fn bits(&self) -> Result<u64> {
+        match self.value {
+            Value::Bits(bits) => Ok(bits),
+            ref value => Err(invalid(format!("expected a scalar, found {value:?}"))),
+        }
+    }
+}
+
+/// Decorations of the module, by target id and member.
+struct Decorations<'m> {
+    annotations: &'m [Instruction],
+}
+
+impl<'m> Decorations<'m> {
+    fn new(annotations: &'m [Instruction]) -> Self {
+        Self { annotations }
+    }
+
+    /// The literal operand of `decoration` on `target`, or on its member.
+    fn get(&self, target: Word, member: Option<u32>, decoration: spirv::Decoration) -> Option<u32> {
+        self.find(target, member, decoration)
+            .map(|operands| operands[0].unwrap_literal_bit32())
+    }
+
+    fn builtin(&self, target: Word) -> Option<spirv::BuiltIn> {
+        self.find(target, None, spirv::Decoration::BuiltIn)
+            .map(|operands| operands[0].unwrap_built_in())
+    }
+
+    /// The operands following the decoration itself.
+    fn find(
+        &self,
+        target: Word,
+        member: Option<u32>,
+        decoration: spirv::Decoration,
+    ) -> Option<&'m [Operand]> {
+        self.annotations.iter().find_map(|inst| {
+            let operands = inst.operands.as_slice();
+            let rest = match (inst.class.opcode, member) {
+                (Op::Decorate, None) => &operands[1..],
+                (Op::MemberDecorate, Some(m)) if operands[1].unwrap_literal_bit32() == m => {
+                    &operands[2..]
+                }
+                _ => return None,
+            };
+            (operands[0].unwrap_id_ref() == target && rest[0] == Operand::Decoration(decoration))
+                .then(|| &rest[1..])
+        })
+    }
+}
+
+struct Builtins {
+    num_workgroups: u32,
+    workgroup_id: u32,
+    local_id: u32,
+    global_id: u32,
+}
+
+impl Builtins {
+    fn value(&self, builtin: spirv::BuiltIn) -> Result<Value> {
+        // The subgroup builtins are scalars.
+        let scalar = match builtin {
+            spirv::BuiltIn::SubgroupSize => Some(SUBGROUP_SIZE),
+            spirv::BuiltIn::SubgroupId => Some(self.local_id / SUBGROUP_SIZE),
+            spirv::BuiltIn::SubgroupLocalInvocationId => Some(self.local_id % SUBGROUP_SIZE),
+            _ => None,
+        };
+        if let Some(scalar) = scalar {
+            return Ok(Value::Bits(u64::from(scalar)));
+        }
+        let x = match builtin {
+            spirv::BuiltIn::NumWorkgroups => self.num_workgroups,
+            spirv::BuiltIn::WorkgroupId => self.workgroup_id,
+            spirv::BuiltIn::LocalInvocationId => self.local_id,
+            spirv::BuiltIn::GlobalInvocationId => self.global_id,
+            builtin => return Err(invalid(format!("unsupported builtin {builtin:?}"))),
+        };
+        // Launches are one-dimensional, so y and z are always 0, except for
+        // the workgroup count.
+        let yz = u32::from(builtin == spirv::BuiltIn::NumWorkgroups);
+        Ok(Value::Composite(vec![
+            Value::Bits(u64::from(x)),
+            Value::Bits(u64::from(yz)),
+            Value::Bits(u64::from(yz)),
+        ]))
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum Step {
+    Running,
+    /// Stopped at an `OpControlBarrier`, which it has executed.
+    Barrier,
+    /// Stopped at a group operation, whose result is left to
+    /// [`Interpreter::group_ops`].
+    GroupOp,
+    Returned,
+}
+
+struct Invocation<'m> {
+    module: &'m Interpreter,
+    builtins: Builtins,
+    subgroup: u32,
+    values: HashMap<Word, Value>,
+    /// Function variables, in declaration order.
+    variables: Vec<Value>,
+    block: usize,
+    /// The block branched from, which selects the incoming value of `OpPhi`.
+    previous_block: Option<Word>,
+    pc: usize,
+    /// Instructions executed so far.
+    steps: u64,
+}
+
+impl Invocation<'_> {
+    /// The instruction last stepped over.
+    fn current(&self) -> &Instruction {
+        &self.module.blocks[self.block].instructions[self.pc - 1]
+    }
+
+    /// Executes the next instruction.
+    fn step(&mut self, memory: &mut Memory) -> Result<Step> {
+        let module = self.module;
+        let inst = &module.blocks[self.block].instructions[self.pc];
+        self.pc += 1;
+        let result = match inst.class.opcode {
+            Op::SelectionMerge | Op::LoopMerge => return Ok(Step::Running),
+            Op::Branch => {
+                self.jump(inst.operands[0].unwrap_id_ref());
+                return Ok(Step::Running);
+            }
+            Op::BranchConditional => {
+                let target = if self.bool(&inst.operands[0])? { 1 } else { 2 };
+                self.jump(inst.operands[target].unwrap_id_ref());
+                return Ok(Step::Running);
+            }
+            Op::Return => return Ok(Step::Returned),
+            Op::ControlBarrier => return Ok(Step::Barrier),
+            Op::GroupNonUniformIAdd
+            | Op::GroupNonUniformFAdd
+            | Op::GroupNonUniformSMin
+            | Op::GroupNonUniformUMin
+            | Op::GroupNonUniformFMin
+            | Op::GroupNonUniformSMax
+            | Op::GroupNonUniformUMax
+            | Op::GroupNonUniformFMax
+            | Op::GroupNonUniformBroadcast
+            | Op::GroupNonUniformShuffle
+            | Op::GroupNonUniformBallot => return Ok(Step::GroupOp),
+            Op::MemoryBarrier => return Ok(Step::Running),
+            Op::Unreachable => return Err(trap("reached OpUnreachable")),
+            Op::Store => {
+                let Value::Pointer(pointer) = self.operand(&inst.operands[0])? else {
+                    return Err(invalid("store through a non-pointer"));
+                };
+                let value = self.operand(&inst.operands[1])?;
+                self.store(memory, &pointer, value)?;
+                return Ok(Step::Running);
+            }
+            Op::Variable => {
+                let pointee = module.pointee(inst.result_type.unwrap());
+                self.variables.push(module.zero(pointee));
+                Value::Pointer(Pointer::Variable {
+                    root: Root::Function(self.variables.len() - 1),
+                    path: Vec::new(),
+                    pointee,
+                })
+            }
+            Op::Load => {
+                let Value::Pointer(pointer) = self.operand(&inst.operands[0])? else {
+                    return Err(invalid("load through a non-pointer"));
+                };
+                self.load(memory, &pointer)?
+            }
+            Op::AtomicIAdd
+            | Op::AtomicFAddEXT
+            | Op::AtomicSMin
+            | Op::AtomicUMin
+            | Op::AtomicSMax
+            | Op::AtomicUMax
+            | Op::AtomicAnd
+            | Op::AtomicOr
+            | Op::AtomicXor
+            | Op::AtomicExchange
+            | Op::AtomicCompareExchange => {
+                let Value::Pointer(pointer) = self.operand(&inst.operands[0])? else {
+                    return Err(invalid("atomic through a non-pointer"));
+                };
+                let ty = inst.result_type.unwrap();
+                let previous = Arg {
+                    value: self.load(memory, &pointer)?,
+                    ty,
+                };
+                // The values follow the scope and the memory semantics, of
+                // which a compare-exchange has two.
+                let updated = match inst.class.opcode {
+                    Op::AtomicCompareExchange => {
+                        let [value, comparator] = &self.args(&inst.operands[4..])?[..] else {
+                            return Err(invalid("compare-exchange without two values"));
+                        };
+                        if previous.value == comparator.value {
+                            value.value.clone()
+                        } else {
+                            previous.value.clone()
+                        }
+                    }
+                    op => module.combine(op, &previous, &self.args(&inst.operands[3..])?[0])?,
+                };
+                self.store(memory, &pointer, updated)?;
+                previous.value
+            }
+            Op::AccessChain => {
+                let Value::Pointer(base) = self.operand(&inst.operands[0])? else {
+                    return Err(invalid("access chain on a non-pointer"));
+                };
+                let mut pointer = base;
+                for index in &inst.operands[1..] {
+                    let index = self.bits(index)? as u32;
+                    pointer = match pointer {
+                        Pointer::Buffer {
+                            binding,
+                            offset,
+                            pointee,
+                        } => {
+                            let (member, member_offset) = module.member(pointee, index)?;
+                            Pointer::Buffer {
+                                binding,
+                                offset: offset + member_offset,
+                                pointee: member,
+                            }
+                        }
+                        Pointer::Variable {
+                            root,
+                            mut path,
+                            pointee,
+                        } => {
+                            let (member, _) = module.member(pointee, index)?;
+                            path.push(index);
+                            Pointer::Variable {
+                                root,
+                                path,
+                                pointee: member,
+                            }
+                        }
+                    };
+                }
+                Value::Pointer(pointer)
+            }
+            Op::ArrayLength => {
+                let Value::Pointer(Pointer::Buffer {
+                    binding,
+                    offset,
+                    pointee,
+                }) = self.operand(&inst.operands[0])?
+                else {
+                    return Err(invalid("array length of a non-buffer pointer"));
+                };
+                let (array, member_offset) =
+                    module.member(pointee, inst.operands[1].unwrap_literal_bit32())?;
+                let Ty::RuntimeArray { stride, .. } = module.ty(array) else {
+                    return Err(invalid("array length of a sized member"));
+                };
+                let size = buffer(memory.buffers, binding)?.len() as u64;
+                let len = size.saturating_sub(offset + member_offset) / u64::from(*stride);
+                Value::Bits(len)
+            }
+            Op::CompositeConstruct => {
+                let is_vector = matches!(module.ty(inst.result_type.unwrap()), Ty::Vector(..));
+                let mut items = Vec::with_capacity(inst.operands.len());
+                for operand in &inst.operands {
+                    match self.operand(operand)? {
+                        // Vectors take the components of smaller vectors.
+                        Value::Composite(components) if is_vector => items.extend(components),
+                        value => items.push(value),
+                    }
+                }
+                Value::Composite(items)
+            }
+            Op::CompositeExtract => {
+                let mut value = self.operand(&inst.operands[0])?;
+                for index in &inst.operands[1..] {
+                    let Value::Composite(mut items) = value else {
+                        return Err(invalid("extract from a non-composite"));
+                    };
+                    value = items.swap_remove(index.unwrap_literal_bit32() as usize);
+                }
+                value
+            }
+            Op::Phi => {
+                let previous = self
+                    .previous_block
+                    .ok_or_else(|| invalid("OpPhi in the entry block"))?;
+                let incoming = inst
+                    .operands
+                    .chunks(2)
+                    .find(|pair| pair[1].unwrap_id_ref() == previous)
+                    .ok_or_else(|| invalid("OpPhi has no value for its predecessor"))?;
+                self.operand(&incoming[0])?
+            }
+            Op::VectorShuffle => {
+                let mut components = Vec::new();
+                for operand in &inst.operands[..2] {
+                    let Value::Composite(items) = self.operand(operand)? else {
+                        return Err(invalid("shuffle of a non-vector"));
+                    };
+                    components.extend(items);
+                }
+                Value::Composite(
+                    inst.operands[2..]
+                        .iter()
+                        .map(|index| components[index.unwrap_literal_bit32() as usize].clone())
+                        .collect(),
+                )
+            }
+            Op::Transpose => {
+                let Value::Composite(columns) = self.operand(&inst.operands[0])? else {
+                    return Err(invalid("transpose of a non-matrix"));
+                };
+                let columns: Vec<Vec<Value>> = columns
+                    .into_iter()
+                    .map(|column| match column {
+                        Value::Composite(items) => Ok(items),
+                        _ => Err(invalid("matrix column is not a vector")),
+                    })
+                    .collect::<Result<_>>()?;
+                Value::Composite(
+                    (0..columns.len())
+                        .map(|row| {
+                            Value::Composite(columns.iter().map(|c| c[row].clone()).collect())
+                        })
+                        .collect(),
+                )
+            }
+            Op::All | Op::Any => {
+                let Value::Composite(items) = self.operand(&inst.operands[0])? else {
+                    return Err(invalid("all or any of a non-vector"));
+                };
+                let mut lanes = items.iter().map(|item| *item == Value::Bool(true));
+                Value::Bool(if inst.class.opcode == Op::All {
+                    lanes.all(|lane| lane)
+                } else {
+                    lanes.any(|lane| lane)
+                })
+            }
+            Op::ExtInst => {
+                let ty = inst.result_type.unwrap();
+                if Some(inst.operands[0].unwrap_id_ref()) != module.glsl {
+                    return Err(invalid("unsupported extended instruction set"));
+                }
+                let Operand::LiteralExtInstInteger(number) = inst.operands[1] else {
+                    return Err(invalid("extended instruction without a number"));
+                };
+                let args = self.args(&inst.operands[2..])?;
+                module.glsl(number, ty, &args)?
+            }
+            op => {
+                let ty = inst.result_type.unwrap();
+                let args = self.args(&inst.operands)?;
+                match op {
+                    Op::Dot => module.dot(&args[0], &args[1])?,
+                    Op::VectorTimesScalar | Op::MatrixTimesScalar => {
+                        module.lanes(Op::FMul, ty, &args)?
+                    }
+                    Op::MatrixTimesVector => module.matrix_times_vector(ty, &args[0], &args[1])?,
+                    Op::MatrixTimesMatrix => {
+                        let Ty::Matrix { column, columns } = *module.ty(ty) else {
+                            return Err(invalid("matrix product of a non-matrix"));
+                        };
+                        Value::Composite(
+                            (0..columns)
+                                .map(|i| {
+                                    let v = module.lane(&args[1], i);
+                                    module.matrix_times_vector(column, &args[0], &v)
+                                })
+                                .collect::<Result<_>>()?,
+                        )
+                    }
+                    op => module.lanes(op, ty, &args)?,
+                }
+            }
+        };
+        self.values.insert(inst.result_id.unwrap(), result);
+        Ok(Step::Running)
+    }
+
+    fn jump(&mut self, label: Word) {
+        self.previous_block = self.module.blocks[self.block].label_id();
+        self.block = self.module.labels[&label];
+        self.pc = 0;
+    }
+
+    fn operand(&self, operand: &Operand) -> Result<Value> {
+        let id = operand.unwrap_id_ref();
+        if let Some(value) = self.values.get(&id).or(self.module.constants.get(&id)) {
+            return Ok(value.clone());
+        }
+        match self.module.globals.get(&id) {
+            Some(Global::Builtin(builtin)) => Ok(Value::Pointer(Pointer::Variable {
+                root: Root::Builtin(*builtin),
+                path: Vec::new(),
+                pointee: 0,
+            })),
+            Some(Global::Buffer { binding, pointee }) => Ok(Value::Pointer(Pointer::Buffer {
+                binding: *binding,
+                offset: 0,
+                pointee: *pointee,
+            })),
+            Some(Global::Workgroup { pointee }) => Ok(Value::Pointer(Pointer::Variable {
+                root: Root::Workgroup(id),
+                path: Vec::new(),
+                pointee: *pointee,
+            })),
+            None => Err(invalid(format!("%{id} is used before it is defined"))),
+        }
+    }
+
+    fn bool(&self, operand: &Operand) -> Result<bool> {
+        match self.operand(operand)? {
+            Value::Bool(b) => Ok(b),
+            value => Err(invalid(format!("expected a bool, found {value:?}"))),
+        }
+    }
+
+    fn bits(&self, operand: &Operand) -> Result<u64> {
+        match self.operand(operand)? {
+            Value::Bits(bits) => Ok(bits),
+            value => Err(invalid(format!("expected a scalar, found {value:?}"))),
+        }
+    }
+
+    /// The values of `operands`, with their types.
+    fn args(&self, operands: &[Operand]) -> Result<Vec<Arg>> {
+        operands
+            .iter()
+            .map(|operand| {
+                Ok(Arg {
+                    value: self.operand(operand)?,
+                    ty: self.module.value_types[&operand.unwrap_id_ref()],
+                })
+            })
+            .collect()
+    }
+
+    fn load(&self, memory: &Memory, pointer: &Pointer) -> Result<Value> {
+        match pointer {
+            Pointer::Buffer {
+                binding,
+                offset,
+                pointee,
+            } => self
+                .module
+                .read(buffer(memory.buffers, *binding)?, *offset, *pointee),
+            Pointer::Variable { root, path, .. } => {
+                let mut value = match root {
+                    Root::Function(slot) => self.variables[*slot].clone(),
+                    Root::Builtin(builtin) => self.builtins.value(*builtin)?,
+                    Root::Workgroup(id) => memory.workgroup[id].clone(),
+                };
+                for &i in path {
+                    let Value::Composite(mut items) = value else {
+                        unreachable!("access chains are type checked");
+                    };
+                    value = items.swap_remove(i as usize);
+                }
+                Ok(value)
+            }
+        }
+    }
+
+    fn store(&mut self, memory: &mut Memory, pointer: &Pointer, value: Value) -> Result<()> {
+        match pointer {
+            Pointer::Buffer {
+                binding,
+                offset,
+                pointee,
+            } => {
+                let bytes = memory
+                    .buffers
+                    .get_mut(*binding as usize)
+                    .ok_or_else(|| unbound(*binding))?;
+                self.module.write(bytes, *offset, *pointee, &value)
+            }
+            Pointer::Variable { root, path, .. } => {
+                let mut target = match root {
+                    Root::Function(slot) => &mut self.variables[*slot],
+                    Root::Workgroup(id) => memory
+                        .workgroup
+                        .get_mut(id)
+                        .expect("workgroup variables are allocated per workgroup"),
+                    Root::Builtin(_) => return Err(invalid("store to an input variable")),
+                };
+                for &i in path {
+                    let Value::Composite(items) = target else {
+                        unreachable!("access chains are type checked");
+                    };
+                    target = &mut items[i as usize];
+                }
+                *target = value;
+                Ok(())
+            }
+        }
+    }
+}
+
+fn buffer(buffers: &[Vec<u8>], binding: u32) -> Result<&[u8]> {
+    buffers
+        .get(binding as usize)
+        .map(Vec::as_slice)
+        .ok_or_else(|| unbound(binding))
+}
+
+fn logical(op: Op) -> Option<fn(bool, bool) -> bool> {
+    Some(match op {
+        Op::LogicalAnd => |a, b| a && b,
+        Op::LogicalOr => |a, b| a || b,
+        Op::LogicalEqual => |a, b| a == b,
+        Op::LogicalNotEqual => |a, b| a != b,
+        _ => return None,
+    })
+}
+
+/// The result of a float instruction, before it is rounded to its width.
+enum Float {
+    Value(f64),
+    Bool(bool),
+}
+
+/// Float operations are computed on `f64`, which rounds `f16` and `f32`
+/// arithmetic to the same results as computing at their own width.
+fn float_op(op: Op) -> Option<fn(f64, f64) -> Float> {
+    Some(match op {
+        Op::FAdd => |a, b| Float::Value(a + b),
+        Op::FSub => |a, b| Float::Value(a - b),
+        Op::FMul => |a, b| Float::Value(a * b),
+        Op::FDiv => |a, b| Float::Value(a / b),
+        Op::FRem => |a, b| Float::Value(a % b),
+        Op::FOrdEqual => |a, b| Float::Bool(a == b),
+        Op::FUnordNotEqual => |a, b| Float::Bool(a != b),
+        Op::FOrdLessThan => |a, b| Float::Bool(a < b),
+        Op::FOrdLessThanEqual => |a, b| Float::Bool(a <= b),
+        Op::FOrdGreaterThan => |a, b| Float::Bool(a > b),
+        Op::FOrdGreaterThanEqual => |a, b| Float::Bool(a >= b),
+        _ => return None,
+    })
+}
+
+/// The low `width` bits of `bits`.
+fn mask(bits: u64, width: u32) -> u64 {
+    match width {
+        64 => bits,
+        width => bits & ((1 << width) - 1),
+    }
+}
+
+/// The bits of the float of `width` bits nearest to `value`.
+fn from_f64(value: f64, width: u32) -> u64 {
+    match width {
+        16 => u64::from(f16::from_f64(value).to_bits()),
+        32 => u64::from((value as f32).to_bits()),
+        _ => value.to_bits(),
+    }
+}
+
+fn invalid(msg: impl Into<String>) -> RyclError {
+    RyclError::InvalidSpirv(msg.into())
+}
+
+fn trap(msg: impl Into<String>) -> RyclError {
+    RyclError::DispatchFailed(msg.into())
+}
+
+fn unbound(binding: u32) -> RyclError {
+    RyclError::InvalidArgument(format!("no buffer is bound to binding {binding}"))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::buffer::to_bytes;
+    use shared_type::ir::{
+        typeck, AtomicOp, BinaryOp, Builtin, Expr, Field, Function, Kernel, Literal, Local,
+        LocalId, Param, Place, ScalarType, Shared, Stmt, StructType, SubgroupOp, SyncOp, Type,
+        UnaryOp,
+    };
+    use shared_type::{Mat3, Vec3};
+    use spirv_codegen::build_module;
+
+    fn int(value: u64) -> Box<Expr> {
+        Box::new(Expr::Literal(Literal::Int(value, None)))
+    }
+
+    fn load(place: Place) -> Box<Expr> {
+        Box::new(Expr::Load(place))
+    }
+
+    fn local(i: u32) -> Place {
+        Place::Local(LocalId(i))
+    }
+
+    fn element(param: usize, index: Box<Expr>) -> Place {
+        Place::Index(Box::new(Place::Param(param)), index)
+    }
+
+    fn array(scalar: ScalarType, len: u32) -> Type {
+        Type::Array(Box::new(Type::Scalar(scalar)), len)
+    }
+
+    fn locals(names: &[&str]) -> Vec<Local> {
+        names
+            .iter()
+            .map(|name| Local {
+                name: name.to_string(),
+                ty: None,
+            })
+            .collect()
+    }
+
+    /// Compiles `kernel` and runs it on `buffers`.
+    fn run(
+        mut kernel: Kernel,
+        local_size: u32,
+        num_workgroups: u32,
+        buffers: &mut [Vec<u8>],
+    ) -> Result<()> {
+        typeck::check(&mut kernel).unwrap();
+        let words = build_module(&kernel, "main", local_size).unwrap();
+        Interpreter::new(&words, "main")?.run(buffers, num_workgroups)
+    }
+
+    fn read_i32s(bytes: &[u8]) -> Vec<i32> {
+        bytes
+            .chunks(4)
+            .map(|word| i32::from_ne_bytes(word.try_into().unwrap()))
+            .collect()
+    }
+
+    #[test]
+    fn test_scale() {
+        // for i in 0..4 { out[i] = out[i] * factor; }
+        let kernel = Kernel {
+            name: "scale".into(),
+            params: vec![
+                Param {
+                    name: "factor".into(),
+                    ty: Type::Scalar(ScalarType::F32),
+                    mutable: false,
+                },
+                Param {
+                    name: "out".into(),
+                    ty: array(ScalarType::F32, 4),
+                    mutable: true,
+                },
+            ],
+            locals: locals(&["i"]),
+            shared: vec![],
+            body: vec![Stmt::For {
+                var: LocalId(0),
+                start: *int(0),
+                end: *int(4),
+                inclusive: false,
+                body: vec![Stmt::Assign {
+                    place: element(1, load(local(0))),
+                    value: Expr::Binary(
+                        BinaryOp::Mul,
+                        load(element(1, load(local(0)))),
+                        load(Place::Param(0)),
+                    ),
+                }],
+            }],
+        };
+        let mut buffers = [to_bytes(&[2.5f32]), to_bytes(&[1f32, -2., 0.5, 4.])];
+        run(kernel, 1, 1, &mut buffers).unwrap();
+        assert_eq!(buffers[1], to_bytes(&[2.5f32, -5., 1.25, 10.]));
+    }
+
+    #[test]
+    fn test_loops_and_branches() {
+        // for i in 0..4 {
+        //     let mut x = values[i];
+        //     let mut steps = 0;
+        //     while x != 1 {
+        //         steps = steps + 1;
+        //         if x % 2 == 0 { x = x / 2; continue; }
+        //         x = 3 * x + 1;
+        //     }
+        //     out[i] = steps;
+        // }
+        let x = || local(1);
+        let steps = || local(2);
+        let kernel = Kernel {
+            name: "collatz".into(),
+            params: vec![
+                Param {
+                    name: "values".into(),
+                    ty: array(ScalarType::I32, 4),
+                    mutable: false,
+                },
+                Param {
+                    name: "out".into(),
+                    ty: array(ScalarType::I32, 4),
+                    mutable: true,
+                },
+            ],
+            locals: locals(&["i", "x", "steps"]),
+            shared: vec![],
+            body: vec![Stmt::For {
+                var: LocalId(0),
+                start: *int(0),
+                end: *int(4),
+                inclusive: false,
+                body: vec![
+                    Stmt::Let {
+                        local: LocalId(1),
+                        init: Some(*load(element(0, load(local(0))))),
+                    },
+                    Stmt::Let {
+                        local: LocalId(2),
+                        init: Some(*int(0)),
+                    },
+                    Stmt::While {
+                        cond: Expr::Binary(BinaryOp::Ne, load(x()), int(1)),
+                        body: vec![
+                            Stmt::Assign {
+                                place: steps(),
+                                value: Expr::Binary(BinaryOp::Add, load(steps()), int(1)),
+                            },
+                            Stmt::If {
+                                cond: Expr::Binary(
+                                    BinaryOp::Eq,
+                                    Box::new(Expr::Binary(BinaryOp::Rem, load(x()), int(2))),
+                                    int(0),
+                                ),
+                                then_block: vec![
+                                    Stmt::Assign {
+                                        place: x(),
+                                        value: Expr::Binary(BinaryOp::Div, load(x()), int(2)),
+                                    },
+                                    Stmt::Continue,
+                                ],
+                                else_block: vec![],
+                            },
+                            Stmt::Assign {
+                                place: x(),
+                                value: Expr::Binary(
+                                    BinaryOp::Add,
+                                    Box::new(Expr::Binary(BinaryOp::Mul, int(3), load(x()))),
+                                    int(1),
+                                ),
+                            },
+                        ],
+                    },
+                    Stmt::Assign {
+                        place: element(1, load(local(0))),
+                        value: *load(steps()),
+                    },
+                ],
+            }],
+        };
+        let mut buffers = [to_bytes(&[1i32, 6, 7, 27]), to_bytes(&[0i32; 4])];
+        run(kernel, 1, 1, &mut buffers).unwrap();
+        assert_eq!(read_i32s(&buffers[1]), [0, 8, 16, 111]);
+    }
+
+    #[test]
+    fn test_inclusive_loop_to_max() {
+        // for i in u32::MAX - 3..=u32::MAX {
+        //     out[0] = out[0] + 1;
+        //     out[1] = i;
+        //     i = i + 2;
+        // }
+        let top = Literal::Int(u64::from(u32::MAX), Some(ScalarType::U32));
+        let kernel = Kernel {
+            name: "to_max".into(),
+            params: vec![Param {
+                name: "out".into(),
+                ty: array(ScalarType::U32, 2),
+                mutable: true,
+            }],
+            locals: locals(&["i"]),
+            shared: vec![],
+            body: vec![Stmt::For {
+                var: LocalId(0),
+                start: Expr::Binary(BinaryOp::Sub, Box::new(Expr::Literal(top)), int(3)),
+                end: Expr::Literal(top),
+                inclusive: true,
+                body: vec![
+                    Stmt::Assign {
+                        place: element(0, int(0)),
+                        value: Expr::Binary(BinaryOp::Add, load(element(0, int(0))), int(1)),
+                    },
+                    Stmt::Assign {
+                        place: element(0, int(1)),
+                        value: *load(local(0)),
+                    },
+                    // Assigning the variable does not skip iterations.
+                    Stmt::Assign {
+                        place: local(0),
+                        value: Expr::Binary(BinaryOp::Add, load(local(0)), int(2)),
+                    },
+                ],
+            }],
+        };
+        let mut buffers = [to_bytes(&[0u32; 2])];
+        run(kernel, 1, 1, &mut buffers).unwrap();
+        assert_eq!(read_i32s(&buffers[0]), [4, -1]);
+    }
+
+    #[test]
+    fn test_short_circuit_and_builtins() {
+        // if num_thread_blocks > 1 && 10 / (num_thread_blocks - 1) > 2 {
+        //     out[0] = (num_thread_blocks * thread_block_size) as i32;
+        // }
+        let blocks = || Box::new(Expr::Builtin(Builtin::NumThreadBlocks));
+        let cond = Expr::Binary(
+            BinaryOp::And,
+            Box::new(Expr::Binary(BinaryOp::Gt, blocks(), int(1))),
+            Box::new(Expr::Binary(
+                BinaryOp::Gt,
+                Box::new(Expr::Binary(
+                    BinaryOp::Div,
+                    int(10),
+                    Box::new(Expr::Binary(BinaryOp::Sub, blocks(), int(1))),
+                )),
+                int(2),
+            )),
+        );
+        let kernel = |cond: Expr| Kernel {
+            name: "guarded".into(),
+            params: vec![Param {
+                name: "out".into(),
+                ty: array(ScalarType::I32, 1),
+                mutable: true,
+            }],
+            locals: vec![],
+            shared: vec![],
+            body: vec![Stmt::If {
+                cond,
+                then_block: vec![Stmt::Assign {
+                    place: element(0, int(0)),
+                    value: Expr::Cast(
+                        Box::new(Expr::Binary(
+                            BinaryOp::Mul,
+                            blocks(),
+                            Box::new(Expr::Builtin(Builtin::ThreadBlockSize)),
+                        )),
+                        ScalarType::I32,
+                    ),
+                }],
+                else_block: vec![],
+            }],
+        };
+
+        let mut buffers = [to_bytes(&[-1i32])];
+        run(kernel(cond.clone()), 8, 3, &mut buffers).unwrap();
+        assert_eq!(read_i32s(&buffers[0]), [24]);
+
+        // With one block, the division by zero on the right is never evaluated.
+        let mut buffers = [to_bytes(&[-1i32])];
+        run(kernel(cond), 8, 1, &mut buffers).unwrap();
+        assert_eq!(read_i32s(&buffers[0]), [-1]);
+    }
+
+    #[test]
+    fn test_thread_ids() {
+        // out[global_id] = block_id * 10 + local_id;
+        let builtin = |builtin| Box::new(Expr::Builtin(builtin));
+        let kernel = Kernel {
+            name: "ids".into(),
+            params: vec![Param {
+                name: "out".into(),
+                ty: array(ScalarType::U32, 6),
+                mutable: true,
+            }],
+            locals: vec![],
+            shared: vec![],
+            body: vec![Stmt::Assign {
+                place: element(0, builtin(Builtin::GlobalId)),
+                value: Expr::Binary(
+                    BinaryOp::Add,
+                    Box::new(Expr::Binary(
+                        BinaryOp::Mul,
+                        builtin(Builtin::BlockId),
+                        int(10),
+                    )),
+                    builtin(Builtin::LocalId),
+                ),
+            }],
+        };
+        let mut buffers = [to_bytes(&[0u32; 6])];
+        run(kernel, 2, 3, &mut buffers).unwrap();
+        assert_eq!(buffers[0], to_bytes(&[0u32, 1, 10, 11, 20, 21]));
+    }
+
+    #[test]
+    fn test_workgroup_variables() {
+        // tile[local_id] = global_id; out[global_id] = tile[local_id] * 3;
+        let builtin = |builtin| Box::new(Expr::Builtin(builtin));
+        let tile = || Place::Index(Box::new(Place::Shared(0)), builtin(Builtin::LocalId));
+        let mut kernel = Kernel {
+            name: "tile".into(),
+            params: vec![Param {
+                name: "out".into(),
+                ty: array(ScalarType::U32, 6),
+                mutable: true,
+            }],
+            locals: vec![],
+            shared: vec![Shared {
+                name: "tile".into(),
+                ty: array(ScalarType::U32, 2),
+            }],
+            body: vec![
+                Stmt::Assign {
+                    place: tile(),
+                    value: Expr::Builtin(Builtin::GlobalId),
+                },
+                Stmt::Assign {
+                    place: element(0, builtin(Builtin::GlobalId)),
+                    value: Expr::Binary(BinaryOp::Mul, load(tile()), int(3)),
+                },
+            ],
+        };
+        typeck::check(&mut kernel).unwrap();
+        let words = build_module(&kernel, "main", 2).unwrap();
+        let module = dr::load_words(&words).unwrap();
+        assert!(module.types_global_values.iter().any(|inst| {
+            inst.class.opcode == Op::Variable
+                && inst.operands[0] == Operand::StorageClass(spirv::StorageClass::Workgroup)
+        }));
+
+        let mut buffers = [to_bytes(&[0u32; 6])];
+        run(kernel, 2, 3, &mut buffers).unwrap();
+        assert_eq!(buffers[0], to_bytes(&[0u32, 3, 6, 9, 12, 15]));
+    }
+
+    #[test]
+    fn test_barrier() {
+        // tile[local_id] = global_id;
+        // block_barrier();
+        // out[global_id] = tile[block_dim - 1 - local_id];
+        let builtin = |builtin| Box::new(Expr::Builtin(builtin));
+        let tile = |index| Place::Index(Box::new(Place::Shared(0)), index);
+        let mirrored = Box::new(Expr::Binary(
+            BinaryOp::Sub,
+            Box::new(Expr::Binary(
+                BinaryOp::Sub,
+                builtin(Builtin::ThreadBlockSize),
+                int(1),
+            )),
+            builtin(Builtin::LocalId),
+        ));
+        let kernel = Kernel {
+            name: "reverse".into(),
+            params: vec![Param {
+                name: "out".into(),
+                ty: array(ScalarType::U32, 8),
+                mutable: true,
+            }],
+            locals: vec![],
+            shared: vec![Shared {
+                name: "tile".into(),
+                ty: array(ScalarType::U32, 4),
+            }],
+            body: vec![
+                Stmt::Assign {
+                    place: tile(builtin(Builtin::LocalId)),
+                    value: Expr::Builtin(Builtin::GlobalId),
+                },
+                Stmt::Sync(SyncOp::BlockBarrier),
+                Stmt::Assign {
+                    place: element(0, builtin(Builtin::GlobalId)),
+                    value: Expr::Load(tile(mirrored)),
+                },
+            ],
+        };
+        let mut buffers = [to_bytes(&[0u32; 8])];
+        run(kernel, 4, 2, &mut buffers).unwrap();
+        assert_eq!(buffers[0], to_bytes(&[3u32, 2, 1, 0, 7, 6, 5, 4]));
+    }
+
+    #[test]
+    fn test_atomics() {
+        // atomic_add(&mut bins[global_id % 2], 1);
+        // atomic_min(&mut extremes[0], 3 - global_id as i32);
+        // atomic_max(&mut extremes[1], 3 - global_id as i32);
+        // atomic_compare_exchange(&mut winner[0], 0, global_id + 1);
+        // atomic_add(&mut sum[0], 0.5);
+        let gid = || Box::new(Expr::Builtin(Builtin::GlobalId));
+        let signed = || {
+            Expr::Binary(
+                BinaryOp::Sub,
+                int(3),
+                Box::new(Expr::Cast(gid(), ScalarType::I32)),
+            )
+        };
+        let atomic = |op, place, args| Stmt::Expr(Expr::Atomic(op, place, args));
+        let param = |name: &str, ty| Param {
+            name: name.into(),
+            ty,
+            mutable: true,
+        };
+        let kernel = Kernel {
+            name: "atomics".into(),
+            params: vec![
+                param("bins", array(ScalarType::U32, 2)),
+                param("extremes", array(ScalarType::I32, 2)),
+                param("winner", array(ScalarType::U32, 1)),
+                param("sum", array(ScalarType::F32, 1)),
+            ],
+            locals: vec![],
+            shared: vec![],
+            body: vec![
+                atomic(
+                    AtomicOp::Add,
+                    element(0, Box::new(Expr::Binary(BinaryOp::Rem, gid(), int(2)))),
+                    vec![*int(1)],
+                ),
+                atomic(AtomicOp::Min, element(1, int(0)), vec![signed()]),
+                atomic(AtomicOp::Max, element(1, int(1)), vec![signed()]),
+                atomic(
+                    AtomicOp::CompareExchange,
+                    element(2, int(0)),
+                    vec![*int(0), Expr::Binary(BinaryOp::Add, gid(), int(1))],
+                ),
+                atomic(
+                    AtomicOp::Add,
+                    element(3, int(0)),
+                    vec![Expr::Literal(Literal::Float(0.5, None))],
+                ),
+            ],
+        };
+        let mut buffers = [
+            to_bytes(&[0u32; 2]),
+            to_bytes(&[i32::MAX, i32::MIN]),
+            to_bytes(&[0u32]),
+            to_bytes(&[1.0f32]),
+        ];
+        run(kernel, 4, 2, &mut buffers).unwrap();
+        assert_eq!(buffers[0], to_bytes(&[4u32, 4]));
+        assert_eq!(buffers[1], to_bytes(&[-4i32, 3]));
+        assert_eq!(buffers[2], to_bytes(&[1u32]));
+        assert_eq!(buffers[3], to_bytes(&[5.0f32]));
+    }
+
+    #[test]
+    fn test_subgroups() {
+        // out[0][global_id] = subgroup_reduce_add(global_id);
+        // out[1][global_id] = subgroup_inclusive_add(global_id);
+        // out[2][global_id] = subgroup_exclusive_add(global_id);
+        // out[3][global_id] = subgroup_reduce_max(global_id);
+        // out[4][global_id] = subgroup_shuffle(global_id, subgroup_local_id ^ 1);
+        // out[5][global_id] = subgroup_broadcast(global_id, 3);
+        // out[6][global_id] = subgroup_ballot(global_id % 3 == 0);
+        // out[7][global_id] = subgroup_id;
+        let builtin = |builtin| Box::new(Expr::Builtin(builtin));
+        let gid = || Expr::Builtin(Builtin::GlobalId);
+        let subgroup = |op, args| Expr::Subgroup(op, args);
+        let values = [
+            subgroup(SubgroupOp::ReduceAdd, vec![gid()]),
+            subgroup(SubgroupOp::InclusiveAdd, vec![gid()]),
+            subgroup(SubgroupOp::ExclusiveAdd, vec![gid()]),
+            subgroup(SubgroupOp::ReduceMax, vec![gid()]),
+            subgroup(
+                SubgroupOp::Shuffle,
+                vec![
+                    gid(),
+                    Expr::Binary(BinaryOp::BitXor, builtin(Builtin::SubgroupLocalId), int(1)),
+                ],
+            ),
+            subgroup(SubgroupOp::Broadcast, vec![gid(), *int(3)]),
+            subgroup(
+                SubgroupOp::Ballot,
+                vec![Expr::Binary(
+                    BinaryOp::Eq,
+                    Box::new(Expr::Binary(BinaryOp::Rem, Box::new(gid()), int(3))),
+                    int(0),
+                )],
+            ),
+            Expr::Builtin(Builtin::SubgroupId),
+        ];
+        let kernel = Kernel {
+            name: "subgroups".into(),
+            params: (0..values.len())
+                .map(|i| Param {
+                    name: format!("out{i}"),
+                    ty: array(ScalarType::U32, 8),
+                    mutable: true,
+                })
+                .collect(),
+            locals: vec![],
+            shared: vec![],
+            body: values
+                .into_iter()
+                .enumerate()
+                .map(|(i, value)| Stmt::Assign {
+                    place: element(i, Box::new(gid())),
+                    value,
+                })
+                .collect(),
+        };
+        let mut buffers = vec![to_bytes(&[0u32; 8]); 8];
+        run(kernel, 8, 1, &mut buffers).unwrap();
+        let expected: [[u32; 8]; 8] = [
+            [6, 6, 6, 6, 22, 22, 22, 22],
+            [0, 1, 3, 6, 4, 9, 15, 22],
+            [0, 0, 1, 3, 0, 4, 9, 15],
+            [3, 3, 3, 3, 7, 7, 7, 7],
+            [1, 0, 3, 2, 5, 4, 7, 6],
+            [3, 3, 3, 3, 7, 7, 7, 7],
+            [
+                0b1001, 0b1001, 0b1001, 0b1001, 0b0100, 0b0100, 0b0100, 0b0100,
+            ],
+            [0, 0, 0, 0, 1, 1, 1, 1],
+        ];
+        for (buffer, expected) in buffers.iter().zip(expected) {
+            assert_eq!(*buffer, to_bytes(&expected));
+        }
+    }
+
+    #[test]
+    fn test_out_of_bounds_index() {
+        // out[index] = 1;
+        let kernel = Kernel {
+            name: "store".into(),
+            params: vec![
+                Param {
+                    name: "index".into(),
+                    ty: Type::Scalar(ScalarType::U32),
+                    mutable: false,
+                },
+                Param {
+                    name: "out".into(),
+                    ty: array(ScalarType::I32, 2),
+                    mutable: true,
+                },
+            ],
+            locals: vec![],
+            shared: vec![],
+            body: vec![Stmt::Assign {
+                place: element(1, load(Place::Param(0))),
+                value: *int(1),
+            }],
+        };
+        let mut buffers = [to_bytes(&[2u32]), to_bytes(&[0i32; 2])];
+        let err = run(kernel, 1, 1, &mut buffers).unwrap_err();
+        assert!(matches!(err, RyclError::DispatchFailed(_)), "{err}");
+    }
+
+    #[test]
+    fn test_slice_length() {
+        // let i = global_id; if i < out.len() { out[i] = values.len() + i; }
+        let slice = Type::Slice(Box::new(Type::Scalar(ScalarType::U32)));
+        let len = |param| Box::new(Expr::Len(Place::Param(param)));
+        let kernel = Kernel {
+            name: "lengths".into(),
+            params: vec![
+                Param {
+                    name: "values".into(),
+                    ty: slice.clone(),
+                    mutable: false,
+                },
+                Param {
+                    name: "out".into(),
+                    ty: slice,
+                    mutable: true,
+                },
+            ],
+            locals: locals(&["i"]),
+            shared: vec![],
+            body: vec![
+                Stmt::Let {
+                    local: LocalId(0),
+                    init: Some(Expr::Builtin(Builtin::GlobalId)),
+                },
+                Stmt::If {
+                    cond: Expr::Binary(BinaryOp::Lt, load(local(0)), len(1)),
+                    then_block: vec![Stmt::Assign {
+                        place: element(1, load(local(0))),
+                        value: Expr::Binary(BinaryOp::Add, len(0), load(local(0))),
+                    }],
+                    else_block: vec![],
+                },
+            ],
+        };
+        let mut buffers = [to_bytes(&[0u32; 5]), to_bytes(&[0u32; 3])];
+        run(kernel, 2, 2, &mut buffers).unwrap();
+        assert_eq!(buffers[1], to_bytes(&[5u32, 6, 7]));
+    }
+
+    #[test]
+    fn test_wide_scalars() {
+        // let i = global_id;
+        // let x = values[i];
+        // out[i] = x as f64 * scale;
+        // big[i] = x > 100;
+        // total[i] = -(x as i8 as i64) << 40;
+        let x = || load(local(1));
+        let cast = |expr, to| Box::new(Expr::Cast(expr, to));
+        let kernel = Kernel {
+            name: "widen".into(),
+            params: vec![
+                Param {
+                    name: "values".into(),
+                    ty: array(ScalarType::U8, 4),
+                    mutable: false,
+                },
+                Param {
+                    name: "scale".into(),
+                    ty: Type::Scalar(ScalarType::F64),
+                    mutable: false,
+                },
+                Param {
+                    name: "out".into(),
+                    ty: array(ScalarType::F64, 4),
+                    mutable: true,
+                },
+                Param {
+                    name: "big".into(),
+                    ty: array(ScalarType::Bool, 4),
+                    mutable: true,
+                },
+                Param {
+                    name: "total".into(),
+                    ty: array(ScalarType::I64, 4),
+                    mutable: true,
+                },
+            ],
+            locals: locals(&["i", "x"]),
+            shared: vec![],
+            body: vec![
+                Stmt::Let {
+                    local: LocalId(0),
+                    init: Some(Expr::Builtin(Builtin::GlobalId)),
+                },
+                Stmt::Let {
+                    local: LocalId(1),
+                    init: Some(*load(element(0, load(local(0))))),
+                },
+                Stmt::Assign {
+                    place: element(2, load(local(0))),
+                    value: Expr::Binary(
+                        BinaryOp::Mul,
+                        cast(x(), ScalarType::F64),
+                        load(Place::Param(1)),
+                    ),
+                },
+                Stmt::Assign {
+                    place: element(3, load(local(0))),
+                    value: Expr::Binary(BinaryOp::Gt, x(), int(100)),
+                },
+                Stmt::Assign {
+                    place: element(4, load(local(0))),
+                    value: Expr::Binary(
+                        BinaryOp::Shl,
+                        Box::new(Expr::Unary(
+                            UnaryOp::Neg,
+                            cast(cast(x(), ScalarType::I8), ScalarType::I64),
+                        )),
+                        int(40),
+                    ),
+                },
+            ],
+        };
+        let mut buffers = [
+            to_bytes(&[1u8, 101, 200, 255]),
+            to_bytes(&[0.5f64]),
+            to_bytes(&[0f64; 4]),
+            to_bytes(&[true; 4]),
+            to_bytes(&[0i64; 4]),
+        ];
+        run(kernel, 4, 1, &mut buffers).unwrap();
+        assert_eq!(buffers[2], to_bytes(&[0.5f64, 50.5, 100., 127.5]));
+        assert_eq!(buffers[3], to_bytes(&[false, true, true, true]));
+        assert_eq!(
+            buffers[4],
+            to_bytes(&[-1i64 << 40, -101 << 40, 56 << 40, 1 << 40])
+        );
+    }
+
+    #[test]
+    fn test_nested_structs() {
+        // let i = global_id;
+        // if i < data.len() {
+        //     data[i].total = data[i].items[0].value + data[i].items[1].value;
+        //     data[i].items[0].flag = !data[i].items[1].flag;
+        //     data[i].items[1] = data[i].items[0];
+        // }
+        let field = |name: &str, ty| Field {
+            name: name.into(),
+            ty,
+        };
+        let inner = Type::Struct(StructType {
+            name: "Inner".into(),
+            fields: vec![
+                field("flag", Type::Scalar(ScalarType::Bool)),
+                field("value", Type::Scalar(ScalarType::F64)),
+            ],
+        });
+        let outer = Type::Struct(StructType {
+            name: "Outer".into(),
+            fields: vec![
+                field("items", Type::Array(Box::new(inner), 2)),
+                field("total", Type::Scalar(ScalarType::F64)),
+            ],
+        });
+        let data = || element(0, load(local(0)));
+        let member = |base: Place, name: &str| Place::Field(Box::new(base), name.into());
+        let item = |i| Place::Index(Box::new(member(data(), "items")), int(i));
+        let kernel = Kernel {
+            name: "nested".into(),
+            params: vec![Param {
+                name: "data".into(),
+                ty: Type::Slice(Box::new(outer)),
+                mutable: true,
+            }],
+            locals: locals(&["i"]),
+            shared: vec![],
+            body: vec![
+                Stmt::Let {
+                    local: LocalId(0),
+                    init: Some(Expr::Builtin(Builtin::GlobalId)),
+                },
+                Stmt::If {
+                    cond: Expr::Binary(
+                        BinaryOp::Lt,
+                        load(local(0)),
+                        Box::new(Expr::Len(Place::Param(0))),
+                    ),
+                    then_block: vec![
+                        Stmt::Assign {
+                            place: member(data(), "total"),
+                            value: Expr::Binary(
+                                BinaryOp::Add,
+                                load(member(item(0), "value")),
+                                load(member(item(1), "value")),
+                            ),
+                        },
+                        Stmt::Assign {
+                            place: member(item(0), "flag"),
+                            value: Expr::Unary(UnaryOp::Not, load(member(item(1), "flag"))),
+                        },
+                        Stmt::Assign {
+                            place: item(1),
+                            value: *load(item(0)),
+                        },
+                    ],
+                    else_block: vec![],
+                },
+            ],
+        };
+        // Inner is 16 bytes: the flag at 0 and the value at 8. Outer is 40
+        // bytes: the items at 0 and the total at 32.
+        let outer_bytes = |flags: [bool; 2], values: [f64; 2], total: f64| {
+            let mut bytes = vec![0; 40];
+            for i in 0..2 {
+                bytes[i * 16..i * 16 + 4].copy_from_slice(&to_bytes(&[flags[i]]));
+                bytes[i * 16 + 8..i * 16 + 16].copy_from_slice(&values[i].to_ne_bytes());
+            }
+            bytes[32..].copy_from_slice(&total.to_ne_bytes());
+            bytes
+        };
+        let mut buffers = [[
+            outer_bytes([false, true], [1.5, 2.0], 0.0),
+            outer_bytes([true, false], [-1.0, 0.25], 0.0),
+        ]
+        .concat()];
+        run(kernel, 4, 1, &mut buffers).unwrap();
+        assert_eq!(
+            buffers[0],
+            [
+                outer_bytes([false, false], [1.5, 1.5], 3.5),
+                outer_bytes([true, true], [-1.0, -1.0], -0.75),
+            ]
+            .concat()
+        );
+    }
+
+    #[test]
+    fn test_vectors_and_matrices() {
+        // let i = global_id;
+        // if i < v.len() {
+        //     let p = v[i];
+        //     v[i] = m * p * 2.0 + Vec3::splat(1.0);
+        //     v[i].z = -v[i].z;
+        //     out[i] = p.dot(p) + p.zyx().x + p.xy().length()
+        //         + p.cross(Vec3::new(0.0, 0.0, 1.0)).x;
+        //     same[i] = m.transpose() * m * p == p;
+        // }
+        let vec3 = Type::Vector(ScalarType::F32, 3);
+        let float = |value| Box::new(Expr::Literal(Literal::Float(value, None)));
+        let binary = |op, lhs, rhs| Box::new(Expr::Binary(op, lhs, rhs));
+        let swizzle = |operand, indices: &[u32]| Box::new(Expr::Swizzle(operand, indices.to_vec()));
+        let call = |function, args: Vec<Box<Expr>>| {
+            Box::new(Expr::Call(function, args.into_iter().map(|a| *a).collect()))
+        };
+        let p = || load(local(1));
+        let m = || load(Place::Param(0));
+        let v = || element(1, load(local(0)));
+        let z = || Place::Field(Box::new(v()), "z".into());
+        let slice = |ty| Type::Slice(Box::new(ty));
+        let kernel = Kernel {
+            name: "vectors".into(),
+            params: vec![
+                Param {
+                    name: "m".into(),
+                    ty: Type::Matrix(ScalarType::F32, 3),
+                    mutable: false,
+                },
+                Param {
+                    name: "v".into(),
+                    ty: slice(vec3.clone()),
+                    mutable: true,
+                },
+                Param {
+                    name: "out".into(),
+                    ty: slice(Type::Scalar(ScalarType::F32)),
+                    mutable: true,
+                },
+                Param {
+                    name: "same".into(),
+                    ty: slice(Type::Scalar(ScalarType::Bool)),
+                    mutable: true,
+                },
+            ],
+            locals: locals(&["i", "p"]),
+            shared: vec![],
+            body: vec![
+                Stmt::Let {
+                    local: LocalId(0),
+                    init: Some(Expr::Builtin(Builtin::GlobalId)),
+                },
+                Stmt::If {
+                    cond: Expr::Binary(
+                        BinaryOp::Lt,
+                        load(local(0)),
+                        Box::new(Expr::Len(Place::Param(1))),
+                    ),
+                    then_block: vec![
+                        Stmt::Let {
+                            local: LocalId(1),
+                            init: Some(*load(v())),
+                        },
+                        Stmt::Assign {
+                            place: v(),
+                            value: *binary(
+                                BinaryOp::Add,
+                                binary(BinaryOp::Mul, binary(BinaryOp::Mul, m(), p()), float(2.0)),
+                                Box::new(Expr::Construct(vec3.clone(), vec![*float(1.0)])),
+                            ),
+                        },
+                        Stmt::Assign {
+                            place: z(),
+                            value: Expr::Unary(UnaryOp::Neg, load(z())),
+                        },
+                        Stmt::Assign {
+                            place: element(2, load(local(0))),
+                            value: *binary(
+                                BinaryOp::Add,
+                                binary(
+                                    BinaryOp::Add,
+                                    binary(
+                                        BinaryOp::Add,
+                                        call(Function::Dot, vec![p(), p()]),
+                                        swizzle(swizzle(p(), &[2, 1, 0]), &[0]),
+                                    ),
+                                    call(Function::Length, vec![swizzle(p(), &[0, 1])]),
+                                ),
+                                swizzle(
+                                    call(
+                                        Function::Cross,
+                                        vec![
+                                            p(),
+                                            Box::new(Expr::Construct(
+                                                vec3.clone(),
+                                                vec![*float(0.0), *float(0.0), *float(1.0)],
+                                            )),
+                                        ],
+                                    ),
+                                    &[0],
+                                ),
+                            ),
+                        },
+                        Stmt::Assign {
+                            place: element(3, load(local(0))),
+                            value: *binary(
+                                BinaryOp::Eq,
+                                binary(
+                                    BinaryOp::Mul,
+                                    binary(
+                                        BinaryOp::Mul,
+                                        call(Function::Transpose, vec![m()]),
+                                        m(),
+                                    ),
+                                    p(),
+                                ),
+                                p(),
+                            ),
+                        },
+                    ],
+                    else_block: vec![],
+                },
+            ],
+        };
+        // A quarter turn around z, scaling z by 2.
+        let m = Mat3::from_cols(
+            Vec3::new(0.0, 1.0, 0.0),
+            Vec3::new(-1.0, 0.0, 0.0),
+            Vec3::new(0.0, 0.0, 2.0),
+        );
+        let mut buffers = [
+            to_bytes(&[m]),
+            to_bytes(&[Vec3::new(3.0, 4.0, 12.0), Vec3::new(-6.0, 8.0, 0.0)]),
+            to_bytes(&[0f32; 2]),
+            to_bytes(&[false; 2]),
+        ];
+        run(kernel, 4, 1, &mut buffers).unwrap();
+        assert_eq!(
+            buffers[1],
+            to_bytes(&[Vec3::new(-7.0, 7.0, -49.0), Vec3::new(-15.0, -11.0, -1.0)])
+        );
+        assert_eq!(buffers[2], to_bytes(&[190f32, 118.0]));
+        assert_eq!(buffers[3], to_bytes(&[false, true]));
+    }
+
+    #[test]
+    fn test_math() {
+        // floats[k] = x.<function k>(...);
+        // ints[k] = n.<function k>(...);
+        // uints[0] = (global_id + 9).clamp(2, 5);
+        let float = |value| Expr::Literal(Literal::Float(value, None));
+        let neg = |operand| Expr::Unary(UnaryOp::Neg, Box::new(operand));
+        let x = || *load(Place::Param(0));
+        let n = || *load(Place::Param(1));
+        let floats = [
+            Expr::Call(Function::Sqrt, vec![x()]),
+            Expr::Call(Function::Exp, vec![x()]),
+            Expr::Call(Function::Log, vec![x()]),
+            Expr::Call(Function::Pow, vec![x(), float(0.5)]),
+            Expr::Call(Function::Sin, vec![x()]),
+            Expr::Call(Function::Cos, vec![x()]),
+            Expr::Call(Function::Tan, vec![x()]),
+            Expr::Call(Function::Floor, vec![x()]),
+            Expr::Call(Function::Ceil, vec![x()]),
+            Expr::Call(Function::Round, vec![x()]),
+            Expr::Call(Function::Fma, vec![x(), x(), float(1.0)]),
+            Expr::Call(Function::Min, vec![x(), float(1.0)]),
+            Expr::Call(Function::Max, vec![x(), float(1.0)]),
+            Expr::Call(Function::Clamp, vec![x(), float(0.0), float(2.0)]),
+            Expr::Call(Function::Abs, vec![neg(x())]),
+            Expr::Call(Function::Mix, vec![x(), float(4.25), float(0.5)]),
+        ];
+        let ints = [
+            Expr::Call(Function::Abs, vec![n()]),
+            Expr::Call(Function::Min, vec![n(), *int(1)]),
+            Expr::Call(Function::Max, vec![n(), *int(1)]),
+            Expr::Call(Function::Clamp, vec![n(), neg(*int(2)), *int(2)]),
+        ];
+        let uint = Expr::Call(
+            Function::Clamp,
+            vec![
+                Expr::Binary(
+                    BinaryOp::Add,
+                    Box::new(Expr::Builtin(Builtin::GlobalId)),
+                    int(9),
+                ),
+                *int(2),
+                *int(5),
+            ],
+        );
+        let scalar = |name: &str, scalar| Param {
+            name: name.into(),
+            ty: Type::Scalar(scalar),
+            mutable: false,
+        };
+        let out = |name: &str, scalar, len| Param {
+            name: name.into(),
+            ty: array(scalar, len),
+            mutable: true,
+        };
+        let assign = |param, index, value| Stmt::Assign {
+            place: element(param, int(index as u64)),
+            value,
+        };
+        let (num_floats, num_ints) = (floats.len(), ints.len());
+        let kernel = Kernel {
+            name: "math".into(),
+            params: vec![
+                scalar("x", ScalarType::F32),
+                scalar("n", ScalarType::I32),
+                out("floats", ScalarType::F32, num_floats as u32),
+                out("ints", ScalarType::I32, num_ints as u32),
+                out("uints", ScalarType::U32, 1),
+            ],
+            locals: vec![],
+            shared: vec![],
+            body: (floats.into_iter().enumerate())
+                .map(|(i, value)| assign(2, i, value))
+                .chain(
+                    ints.into_iter()
+                        .enumerate()
+                        .map(|(i, value)| assign(3, i, value)),
+                )
+                .chain([assign(4, 0, uint)])
+                .collect(),
+        };
+        let mut buffers = [
+            to_bytes(&[2.25f32]),
+            to_bytes(&[-3i32]),
+            to_bytes(&vec![0f32; num_floats]),
+            to_bytes(&vec![0i32; num_ints]),
+            to_bytes(&[0u32]),
+        ];
+        run(kernel, 1, 1, &mut buffers).unwrap();
+
+        let x = 2.25f32;
+        let expected = [
+            x.sqrt(),
+            x.exp(),
+            x.ln(),
+            x.powf(0.5),
+            x.sin(),
+            x.cos(),
+            x.tan(),
+            2.,
+            3.,
+            2.,
+            x.mul_add(x, 1.),
+            1.,
+            x,
+            2.,
+            x,
+            3.25,
+        ];
+        let floats = buffers[2]
+            .chunks(4)
+            .map(|word| f32::from_ne_bytes(word.try_into().unwrap()));
+        for (i, (value, expected)) in floats.zip(expected).enumerate() {
+            assert!(
+                (value - expected).abs() <= expected.abs() * 1e-6,
+                "function {i}: {value} != {expected}"
+            );
+        }
+        assert_eq!(read_i32s(&buffers[3]), [3, -3, 1, -2]);
+        assert_eq!(buffers[4], to_bytes(&[5u32]));
+    }
+}
@@ -1,134 +1,95 @@
+use std::any::Any;
+use std::fmt;
 use std::sync::Arc;
 
-use rspirv::{binary::Assemble, spirv};
-use shared_type::KernelFn;
-use vulkano::buffer::{Buffer, BufferCreateInfo, BufferUsage};
+use shared_type::ir::Kernel;
+use vulkano::buffer::{self, Buffer, BufferCreateInfo, Subbuffer};
 use vulkano::command_buffer::allocator::StandardCommandBufferAllocator;
 use vulkano::command_buffer::{AutoCommandBufferBuilder, CommandBufferUsage};
 use vulkano::descriptor_set::allocator::StandardDescriptorSetAllocator;
-use vulkano::descriptor_set::{DescriptorSet, WriteDescriptorSet};
-use vulkano::device::physical::PhysicalDeviceType;
+use vulkano::descriptor_set::{PersistentDescriptorSet, WriteDescriptorSet};
+use vulkano::device::physical::{PhysicalDevice, PhysicalDeviceType, SubgroupFeatures};
 use vulkano::device::{
-    Device, DeviceCreateInfo, DeviceExtensions, Queue, QueueCreateInfo, QueueFlags,
+    Device, DeviceCreateInfo, DeviceExtensions, Features, Queue, QueueCreateInfo, QueueFlags,
 };
 use vulkano::instance::{Instance, InstanceCreateFlags, InstanceCreateInfo};
 use vulkano::library::VulkanLibrary;
 use vulkano::memory::allocator::{AllocationCreateInfo, MemoryTypeFilter, StandardMemoryAllocator};
+use vulkano::memory::MemoryHeapFlags;
 use vulkano::pipeline::compute::ComputePipelineCreateInfo;
 use vulkano::pipeline::layout::PipelineDescriptorSetLayoutCreateInfo;
 use vulkano::pipeline::{
     ComputePipeline, Pipeline, PipelineBindPoint, PipelineLayout, PipelineShaderStageCreateInfo,
 };
-use vulkano::shader::{spirv as vulkano_spirv, ShaderModule, ShaderModuleCreateInfo};
+use vulkano::shader::{ShaderModule, ShaderModuleCreateInfo, ShaderStages};
 use vulkano::sync::{self, GpuFuture};
 
-use super::device_ctx::DeviceCtx;
+use super::device_ctx::{
+    BufferRange, DeviceCtx, DeviceFeatures, DeviceLimits, DeviceMemory, Module,
+};
+use super::error::{Result, RyclError};
+use crate::buffer::BufferUsage;
+use crate::device::{DeviceInfo, DeviceSelector, DeviceType};
 
-pub struct Vulkan<'a> {
+/// A Vulkan device with its compute queue and allocators.
+///
+/// Creating the device is expensive, so a context is created once and shared
+/// across launches.
+pub struct Vulkan {
     device_id: i32,
     device_type: i32,
-    entry_point: &'a str,
+    limits: DeviceLimits,
+    features: DeviceFeatures,
+    device: Arc<Device>,
+    queue: Arc<Queue>,
+    memory_allocator: Arc<StandardMemoryAllocator>,
+    descriptor_set_allocator: StandardDescriptorSetAllocator,
+    command_buffer_allocator: StandardCommandBufferAllocator,
 }
 
-impl<'a> DeviceCtx for Vulkan<'a> {
-    fn device_id(&self) -> i32 {
-        self.device_id
-    }
-    fn device_type(&self) -> i32 {
-        self.device_type
-    }
-    fn entry_point(&self) -> &str {
-        &self.entry_point
-    }
-}
-
-impl<'a> Vulkan<'a> {
-    pub(crate) fn new(device_id: i32, device_type: i32, entry_point: &'a str) -> Self {
-        Self {
-            device_id,
-            device_type,
-            entry_point,
-        }
+impl Vulkan {
+    /// Opens the most capable device with a compute queue.
+    pub fn new() -> Result<Self> {
+        Self::with_device(&DeviceSelector::Best)
     }
 
-    pub(crate) fn build_spirv(&self, kernel: &str) -> Vec<u32> {
-        // Building
-        let mut b = rspirv::dr::Builder::new();
-        b.set_version(1, 0);
-        b.capability(spirv::Capability::Shader);
-        b.memory_model(spirv::AddressingModel::Logical, spirv::MemoryModel::GLSL450);
-        let void = b.type_void();
-        let voidf = b.type_function(void, vec![]);
-        let fun = b
-            .begin_function(
-                void,
-                None,
-                spirv::FunctionControl::DONT_INLINE | spirv::FunctionControl::CONST,
-                voidf,
-            )
-            .unwrap();
-        b.begin_block(None).unwrap();
-        // fixme: add spir-v code
-        b.ret().unwrap();
-        b.end_function().unwrap();
-        b.entry_point(spirv::ExecutionModel::Vertex, fun, self.entry_point, vec![]);
-        let module = b.module();
-
-        // Assembling
-        module.assemble()
+    /// Lists the devices that can run kernels.
+    pub fn devices() -> Result<Vec<DeviceInfo>> {
+        Ok(usable_devices(&instance()?)?
+            .into_iter()
+            .map(|(_, _, info)| info)
+            .collect())
     }
 
+    /// Opens the device picked by `selector` among [`Vulkan::devices`].
+    pub fn with_device(selector: &DeviceSelector) -> Result<Self> {
+        let devices = usable_devices(&instance()?)?;
+        if devices.is_empty() {
+            return Err(RyclError::NoComputeDevice);
+        }
+        let infos: Vec<DeviceInfo> = devices.iter().map(|(_, _, info)| info.clone()).collect();
+        let index = selector
+            .select(&infos)
+            .ok_or_else(|| RyclError::DeviceNotFound(format!("{selector:?}")))?
+            .index;
+        let (physical_device, queue_family_index, info) = devices
+            .into_iter()
+            .nth(index)
+            .ok_or_else(|| RyclError::DeviceNotFound(format!("{selector:?}")))?;
 
-
-    // fixme: add real implementation
-    pub(crate) fn run(&self, spirv_binary: &Vec<u32>) {
-        // fixme: error handling
-        let library = VulkanLibrary::new().unwrap();
-        let instance_create_info = InstanceCreateInfo {
-            flags: InstanceCreateFlags::ENUMERATE_PORTABILITY,
-            ..Default::default()
-        };
-        let instance = Instance::new(library, instance_create_info).unwrap();
-
-        // Choose which physical device to use.
-        let device_extensions = DeviceExtensions {
-            khr_storage_buffer_storage_class: true,
-            ..DeviceExtensions::empty()
-        };
-
-        let (physical_device, queue_family_index) = instance
-            .enumerate_physical_devices()
-            .unwrap()
-            .filter(|p| p.supported_extensions().contains(&device_extensions))
-            .filter_map(|p| {
-                // The Vulkan specs guarantee that a compliant implementation must provide at least one
-                // queue that supports compute operations.
-                p.queue_family_properties()
-                    .iter()
-                    .position(|q| q.queue_flags.intersects(QueueFlags::COMPUTE))
-                    .map(|i| (p, i as u32))
-            })
-            .min_by_key(|(p, _)| match p.properties().device_type {
-                PhysicalDeviceType::DiscreteGpu => 0,
-                PhysicalDeviceType::IntegratedGpu => 1,
-                PhysicalDeviceType::VirtualGpu => 2,
-                PhysicalDeviceType::Cpu => 3,
-                PhysicalDeviceType::Other => 4,
-                _ => 5,
-            })
-            .unwrap();
-
-        println!(
-            "Using device: {} (type: {:?})",
-            physical_device.properties().device_name,
-            physical_device.properties().device_type,
-        );
-
-        // Now initializing the device.
+        // Now initializing the device, with the optional types it supports.
+        let enabled_extensions = physical_device
+            .supported_extensions()
+            .intersection(&OPTIONAL_DEVICE_EXTENSIONS)
+            .union(&DEVICE_EXTENSIONS);
+        let enabled_features = physical_device
+            .supported_features()
+            .intersection(&OPTIONAL_FEATURES);
         let (device, mut queues) = Device::new(
             physical_device,
             DeviceCreateInfo {
-                enabled_extensions: device_extensions,
+                enabled_extensions,
+                enabled_features,
                 queue_create_infos: vec![QueueCreateInfo {
                     queue_family_index,
                     ..Default::default()
@@ -136,51 +97,129 @@ impl<'a> Vulkan<'a> {
                 ..Default::default()
             },
         )
-        .unwrap();
+        .map_err(|_| RyclError::NoComputeDevice)?;
 
         // Since we can request multiple queues, the `queues` variable is in fact an iterator. In this
         // implementation we use only one queue, so we just retrieve the first and only element of the
         // iterator and throw it away.
-        let queue = queues.next().unwrap();
+        let queue = queues.next().ok_or(RyclError::NoComputeDevice)?;
+
+        Ok(Self {
+            device_id: info.index as i32,
+            device_type: device.physical_device().properties().device_type as i32,
+            limits: info.limits,
+            features: info.features,
+            memory_allocator: Arc::new(StandardMemoryAllocator::new_default(device.clone())),
+            descriptor_set_allocator: StandardDescriptorSetAllocator::new(
+                device.clone(),
+                Default::default(),
+            ),
+            command_buffer_allocator: StandardCommandBufferAllocator::new(
+                device.clone(),
+                Default::default(),
+            ),
+            device,
+            queue,
+        })
+    }
+}
+
+struct VulkanModule {
+    pipeline: Arc<ComputePipeline>,
+}
+
+struct VulkanMemory {
+    buffer: Subbuffer<[u8]>,
+}
+
+impl DeviceMemory for VulkanMemory {
+    fn size(&self) -> u64 {
+        self.buffer.size()
+    }
+
+    fn read(&self, offset: u64, out: &mut [u8]) -> Result<()> {
+        let bytes = self.buffer.read().map_err(dispatch_failed)?;
+        let offset = offset as usize;
+        out.copy_from_slice(&bytes[offset..offset + out.len()]);
+        Ok(())
+    }
+
+    fn write(&self, offset: u64, data: &[u8]) -> Result<()> {
+        let mut bytes = self.buffer.write().map_err(dispatch_failed)?;
+        let offset = offset as usize;
+        bytes[offset..offset + data.len()].copy_from_slice(data);
+        Ok(())
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+impl DeviceCtx for Vulkan {
+    fn device_id(&self) -> i32 {
+        self.device_id
+    }
+
+    fn device_type(&self) -> i32 {
+        self.device_type
+    }
+
+    fn limits(&self) -> DeviceLimits {
+        self.limits
+    }
+
+    fn features(&self) -> DeviceFeatures {
+        self.features
+    }
+
+    fn compile(&self, kernel: &Kernel, thread_block_size: u32) -> Result<Module> {
+        let spirv_binary = spirv_codegen::build_spirv(kernel, thread_block_size)?;
+        self.load_spirv(kernel, &spirv_binary, thread_block_size)
+    }
+
+    fn load_spirv(
+        &self,
+        kernel: &Kernel,
+        spirv_binary: &[u32],
+        _thread_block_size: u32,
+    ) -> Result<Module> {
+        let device = &self.device;
 
         let pipeline = {
             let cs = {
-                let code = spirv_binary.as_slice();
-
                 let module = unsafe {
-                    ShaderModule::new(device.clone(), ShaderModuleCreateInfo::new(code)).unwrap()
+                    ShaderModule::new(device.clone(), ShaderModuleCreateInfo::new(spirv_binary))
+                        .map_err(invalid_spirv)?
                 };
 
-                module.entry_point(&self.entry_point).unwrap()
+                module
+                    .entry_point(&kernel.name)
+                    .ok_or_else(|| RyclError::EntryPointNotFound(kernel.name.clone()))?
             };
             let stage = PipelineShaderStageCreateInfo::new(cs);
             let layout = PipelineLayout::new(
                 device.clone(),
                 PipelineDescriptorSetLayoutCreateInfo::from_stages([&stage])
                     .into_pipeline_layout_create_info(device.clone())
-                    .unwrap(),
+                    .map_err(|err| invalid_spirv(err.error))?,
             )
-            .unwrap();
+            .map_err(invalid_spirv)?;
             ComputePipeline::new(
                 device.clone(),
                 None,
                 ComputePipelineCreateInfo::stage_layout(stage, layout),
             )
-            .unwrap()
+            .map_err(invalid_spirv)?
         };
+        Ok(Module::new(VulkanModule { pipeline }))
+    }
 
-        let memory_allocator = Arc::new(StandardMemoryAllocator::new_default(device.clone()));
-        let descriptor_set_allocator = Arc::new(StandardDescriptorSetAllocator::new(
-            device.clone(),
-            Default::default(),
-        ));
-        let command_buffer_allocator =
-            StandardCommandBufferAllocator::new(device.clone(), Default::default());
-
-        let data_buffer = Buffer::from_iter(
-            memory_allocator,
+    fn allocate(&self, bytes: &[u8], usage: BufferUsage) -> Result<Arc<dyn DeviceMemory>> {
+        let buffer = Buffer::from_iter(
+            self.memory_allocator.clone(),
             BufferCreateInfo {
-                usage: BufferUsage::STORAGE_BUFFER,
+                usage: vulkano_usage(usage),
                 ..Default::default()
             },
             AllocationCreateInfo {
@@ -188,70 +227,261 @@ impl<'a> Vulkan<'a> {
                     | MemoryTypeFilter::HOST_RANDOM_ACCESS,
                 ..Default::default()
             },
-            // Iterator that produces the data.
-            0..65536u32,
+            bytes.iter().copied(),
         )
-        .unwrap();
+        .map_err(|err| RyclError::OutOfDeviceMemory(err.to_string()))?;
+        Ok(Arc::new(VulkanMemory { buffer }))
+    }
+
+    /// Dispatches the kernel and waits for it to finish, so launches are
+    /// complete as soon as this returns.
+    fn launch(
+        &self,
+        module: &Module,
+        buffers: &[BufferRange],
+        num_thread_blocks: u32,
+    ) -> Result<()> {
+        let pipeline = &module
+            .downcast_ref::<VulkanModule>()
+            .ok_or_else(|| foreign("module"))?
+            .pipeline;
+        let buffers = buffers
+            .iter()
+            .map(|range| {
+                let memory = range
+                    .memory
+                    .as_any()
+                    .downcast_ref::<VulkanMemory>()
+                    .ok_or_else(|| foreign("buffer"))?;
+                Ok(memory
+                    .buffer
+                    .clone()
+                    .slice(range.offset..range.offset + range.size))
+            })
+            .collect::<Result<Vec<_>>>()?;
 
         let mut builder = AutoCommandBufferBuilder::primary(
-            &command_buffer_allocator,
-            queue.queue_family_index(),
+            &self.command_buffer_allocator,
+            self.queue.queue_family_index(),
             CommandBufferUsage::OneTimeSubmit,
         )
-        .unwrap();
+        .map_err(dispatch_failed)?;
 
-        // Note that we clone the pipeline and the set. Since they are both wrapped in an `Arc`,
-        // this only clones the `Arc` and not the whole pipeline or set (which aren't cloneable
-        // anyway). In this example we would avoid cloning them since this is the last time we use
-        // them, but in real code you would probably need to clone them.
         builder
             .bind_pipeline_compute(pipeline.clone())
-            .unwrap()
-            .push_descriptor_set(
-                PipelineBindPoint::Compute,
-                pipeline.layout().clone(),
-                0,
-                [WriteDescriptorSet::buffer(0, data_buffer.clone())]
-                    .into_iter()
-                    .collect(),
+            .map_err(dispatch_failed)?;
+        // A kernel without arguments has no descriptor set to bind.
+        if !buffers.is_empty() {
+            let layout = pipeline.layout().set_layouts()[0].clone();
+            let set = PersistentDescriptorSet::new(
+                &self.descriptor_set_allocator,
+                layout,
+                buffers.iter().enumerate().map(|(binding, buffer)| {
+                    WriteDescriptorSet::buffer(binding as u32, buffer.clone())
+                }),
+                [],
             )
-            .unwrap();
+            .map_err(dispatch_failed)?;
+            builder
+                .bind_descriptor_sets(
+                    PipelineBindPoint::Compute,
+                    pipeline.layout().clone(),
+                    0,
+                    set,
+                )
+                .map_err(dispatch_failed)?;
+        }
+        builder
+            .dispatch([num_thread_blocks, 1, 1])
+            .map_err(dispatch_failed)?;
 
         // Finish building the command buffer by calling `build`.
-        let command_buffer = builder.build().unwrap();
+        let command_buffer = builder.build().map_err(dispatch_failed)?;
 
         // Let's execute this command buffer now.
-        let future = sync::now(device)
-            .then_execute(queue, command_buffer)
-            .unwrap()
+        let future = sync::now(self.device.clone())
+            .then_execute(self.queue.clone(), command_buffer)
+            .map_err(dispatch_failed)?
             // This line instructs the GPU to signal a *fence* once the command buffer has finished
             // execution. A fence is a Vulkan object that allows the CPU to know when the GPU has
             // reached a certain point. We need to signal a fence here because below we want to block
             // the CPU until the GPU has reached that point in the execution.
             .then_signal_fence_and_flush()
-            .unwrap();
-
-        // Blocks execution until the GPU has finished the operation. This method only exists on the
-        // future that corresponds to a signalled fence. In other words, this method wouldn't be
-        // available if we didn't call `.then_signal_fence_and_flush()` earlier. The `None` parameter
-        // is an optional timeout.
-        //
-        // Note however that dropping the `future` variable (with `drop(future)` for example) would
-        // block execution as well, and this would be the case even if we didn't call
-        // `.then_signal_fence_and_flush()`. Therefore the actual point of calling
-        // `.then_signal_fence_and_flush()` and `.wait()` is to make things more explicit. In the
-        // future, if the Rust language gets linear types vulkano may get modified so that only
-        // fence-signalled futures can get destroyed like this.
-        future.wait(None).unwrap();
-
-        // Now that the GPU is done, the content of the buffer should have been modified. Let's check
-        // it out. The call to `read()` would return an error if the buffer was still in use by the
-        // GPU.
-        let data_buffer_content = data_buffer.read().unwrap();
-        for n in 0..65536u32 {
-            assert_eq!(data_buffer_content[n as usize], n * 12);
-        }
+            .map_err(dispatch_failed)?;
+
+        // Blocks execution until the GPU has finished the operation. The `None` parameter is an
+        // optional timeout.
+        future.wait(None).map_err(dispatch_failed)
+    }
+
+    fn synchronize(&self) -> Result<()> {
+        // `launch` already waits for its dispatch to finish.
+        Ok(())
+    }
+}
+
+const DEVICE_EXTENSIONS: DeviceExtensions = DeviceExtensions {
+    khr_storage_buffer_storage_class: true,
+    ..DeviceExtensions::empty()
+};
+
+/// Extensions for the 8- and 16-bit types, enabled when they are supported.
+/// They are core in Vulkan 1.2.
+const OPTIONAL_DEVICE_EXTENSIONS: DeviceExtensions = DeviceExtensions {
+    khr_8bit_storage: true,
+    khr_16bit_storage: true,
+    khr_shader_float16_int8: true,
+    ext_shader_atomic_float: true,
+    ..DeviceExtensions::empty()
+};
 
-        println!("Success");
+/// Features for the scalar types beyond 32 bits and for `f32` atomic
+/// additions, enabled when they are supported.
+const OPTIONAL_FEATURES: Features = Features {
+    shader_float16: true,
+    shader_float64: true,
+    shader_int8: true,
+    shader_int16: true,
+    shader_int64: true,
+    storage_buffer8_bit_access: true,
+    storage_buffer16_bit_access: true,
+    shader_buffer_float32_atomic_add: true,
+    shader_shared_float32_atomic_add: true,
+    ..Features::empty()
+};
+
+fn instance() -> Result<Arc<Instance>> {
+    let library = VulkanLibrary::new().map_err(|err| RyclError::NoVulkanLoader(err.to_string()))?;
+    let instance_create_info = InstanceCreateInfo {
+        flags: InstanceCreateFlags::ENUMERATE_PORTABILITY,
+        ..Default::default()
+    };
+    Instance::new(library, instance_create_info)
+        .map_err(|err| RyclError::NoVulkanLoader(err.to_string()))
+}
+
+/// Devices with the required extensions and a compute queue, with the index
+/// of that queue's family.
+fn usable_devices(instance: &Arc<Instance>) -> Result<Vec<(Arc<PhysicalDevice>, u32, DeviceInfo)>> {
+    Ok(instance
+        .enumerate_physical_devices()
+        .map_err(|_| RyclError::NoComputeDevice)?
+        .filter(|p| p.supported_extensions().contains(&DEVICE_EXTENSIONS))
+        .filter_map(|p| {
+            // The Vulkan specs guarantee that a compliant implementation must provide at least one
+            // queue that supports compute operations.
+            let queue_family_index = p
+                .queue_family_properties()
+                .iter()
+                .position(|q| q.queue_flags.intersects(QueueFlags::COMPUTE))?;
+            Some((p, queue_family_index as u32))
+        })
+        .enumerate()
+        .map(|(index, (p, queue_family_index))| {
+            let info = device_info(index, &p);
+            (p, queue_family_index, info)
+        })
+        .collect())
+}
+
+fn device_info(index: usize, physical_device: &PhysicalDevice) -> DeviceInfo {
+    let properties = physical_device.properties();
+    DeviceInfo {
+        index,
+        name: properties.device_name.clone(),
+        vendor_id: properties.vendor_id,
+        device_type: match properties.device_type {
+            PhysicalDeviceType::DiscreteGpu => DeviceType::DiscreteGpu,
+            PhysicalDeviceType::IntegratedGpu => DeviceType::IntegratedGpu,
+            PhysicalDeviceType::VirtualGpu => DeviceType::VirtualGpu,
+            PhysicalDeviceType::Cpu => DeviceType::Cpu,
+            _ => DeviceType::Other,
+        },
+        memory_size: physical_device
+            .memory_properties()
+            .memory_heaps
+            .iter()
+            .filter(|heap| heap.flags.intersects(MemoryHeapFlags::DEVICE_LOCAL))
+            .map(|heap| heap.size)
+            .sum(),
+        limits: DeviceLimits {
+            max_thread_block_size: properties.max_compute_work_group_size[0]
+                .min(properties.max_compute_work_group_invocations),
+            max_thread_blocks: properties.max_compute_work_group_count[0],
+            max_buffer_size: u64::from(properties.max_storage_buffer_range),
+            max_kernel_args: properties.max_per_stage_descriptor_storage_buffers,
+            min_buffer_offset_alignment: properties
+                .min_storage_buffer_offset_alignment
+                .as_devicesize(),
+            subgroup_size: properties.subgroup_size.unwrap_or(1),
+        },
+        features: device_features(physical_device),
+    }
+}
+
+/// The scalar types and operations `physical_device` can run. 8- and
+/// 16-bit types must also be accessible in storage buffers, `f32` atomic
+/// additions must work on both buffers and shared arrays, and every
+/// subgroup operation kernels use must be available to compute shaders.
+fn device_features(physical_device: &PhysicalDevice) -> DeviceFeatures {
+    let supported = physical_device.supported_features();
+    let properties = physical_device.properties();
+    let subgroup_operations = SubgroupFeatures::BASIC
+        | SubgroupFeatures::ARITHMETIC
+        | SubgroupFeatures::BALLOT
+        | SubgroupFeatures::SHUFFLE;
+    DeviceFeatures {
+        float16: supported.shader_float16 && supported.storage_buffer16_bit_access,
+        float64: supported.shader_float64,
+        int8: supported.shader_int8 && supported.storage_buffer8_bit_access,
+        int16: supported.shader_int16 && supported.storage_buffer16_bit_access,
+        int64: supported.shader_int64,
+        float32_atomic_add: supported.shader_buffer_float32_atomic_add
+            && supported.shader_shared_float32_atomic_add,
+        subgroups: properties
+            .subgroup_supported_operations
+            .is_some_and(|operations| operations.contains(subgroup_operations))
+            && properties
+                .subgroup_supported_stages
+                .is_some_and(|stages| stages.intersects(ShaderStages::COMPUTE)),
+    }
+}
+
+fn vulkano_usage(usage: BufferUsage) -> buffer::BufferUsage {
+    let mut vulkano_usage = buffer::BufferUsage::empty();
+    if usage.contains(BufferUsage::STORAGE) {
+        vulkano_usage |= buffer::BufferUsage::STORAGE_BUFFER;
+    }
+    if usage.contains(BufferUsage::TRANSFER_SRC) {
+        vulkano_usage |= buffer::BufferUsage::TRANSFER_SRC;
+    }
+    if usage.contains(BufferUsage::TRANSFER_DST) {
+        vulkano_usage |= buffer::BufferUsage::TRANSFER_DST;
+    }
+    vulkano_usage
+}
+
+fn foreign(what: &str) -> RyclError {
+    RyclError::InvalidArgument(format!("{what} was created by another backend"))
+}
+
+fn invalid_spirv(err: impl fmt::Display) -> RyclError {
+    RyclError::InvalidSpirv(err.to_string())
+}
+
+fn dispatch_failed(err: impl fmt::Display) -> RyclError {
+    RyclError::DispatchFailed(err.to_string())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_vulkano_usage() {
+        assert_eq!(
+            vulkano_usage(BufferUsage::STORAGE | BufferUsage::TRANSFER_DST),
+            buffer::BufferUsage::STORAGE_BUFFER | buffer::BufferUsage::TRANSFER_DST
+        );
     }
 }
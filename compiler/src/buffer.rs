@@ -0,0 +1,209 @@
+//! Typed device buffers.
+use std::marker::PhantomData;
+use std::ops::{BitOr, Bound, RangeBounds};
+
+use shared_type::ir::layout;
+use shared_type::DeviceCopy;
+
+use crate::backend::device_ctx::BufferRange;
+use crate::backend::error::{Result, RyclError};
+use crate::context::Context;
+
+/// How a [`DeviceBuffer`] may be used.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct BufferUsage(u32);
+
+impl BufferUsage {
+    /// Bound to kernel arguments.
+    pub const STORAGE: Self = Self(1);
+    /// Source of device-side copies.
+    pub const TRANSFER_SRC: Self = Self(1 << 1);
+    /// Destination of device-side copies.
+    pub const TRANSFER_DST: Self = Self(1 << 2);
+
+    pub fn contains(self, other: Self) -> bool {
+        self.0 & other.0 == other.0
+    }
+}
+
+impl Default for BufferUsage {
+    fn default() -> Self {
+        Self::STORAGE
+    }
+}
+
+impl BitOr for BufferUsage {
+    type Output = Self;
+
+    fn bitor(self, rhs: Self) -> Self {
+        Self(self.0 | rhs.0)
+    }
+}
+
+/// A buffer of `T`s in device memory, stored in their std430 layout.
+///
+/// Slicing a buffer returns a view of the same memory, so writes through a
+/// view are visible in the buffer it was taken from.
+pub struct DeviceBuffer<T> {
+    range: BufferRange,
+    len: usize,
+    usage: BufferUsage,
+    _marker: PhantomData<T>,
+}
+
+impl<T: DeviceCopy> DeviceBuffer<T> {
+    /// Allocates a buffer holding a copy of `values`, which must not be empty.
+    pub fn from_slice(context: &Context, values: &[T], usage: BufferUsage) -> Result<Self> {
+        if values.is_empty() {
+            return Err(RyclError::InvalidArgument(
+                "device buffers cannot be empty".to_string(),
+            ));
+        }
+        let memory = context.backend().allocate(&to_bytes(values), usage)?;
+        Ok(Self {
+            range: BufferRange::new(memory),
+            len: values.len(),
+            usage,
+            _marker: PhantomData,
+        })
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn usage(&self) -> BufferUsage {
+        self.usage
+    }
+
+    /// Copies the buffer into a new vector. Fails if a running kernel is
+    /// using the buffer.
+    pub fn to_vec(&self) -> Result<Vec<T>> {
+        let mut bytes = vec![0; self.range.size as usize];
+        self.range.read(&mut bytes)?;
+        let stride = stride::<T>();
+        Ok((0..self.len)
+            .map(|i| T::read_bytes(&bytes[i * stride..]))
+            .collect())
+    }
+
+    /// Overwrites the buffer with `values`, which must have the buffer's length.
+    pub fn copy_from_host(&self, values: &[T]) -> Result<()> {
+        self.check_len(values.len())?;
+        self.range.write(&to_bytes(values))
+    }
+
+    /// Copies the buffer into `out`, which must have the buffer's length.
+    pub fn copy_to_host(&self, out: &mut [T]) -> Result<()> {
+        self.check_len(out.len())?;
+        let mut bytes = vec![0; self.range.size as usize];
+        self.range.read(&mut bytes)?;
+        let stride = stride::<T>();
+        for (i, value) in out.iter_mut().enumerate() {
+            *value = T::read_bytes(&bytes[i * stride..]);
+        }
+        Ok(())
+    }
+
+    fn check_len(&self, len: usize) -> Result<()> {
+        if len != self.len {
+            return Err(RyclError::InvalidArgument(format!(
+                "host slice has {len} elements but the device buffer has {}",
+                self.len
+            )));
+        }
+        Ok(())
+    }
+
+    /// A view of the elements in `range`. A view can only be bound to a
+    /// kernel argument if it starts at a multiple of the device's
+    /// `min_buffer_offset_alignment` bytes.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `range` is empty or out of bounds.
+    pub fn slice(&self, range: impl RangeBounds<usize>) -> Self {
+        let start = match range.start_bound() {
+            Bound::Included(&i) => i,
+            Bound::Excluded(&i) => i + 1,
+            Bound::Unbounded => 0,
+        };
+        let end = match range.end_bound() {
+            Bound::Included(&i) => i + 1,
+            Bound::Excluded(&i) => i,
+            Bound::Unbounded => self.len,
+        };
+        assert!(
+            start < end && end <= self.len,
+            "range {start}..{end} is empty or out of bounds for a buffer of length {}",
+            self.len
+        );
+        let stride = stride::<T>() as u64;
+        Self {
+            range: BufferRange {
+                memory: self.range.memory.clone(),
+                offset: self.range.offset + start as u64 * stride,
+                size: (end - start) as u64 * stride,
+            },
+            len: end - start,
+            usage: self.usage,
+            _marker: PhantomData,
+        }
+    }
+
+    pub(crate) fn range(&self) -> &BufferRange {
+        &self.range
+    }
+}
+
+impl<T> Clone for DeviceBuffer<T> {
+    fn clone(&self) -> Self {
+        Self {
+            range: self.range.clone(),
+            len: self.len,
+            usage: self.usage,
+            _marker: PhantomData,
+        }
+    }
+}
+
+fn stride<T: DeviceCopy>() -> usize {
+    layout::array_stride(&T::kernel_type()) as usize
+}
+
+/// Encodes `values` as an array in device layout.
+pub(crate) fn to_bytes<T: DeviceCopy>(values: &[T]) -> Vec<u8> {
+    let stride = stride::<T>();
+    let mut bytes = vec![0; stride * values.len()];
+    for (i, value) in values.iter().enumerate() {
+        value.write_bytes(&mut bytes[i * stride..]);
+    }
+    bytes
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_usage_flags() {
+        let usage = BufferUsage::STORAGE | BufferUsage::TRANSFER_DST;
+        assert!(usage.contains(BufferUsage::STORAGE));
+        assert!(!usage.contains(BufferUsage::TRANSFER_SRC));
+    }
+
+    #[test]
+    fn test_to_bytes() {
+        assert_eq!(
+            to_bytes(&[[1u32, 2], [3, 4]]),
+            [1u32, 2, 3, 4]
+                .iter()
+                .flat_map(|v| v.to_ne_bytes())
+                .collect::<Vec<_>>()
+        );
+    }
+}
@@ -0,0 +1,144 @@
+//! Long-lived device state shared by queues and buffers.
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, PoisonError};
+
+use shared_type::ir::{Kernel, Type};
+
+use crate::backend::cpu::Cpu;
+use crate::backend::device_ctx::{DeviceCtx, Module};
+use crate::backend::error::Result;
+use crate::backend::vulkan::Vulkan;
+use crate::device::{DeviceInfo, DeviceSelector};
+
+/// A device opened for running kernels.
+///
+/// Cloning a context is cheap: clones share the same device, queue and
+/// allocators, so one context can serve any number of launches. Kernels are
+/// compiled on their first launch and the modules are shared by all clones.
+#[derive(Clone)]
+pub struct Context {
+    backend: Arc<dyn DeviceCtx>,
+    modules: Arc<ModuleCache>,
+}
+
+impl Context {
+    /// Opens the most capable Vulkan device with a compute queue.
+    pub fn new() -> Result<Self> {
+        Ok(Self::from_backend(Arc::new(Vulkan::new()?)))
+    }
+
+    /// Opens the Vulkan device picked by `selector`.
+    pub fn with_device(selector: impl Into<DeviceSelector>) -> Result<Self> {
+        Ok(Self::from_backend(Arc::new(Vulkan::with_device(
+            &selector.into(),
+        )?)))
+    }
+
+    /// Lists the Vulkan devices that can run kernels.
+    pub fn devices() -> Result<Vec<DeviceInfo>> {
+        Vulkan::devices()
+    }
+
+    /// Runs kernels on host threads, without a GPU.
+    pub fn cpu() -> Self {
+        Self::from_backend(Arc::new(Cpu::new()))
+    }
+
+    pub fn from_backend(backend: Arc<dyn DeviceCtx>) -> Self {
+        Self {
+            backend,
+            modules: Arc::default(),
+        }
+    }
+
+    pub fn backend(&self) -> &Arc<dyn DeviceCtx> {
+        &self.backend
+    }
+
+    pub(crate) fn modules(&self) -> &ModuleCache {
+        &self.modules
+    }
+}
+
+/// Compiled modules, one per kernel instantiation and thread block size.
+///
+/// Each instantiation of a generic kernel has its own IR, whose parameters
+/// have the concrete types. Modules are looked up by kernel name and
+/// parameter types, and instantiations that only differ in their bodies are
+/// told apart by comparing the whole IR.
+#[derive(Default)]
+pub(crate) struct ModuleCache {
+    modules: Mutex<HashMap<ModuleKey, Vec<(Kernel, Module)>>>,
+}
+
+/// Kernel name, parameter types and thread block size.
+type ModuleKey = (String, Vec<Type>, u32);
+
+impl ModuleCache {
+    /// The module of `kernel` for thread blocks of `thread_block_size`
+    /// threads, built with `compile` if it has not been yet.
+    pub(crate) fn get_or_compile(
+        &self,
+        kernel: &Kernel,
+        thread_block_size: u32,
+        compile: impl FnOnce() -> Result<Module>,
+    ) -> Result<Module> {
+        let key = (
+            kernel.name.clone(),
+            kernel.params.iter().map(|param| param.ty.clone()).collect(),
+            thread_block_size,
+        );
+        // A panicking compile leaves the cache unchanged, so it stays usable.
+        let mut modules = self.modules.lock().unwrap_or_else(PoisonError::into_inner);
+        let instances = modules.entry(key).or_default();
+        if let Some((_, module)) = instances.iter().find(|(cached, _)| cached == kernel) {
+            return Ok(module.clone());
+        }
+        let module = compile()?;
+        instances.push((kernel.clone(), module.clone()));
+        Ok(module)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use shared_type::ir::{Param, ScalarType};
+
+    fn kernel(ty: ScalarType) -> Kernel {
+        Kernel {
+            name: "k".into(),
+            params: vec![Param {
+                name: "p".into(),
+                ty: Type::Slice(Box::new(Type::Scalar(ty))),
+                mutable: true,
+            }],
+            locals: Vec::new(),
+            shared: Vec::new(),
+            body: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn test_module_cache() {
+        let cache = ModuleCache::default();
+        let mut compiles = 0;
+        let mut compile = |kernel: &Kernel, thread_block_size| {
+            cache
+                .get_or_compile(kernel, thread_block_size, || {
+                    compiles += 1;
+                    Ok(Module::new(()))
+                })
+                .unwrap();
+        };
+        compile(&kernel(ScalarType::F32), 64);
+        compile(&kernel(ScalarType::I32), 64);
+        compile(&kernel(ScalarType::F32), 64);
+        compile(&kernel(ScalarType::F32), 32);
+        let mut other = kernel(ScalarType::F32);
+        other.body.push(shared_type::ir::Stmt::Return);
+        compile(&other, 64);
+        compile(&kernel(ScalarType::I32), 64);
+        assert_eq!(compiles, 4);
+    }
+}
@@ -0,0 +1,163 @@
+//! Device enumeration and selection.
+use std::fmt;
+use std::sync::Arc;
+
+use crate::backend::device_ctx::{DeviceFeatures, DeviceLimits};
+
+/// Kind of a physical device.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum DeviceType {
+    DiscreteGpu,
+    IntegratedGpu,
+    VirtualGpu,
+    Cpu,
+    Other,
+}
+
+/// Description of a device that can run kernels.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DeviceInfo {
+    /// Position of the device in the list returned by
+    /// [`Context::devices`](crate::Context::devices).
+    pub index: usize,
+    pub name: String,
+    /// PCI vendor id, such as `0x10de` for NVIDIA.
+    pub vendor_id: u32,
+    pub device_type: DeviceType,
+    /// Size of device-local memory, in bytes.
+    pub memory_size: u64,
+    pub limits: DeviceLimits,
+    pub features: DeviceFeatures,
+}
+
+/// Which device a context opens.
+#[derive(Clone, Default)]
+pub enum DeviceSelector {
+    /// The most capable device: discrete GPUs are preferred over integrated
+    /// ones, then virtual GPUs and CPUs.
+    #[default]
+    Best,
+    /// The device at this [`DeviceInfo::index`].
+    Index(usize),
+    /// The first device whose name contains this string, ignoring case.
+    Name(String),
+    /// The first device of this type.
+    Type(DeviceType),
+    /// The first device accepted by the predicate.
+    Predicate(Arc<dyn Fn(&DeviceInfo) -> bool + Send + Sync>),
+}
+
+impl DeviceSelector {
+    pub fn predicate(f: impl Fn(&DeviceInfo) -> bool + Send + Sync + 'static) -> Self {
+        Self::Predicate(Arc::new(f))
+    }
+
+    /// The device picked from `devices`, if any matches.
+    pub fn select<'d>(&self, devices: &'d [DeviceInfo]) -> Option<&'d DeviceInfo> {
+        let mut devices = devices.iter();
+        match self {
+            DeviceSelector::Best => devices.min_by_key(|d| match d.device_type {
+                DeviceType::DiscreteGpu => 0,
+                DeviceType::IntegratedGpu => 1,
+                DeviceType::VirtualGpu => 2,
+                DeviceType::Cpu => 3,
+                DeviceType::Other => 4,
+            }),
+            DeviceSelector::Index(index) => devices.find(|d| d.index == *index),
+            DeviceSelector::Name(name) => {
+                let name = name.to_lowercase();
+                devices.find(|d| d.name.to_lowercase().contains(&name))
+            }
+            DeviceSelector::Type(ty) => devices.find(|d| d.device_type == *ty),
+            DeviceSelector::Predicate(f) => devices.find(|d| f(d)),
+        }
+    }
+}
+
+impl fmt::Debug for DeviceSelector {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DeviceSelector::Best => write!(f, "Best"),
+            DeviceSelector::Index(index) => f.debug_tuple("Index").field(index).finish(),
+            DeviceSelector::Name(name) => f.debug_tuple("Name").field(name).finish(),
+            DeviceSelector::Type(ty) => f.debug_tuple("Type").field(ty).finish(),
+            DeviceSelector::Predicate(_) => write!(f, "Predicate(..)"),
+        }
+    }
+}
+
+impl From<usize> for DeviceSelector {
+    fn from(index: usize) -> Self {
+        Self::Index(index)
+    }
+}
+
+impl From<&str> for DeviceSelector {
+    fn from(name: &str) -> Self {
+        Self::Name(name.to_string())
+    }
+}
+
+impl From<DeviceType> for DeviceSelector {
+    fn from(ty: DeviceType) -> Self {
+        Self::Type(ty)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn devices() -> Vec<DeviceInfo> {
+        let limits = DeviceLimits {
+            max_thread_block_size: 1024,
+            max_thread_blocks: 65535,
+            max_buffer_size: 1 << 27,
+            max_kernel_args: 16,
+            min_buffer_offset_alignment: 16,
+            subgroup_size: 32,
+        };
+        [
+            ("llvmpipe (LLVM 17.0.6, 256 bits)", DeviceType::Cpu, 0),
+            (
+                "Intel(R) UHD Graphics 630",
+                DeviceType::IntegratedGpu,
+                1 << 30,
+            ),
+            ("NVIDIA GeForce RTX 3080", DeviceType::DiscreteGpu, 10 << 30),
+        ]
+        .into_iter()
+        .enumerate()
+        .map(|(index, (name, device_type, memory_size))| DeviceInfo {
+            index,
+            name: name.to_string(),
+            vendor_id: 0,
+            device_type,
+            memory_size,
+            limits,
+            features: DeviceFeatures::default(),
+        })
+        .collect()
+    }
+
+    #[test]
+    fn test_select() {
+        let devices = devices();
+        let selected = |selector: DeviceSelector| selector.select(&devices).map(|d| d.index);
+        assert_eq!(selected(DeviceSelector::Best), Some(2));
+        assert_eq!(selected(1.into()), Some(1));
+        assert_eq!(selected(3.into()), None);
+        assert_eq!(selected("geforce".into()), Some(2));
+        assert_eq!(selected(DeviceType::Cpu.into()), Some(0));
+        assert_eq!(selected(DeviceType::VirtualGpu.into()), None);
+        assert_eq!(
+            selected(DeviceSelector::predicate(|d| d.memory_size >= 1 << 30)),
+            Some(1)
+        );
+    }
+
+    #[test]
+    fn test_best_of_none() {
+        assert!(DeviceSelector::Best.select(&[]).is_none());
+    }
+}
@@ -1 +1,17 @@
 mod backend;
+mod buffer;
+mod context;
+mod device;
+mod queue;
+
+pub use backend::cpu::Cpu;
+pub use backend::device_ctx::{
+    BufferRange, DeviceCtx, DeviceFeatures, DeviceLimits, DeviceMemory, Module,
+};
+pub use backend::error::{Result, RyclError};
+pub use backend::vulkan::Vulkan;
+pub use buffer::{BufferUsage, DeviceBuffer};
+pub use context::Context;
+pub use device::{DeviceInfo, DeviceSelector, DeviceType};
+pub use queue::{KernelArg, LaunchConfig, Queue};
+pub use spirv_codegen::build_spirv;
@@ -0,0 +1,483 @@
+//! Host API for launching kernels.
+use shared_type::ir::{layout, Kernel, Type};
+use shared_type::{DeviceCopy, KernelFn};
+
+use crate::backend::device_ctx::{BufferRange, DeviceFeatures, DeviceLimits};
+use crate::backend::error::{Result, RyclError};
+use crate::buffer::{to_bytes, BufferUsage, DeviceBuffer};
+use crate::context::Context;
+
+/// How many threads a launch runs: `num_thread_blocks` blocks of
+/// `thread_block_size` threads each.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct LaunchConfig {
+    pub num_thread_blocks: u32,
+    pub thread_block_size: u32,
+}
+
+impl From<(u32, u32)> for LaunchConfig {
+    fn from((num_thread_blocks, thread_block_size): (u32, u32)) -> Self {
+        Self {
+            num_thread_blocks,
+            thread_block_size,
+        }
+    }
+}
+
+type ReadBack<'a> = Box<dyn FnOnce(&[u8]) + 'a>;
+
+enum ArgData<'a> {
+    Host {
+        bytes: Vec<u8>,
+        read_back: Option<ReadBack<'a>>,
+    },
+    Device {
+        range: BufferRange,
+        usage: BufferUsage,
+    },
+}
+
+/// A kernel argument: a host slice, or a [`DeviceBuffer`] bound in place.
+///
+/// A slice or buffer of one value binds to a parameter of the value's type,
+/// and a longer one to an array parameter of the same length. Slice
+/// parameters take any non-empty slice or buffer of their element type.
+pub struct KernelArg<'a> {
+    elem: Type,
+    len: usize,
+    data: ArgData<'a>,
+}
+
+impl<'a> KernelArg<'a> {
+    /// An argument the kernel only reads.
+    pub fn input<T: DeviceCopy>(values: &[T]) -> Self {
+        Self {
+            elem: T::kernel_type(),
+            len: values.len(),
+            data: ArgData::Host {
+                bytes: to_bytes(values),
+                read_back: None,
+            },
+        }
+    }
+
+    /// An argument bound to a `mut` parameter. `values` is uploaded before the
+    /// launch and overwritten with the kernel's writes after it.
+    pub fn output<T: DeviceCopy>(values: &'a mut [T]) -> Self {
+        let stride = layout::array_stride(&T::kernel_type()) as usize;
+        Self {
+            elem: T::kernel_type(),
+            len: values.len(),
+            data: ArgData::Host {
+                bytes: to_bytes(values),
+                read_back: Some(Box::new(move |bytes: &[u8]| {
+                    for (i, value) in values.iter_mut().enumerate() {
+                        *value = T::read_bytes(&bytes[i * stride..]);
+                    }
+                })),
+            },
+        }
+    }
+
+    /// An argument that stays in device memory. The kernel reads and writes
+    /// `buffer` directly, so no copies are made before or after the launch.
+    ///
+    /// The launch fails if the buffer was created without
+    /// [`BufferUsage::STORAGE`], or if it is a view that does not start at a
+    /// multiple of the device's `min_buffer_offset_alignment`.
+    pub fn buffer<T: DeviceCopy>(buffer: &DeviceBuffer<T>) -> Self {
+        Self {
+            elem: T::kernel_type(),
+            len: buffer.len(),
+            data: ArgData::Device {
+                range: buffer.range().clone(),
+                usage: buffer.usage(),
+            },
+        }
+    }
+
+    fn matches(&self, ty: &Type) -> bool {
+        match ty {
+            Type::Array(elem, len) if **elem == self.elem => *len as usize == self.len,
+            // Buffers cannot be empty, so neither can slices.
+            Type::Slice(elem) if **elem == self.elem => self.len > 0,
+            _ => self.len == 1 && *ty == self.elem,
+        }
+    }
+}
+
+/// Launches kernels on the device of a [`Context`].
+pub struct Queue {
+    context: Context,
+}
+
+impl Queue {
+    pub fn new(context: &Context) -> Self {
+        Self {
+            context: context.clone(),
+        }
+    }
+
+    /// Runs `kernel` with one argument per kernel parameter, in declaration
+    /// order, and waits for it to finish. Arguments created with
+    /// [`KernelArg::output`] hold the kernel's results afterwards.
+    ///
+    /// `kernel` is the handle generated by `#[kernel_fn]`, or a lowered
+    /// [`Kernel`]. It is compiled on its first launch in this queue's
+    /// context. Each instantiation of a generic kernel, such as
+    /// `ScaleKernel::<f32>::new()`, is compiled to its own module. Kernels
+    /// embedding their SPIR-V skip code generation.
+    pub fn launch<'a, K: KernelFn + ?Sized>(
+        &self,
+        kernel: &K,
+        args: impl IntoIterator<Item = KernelArg<'a>>,
+        config: impl Into<LaunchConfig>,
+    ) -> Result<()> {
+        let config = config.into();
+        let args: Vec<KernelArg<'a>> = args.into_iter().collect();
+        let backend = self.context.backend();
+        if let Some(size) = kernel.workgroup_size() {
+            if config.thread_block_size != size {
+                return Err(RyclError::InvalidArgument(format!(
+                    "kernel `{}` must be launched with thread blocks of {size} threads, got {}",
+                    kernel.entry_point(),
+                    config.thread_block_size
+                )));
+            }
+        }
+        let spirv = kernel.spirv();
+        let kernel = &kernel.module()?;
+        check_args(kernel, &args)?;
+        check_config(&backend.limits(), &config, args.len())?;
+        check_bindings(&backend.limits(), kernel, &args)?;
+
+        let modules = self.context.modules();
+        let module = modules.get_or_compile(kernel, config.thread_block_size, || {
+            check_features(&backend.features(), kernel)?;
+            match spirv {
+                Some(spirv) => backend.load_spirv(kernel, spirv, config.thread_block_size),
+                None => backend.compile(kernel, config.thread_block_size),
+            }
+        })?;
+        let mut buffers = Vec::with_capacity(args.len());
+        let mut read_backs = Vec::new();
+        for arg in args {
+            match arg.data {
+                ArgData::Host { bytes, read_back } => {
+                    let buffer = BufferRange::new(backend.allocate(&bytes, BufferUsage::STORAGE)?);
+                    if let Some(read_back) = read_back {
+                        read_backs.push((buffer.clone(), read_back));
+                    }
+                    buffers.push(buffer);
+                }
+                ArgData::Device { range, .. } => buffers.push(range),
+            }
+        }
+        backend.launch(&module, &buffers, config.num_thread_blocks)?;
+        if !read_backs.is_empty() {
+            backend.synchronize()?;
+        }
+        for (buffer, read_back) in read_backs {
+            let mut bytes = vec![0; buffer.size as usize];
+            buffer.read(&mut bytes)?;
+            read_back(&bytes);
+        }
+        Ok(())
+    }
+
+    /// Waits for every launch on this queue's device to finish.
+    pub fn synchronize(&self) -> Result<()> {
+        self.context.backend().synchronize()
+    }
+}
+
+fn check_config(limits: &DeviceLimits, config: &LaunchConfig, num_args: usize) -> Result<()> {
+    if config.thread_block_size == 0 || config.thread_block_size > limits.max_thread_block_size {
+        return Err(RyclError::InvalidArgument(format!(
+            "thread block size {} is not in 1..={}",
+            config.thread_block_size, limits.max_thread_block_size
+        )));
+    }
+    if config.num_thread_blocks > limits.max_thread_blocks {
+        return Err(RyclError::InvalidArgument(format!(
+            "{} thread blocks exceed the device limit of {}",
+            config.num_thread_blocks, limits.max_thread_blocks
+        )));
+    }
+    if num_args > limits.max_kernel_args as usize {
+        return Err(RyclError::InvalidArgument(format!(
+            "{num_args} kernel arguments exceed the device limit of {}",
+            limits.max_kernel_args
+        )));
+    }
+    Ok(())
+}
+
+/// Checks that every argument fits in a storage buffer of the device, and
+/// that the device buffers can be bound to the kernel where they start.
+fn check_bindings(limits: &DeviceLimits, kernel: &Kernel, args: &[KernelArg]) -> Result<()> {
+    for (param, arg) in kernel.params.iter().zip(args) {
+        let size = match &arg.data {
+            ArgData::Host { bytes, .. } => bytes.len() as u64,
+            ArgData::Device { range, usage } => {
+                if !usage.contains(BufferUsage::STORAGE) {
+                    return Err(RyclError::InvalidArgument(format!(
+                        "buffer bound to argument `{}` of kernel `{}` lacks `BufferUsage::STORAGE`",
+                        param.name, kernel.name
+                    )));
+                }
+                if range.offset % limits.min_buffer_offset_alignment != 0 {
+                    return Err(RyclError::InvalidArgument(format!(
+                        "buffer bound to argument `{}` of kernel `{}` starts at byte {}, \
+                         which is not a multiple of the device's offset alignment of {}",
+                        param.name, kernel.name, range.offset, limits.min_buffer_offset_alignment
+                    )));
+                }
+                range.size
+            }
+        };
+        if size > limits.max_buffer_size {
+            return Err(RyclError::InvalidArgument(format!(
+                "argument `{}` of kernel `{}` takes {size} bytes, more than the device limit of {}",
+                param.name, kernel.name, limits.max_buffer_size
+            )));
+        }
+    }
+    Ok(())
+}
+
+/// Checks that the device supports every scalar type and atomic operation
+/// of the type-checked `kernel`.
+fn check_features(features: &DeviceFeatures, kernel: &Kernel) -> Result<()> {
+    if let Some(ty) = kernel
+        .scalar_types()
+        .into_iter()
+        .find(|ty| !features.supports(*ty))
+    {
+        return Err(RyclError::UnsupportedType(format!(
+            "kernel `{}` uses `{}`, which the device does not support",
+            kernel.name,
+            ty.rust_name()
+        )));
+    }
+    if kernel.uses_float_atomics() && !features.float32_atomic_add {
+        return Err(RyclError::UnsupportedType(format!(
+            "kernel `{}` adds to `f32` values atomically, which the device does not support",
+            kernel.name
+        )));
+    }
+    if kernel.uses_subgroups() && !features.subgroups {
+        return Err(RyclError::UnsupportedType(format!(
+            "kernel `{}` uses subgroup operations, which the device does not support",
+            kernel.name
+        )));
+    }
+    Ok(())
+}
+
+fn check_args(kernel: &Kernel, args: &[KernelArg]) -> Result<()> {
+    if args.len() != kernel.params.len() {
+        return Err(RyclError::InvalidArgument(format!(
+            "kernel `{}` takes {} arguments but {} were given",
+            kernel.name,
+            kernel.params.len(),
+            args.len()
+        )));
+    }
+    for (param, arg) in kernel.params.iter().zip(args) {
+        if !arg.matches(&param.ty) {
+            return Err(RyclError::InvalidArgument(format!(
+                "argument `{}` of kernel `{}` has type {:?}, got {} value(s) of type {:?}",
+                param.name, kernel.name, param.ty, arg.len, arg.elem
+            )));
+        }
+        if let ArgData::Host { read_back, .. } = &arg.data {
+            if param.mutable != read_back.is_some() {
+                return Err(RyclError::InvalidArgument(format!(
+                    "argument `{}` of kernel `{}` must be passed with `KernelArg::{}`",
+                    param.name,
+                    kernel.name,
+                    if param.mutable { "output" } else { "input" }
+                )));
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use shared_type::ir::{AtomicOp, Builtin, Expr, Literal, Param, Place, ScalarType, Stmt};
+
+    fn kernel(params: Vec<Param>) -> Kernel {
+        Kernel {
+            name: "k".into(),
+            params,
+            locals: Vec::new(),
+            shared: Vec::new(),
+            body: Vec::new(),
+        }
+    }
+
+    fn param(ty: Type, mutable: bool) -> Param {
+        Param {
+            name: "p".into(),
+            ty,
+            mutable,
+        }
+    }
+
+    #[test]
+    fn test_check_args() {
+        let i32_ty = Type::Scalar(ScalarType::I32);
+        let array_ty = Type::Array(Box::new(i32_ty.clone()), 2);
+        let k = kernel(vec![param(i32_ty, false), param(array_ty, true)]);
+
+        let mut out = [0i32; 2];
+        assert!(check_args(
+            &k,
+            &[KernelArg::input(&[1i32]), KernelArg::output(&mut out)]
+        )
+        .is_ok());
+        assert!(check_args(&k, &[KernelArg::input(&[1i32])]).is_err());
+        assert!(check_args(
+            &k,
+            &[KernelArg::input(&[1u32]), KernelArg::output(&mut out)]
+        )
+        .is_err());
+        assert!(check_args(&k, &[KernelArg::input(&[1i32]), KernelArg::input(&out)]).is_err());
+        let mut short = [0i32; 1];
+        assert!(check_args(
+            &k,
+            &[KernelArg::input(&[1i32]), KernelArg::output(&mut short)]
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_check_args_slice() {
+        let f32_ty = Type::Scalar(ScalarType::F32);
+        let k = kernel(vec![param(Type::Slice(Box::new(f32_ty)), true)]);
+
+        let mut out = [0f32; 5];
+        assert!(check_args(&k, &[KernelArg::output(&mut out)]).is_ok());
+        assert!(check_args(&k, &[KernelArg::output(&mut out[..1])]).is_ok());
+        assert!(check_args(&k, &[KernelArg::output(&mut [0f32; 0])]).is_err());
+        assert!(check_args(&k, &[KernelArg::output(&mut [0u32; 5])]).is_err());
+    }
+
+    #[test]
+    fn test_check_args_error() {
+        let k = kernel(vec![param(Type::Scalar(ScalarType::U32), false)]);
+        let result = check_args(&k, &[]);
+        assert!(matches!(result, Err(RyclError::InvalidArgument(_))));
+    }
+
+    #[test]
+    fn test_check_config() {
+        let limits = DeviceLimits {
+            max_thread_block_size: 256,
+            max_thread_blocks: 1024,
+            max_buffer_size: 1 << 20,
+            max_kernel_args: 4,
+            min_buffer_offset_alignment: 16,
+            subgroup_size: 32,
+        };
+        assert!(check_config(&limits, &(1024, 256).into(), 4).is_ok());
+        assert!(check_config(&limits, &(1, 0).into(), 0).is_err());
+        assert!(check_config(&limits, &(1, 512).into(), 0).is_err());
+        assert!(check_config(&limits, &(2048, 1).into(), 0).is_err());
+        assert!(check_config(&limits, &(1, 1).into(), 5).is_err());
+    }
+
+    #[test]
+    fn test_check_bindings() {
+        let limits = DeviceLimits {
+            max_thread_block_size: 256,
+            max_thread_blocks: 1024,
+            max_buffer_size: 64,
+            max_kernel_args: 4,
+            min_buffer_offset_alignment: 16,
+            subgroup_size: 32,
+        };
+        let u32_ty = Type::Scalar(ScalarType::U32);
+        let k = kernel(vec![param(Type::Slice(Box::new(u32_ty)), false)]);
+        let context = Context::cpu();
+        let buffer = DeviceBuffer::from_slice(&context, &[0u32; 16], BufferUsage::STORAGE).unwrap();
+        let check = |arg| check_bindings(&limits, &k, &[arg]);
+
+        assert!(check(KernelArg::buffer(&buffer)).is_ok());
+        assert!(check(KernelArg::buffer(&buffer.slice(4..))).is_ok());
+        let result = check(KernelArg::buffer(&buffer.slice(1..)));
+        assert!(matches!(result, Err(RyclError::InvalidArgument(msg)) if msg.contains("byte 4")));
+
+        assert!(check(KernelArg::input(&[0u32; 16])).is_ok());
+        assert!(check(KernelArg::input(&[0u32; 17])).is_err());
+
+        let staging =
+            DeviceBuffer::from_slice(&context, &[0u32; 4], BufferUsage::TRANSFER_SRC).unwrap();
+        let result = check(KernelArg::buffer(&staging));
+        assert!(matches!(result, Err(RyclError::InvalidArgument(msg)) if msg.contains("STORAGE")));
+    }
+
+    #[test]
+    fn test_check_features() {
+        let k = kernel(vec![
+            param(Type::Scalar(ScalarType::U32), false),
+            param(Type::Slice(Box::new(Type::Scalar(ScalarType::F64))), true),
+        ]);
+        assert!(check_features(&DeviceFeatures::ALL, &k).is_ok());
+        let result = check_features(&DeviceFeatures::default(), &k);
+        assert!(matches!(result, Err(RyclError::UnsupportedType(msg)) if msg.contains("`f64`")));
+        let float64 = DeviceFeatures {
+            float64: true,
+            ..Default::default()
+        };
+        assert!(check_features(&float64, &k).is_ok());
+    }
+
+    #[test]
+    fn test_check_float_atomics() {
+        let f32_ty = Type::Scalar(ScalarType::F32);
+        let mut k = kernel(vec![param(Type::Slice(Box::new(f32_ty)), true)]);
+        k.body = vec![Stmt::Expr(Expr::Atomic(
+            AtomicOp::Add,
+            Place::Index(
+                Box::new(Place::Param(0)),
+                Box::new(Expr::Literal(Literal::Int(0, Some(ScalarType::U32)))),
+            ),
+            vec![Expr::Literal(Literal::Float(1.0, Some(ScalarType::F32)))],
+        ))];
+        assert!(check_features(&DeviceFeatures::ALL, &k).is_ok());
+        let result = check_features(&DeviceFeatures::default(), &k);
+        assert!(
+            matches!(result, Err(RyclError::UnsupportedType(msg)) if msg.contains("atomically"))
+        );
+    }
+
+    #[test]
+    fn test_check_subgroups() {
+        let mut k = kernel(vec![]);
+        k.body = vec![Stmt::Expr(Expr::Builtin(Builtin::SubgroupSize))];
+        assert!(check_features(&DeviceFeatures::ALL, &k).is_ok());
+        let result = check_features(&DeviceFeatures::default(), &k);
+        assert!(matches!(result, Err(RyclError::UnsupportedType(msg)) if msg.contains("subgroup")));
+    }
+
+    #[test]
+    fn test_output_reads_back() {
+        let mut values = [1.0f32, 2.0];
+        let ArgData::Host {
+            mut bytes,
+            read_back,
+        } = KernelArg::output(&mut values).data
+        else {
+            unreachable!()
+        };
+        assert_eq!(bytes.len(), 8);
+        bytes[..4].copy_from_slice(&5.0f32.to_ne_bytes());
+        read_back.unwrap()(&bytes);
+        assert_eq!(values, [5.0, 2.0]);
+    }
+}
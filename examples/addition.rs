@@ -1,11 +1,26 @@
+use compiler::{Context, KernelArg, Queue, RyclError};
 use rycl_derive::kernel_fn;
 
 #[kernel_fn]
-fn add(a: i32, b: i32, num_thread_blocks: u32, thread_block_size: u32) -> i32 {
-    a + b
+fn add(a: i32, b: i32, mut c: [i32; 1], num_thread_blocks: u32, thread_block_size: u32) {
+    if num_thread_blocks * thread_block_size == 1 {
+        c[0] = a + b;
+    }
 }
 
-fn main() {
-    let result = add(1, 1, 1, 1);
-    println!("Addition result from kernel: {}", result);
+fn main() -> Result<(), RyclError> {
+    let mut c = [0];
+    let context = Context::new()?;
+    let queue = Queue::new(&context);
+    queue.launch(
+        &AddKernel,
+        [
+            KernelArg::input(&[1]),
+            KernelArg::input(&[2]),
+            KernelArg::output(&mut c),
+        ],
+        (1, 1),
+    )?;
+    println!("1 + 2 = {}", c[0]);
+    Ok(())
 }
@@ -0,0 +1,341 @@
+//! Conversion of kernel IR back into Rust tokens that rebuild it at runtime.
+use proc_macro2::{Literal as LitToken, TokenStream};
+use quote::{format_ident, quote};
+use shared_type::ir::{
+    AtomicOp, BinaryOp, Builtin, Expr, Field, Function, Kernel, Literal, Local, LocalId, Param,
+    Place, ScalarType, Shared, Stmt, StructType, SubgroupOp, SyncOp, Type, UnaryOp,
+};
+
+pub(crate) trait ToIrTokens {
+    fn to_ir_tokens(&self) -> TokenStream;
+}
+
+impl<T: ToIrTokens> ToIrTokens for Vec<T> {
+    fn to_ir_tokens(&self) -> TokenStream {
+        let items = self.iter().map(ToIrTokens::to_ir_tokens);
+        quote! { ::std::vec![#(#items),*] }
+    }
+}
+
+impl<T: ToIrTokens> ToIrTokens for Option<T> {
+    fn to_ir_tokens(&self) -> TokenStream {
+        match self {
+            Some(value) => {
+                let value = value.to_ir_tokens();
+                quote! { ::std::option::Option::Some(#value) }
+            }
+            None => quote! { ::std::option::Option::None },
+        }
+    }
+}
+
+impl<T: ToIrTokens> ToIrTokens for Box<T> {
+    fn to_ir_tokens(&self) -> TokenStream {
+        let value = (**self).to_ir_tokens();
+        quote! { ::std::boxed::Box::new(#value) }
+    }
+}
+
+impl ToIrTokens for String {
+    fn to_ir_tokens(&self) -> TokenStream {
+        quote! { ::std::string::String::from(#self) }
+    }
+}
+
+// Fieldless enums are spelled the same in the macro and in the generated code.
+macro_rules! unit_enum_tokens {
+    ($($ty:ident),*) => {
+        $(impl ToIrTokens for $ty {
+            fn to_ir_tokens(&self) -> TokenStream {
+                let variant = format_ident!("{}", format!("{:?}", self));
+                quote! { ::shared_type::ir::$ty::#variant }
+            }
+        })*
+    };
+}
+
+unit_enum_tokens!(ScalarType, Builtin, SyncOp, AtomicOp, SubgroupOp, UnaryOp, BinaryOp, Function);
+
+impl ToIrTokens for LocalId {
+    fn to_ir_tokens(&self) -> TokenStream {
+        let id = self.0;
+        quote! { ::shared_type::ir::LocalId(#id) }
+    }
+}
+
+impl ToIrTokens for Type {
+    fn to_ir_tokens(&self) -> TokenStream {
+        match self {
+            Type::Scalar(scalar) => {
+                let scalar = scalar.to_ir_tokens();
+                quote! { ::shared_type::ir::Type::Scalar(#scalar) }
+            }
+            Type::Array(elem, len) => {
+                let elem = elem.to_ir_tokens();
+                quote! { ::shared_type::ir::Type::Array(#elem, #len) }
+            }
+            Type::Slice(elem) => {
+                let elem = elem.to_ir_tokens();
+                quote! { ::shared_type::ir::Type::Slice(#elem) }
+            }
+            Type::Struct(s) => {
+                let s = s.to_ir_tokens();
+                quote! { ::shared_type::ir::Type::Struct(#s) }
+            }
+            Type::Vector(scalar, len) => {
+                let scalar = scalar.to_ir_tokens();
+                quote! { ::shared_type::ir::Type::Vector(#scalar, #len) }
+            }
+            Type::Matrix(scalar, len) => {
+                let scalar = scalar.to_ir_tokens();
+                quote! { ::shared_type::ir::Type::Matrix(#scalar, #len) }
+            }
+            Type::Named(name) => {
+                let ty: syn::Type =
+                    syn::parse_str(name).expect("named kernel types come from parsed syntax");
+                quote! { <#ty as ::shared_type::KernelType>::kernel_type() }
+            }
+        }
+    }
+}
+
+impl ToIrTokens for StructType {
+    fn to_ir_tokens(&self) -> TokenStream {
+        let name = self.name.to_ir_tokens();
+        let fields = self.fields.to_ir_tokens();
+        quote! { ::shared_type::ir::StructType { name: #name, fields: #fields } }
+    }
+}
+
+impl ToIrTokens for Field {
+    fn to_ir_tokens(&self) -> TokenStream {
+        let name = self.name.to_ir_tokens();
+        let ty = self.ty.to_ir_tokens();
+        quote! { ::shared_type::ir::Field { name: #name, ty: #ty } }
+    }
+}
+
+impl ToIrTokens for Kernel {
+    fn to_ir_tokens(&self) -> TokenStream {
+        let name = self.name.to_ir_tokens();
+        let params = self.params.to_ir_tokens();
+        let locals = self.locals.to_ir_tokens();
+        let shared = self.shared.to_ir_tokens();
+        let body = self.body.to_ir_tokens();
+        quote! {
+            ::shared_type::ir::Kernel {
+                name: #name,
+                params: #params,
+                locals: #locals,
+                shared: #shared,
+                body: #body,
+            }
+        }
+    }
+}
+
+impl ToIrTokens for Param {
+    fn to_ir_tokens(&self) -> TokenStream {
+        let name = self.name.to_ir_tokens();
+        let ty = self.ty.to_ir_tokens();
+        let mutable = self.mutable;
+        quote! { ::shared_type::ir::Param { name: #name, ty: #ty, mutable: #mutable } }
+    }
+}
+
+impl ToIrTokens for Local {
+    fn to_ir_tokens(&self) -> TokenStream {
+        let name = self.name.to_ir_tokens();
+        let ty = self.ty.to_ir_tokens();
+        quote! { ::shared_type::ir::Local { name: #name, ty: #ty } }
+    }
+}
+
+impl ToIrTokens for Shared {
+    fn to_ir_tokens(&self) -> TokenStream {
+        let name = self.name.to_ir_tokens();
+        let ty = self.ty.to_ir_tokens();
+        quote! { ::shared_type::ir::Shared { name: #name, ty: #ty } }
+    }
+}
+
+impl ToIrTokens for Stmt {
+    fn to_ir_tokens(&self) -> TokenStream {
+        match self {
+            Stmt::Let { local, init } => {
+                let local = local.to_ir_tokens();
+                let init = init.to_ir_tokens();
+                quote! { ::shared_type::ir::Stmt::Let { local: #local, init: #init } }
+            }
+            Stmt::Assign { place, value } => {
+                let place = place.to_ir_tokens();
+                let value = value.to_ir_tokens();
+                quote! { ::shared_type::ir::Stmt::Assign { place: #place, value: #value } }
+            }
+            Stmt::If {
+                cond,
+                then_block,
+                else_block,
+            } => {
+                let cond = cond.to_ir_tokens();
+                let then_block = then_block.to_ir_tokens();
+                let else_block = else_block.to_ir_tokens();
+                quote! {
+                    ::shared_type::ir::Stmt::If {
+                        cond: #cond,
+                        then_block: #then_block,
+                        else_block: #else_block,
+                    }
+                }
+            }
+            Stmt::For {
+                var,
+                start,
+                end,
+                inclusive,
+                body,
+            } => {
+                let var = var.to_ir_tokens();
+                let start = start.to_ir_tokens();
+                let end = end.to_ir_tokens();
+                let body = body.to_ir_tokens();
+                quote! {
+                    ::shared_type::ir::Stmt::For {
+                        var: #var,
+                        start: #start,
+                        end: #end,
+                        inclusive: #inclusive,
+                        body: #body,
+                    }
+                }
+            }
+            Stmt::While { cond, body } => {
+                let cond = cond.to_ir_tokens();
+                let body = body.to_ir_tokens();
+                quote! { ::shared_type::ir::Stmt::While { cond: #cond, body: #body } }
+            }
+            Stmt::Break => quote! { ::shared_type::ir::Stmt::Break },
+            Stmt::Continue => quote! { ::shared_type::ir::Stmt::Continue },
+            Stmt::Return => quote! { ::shared_type::ir::Stmt::Return },
+            Stmt::Sync(op) => {
+                let op = op.to_ir_tokens();
+                quote! { ::shared_type::ir::Stmt::Sync(#op) }
+            }
+            Stmt::Expr(expr) => {
+                let expr = expr.to_ir_tokens();
+                quote! { ::shared_type::ir::Stmt::Expr(#expr) }
+            }
+        }
+    }
+}
+
+impl ToIrTokens for Place {
+    fn to_ir_tokens(&self) -> TokenStream {
+        match self {
+            Place::Param(i) => quote! { ::shared_type::ir::Place::Param(#i) },
+            Place::Local(id) => {
+                let id = id.to_ir_tokens();
+                quote! { ::shared_type::ir::Place::Local(#id) }
+            }
+            Place::Shared(i) => quote! { ::shared_type::ir::Place::Shared(#i) },
+            Place::Index(base, index) => {
+                let base = base.to_ir_tokens();
+                let index = index.to_ir_tokens();
+                quote! { ::shared_type::ir::Place::Index(#base, #index) }
+            }
+            Place::Field(base, name) => {
+                let base = base.to_ir_tokens();
+                let name = name.to_ir_tokens();
+                quote! { ::shared_type::ir::Place::Field(#base, #name) }
+            }
+        }
+    }
+}
+
+impl ToIrTokens for Expr {
+    fn to_ir_tokens(&self) -> TokenStream {
+        match self {
+            Expr::Literal(lit) => {
+                let lit = lit.to_ir_tokens();
+                quote! { ::shared_type::ir::Expr::Literal(#lit) }
+            }
+            Expr::Load(place) => {
+                let place = place.to_ir_tokens();
+                quote! { ::shared_type::ir::Expr::Load(#place) }
+            }
+            Expr::Len(place) => {
+                let place = place.to_ir_tokens();
+                quote! { ::shared_type::ir::Expr::Len(#place) }
+            }
+            Expr::Builtin(builtin) => {
+                let builtin = builtin.to_ir_tokens();
+                quote! { ::shared_type::ir::Expr::Builtin(#builtin) }
+            }
+            Expr::Unary(op, operand) => {
+                let op = op.to_ir_tokens();
+                let operand = operand.to_ir_tokens();
+                quote! { ::shared_type::ir::Expr::Unary(#op, #operand) }
+            }
+            Expr::Binary(op, lhs, rhs) => {
+                let op = op.to_ir_tokens();
+                let lhs = lhs.to_ir_tokens();
+                let rhs = rhs.to_ir_tokens();
+                quote! { ::shared_type::ir::Expr::Binary(#op, #lhs, #rhs) }
+            }
+            Expr::Cast(operand, ty) => {
+                let operand = operand.to_ir_tokens();
+                let ty = ty.to_ir_tokens();
+                quote! { ::shared_type::ir::Expr::Cast(#operand, #ty) }
+            }
+            Expr::Select(cond, then_expr, else_expr) => {
+                let cond = cond.to_ir_tokens();
+                let then_expr = then_expr.to_ir_tokens();
+                let else_expr = else_expr.to_ir_tokens();
+                quote! { ::shared_type::ir::Expr::Select(#cond, #then_expr, #else_expr) }
+            }
+            Expr::Construct(ty, args) => {
+                let ty = ty.to_ir_tokens();
+                let args = args.to_ir_tokens();
+                quote! { ::shared_type::ir::Expr::Construct(#ty, #args) }
+            }
+            Expr::Swizzle(operand, indices) => {
+                let operand = operand.to_ir_tokens();
+                quote! { ::shared_type::ir::Expr::Swizzle(#operand, ::std::vec![#(#indices),*]) }
+            }
+            Expr::Call(function, args) => {
+                let function = function.to_ir_tokens();
+                let args = args.to_ir_tokens();
+                quote! { ::shared_type::ir::Expr::Call(#function, #args) }
+            }
+            Expr::Atomic(op, place, args) => {
+                let op = op.to_ir_tokens();
+                let place = place.to_ir_tokens();
+                let args = args.to_ir_tokens();
+                quote! { ::shared_type::ir::Expr::Atomic(#op, #place, #args) }
+            }
+            Expr::Subgroup(op, args) => {
+                let op = op.to_ir_tokens();
+                let args = args.to_ir_tokens();
+                quote! { ::shared_type::ir::Expr::Subgroup(#op, #args) }
+            }
+        }
+    }
+}
+
+impl ToIrTokens for Literal {
+    fn to_ir_tokens(&self) -> TokenStream {
+        match self {
+            Literal::Bool(b) => quote! { ::shared_type::ir::Literal::Bool(#b) },
+            Literal::Int(value, ty) => {
+                let value = LitToken::u64_suffixed(*value);
+                let ty = ty.to_ir_tokens();
+                quote! { ::shared_type::ir::Literal::Int(#value, #ty) }
+            }
+            Literal::Float(value, ty) => {
+                let value = LitToken::f64_suffixed(*value);
+                let ty = ty.to_ir_tokens();
+                quote! { ::shared_type::ir::Literal::Float(#value, #ty) }
+            }
+        }
+    }
+}
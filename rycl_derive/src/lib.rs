@@ -1,24 +1,44 @@
 extern crate proc_macro;
+pub(crate) mod ir_tokens;
+pub(crate) mod lower;
 pub(crate) mod ty_check;
 
+use ir_tokens::ToIrTokens;
 use proc_macro::TokenStream;
-use quote::quote;
+use quote::{format_ident, quote};
 #[allow(unused_imports)]
-use shared_type::{DeviceStructMarker, KernelFn, Primitive};
+use shared_type::{DeviceStructMarker, Primitive};
 use smallvec::SmallVec;
 use syn::{
-    parse_macro_input, parse_quote, visit_mut::VisitMut, Error, Expr, ExprArray, ExprForLoop,
-    Fields, FnArg, GenericParam, ItemFn, ItemStruct, Pat, PatIdent, PatType, TraitBound, Type,
-    TypeParamBound,
+    parse_macro_input, parse_quote, Error, Fields, FnArg, GenericParam, ItemFn, ItemStruct, LitInt,
+    Pat, PatIdent, PatType, TraitBound, TypeParamBound,
 };
 use ty_check::*;
 
-/// Marker trait for kernel functions, user should not implement this trait manually
-/// This trait is used to check if the customize type is valid in kernel functions
-
 // kernel attribute macro for GPU kernel functions
 #[proc_macro_attribute]
-pub fn kernel_fn(_args: TokenStream, input: TokenStream) -> TokenStream {
+pub fn kernel_fn(args: TokenStream, input: TokenStream) -> TokenStream {
+    let mut workgroup_size: Option<u32> = None;
+    let mut embed_spirv = None;
+    let args_parser = syn::meta::parser(|meta| {
+        if meta.path.is_ident("spirv") {
+            embed_spirv = Some(meta.path.clone());
+            Ok(())
+        } else if meta.path.is_ident("workgroup_size") {
+            let size: LitInt = meta.value()?.parse()?;
+            match size.base10_parse()? {
+                0 => Err(Error::new_spanned(size, "workgroup size must not be zero")),
+                size => {
+                    workgroup_size = Some(size);
+                    Ok(())
+                }
+            }
+        } else {
+            Err(meta
+                .error("unsupported kernel_fn argument, expected `workgroup_size = N` or `spirv`"))
+        }
+    });
+    parse_macro_input!(args with args_parser);
     let mut input_fn = parse_macro_input!(input as ItemFn);
 
     // Check if the function contains `num_thread_blocks: u32` and `thread_block_size: u32`
@@ -27,20 +47,15 @@ pub fn kernel_fn(_args: TokenStream, input: TokenStream) -> TokenStream {
     let mut generic_params = GenericParamSet::new();
     let mut errors = SmallVec::<[proc_macro2::TokenStream; 4]>::new();
 
-    // Extract the function's generic parameters (if any)
-    // fixme: this does not work
+    // Generic parameters are instantiated per launch, through the `<name>_ir`
+    // function. They must be bounded by `Primitive` or `DeviceStructMarker`,
+    // and default to kernel structs.
     for param in input_fn.sig.generics.params.iter_mut() {
         if let GenericParam::Type(type_param) = param {
-            // Add the DeviceStructMarker trait bound if it's missing
-            if !type_param
-                .bounds
-                .iter()
-                .any(|bound| matches!(bound, TypeParamBound::Trait(TraitBound { path, .. }) if path.is_ident("DeviceStructMarker")))
-            {
+            if !type_param.bounds.iter().any(is_kernel_bound) {
                 type_param.bounds.push(parse_quote!(DeviceStructMarker));
-                generic_params.insert(type_param.ident.to_string());
-
             }
+            generic_params.insert(type_param.ident.to_string());
         }
     }
     for arg in &input_fn.sig.inputs {
@@ -53,9 +68,9 @@ pub fn kernel_fn(_args: TokenStream, input: TokenStream) -> TokenStream {
                 if ident == "thread_block_size" && is_u32(arg_type) {
                     has_thread_block_size = true;
                 }
-                if !is_valid_type(arg_type, &generic_params) {
+                if !is_valid_arg_type(arg_type, &generic_params) {
                     errors.push(
-                        Error::new_spanned(arg_type, format!("argument type is not allowed in kernel functions, allowed types are: {:?} and KernelStruct", ALLOWED_PRIMITIVE_TYPES))
+                        Error::new_spanned(arg_type, format!("argument type is not allowed in kernel functions, allowed types are: {:?}, KernelStruct and slices of them", ALLOWED_PRIMITIVE_TYPES))
                             .into_compile_error()
                     );
                 }
@@ -78,17 +93,253 @@ pub fn kernel_fn(_args: TokenStream, input: TokenStream) -> TokenStream {
         errors.push(Error::new_spanned(&input_fn.sig, error_msg).into_compile_error());
     }
 
-    let expanded = quote! {
-        impl KernelFn for #input_fn {}
-    };
-    let output = quote! {
+    // The embedded module is generated for a fixed thread block size
+    if let (Some(path), None) = (&embed_spirv, workgroup_size) {
+        errors.push(
+            Error::new_spanned(path, "embedding SPIR-V requires `workgroup_size = N`")
+                .into_compile_error(),
+        );
+    }
+
+    // Lower the body into kernel IR, exposed through `<name>_ir()` and the
+    // kernel handle
+    let mut expanded = proc_macro2::TokenStream::new();
+    if errors.is_empty() {
+        let spirv = lower::lower_kernel(&input_fn).and_then(|kernel| {
+            let spirv = match (&embed_spirv, workgroup_size) {
+                (Some(_), Some(size)) => embedded_spirv(&input_fn, &kernel, size)?,
+                _ => None,
+            };
+            Ok((kernel, spirv))
+        });
+        match spirv {
+            Ok((kernel, spirv)) => {
+                expanded = kernel_items(&input_fn, kernel, workgroup_size, spirv)
+            }
+            Err(err) => errors.push(err.into_compile_error()),
+        }
+    }
+    // Only the arguments the macro gives a meaning to are exempt from lints.
+    // Kernels that use the thread-index intrinsics may ignore the launch
+    // configuration arguments they are required to take. Writes to `mut`
+    // array arguments are read back from the device but not on the host.
+    // rustc reports those writes at the assignment and ignores parameter
+    // attributes for them, so that single lint is allowed on the function
+    // of kernels that take such arguments.
+    let mut has_output_arrays = false;
+    for arg in input_fn.sig.inputs.iter_mut() {
+        let FnArg::Typed(PatType { attrs, pat, ty, .. }) = arg else {
+            continue;
+        };
+        let Pat::Ident(PatIdent {
+            ident, mutability, ..
+        }) = &**pat
+        else {
+            continue;
+        };
+        if ident == "num_thread_blocks" || ident == "thread_block_size" {
+            attrs.push(parse_quote!(#[allow(unused_variables)]));
+        } else if mutability.is_some() && matches!(&**ty, syn::Type::Array(_)) {
+            has_output_arrays = true;
+        }
+    }
+    if has_output_arrays {
+        input_fn
+            .attrs
+            .push(parse_quote!(#[allow(unused_assignments)]));
+    }
+    TokenStream::from(quote! {
         #(#errors)*
         #input_fn
+        #expanded
+    })
+}
+
+// The `<name>_ir()` function and the `<Name>Kernel` handle of a kernel function
+fn kernel_items(
+    input_fn: &ItemFn,
+    kernel: shared_type::ir::Kernel,
+    workgroup_size: Option<u32>,
+    spirv: Option<Vec<u32>>,
+) -> proc_macro2::TokenStream {
+    let vis = &input_fn.vis;
+    let fn_name = &input_fn.sig.ident;
+    let name = fn_name.to_string();
+    let ir_fn = format_ident!("{}_ir", fn_name);
+    let handle = format_ident!("{}Kernel", camel_case(&name));
+    let generics = &input_fn.sig.generics;
+    let (impl_generics, ty_generics, where_clause) = generics.split_for_impl();
+    let turbofish = ty_generics.as_turbofish();
+    // Lifetimes of the kernel function are late-bound and cannot be given
+    let fn_args: Vec<_> = generics
+        .params
+        .iter()
+        .filter_map(|param| match param {
+            GenericParam::Type(param) => Some(&param.ident),
+            GenericParam::Const(param) => Some(&param.ident),
+            GenericParam::Lifetime(_) => None,
+        })
+        .collect();
+    let ir_doc = format!("Kernel IR of [`{name}`].");
+    let handle_doc = format!("Handle of the [`{name}`] kernel, passed to the launch API.");
+    let kernel = kernel.to_ir_tokens();
+    let workgroup_size = match workgroup_size {
+        Some(size) => quote!(::std::option::Option::Some(#size)),
+        None => quote!(::std::option::Option::None),
+    };
+    let spirv = match spirv {
+        Some(words) => {
+            let len = words.len();
+            quote! {
+                static SPIRV: [u32; #len] = [#(#words),*];
+                ::std::option::Option::Some(&SPIRV)
+            }
+        }
+        None => quote!(::std::option::Option::None),
     };
 
-    print!("{}", output);
+    // Generic handles carry their parameters in a `PhantomData`
+    let phantom: Vec<_> = generics
+        .params
+        .iter()
+        .filter_map(|param| match param {
+            GenericParam::Type(param) => {
+                let ident = &param.ident;
+                Some(quote!(#ident))
+            }
+            GenericParam::Lifetime(param) => {
+                let lifetime = &param.lifetime;
+                Some(quote!(&#lifetime ()))
+            }
+            GenericParam::Const(_) => None,
+        })
+        .collect();
+    let (definition, value) = if generics.params.is_empty() {
+        (quote!(;), quote!(Self))
+    } else {
+        (
+            quote! {
+                (::std::marker::PhantomData<fn() -> (#(#phantom,)*)>) #where_clause;
+            },
+            quote!(Self(::std::marker::PhantomData)),
+        )
+    };
+
+    quote! {
+        #[doc = #ir_doc]
+        #vis fn #ir_fn #impl_generics () -> ::shared_type::ir::Kernel #where_clause {
+            // Kernels are launched through their IR, which stands for the
+            // function on devices
+            let _ = #fn_name::<#(#fn_args),*>;
+            #kernel
+        }
+
+        #[doc = #handle_doc]
+        #vis struct #handle #impl_generics #definition
+
+        impl #impl_generics #handle #ty_generics #where_clause {
+            pub const fn new() -> Self {
+                #value
+            }
+        }
+
+        impl #impl_generics ::std::clone::Clone for #handle #ty_generics #where_clause {
+            fn clone(&self) -> Self {
+                *self
+            }
+        }
+
+        impl #impl_generics ::std::marker::Copy for #handle #ty_generics #where_clause {}
+
+        impl #impl_generics ::std::default::Default for #handle #ty_generics #where_clause {
+            fn default() -> Self {
+                Self::new()
+            }
+        }
+
+        impl #impl_generics ::std::fmt::Debug for #handle #ty_generics #where_clause {
+            fn fmt(&self, f: &mut ::std::fmt::Formatter<'_>) -> ::std::fmt::Result {
+                f.write_str(::std::stringify!(#handle))
+            }
+        }
+
+        impl #impl_generics ::shared_type::KernelFn for #handle #ty_generics #where_clause {
+            fn entry_point(&self) -> &str {
+                #name
+            }
 
-    output.into()
+            fn args(&self) -> ::std::vec::Vec<::shared_type::ArgDesc> {
+                ::shared_type::KernelFn::args(&#ir_fn #turbofish ())
+            }
+
+            fn workgroup_size(&self) -> ::std::option::Option<u32> {
+                #workgroup_size
+            }
+
+            fn module(
+                &self,
+            ) -> ::std::result::Result<::shared_type::ir::Kernel, ::shared_type::ir::typeck::TypeError>
+            {
+                ::shared_type::KernelFn::module(&#ir_fn #turbofish ())
+            }
+
+            fn spirv(&self) -> ::std::option::Option<&'static [u32]> {
+                #spirv
+            }
+        }
+    }
+}
+
+// The SPIR-V module of `kernel`, generated at expansion time. Generic
+// kernels and kernels using kernel structs have types only `KernelType` can
+// resolve, so their modules are generated at launch time instead.
+fn embedded_spirv(
+    input_fn: &ItemFn,
+    kernel: &shared_type::ir::Kernel,
+    workgroup_size: u32,
+) -> syn::Result<Option<Vec<u32>>> {
+    let resolved = kernel
+        .params
+        .iter()
+        .map(|param| &param.ty)
+        .chain(kernel.shared.iter().map(|shared| &shared.ty))
+        .chain(kernel.locals.iter().filter_map(|local| local.ty.as_ref()))
+        .all(shared_type::ir::Type::is_resolved);
+    if !input_fn.sig.generics.params.is_empty() || !resolved {
+        return Ok(None);
+    }
+    let mut kernel = kernel.clone();
+    shared_type::ir::typeck::check(&mut kernel)
+        .map_err(|err| Error::new_spanned(&input_fn.sig, err))?;
+    spirv_codegen::build_spirv(&kernel, workgroup_size)
+        .map(Some)
+        .map_err(|err| Error::new_spanned(&input_fn.sig, err))
+}
+
+// `copy_all` -> `CopyAll`
+fn camel_case(name: &str) -> String {
+    name.split('_')
+        .flat_map(|word| {
+            let mut chars = word.chars();
+            chars
+                .next()
+                .map(|first| first.to_ascii_uppercase())
+                .into_iter()
+                .chain(chars)
+        })
+        .collect()
+}
+
+// Whether `bound` makes a generic parameter a kernel type
+fn is_kernel_bound(bound: &TypeParamBound) -> bool {
+    match bound {
+        TypeParamBound::Trait(TraitBound { path, .. }) => {
+            path.segments.last().is_some_and(|segment| {
+                segment.ident == "Primitive" || segment.ident == "DeviceStructMarker"
+            })
+        }
+        _ => false,
+    }
 }
 
 // kernel attribute macro for GPU kernel structs
@@ -115,6 +366,11 @@ pub fn kernel_struct(_args: TokenStream, input: TokenStream) -> TokenStream {
         }
     }
 
+    if let Fields::Unnamed(fields) = &input.fields {
+        errors.push(
+            Error::new_spanned(fields, "kernel structs must have named fields").to_compile_error(),
+        );
+    }
     if let Fields::Named(fields) = &input.fields {
         for field in fields.named.iter() {
             if !is_valid_type(&field.ty, &generic_params) {
@@ -122,7 +378,7 @@ pub fn kernel_struct(_args: TokenStream, input: TokenStream) -> TokenStream {
                     Error::new(
                         struct_name.span(),
                         format!(
-                            "Field `{}` in struct `{}` is not a primitive type.",
+                            "Field `{}` in struct `{}` is not a primitive type, an array or a kernel struct.",
                             field.ident.as_ref().unwrap(),
                             struct_name
                         ),
@@ -133,48 +389,95 @@ pub fn kernel_struct(_args: TokenStream, input: TokenStream) -> TokenStream {
         }
     }
 
-    let expanded = quote! {
-        impl DeviceStructMarker for #struct_name {}
-    };
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+    let mut fields_ir = Vec::new();
+    let mut field_names = Vec::new();
+    let mut field_types = Vec::new();
+    for field in input.fields.iter() {
+        if let Some(ident) = &field.ident {
+            let name = ident.to_string();
+            let ty = &field.ty;
+            fields_ir.push(quote! {
+                ::shared_type::ir::Field {
+                    name: ::std::string::String::from(#name),
+                    ty: <#ty as ::shared_type::KernelType>::kernel_type(),
+                }
+            });
+            field_names.push(ident);
+            field_types.push(ty);
+        }
+    }
+    // Structs are copied field by field, so every field must be copyable
+    let mut copy_generics = input.generics.clone();
+    let copy_where = copy_generics.make_where_clause();
+    for ty in &field_types {
+        copy_where
+            .predicates
+            .push(parse_quote!(#ty: ::shared_type::DeviceCopy));
+    }
+    let copy_where = &copy_generics.where_clause;
+    let num_fields = field_types.len();
+    let field_index = 0..num_fields;
+    let field_index_read = 0..num_fields;
+    let name = struct_name.to_string();
+    let std430_doc = format!("Field offsets, size and alignment of `{name}` in storage buffers.");
+    let std140_doc = format!("Field offsets, size and alignment of `{name}` in uniform buffers.");
+    // The impls would only repeat the field errors
+    let expanded = if !errors.is_empty() {
+        proc_macro2::TokenStream::new()
+    } else {
+        quote! {
+            impl #impl_generics DeviceStructMarker for #struct_name #ty_generics #where_clause {}
 
-    TokenStream::from(quote! {
-        #(#errors)*
-        #input
-        #expanded
-    })
-}
+            impl #impl_generics #struct_name #ty_generics #where_clause {
+                #[doc = #std430_doc]
+                pub const STD430_LAYOUT: ::shared_type::ir::layout::StructLayout<#num_fields> =
+                    ::shared_type::ir::layout::StructLayout::new(
+                        [#(<#field_types as ::shared_type::KernelType>::STD430),*],
+                        ::shared_type::ir::layout::Rules::Std430,
+                    );
+                #[doc = #std140_doc]
+                pub const STD140_LAYOUT: ::shared_type::ir::layout::StructLayout<#num_fields> =
+                    ::shared_type::ir::layout::StructLayout::new(
+                        [#(<#field_types as ::shared_type::KernelType>::STD140),*],
+                        ::shared_type::ir::layout::Rules::Std140,
+                    );
+            }
 
-struct KernelSyntaxValidator;
+            impl #impl_generics ::shared_type::KernelType for #struct_name #ty_generics #where_clause {
+                const STD430: ::shared_type::ir::layout::Layout = Self::STD430_LAYOUT.layout();
+                const STD140: ::shared_type::ir::layout::Layout = Self::STD140_LAYOUT.layout();
 
-impl VisitMut for KernelSyntaxValidator {
-    fn visit_expr_mut(&mut self, expr: &mut Expr) {
-        match expr {
-            Expr::ForLoop(ExprForLoop {
-                attrs,
-                label,
-                for_token,
-                pat,
-                in_token,
-                expr,
-                body,
-            }) => {
-                todo!()
+                fn kernel_type() -> ::shared_type::ir::Type {
+                    ::shared_type::ir::Type::Struct(::shared_type::ir::StructType {
+                        name: ::std::string::String::from(#name),
+                        fields: ::std::vec![#(#fields_ir),*],
+                    })
+                }
             }
 
-            Expr::Array(syn::ExprArray {
-                attrs,
-                bracket_token,
-                elems,
-            }) => {
-                todo!()
-            }
-            Expr::Path(expr_path) => {
-                todo!()
-            }
-            _ => {
-                Error::new_spanned(expr, "expression not allowed in kernel functions")
-                    .into_compile_error();
+            impl #impl_generics ::shared_type::DeviceCopy for #struct_name #ty_generics #copy_where {
+                fn write_bytes(&self, out: &mut [u8]) {
+                    #(::shared_type::DeviceCopy::write_bytes(
+                        &self.#field_names,
+                        &mut out[Self::STD430_LAYOUT.offsets[#field_index] as usize..],
+                    );)*
+                }
+
+                fn read_bytes(bytes: &[u8]) -> Self {
+                    Self {
+                        #(#field_names: ::shared_type::DeviceCopy::read_bytes(
+                            &bytes[Self::STD430_LAYOUT.offsets[#field_index_read] as usize..],
+                        ),)*
+                    }
+                }
             }
         }
-    }
+    };
+
+    TokenStream::from(quote! {
+        #(#errors)*
+        #input
+        #expanded
+    })
 }
@@ -0,0 +1,1555 @@
+//! Lowering of `#[kernel_fn]` bodies from Rust syntax into kernel IR.
+use std::collections::HashMap;
+
+use quote::ToTokens;
+use shared_type::ir::{
+    vector_component, AtomicOp, BinaryOp, Block, Builtin, Expr, Function, Kernel, Literal, Local,
+    LocalId, Param, Place, ScalarType, Shared, Stmt, SubgroupOp, SyncOp, Type, UnaryOp,
+};
+use syn::spanned::Spanned;
+use syn::{Error, FnArg, ItemFn, Lit, Pat, PatIdent, PatType, RangeLimits, Result, ReturnType};
+
+/// Names of the launch configuration arguments every kernel must take.
+pub(crate) const NUM_THREAD_BLOCKS: &str = "num_thread_blocks";
+pub(crate) const THREAD_BLOCK_SIZE: &str = "thread_block_size";
+
+#[derive(Clone, Copy)]
+enum Binding {
+    Param(usize),
+    Local(LocalId),
+    Shared(usize),
+    Builtin(Builtin),
+}
+
+struct Lowerer {
+    params: Vec<Param>,
+    locals: Vec<Local>,
+    shared: Vec<Shared>,
+    scopes: Vec<HashMap<String, Binding>>,
+    /// Statements that must run before the statement being lowered, such
+    /// as the branches of `if` expressions that cannot be selects.
+    pending: Block,
+}
+
+/// Lowers a kernel function into IR. The argument types are expected to have
+/// been validated already.
+pub(crate) fn lower_kernel(item: &ItemFn) -> Result<Kernel> {
+    if let ReturnType::Type(_, ty) = &item.sig.output {
+        if !matches!(&**ty, syn::Type::Tuple(t) if t.elems.is_empty()) {
+            return Err(Error::new_spanned(
+                ty,
+                "kernel functions cannot return a value, write results to a mutable argument instead",
+            ));
+        }
+    }
+
+    let mut lowerer = Lowerer {
+        params: Vec::new(),
+        locals: Vec::new(),
+        shared: Vec::new(),
+        scopes: vec![HashMap::new()],
+        pending: Vec::new(),
+    };
+    for arg in &item.sig.inputs {
+        let FnArg::Typed(PatType { pat, ty, .. }) = arg else {
+            return Err(Error::new_spanned(
+                arg,
+                "kernel functions cannot take `self`",
+            ));
+        };
+        let Pat::Ident(PatIdent {
+            ident, mutability, ..
+        }) = &**pat
+        else {
+            return Err(Error::new_spanned(
+                pat,
+                "kernel arguments must be plain identifiers",
+            ));
+        };
+        let name = ident.to_string();
+        let binding = match name.as_str() {
+            NUM_THREAD_BLOCKS => Binding::Builtin(Builtin::NumThreadBlocks),
+            THREAD_BLOCK_SIZE => Binding::Builtin(Builtin::ThreadBlockSize),
+            _ => {
+                // `&mut [T]` is as writable as a `mut` array argument
+                let (ty, mutable) = match &**ty {
+                    syn::Type::Reference(reference) => match &*reference.elem {
+                        syn::Type::Slice(slice) => (
+                            Type::Slice(Box::new(lower_type(&slice.elem)?)),
+                            reference.mutability.is_some(),
+                        ),
+                        _ => {
+                            return Err(Error::new_spanned(
+                                ty,
+                                "type not supported in kernel functions",
+                            ))
+                        }
+                    },
+                    ty => (lower_type(ty)?, mutability.is_some()),
+                };
+                lowerer.params.push(Param {
+                    name: name.clone(),
+                    ty,
+                    mutable,
+                });
+                Binding::Param(lowerer.params.len() - 1)
+            }
+        };
+        lowerer.scopes[0].insert(name, binding);
+    }
+
+    let body = lowerer.block(&item.block)?;
+    Ok(Kernel {
+        name: item.sig.ident.to_string(),
+        params: lowerer.params,
+        locals: lowerer.locals,
+        shared: lowerer.shared,
+        body,
+    })
+}
+
+/// Lowers a type written in a kernel. Types the macro cannot see into are
+/// kept as [`Type::Named`] and resolved through `KernelType` at runtime.
+pub(crate) fn lower_type(ty: &syn::Type) -> Result<Type> {
+    match ty {
+        syn::Type::Path(type_path) => {
+            if let Some(ident) = type_path.path.get_ident() {
+                if let Some(scalar) = ScalarType::from_rust_name(&ident.to_string()) {
+                    return Ok(Type::Scalar(scalar));
+                }
+            }
+            Ok(Type::Named(ty.to_token_stream().to_string()))
+        }
+        syn::Type::Array(array) => match &array.len {
+            syn::Expr::Lit(syn::ExprLit {
+                lit: Lit::Int(len), ..
+            }) => Ok(Type::Array(
+                Box::new(lower_type(&array.elem)?),
+                len.base10_parse()?,
+            )),
+            // The length is a const generic, let `KernelType` resolve it.
+            _ => Ok(Type::Named(ty.to_token_stream().to_string())),
+        },
+        syn::Type::Paren(paren) => lower_type(&paren.elem),
+        syn::Type::Group(group) => lower_type(&group.elem),
+        _ => Err(Error::new_spanned(
+            ty,
+            "type not supported in kernel functions",
+        )),
+    }
+}
+
+fn scalar_type(ty: &syn::Type) -> Result<ScalarType> {
+    match lower_type(ty)? {
+        Type::Scalar(scalar) => Ok(scalar),
+        _ => Err(Error::new_spanned(
+            ty,
+            "only casts to primitive types are supported in kernel functions",
+        )),
+    }
+}
+
+fn unsupported(tokens: impl ToTokens) -> Error {
+    Error::new_spanned(tokens, "expression not allowed in kernel functions")
+}
+
+impl Lowerer {
+    fn lookup(&self, name: &str) -> Option<Binding> {
+        self.scopes
+            .iter()
+            .rev()
+            .find_map(|scope| scope.get(name).copied())
+    }
+
+    fn declare(&mut self, name: String, ty: Option<Type>) -> LocalId {
+        let id = LocalId(self.locals.len() as u32);
+        self.locals.push(Local {
+            name: name.clone(),
+            ty,
+        });
+        self.scopes
+            .last_mut()
+            .expect("scope stack is never empty")
+            .insert(name, Binding::Local(id));
+        id
+    }
+
+    /// A local for a value that the lowering introduces, which no name
+    /// refers to.
+    fn temp(&mut self) -> LocalId {
+        let id = LocalId(self.locals.len() as u32);
+        self.locals.push(Local {
+            name: format!("_tmp{}", id.0),
+            ty: None,
+        });
+        id
+    }
+
+    /// Runs `lower` with nothing pending, returning its result and the
+    /// statements it left pending.
+    fn isolated<T>(&mut self, lower: impl FnOnce(&mut Self) -> Result<T>) -> Result<(T, Block)> {
+        let outer = std::mem::take(&mut self.pending);
+        let result = lower(self);
+        let pending = std::mem::replace(&mut self.pending, outer);
+        Ok((result?, pending))
+    }
+
+    /// Evaluates `value` into a temporary ahead of the current statement.
+    fn hoist(&mut self, value: Expr) -> Expr {
+        let temp = self.temp();
+        self.pending.push(Stmt::Let {
+            local: temp,
+            init: Some(value),
+        });
+        Expr::Load(Place::Local(temp))
+    }
+
+    /// Hoists the indices of `place` that cannot be evaluated twice, outer
+    /// ones first.
+    fn hoist_indices(&mut self, place: Place) -> Place {
+        match place {
+            Place::Index(base, index) => {
+                let base = self.hoist_indices(*base);
+                let index = if index.is_speculatable() {
+                    *index
+                } else {
+                    self.hoist(*index)
+                };
+                Place::Index(Box::new(base), Box::new(index))
+            }
+            Place::Field(base, field) => Place::Field(Box::new(self.hoist_indices(*base)), field),
+            place => place,
+        }
+    }
+
+    /// Moves the pending statements to `out`, ahead of the statement about
+    /// to be pushed.
+    fn flush(&mut self, out: &mut Block) {
+        out.append(&mut self.pending);
+    }
+
+    fn block(&mut self, block: &syn::Block) -> Result<Block> {
+        self.scopes.push(HashMap::new());
+        let mut out = Vec::new();
+        let result = block
+            .stmts
+            .iter()
+            .try_for_each(|stmt| self.stmt(stmt, &mut out));
+        self.scopes.pop();
+        result.map(|()| out)
+    }
+
+    fn stmt(&mut self, stmt: &syn::Stmt, out: &mut Block) -> Result<()> {
+        match stmt {
+            syn::Stmt::Local(local) => {
+                if let Some(init) = &local.init {
+                    if let Some((_, diverge)) = &init.diverge {
+                        return Err(Error::new_spanned(
+                            diverge,
+                            "`let else` is not supported in kernel functions",
+                        ));
+                    }
+                }
+                let (pat, ty) = match &local.pat {
+                    Pat::Type(PatType { pat, ty, .. }) => (&**pat, Some(lower_type(ty)?)),
+                    pat => (pat, None),
+                };
+                if let Some(call) = local
+                    .init
+                    .as_ref()
+                    .and_then(|init| shared_array(&init.expr))
+                {
+                    return self.shared_array(pat, ty, call);
+                }
+                let init = match &local.init {
+                    Some(init) => Some(self.expr(&init.expr)?),
+                    None => None,
+                };
+                self.flush(out);
+                match pat {
+                    Pat::Ident(PatIdent {
+                        ident,
+                        by_ref: None,
+                        subpat: None,
+                        ..
+                    }) => {
+                        // Declared after lowering the initializer, so that
+                        // `let x = x + 1;` reads the shadowed binding.
+                        let local = self.declare(ident.to_string(), ty);
+                        out.push(Stmt::Let { local, init });
+                    }
+                    Pat::Wild(_) => {
+                        if let Some(init) = init {
+                            out.push(Stmt::Expr(init));
+                        }
+                    }
+                    pat => {
+                        return Err(Error::new_spanned(
+                            pat,
+                            "only simple `let` bindings are supported in kernel functions",
+                        ));
+                    }
+                }
+                Ok(())
+            }
+            syn::Stmt::Expr(expr, _) => self.expr_stmt(expr, out),
+            syn::Stmt::Item(item) => Err(Error::new_spanned(
+                item,
+                "items are not allowed in kernel functions",
+            )),
+            syn::Stmt::Macro(mac) => Err(Error::new_spanned(
+                mac,
+                "macros are not allowed in kernel functions",
+            )),
+        }
+    }
+
+    /// Declares the array of `let #pat: #ty = shared_array::<T, N>();`,
+    /// which lives for the whole thread block rather than in a local.
+    fn shared_array(&mut self, pat: &Pat, ty: Option<Type>, call: &syn::ExprCall) -> Result<()> {
+        let Pat::Ident(PatIdent {
+            ident,
+            by_ref: None,
+            subpat: None,
+            ..
+        }) = pat
+        else {
+            return Err(Error::new_spanned(
+                pat,
+                "shared arrays must be bound to a plain identifier",
+            ));
+        };
+        if !call.args.is_empty() {
+            return Err(Error::new_spanned(
+                &call.args,
+                "`shared_array` takes no arguments",
+            ));
+        }
+        let ty = match (shared_array_type(call)?, ty) {
+            (Some(ty), _) | (None, Some(ty)) => ty,
+            (None, None) => {
+                return Err(Error::new_spanned(
+                    call,
+                    "cannot infer the type of the shared array, write `shared_array::<T, N>()`",
+                ))
+            }
+        };
+        let name = ident.to_string();
+        self.shared.push(Shared {
+            name: name.clone(),
+            ty,
+        });
+        self.scopes
+            .last_mut()
+            .expect("scope stack is never empty")
+            .insert(name, Binding::Shared(self.shared.len() - 1));
+        Ok(())
+    }
+
+    fn expr_stmt(&mut self, expr: &syn::Expr, out: &mut Block) -> Result<()> {
+        match expr {
+            syn::Expr::Assign(assign) => {
+                let place = self.place(&assign.left)?;
+                let value = self.expr(&assign.right)?;
+                self.flush(out);
+                out.push(Stmt::Assign { place, value });
+            }
+            syn::Expr::Binary(binary) if compound_op(&binary.op).is_some() => {
+                let op = compound_op(&binary.op).unwrap();
+                // Rust evaluates the right operand before the place, so it
+                // goes into a temporary first unless it is speculatable and
+                // the place has no indices with effects. Those are then
+                // evaluated once into temporaries, as the place is both
+                // loaded and stored.
+                let mut rhs = self.expr(&binary.right)?;
+                let mut place = self.place(&binary.left)?;
+                let hoist_indices = !indices_speculatable(&place);
+                if !rhs.is_speculatable() || (hoist_indices && !matches!(rhs, Expr::Literal(_))) {
+                    rhs = self.hoist(rhs);
+                }
+                if hoist_indices {
+                    place = self.hoist_indices(place);
+                }
+                self.flush(out);
+                out.push(Stmt::Assign {
+                    value: Expr::Binary(op, Box::new(Expr::Load(place.clone())), Box::new(rhs)),
+                    place,
+                });
+            }
+            syn::Expr::If(expr_if) => self.if_stmt(expr_if, out)?,
+            syn::Expr::ForLoop(for_loop) => {
+                if let Some(label) = &for_loop.label {
+                    return Err(Error::new_spanned(
+                        label,
+                        "loop labels are not supported in kernel functions",
+                    ));
+                }
+                let syn::Expr::Range(range) = &*for_loop.expr else {
+                    return Err(Error::new_spanned(
+                        &for_loop.expr,
+                        "kernel `for` loops must iterate over a range `a..b` or `a..=b`",
+                    ));
+                };
+                let (Some(start), Some(end)) = (&range.start, &range.end) else {
+                    return Err(Error::new_spanned(
+                        range,
+                        "kernel `for` loops must iterate over a bounded range",
+                    ));
+                };
+                let start = self.expr(start)?;
+                let end = self.expr(end)?;
+                self.flush(out);
+                let Pat::Ident(PatIdent { ident, .. }) = &*for_loop.pat else {
+                    return Err(Error::new_spanned(
+                        &for_loop.pat,
+                        "kernel `for` loop variables must be plain identifiers",
+                    ));
+                };
+                self.scopes.push(HashMap::new());
+                let var = self.declare(ident.to_string(), None);
+                let body = self.block(&for_loop.body);
+                self.scopes.pop();
+                out.push(Stmt::For {
+                    var,
+                    start,
+                    end,
+                    inclusive: matches!(range.limits, RangeLimits::Closed(_)),
+                    body: body?,
+                });
+            }
+            syn::Expr::While(while_loop) => {
+                if let Some(label) = &while_loop.label {
+                    return Err(Error::new_spanned(
+                        label,
+                        "loop labels are not supported in kernel functions",
+                    ));
+                }
+                let (cond, cond_block) = self.isolated(|this| this.expr(&while_loop.cond))?;
+                let body = self.block(&while_loop.body)?;
+                if cond_block.is_empty() {
+                    out.push(Stmt::While { cond, body });
+                } else {
+                    // The statements of the condition run on every iteration.
+                    let mut block = cond_block;
+                    block.push(Stmt::If {
+                        cond: Expr::Unary(UnaryOp::Not, Box::new(cond)),
+                        then_block: vec![Stmt::Break],
+                        else_block: Vec::new(),
+                    });
+                    block.extend(body);
+                    out.push(Stmt::While {
+                        cond: Expr::Literal(Literal::Bool(true)),
+                        body: block,
+                    });
+                }
+            }
+            syn::Expr::Loop(loop_expr) => {
+                if let Some(label) = &loop_expr.label {
+                    return Err(Error::new_spanned(
+                        label,
+                        "loop labels are not supported in kernel functions",
+                    ));
+                }
+                let body = self.block(&loop_expr.body)?;
+                out.push(Stmt::While {
+                    cond: Expr::Literal(Literal::Bool(true)),
+                    body,
+                });
+            }
+            syn::Expr::Break(brk) if brk.label.is_none() && brk.expr.is_none() => {
+                out.push(Stmt::Break)
+            }
+            syn::Expr::Continue(cont) if cont.label.is_none() => out.push(Stmt::Continue),
+            syn::Expr::Return(ret) if ret.expr.is_none() => out.push(Stmt::Return),
+            syn::Expr::Block(block) if block.label.is_none() => {
+                out.extend(self.block(&block.block)?);
+            }
+            syn::Expr::Call(call) if sync_op(call).is_some() => {
+                if !call.args.is_empty() {
+                    return Err(Error::new_spanned(
+                        &call.args,
+                        "barriers and fences take no arguments",
+                    ));
+                }
+                out.push(Stmt::Sync(sync_op(call).unwrap()));
+            }
+            syn::Expr::Paren(paren) => self.expr_stmt(&paren.expr, out)?,
+            expr => {
+                let expr = self.expr(expr)?;
+                self.flush(out);
+                out.push(Stmt::Expr(expr));
+            }
+        }
+        Ok(())
+    }
+
+    fn if_stmt(&mut self, expr_if: &syn::ExprIf, out: &mut Block) -> Result<()> {
+        let cond = self.expr(&expr_if.cond)?;
+        self.flush(out);
+        let then_block = self.block(&expr_if.then_branch)?;
+        let else_block = match &expr_if.else_branch {
+            None => Vec::new(),
+            Some((_, else_expr)) => match &**else_expr {
+                syn::Expr::If(nested) => {
+                    let mut block = Vec::new();
+                    self.if_stmt(nested, &mut block)?;
+                    block
+                }
+                syn::Expr::Block(block) => self.block(&block.block)?,
+                other => return Err(unsupported(other)),
+            },
+        };
+        out.push(Stmt::If {
+            cond,
+            then_block,
+            else_block,
+        });
+        Ok(())
+    }
+
+    /// Lowers an `if` used as a value to a select if both branches can be
+    /// evaluated whatever the condition, and otherwise to a branch storing
+    /// into a temporary, so that `if i < a.len() { a[i] } else { 0 }` only
+    /// reads `a[i]` when it is in bounds.
+    fn if_expr(&mut self, expr_if: &syn::ExprIf) -> Result<Expr> {
+        let cond = self.expr(&expr_if.cond)?;
+        let then_branch = self.isolated(|this| this.value_block(&expr_if.then_branch))?;
+        let else_branch = self.isolated(|this| match &expr_if.else_branch {
+            Some((_, else_expr)) => match &**else_expr {
+                syn::Expr::Block(block) => this.value_block(&block.block),
+                nested @ syn::Expr::If(_) => this.expr(nested),
+                other => Err(unsupported(other)),
+            },
+            None => Err(unsupported(expr_if)),
+        })?;
+        Ok(match (then_branch, else_branch) {
+            ((then_expr, then_block), (else_expr, else_block))
+                if then_block.is_empty()
+                    && else_block.is_empty()
+                    && then_expr.is_speculatable()
+                    && else_expr.is_speculatable() =>
+            {
+                Expr::Select(Box::new(cond), Box::new(then_expr), Box::new(else_expr))
+            }
+            (then_branch, else_branch) => self.branch_value(cond, then_branch, else_branch),
+        })
+    }
+
+    /// Queues a branch on `cond` that runs the statements of one branch and
+    /// stores its value into a temporary, and returns the load of it.
+    fn branch_value(
+        &mut self,
+        cond: Expr,
+        then_branch: (Expr, Block),
+        else_branch: (Expr, Block),
+    ) -> Expr {
+        let temp = self.temp();
+        let store = |(value, mut block): (Expr, Block)| {
+            block.push(Stmt::Assign {
+                place: Place::Local(temp),
+                value,
+            });
+            block
+        };
+        self.pending.push(Stmt::Let {
+            local: temp,
+            init: None,
+        });
+        self.pending.push(Stmt::If {
+            cond,
+            then_block: store(then_branch),
+            else_block: store(else_branch),
+        });
+        Expr::Load(Place::Local(temp))
+    }
+
+    fn place(&mut self, expr: &syn::Expr) -> Result<Place> {
+        match expr {
+            syn::Expr::Path(path) => match self.path(path)? {
+                Binding::Param(i) => Ok(Place::Param(i)),
+                Binding::Local(id) => Ok(Place::Local(id)),
+                Binding::Shared(i) => Ok(Place::Shared(i)),
+                Binding::Builtin(_) => Err(Error::new_spanned(
+                    path,
+                    "launch configuration arguments cannot be assigned to",
+                )),
+            },
+            syn::Expr::Index(index) => Ok(Place::Index(
+                Box::new(self.place(&index.expr)?),
+                Box::new(self.expr(&index.index)?),
+            )),
+            syn::Expr::Field(field) => match &field.member {
+                syn::Member::Named(ident) => Ok(Place::Field(
+                    Box::new(self.place(&field.base)?),
+                    ident.to_string(),
+                )),
+                syn::Member::Unnamed(_) => Err(unsupported(field)),
+            },
+            syn::Expr::Paren(paren) => self.place(&paren.expr),
+            expr => Err(Error::new_spanned(
+                expr,
+                "expression is not assignable in kernel functions",
+            )),
+        }
+    }
+
+    fn path(&self, path: &syn::ExprPath) -> Result<Binding> {
+        let ident = path
+            .path
+            .get_ident()
+            .filter(|_| path.qself.is_none())
+            .ok_or_else(|| unsupported(path))?;
+        self.lookup(&ident.to_string())
+            .ok_or_else(|| Error::new_spanned(ident, "unknown variable in kernel function"))
+    }
+
+    fn expr(&mut self, expr: &syn::Expr) -> Result<Expr> {
+        match expr {
+            syn::Expr::Lit(lit) => lower_lit(&lit.lit).map(Expr::Literal),
+            syn::Expr::Path(path) if path.path.segments.len() > 1 => vector_constant(path),
+            syn::Expr::Path(path) => Ok(match self.path(path)? {
+                Binding::Param(i) => Expr::Load(Place::Param(i)),
+                Binding::Local(id) => Expr::Load(Place::Local(id)),
+                Binding::Shared(i) => Expr::Load(Place::Shared(i)),
+                Binding::Builtin(builtin) => Expr::Builtin(builtin),
+            }),
+            // Components of vectors that are not stored anywhere, such as
+            // `(a + b).x`, are extracted from the value.
+            syn::Expr::Field(field) if !is_place(&field.base) => match &field.member {
+                syn::Member::Named(ident) => {
+                    let component =
+                        vector_component(&ident.to_string()).ok_or_else(|| unsupported(field))?;
+                    Ok(Expr::Swizzle(
+                        Box::new(self.expr(&field.base)?),
+                        vec![component],
+                    ))
+                }
+                syn::Member::Unnamed(_) => Err(unsupported(field)),
+            },
+            syn::Expr::Index(_) | syn::Expr::Field(_) => Ok(Expr::Load(self.place(expr)?)),
+            syn::Expr::Unary(unary) => {
+                let op = match unary.op {
+                    syn::UnOp::Neg(_) => UnaryOp::Neg,
+                    syn::UnOp::Not(_) => UnaryOp::Not,
+                    _ => return Err(unsupported(unary)),
+                };
+                Ok(Expr::Unary(op, Box::new(self.expr(&unary.expr)?)))
+            }
+            syn::Expr::Binary(binary) => {
+                let op = binary_op(&binary.op).ok_or_else(|| unsupported(binary))?;
+                let lhs = self.expr(&binary.left)?;
+                let (rhs, rhs_block) = self.isolated(|this| this.expr(&binary.right))?;
+                let constant = |b| (Expr::Literal(Literal::Bool(b)), Vec::new());
+                Ok(match op {
+                    _ if rhs_block.is_empty() => Expr::Binary(op, Box::new(lhs), Box::new(rhs)),
+                    // The right operand of `&&` and `||`, statements
+                    // included, only runs when it decides the result.
+                    BinaryOp::And => self.branch_value(lhs, (rhs, rhs_block), constant(false)),
+                    BinaryOp::Or => self.branch_value(lhs, constant(true), (rhs, rhs_block)),
+                    _ => {
+                        self.pending.extend(rhs_block);
+                        Expr::Binary(op, Box::new(lhs), Box::new(rhs))
+                    }
+                })
+            }
+            syn::Expr::Cast(cast) => Ok(Expr::Cast(
+                Box::new(self.expr(&cast.expr)?),
+                scalar_type(&cast.ty)?,
+            )),
+            syn::Expr::If(expr_if) => self.if_expr(expr_if),
+            syn::Expr::Call(call) => {
+                if let Some(ty) = constructor(call) {
+                    Ok(Expr::Construct(ty, self.exprs(&call.args)?))
+                } else if let Some(op) = atomic_op(call) {
+                    self.atomic(op, call)
+                } else if let Some(op) = subgroup_op(call) {
+                    self.subgroup(op, call)
+                } else {
+                    intrinsic(call)
+                }
+            }
+            syn::Expr::MethodCall(call) if call.method == "len" && call.args.is_empty() => {
+                Ok(Expr::Len(self.place(&call.receiver)?))
+            }
+            syn::Expr::MethodCall(call) => self.method_call(call),
+            syn::Expr::Paren(paren) => self.expr(&paren.expr),
+            syn::Expr::Group(group) => self.expr(&group.expr),
+            expr => Err(unsupported(expr)),
+        }
+    }
+
+    /// Lowers a call to an atomic intrinsic, whose first argument must be
+    /// `&mut` followed by the element to update.
+    fn atomic(&mut self, op: AtomicOp, call: &syn::ExprCall) -> Result<Expr> {
+        let arity = if op == AtomicOp::CompareExchange {
+            3
+        } else {
+            2
+        };
+        if call.args.len() != arity {
+            return Err(Error::new_spanned(
+                &call.args,
+                format!("expected {arity} arguments to an atomic intrinsic"),
+            ));
+        }
+        let mut args = call.args.iter();
+        let place = match args.next().expect("arity was checked") {
+            syn::Expr::Reference(reference) if reference.mutability.is_some() => {
+                self.place(&reference.expr)?
+            }
+            target => {
+                return Err(Error::new_spanned(
+                    target,
+                    "atomics take the element to update as `&mut element`",
+                ))
+            }
+        };
+        Ok(Expr::Atomic(op, place, self.exprs(args)?))
+    }
+
+    fn subgroup(&mut self, op: SubgroupOp, call: &syn::ExprCall) -> Result<Expr> {
+        let arity = match op {
+            SubgroupOp::Broadcast | SubgroupOp::Shuffle => 2,
+            _ => 1,
+        };
+        if call.args.len() != arity {
+            return Err(Error::new_spanned(
+                &call.args,
+                format!("expected {arity} argument(s) to a subgroup intrinsic"),
+            ));
+        }
+        Ok(Expr::Subgroup(op, self.exprs(&call.args)?))
+    }
+
+    fn exprs<'e>(&mut self, exprs: impl IntoIterator<Item = &'e syn::Expr>) -> Result<Vec<Expr>> {
+        exprs.into_iter().map(|expr| self.expr(expr)).collect()
+    }
+
+    /// Lowers the vector and matrix methods, the math methods of `f32`,
+    /// and swizzles such as `v.xyz()`.
+    fn method_call(&mut self, call: &syn::ExprMethodCall) -> Result<Expr> {
+        let method = call.method.to_string();
+        let (function, arity) = match method.as_str() {
+            "dot" => (Function::Dot, 1),
+            "cross" => (Function::Cross, 1),
+            "length" => (Function::Length, 0),
+            "normalize" => (Function::Normalize, 0),
+            "transpose" => (Function::Transpose, 0),
+            "sqrt" => (Function::Sqrt, 0),
+            "exp" => (Function::Exp, 0),
+            "ln" => (Function::Log, 0),
+            "powf" => (Function::Pow, 1),
+            "sin" => (Function::Sin, 0),
+            "cos" => (Function::Cos, 0),
+            "tan" => (Function::Tan, 0),
+            "floor" => (Function::Floor, 0),
+            "ceil" => (Function::Ceil, 0),
+            "round" => (Function::Round, 0),
+            "mul_add" => (Function::Fma, 2),
+            "min" => (Function::Min, 1),
+            "max" => (Function::Max, 1),
+            "clamp" => (Function::Clamp, 2),
+            "abs" => (Function::Abs, 0),
+            "mix" => (Function::Mix, 2),
+            "length_squared" if call.args.is_empty() => {
+                let receiver = self.expr(&call.receiver)?;
+                return Ok(Expr::Call(Function::Dot, vec![receiver.clone(), receiver]));
+            }
+            name if call.args.is_empty() => {
+                let indices = (2..=4)
+                    .contains(&name.len())
+                    .then(|| {
+                        name.chars()
+                            .map(|c| vector_component(&c.to_string()))
+                            .collect()
+                    })
+                    .flatten()
+                    .ok_or_else(|| unsupported(call))?;
+                return Ok(Expr::Swizzle(Box::new(self.expr(&call.receiver)?), indices));
+            }
+            _ => return Err(unsupported(call)),
+        };
+        if call.args.len() != arity {
+            return Err(unsupported(call));
+        }
+        let mut args = vec![self.expr(&call.receiver)?];
+        args.extend(self.exprs(&call.args)?);
+        Ok(Expr::Call(function, args))
+    }
+
+    // The branches of an `if` used as a value must be single expressions.
+    fn value_block(&mut self, block: &syn::Block) -> Result<Expr> {
+        match block.stmts.as_slice() {
+            [syn::Stmt::Expr(expr, None)] => self.expr(expr),
+            _ => Err(Error::new(
+                block.span(),
+                "`if` expressions used as values must have a single expression in each branch",
+            )),
+        }
+    }
+}
+
+/// Whether `expr` denotes a memory location, which `Lowerer::place` lowers.
+fn is_place(expr: &syn::Expr) -> bool {
+    match expr {
+        syn::Expr::Path(_) => true,
+        syn::Expr::Index(index) => is_place(&index.expr),
+        syn::Expr::Field(field) => is_place(&field.base),
+        syn::Expr::Paren(paren) => is_place(&paren.expr),
+        _ => false,
+    }
+}
+
+/// The kernel type of the vector or matrix type called `name`.
+fn vector_type(name: &str) -> Option<Type> {
+    Some(match name {
+        "Vec2" => Type::Vector(ScalarType::F32, 2),
+        "Vec3" => Type::Vector(ScalarType::F32, 3),
+        "Vec4" => Type::Vector(ScalarType::F32, 4),
+        "Mat2" => Type::Matrix(ScalarType::F32, 2),
+        "Mat3" => Type::Matrix(ScalarType::F32, 3),
+        "Mat4" => Type::Matrix(ScalarType::F32, 4),
+        _ => return None,
+    })
+}
+
+/// The vector or matrix type and associated item named by the last two
+/// segments of `path`, such as `Vec3` and `new` in `shared_type::Vec3::new`.
+fn vector_item(path: &syn::ExprPath) -> Option<(Type, String)> {
+    if path.qself.is_some() {
+        return None;
+    }
+    let segments: Vec<_> = path.path.segments.iter().collect();
+    let [.., ty, item] = segments.as_slice() else {
+        return None;
+    };
+    Some((vector_type(&ty.ident.to_string())?, item.ident.to_string()))
+}
+
+/// The type built by a call to a vector or matrix constructor:
+/// `VecN::new`, `VecN::splat` or `MatN::from_cols`.
+fn constructor(call: &syn::ExprCall) -> Option<Type> {
+    let syn::Expr::Path(path) = &*call.func else {
+        return None;
+    };
+    match vector_item(path)? {
+        (ty @ Type::Vector(..), item) if item == "new" || item == "splat" => Some(ty),
+        (ty @ Type::Matrix(..), item) if item == "from_cols" => Some(ty),
+        _ => None,
+    }
+}
+
+/// Lowers the `ZERO` and `ONE` vectors, and the `ZERO` and `IDENTITY`
+/// matrices.
+fn vector_constant(path: &syn::ExprPath) -> Result<Expr> {
+    let float = |value| Expr::Literal(Literal::Float(value, Some(ScalarType::F32)));
+    let splat = |ty: &Type, value| Expr::Construct(ty.clone(), vec![float(value)]);
+    let (ty, item) = vector_item(path).ok_or_else(|| unsupported(path))?;
+    match (&ty, item.as_str()) {
+        (Type::Vector(..), "ZERO") => Ok(splat(&ty, 0.0)),
+        (Type::Vector(..), "ONE") => Ok(splat(&ty, 1.0)),
+        (Type::Matrix(scalar, len), "ZERO" | "IDENTITY") => {
+            let columns = (0..*len)
+                .map(|column| {
+                    let components = (0..*len)
+                        .map(|row| float(f64::from(u8::from(item == "IDENTITY" && row == column))))
+                        .collect();
+                    Expr::Construct(Type::Vector(*scalar, *len), components)
+                })
+                .collect();
+            Ok(Expr::Construct(ty, columns))
+        }
+        _ => Err(unsupported(path)),
+    }
+}
+
+/// The name of the function `call` calls, by which intrinsics are matched
+/// whatever path they are imported through.
+fn callee_name(call: &syn::ExprCall) -> Option<String> {
+    match &*call.func {
+        syn::Expr::Path(path) if path.qself.is_none() => path
+            .path
+            .segments
+            .last()
+            .map(|segment| segment.ident.to_string()),
+        _ => None,
+    }
+}
+
+/// The call to `shared_array` that `expr` is, if any.
+fn shared_array(expr: &syn::Expr) -> Option<&syn::ExprCall> {
+    match expr {
+        syn::Expr::Call(call) if callee_name(call).as_deref() == Some("shared_array") => Some(call),
+        _ => None,
+    }
+}
+
+/// The barrier or fence `call` is a call to, if any.
+fn sync_op(call: &syn::ExprCall) -> Option<SyncOp> {
+    match callee_name(call)?.as_str() {
+        "block_barrier" => Some(SyncOp::BlockBarrier),
+        "memory_fence_block" => Some(SyncOp::BlockFence),
+        "memory_fence_device" => Some(SyncOp::DeviceFence),
+        _ => None,
+    }
+}
+
+/// The atomic operation `call` is a call to, if any.
+fn atomic_op(call: &syn::ExprCall) -> Option<AtomicOp> {
+    Some(match callee_name(call)?.as_str() {
+        "atomic_add" => AtomicOp::Add,
+        "atomic_min" => AtomicOp::Min,
+        "atomic_max" => AtomicOp::Max,
+        "atomic_and" => AtomicOp::And,
+        "atomic_or" => AtomicOp::Or,
+        "atomic_xor" => AtomicOp::Xor,
+        "atomic_exchange" => AtomicOp::Exchange,
+        "atomic_compare_exchange" => AtomicOp::CompareExchange,
+        _ => return None,
+    })
+}
+
+/// The subgroup operation `call` is a call to, if any.
+fn subgroup_op(call: &syn::ExprCall) -> Option<SubgroupOp> {
+    Some(match callee_name(call)?.as_str() {
+        "subgroup_reduce_add" => SubgroupOp::ReduceAdd,
+        "subgroup_reduce_min" => SubgroupOp::ReduceMin,
+        "subgroup_reduce_max" => SubgroupOp::ReduceMax,
+        "subgroup_inclusive_add" => SubgroupOp::InclusiveAdd,
+        "subgroup_exclusive_add" => SubgroupOp::ExclusiveAdd,
+        "subgroup_broadcast" => SubgroupOp::Broadcast,
+        "subgroup_shuffle" => SubgroupOp::Shuffle,
+        "subgroup_ballot" => SubgroupOp::Ballot,
+        _ => return None,
+    })
+}
+
+/// The `[T; N]` type of `shared_array::<T, N>()`, if spelled out.
+fn shared_array_type(call: &syn::ExprCall) -> Result<Option<Type>> {
+    let syn::Expr::Path(path) = &*call.func else {
+        return Ok(None);
+    };
+    let syn::PathArguments::AngleBracketed(generics) = &path
+        .path
+        .segments
+        .last()
+        .expect("path has a segment")
+        .arguments
+    else {
+        return Ok(None);
+    };
+    let args: Vec<_> = generics.args.iter().collect();
+    let (elem, len) = match args[..] {
+        [syn::GenericArgument::Type(elem), len] => (elem, len),
+        _ => {
+            return Err(Error::new_spanned(
+                generics,
+                "expected `shared_array::<T, N>()`",
+            ))
+        }
+    };
+    // A const generic length is parsed as a type argument.
+    let array: syn::Type = match len {
+        syn::GenericArgument::Const(len) => syn::parse_quote!([#elem; #len]),
+        syn::GenericArgument::Type(len) => syn::parse_quote!([#elem; #len]),
+        len => return Err(Error::new_spanned(len, "expected an array length")),
+    };
+    lower_type(&array).map(Some)
+}
+
+/// Lowers a call to one of the thread-index intrinsics of
+/// `shared_type::intrinsics`.
+fn intrinsic(call: &syn::ExprCall) -> Result<Expr> {
+    if sync_op(call).is_some() {
+        return Err(Error::new_spanned(
+            call,
+            "barriers and fences can only be called as statements",
+        ));
+    }
+    let builtin = match callee_name(call).as_deref() {
+        Some("global_id") => Builtin::GlobalId,
+        Some("local_id") => Builtin::LocalId,
+        Some("block_id") => Builtin::BlockId,
+        Some("block_dim") => Builtin::ThreadBlockSize,
+        Some("grid_dim") => Builtin::NumThreadBlocks,
+        Some("subgroup_size") => Builtin::SubgroupSize,
+        Some("subgroup_id") => Builtin::SubgroupId,
+        Some("subgroup_local_id") => Builtin::SubgroupLocalId,
+        Some("shared_array") => {
+            return Err(Error::new_spanned(
+                call,
+                "`shared_array` can only initialize a `let` binding",
+            ))
+        }
+        _ => {
+            return Err(Error::new_spanned(
+                &call.func,
+                "only thread-index intrinsics can be called in kernel functions",
+            ))
+        }
+    };
+    if !call.args.is_empty() {
+        return Err(Error::new_spanned(
+            &call.args,
+            "thread-index intrinsics take no arguments",
+        ));
+    }
+    Ok(Expr::Builtin(builtin))
+}
+
+fn lower_lit(lit: &Lit) -> Result<Literal> {
+    let suffix_type = |suffix: &str| {
+        if suffix.is_empty() {
+            Ok(None)
+        } else {
+            ScalarType::from_rust_name(suffix).map(Some).ok_or_else(|| {
+                Error::new_spanned(lit, "literal type not supported in kernel functions")
+            })
+        }
+    };
+    match lit {
+        Lit::Bool(b) => Ok(Literal::Bool(b.value)),
+        Lit::Int(int) => Ok(Literal::Int(
+            int.base10_parse()?,
+            suffix_type(int.suffix())?,
+        )),
+        Lit::Float(float) => Ok(Literal::Float(
+            float.base10_parse()?,
+            suffix_type(float.suffix())?,
+        )),
+        lit => Err(unsupported(lit)),
+    }
+}
+
+fn binary_op(op: &syn::BinOp) -> Option<BinaryOp> {
+    Some(match op {
+        syn::BinOp::Add(_) => BinaryOp::Add,
+        syn::BinOp::Sub(_) => BinaryOp::Sub,
+        syn::BinOp::Mul(_) => BinaryOp::Mul,
+        syn::BinOp::Div(_) => BinaryOp::Div,
+        syn::BinOp::Rem(_) => BinaryOp::Rem,
+        syn::BinOp::And(_) => BinaryOp::And,
+        syn::BinOp::Or(_) => BinaryOp::Or,
+        syn::BinOp::BitXor(_) => BinaryOp::BitXor,
+        syn::BinOp::BitAnd(_) => BinaryOp::BitAnd,
+        syn::BinOp::BitOr(_) => BinaryOp::BitOr,
+        syn::BinOp::Shl(_) => BinaryOp::Shl,
+        syn::BinOp::Shr(_) => BinaryOp::Shr,
+        syn::BinOp::Eq(_) => BinaryOp::Eq,
+        syn::BinOp::Lt(_) => BinaryOp::Lt,
+        syn::BinOp::Le(_) => BinaryOp::Le,
+        syn::BinOp::Ne(_) => BinaryOp::Ne,
+        syn::BinOp::Ge(_) => BinaryOp::Ge,
+        syn::BinOp::Gt(_) => BinaryOp::Gt,
+        _ => return None,
+    })
+}
+
+/// Whether every index of `place` can be evaluated more than once.
+fn indices_speculatable(place: &Place) -> bool {
+    match place {
+        Place::Param(_) | Place::Local(_) | Place::Shared(_) => true,
+        Place::Index(base, index) => indices_speculatable(base) && index.is_speculatable(),
+        Place::Field(base, _) => indices_speculatable(base),
+    }
+}
+
+// Maps `a op= b` to the `op` it applies.
+fn compound_op(op: &syn::BinOp) -> Option<BinaryOp> {
+    Some(match op {
+        syn::BinOp::AddAssign(_) => BinaryOp::Add,
+        syn::BinOp::SubAssign(_) => BinaryOp::Sub,
+        syn::BinOp::MulAssign(_) => BinaryOp::Mul,
+        syn::BinOp::DivAssign(_) => BinaryOp::Div,
+        syn::BinOp::RemAssign(_) => BinaryOp::Rem,
+        syn::BinOp::BitXorAssign(_) => BinaryOp::BitXor,
+        syn::BinOp::BitAndAssign(_) => BinaryOp::BitAnd,
+        syn::BinOp::BitOrAssign(_) => BinaryOp::BitOr,
+        syn::BinOp::ShlAssign(_) => BinaryOp::Shl,
+        syn::BinOp::ShrAssign(_) => BinaryOp::Shr,
+        _ => return None,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use syn::parse_quote;
+
+    #[test]
+    fn test_lower_kernel() {
+        let item: ItemFn = parse_quote! {
+            fn k(a: [f32; 4], mut out: [f32; 4], num_thread_blocks: u32, thread_block_size: u32) {
+                let n = num_thread_blocks * thread_block_size;
+                for i in 0..4 {
+                    if n > 1 {
+                        out[i] += a[i] * 2.0;
+                    }
+                }
+            }
+        };
+        let kernel = lower_kernel(&item).unwrap();
+        assert_eq!(kernel.params.len(), 2);
+        assert!(!kernel.params[0].mutable);
+        assert!(kernel.params[1].mutable);
+        // `n`, `i` and the right operand of `+=`, which indexes `a`.
+        assert_eq!(kernel.locals.len(), 3);
+        assert_eq!(
+            kernel.body[0],
+            Stmt::Let {
+                local: LocalId(0),
+                init: Some(Expr::Binary(
+                    BinaryOp::Mul,
+                    Box::new(Expr::Builtin(Builtin::NumThreadBlocks)),
+                    Box::new(Expr::Builtin(Builtin::ThreadBlockSize)),
+                )),
+            }
+        );
+        assert!(matches!(
+            kernel.body[1],
+            Stmt::For {
+                inclusive: false,
+                ..
+            }
+        ));
+    }
+
+    #[test]
+    fn test_lower_value_ifs() {
+        let item: ItemFn = parse_quote! {
+            fn k(a: &[u32], num_thread_blocks: u32, thread_block_size: u32) {
+                let x = if a.len() > 1 { 1 } else { 2 };
+                let y = if x < a.len() { a[x] } else { 0 };
+            }
+        };
+        let kernel = lower_kernel(&item).unwrap();
+        let int = |value| Expr::Literal(Literal::Int(value, None));
+        let x = || Box::new(Expr::Load(Place::Local(LocalId(0))));
+        let Stmt::Let {
+            init: Some(Expr::Select(..)),
+            ..
+        } = &kernel.body[0]
+        else {
+            panic!("expected a select, got {:?}", kernel.body[0]);
+        };
+        // The guarded read becomes a branch storing into a temporary.
+        let temp = Place::Local(LocalId(1));
+        assert_eq!(
+            kernel.body[1..],
+            [
+                Stmt::Let {
+                    local: LocalId(1),
+                    init: None,
+                },
+                Stmt::If {
+                    cond: Expr::Binary(BinaryOp::Lt, x(), Box::new(Expr::Len(Place::Param(0)))),
+                    then_block: vec![Stmt::Assign {
+                        place: temp.clone(),
+                        value: Expr::Load(Place::Index(Box::new(Place::Param(0)), x())),
+                    }],
+                    else_block: vec![Stmt::Assign {
+                        place: temp.clone(),
+                        value: int(0),
+                    }],
+                },
+                Stmt::Let {
+                    local: LocalId(2),
+                    init: Some(Expr::Load(temp)),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_lower_compound_assign_with_atomic_index() {
+        let item: ItemFn = parse_quote! {
+            fn k(c: &mut [u32], a: &mut [u32], num_thread_blocks: u32, thread_block_size: u32) {
+                a[global_id() as usize] += 1;
+                a[atomic_add(&mut c[0], 1) as usize] += a[1];
+                c[0] += atomic_add(&mut c[0], 1);
+            }
+        };
+        let kernel = lower_kernel(&item).unwrap();
+        // Speculatable indices are evaluated twice, as before.
+        let Stmt::Assign { place, .. } = &kernel.body[0] else {
+            panic!("expected an assignment, got {:?}", kernel.body[0]);
+        };
+        assert!(matches!(place, Place::Index(_, index) if matches!(**index, Expr::Cast(..))));
+        // The right operand, then the atomic index, are evaluated once.
+        let [Stmt::Let {
+            local: rhs,
+            init: Some(Expr::Load(Place::Index(..))),
+        }, Stmt::Let {
+            local: index,
+            init: Some(Expr::Cast(..)),
+        }, Stmt::Assign { place, value }] = &kernel.body[1..4]
+        else {
+            panic!("expected hoisted operands, got {:?}", &kernel.body[1..4]);
+        };
+        let temp = |local| Box::new(Expr::Load(Place::Local(local)));
+        let place_expected = Place::Index(Box::new(Place::Param(1)), temp(*index));
+        assert_eq!(*place, place_expected);
+        assert_eq!(
+            *value,
+            Expr::Binary(
+                BinaryOp::Add,
+                Box::new(Expr::Load(place_expected)),
+                temp(*rhs)
+            )
+        );
+        // A right operand with effects runs before the place is loaded.
+        let [Stmt::Let {
+            local: rhs,
+            init: Some(Expr::Atomic(..)),
+        }, Stmt::Assign { value, .. }] = &kernel.body[4..]
+        else {
+            panic!(
+                "expected a hoisted right operand, got {:?}",
+                &kernel.body[4..]
+            );
+        };
+        assert!(
+            matches!(value, Expr::Binary(BinaryOp::Add, _, rhs_value) if **rhs_value == *temp(*rhs))
+        );
+    }
+
+    #[test]
+    fn test_lower_intrinsics() {
+        let item: ItemFn = parse_quote! {
+            fn k(mut out: [u32; 4], num_thread_blocks: u32, thread_block_size: u32) {
+                out[global_id()] = intrinsics::block_dim();
+            }
+        };
+        let kernel = lower_kernel(&item).unwrap();
+        assert_eq!(
+            kernel.body[0],
+            Stmt::Assign {
+                place: Place::Index(
+                    Box::new(Place::Param(0)),
+                    Box::new(Expr::Builtin(Builtin::GlobalId)),
+                ),
+                value: Expr::Builtin(Builtin::ThreadBlockSize),
+            }
+        );
+
+        let item: ItemFn = parse_quote! {
+            fn k(mut out: [u32; 4], num_thread_blocks: u32, thread_block_size: u32) {
+                out[0] = helper();
+            }
+        };
+        assert!(lower_kernel(&item).is_err());
+    }
+
+    #[test]
+    fn test_lower_shared_arrays() {
+        let item: ItemFn = parse_quote! {
+            fn k<const N: usize>(mut out: [f32; 4], num_thread_blocks: u32, thread_block_size: u32) {
+                let mut tile = intrinsics::shared_array::<f32, 4>();
+                let mut sums: [u32; 2] = shared_array();
+                let mut wide = shared_array::<Particle, N>();
+                tile[local_id()] = out[global_id()];
+                out[global_id()] = tile[0];
+            }
+        };
+        let kernel = lower_kernel(&item).unwrap();
+        let f32_ty = Type::Scalar(ScalarType::F32);
+        assert_eq!(
+            kernel.shared,
+            [
+                Shared {
+                    name: "tile".into(),
+                    ty: Type::Array(Box::new(f32_ty), 4),
+                },
+                Shared {
+                    name: "sums".into(),
+                    ty: Type::Array(Box::new(Type::Scalar(ScalarType::U32)), 2),
+                },
+                Shared {
+                    name: "wide".into(),
+                    ty: Type::Named("[Particle ; N]".into()),
+                },
+            ]
+        );
+        assert!(kernel.locals.is_empty());
+        assert_eq!(
+            kernel.body[0],
+            Stmt::Assign {
+                place: Place::Index(
+                    Box::new(Place::Shared(0)),
+                    Box::new(Expr::Builtin(Builtin::LocalId)),
+                ),
+                value: Expr::Load(Place::Index(
+                    Box::new(Place::Param(0)),
+                    Box::new(Expr::Builtin(Builtin::GlobalId)),
+                )),
+            }
+        );
+
+        for body in [
+            quote::quote! { let tile = shared_array(); },
+            quote::quote! { let (a, b) = shared_array::<f32, 4>(); },
+            quote::quote! { out[0] = shared_array::<f32, 4>()[0]; },
+        ] {
+            let item: ItemFn = parse_quote! {
+                fn k(mut out: [f32; 4], num_thread_blocks: u32, thread_block_size: u32) { #body }
+            };
+            assert!(lower_kernel(&item).is_err());
+        }
+    }
+
+    #[test]
+    fn test_lower_barriers() {
+        let item: ItemFn = parse_quote! {
+            fn k(num_thread_blocks: u32, thread_block_size: u32) {
+                block_barrier();
+                intrinsics::memory_fence_block();
+                memory_fence_device();
+            }
+        };
+        assert_eq!(
+            lower_kernel(&item).unwrap().body,
+            [
+                Stmt::Sync(SyncOp::BlockBarrier),
+                Stmt::Sync(SyncOp::BlockFence),
+                Stmt::Sync(SyncOp::DeviceFence),
+            ]
+        );
+
+        let item: ItemFn = parse_quote! {
+            fn k(num_thread_blocks: u32, thread_block_size: u32) {
+                let x = block_barrier();
+            }
+        };
+        assert!(lower_kernel(&item).is_err());
+    }
+
+    #[test]
+    fn test_lower_atomics() {
+        let item: ItemFn = parse_quote! {
+            fn k(bins: &mut [u32], num_thread_blocks: u32, thread_block_size: u32) {
+                atomic_add(&mut bins[0], 1);
+                let old = intrinsics::atomic_compare_exchange(&mut bins[1], 0, 2);
+            }
+        };
+        let kernel = lower_kernel(&item).unwrap();
+        let bin = |index| {
+            Place::Index(
+                Box::new(Place::Param(0)),
+                Box::new(Expr::Literal(Literal::Int(index, None))),
+            )
+        };
+        let int = |value| Expr::Literal(Literal::Int(value, None));
+        assert_eq!(
+            kernel.body,
+            [
+                Stmt::Expr(Expr::Atomic(AtomicOp::Add, bin(0), vec![int(1)])),
+                Stmt::Let {
+                    local: LocalId(0),
+                    init: Some(Expr::Atomic(
+                        AtomicOp::CompareExchange,
+                        bin(1),
+                        vec![int(0), int(2)],
+                    )),
+                },
+            ]
+        );
+
+        for body in [
+            quote::quote! { atomic_add(bins[0], 1); },
+            quote::quote! { atomic_max(&mut bins[0]); },
+        ] {
+            let item: ItemFn = parse_quote! {
+                fn k(bins: &mut [u32], num_thread_blocks: u32, thread_block_size: u32) {
+                    #body
+                }
+            };
+            assert!(lower_kernel(&item).is_err());
+        }
+    }
+
+    #[test]
+    fn test_lower_subgroups() {
+        let item: ItemFn = parse_quote! {
+            fn k(x: f32, num_thread_blocks: u32, thread_block_size: u32) {
+                let lane = subgroup_local_id();
+                let first = intrinsics::subgroup_broadcast(x, 0);
+                let sum = subgroup_reduce_add(x);
+            }
+        };
+        let kernel = lower_kernel(&item).unwrap();
+        let x = || Expr::Load(Place::Param(0));
+        let inits: Vec<_> = kernel
+            .body
+            .iter()
+            .map(|stmt| match stmt {
+                Stmt::Let { init, .. } => init.clone().unwrap(),
+                stmt => panic!("unexpected {stmt:?}"),
+            })
+            .collect();
+        assert_eq!(
+            inits,
+            [
+                Expr::Builtin(Builtin::SubgroupLocalId),
+                Expr::Subgroup(
+                    SubgroupOp::Broadcast,
+                    vec![x(), Expr::Literal(Literal::Int(0, None))]
+                ),
+                Expr::Subgroup(SubgroupOp::ReduceAdd, vec![x()]),
+            ]
+        );
+
+        let item: ItemFn = parse_quote! {
+            fn k(x: f32, num_thread_blocks: u32, thread_block_size: u32) {
+                let y = subgroup_shuffle(x);
+            }
+        };
+        assert!(lower_kernel(&item).is_err());
+    }
+
+    #[test]
+    fn test_lower_math_methods() {
+        let item: ItemFn = parse_quote! {
+            fn k(x: f32, num_thread_blocks: u32, thread_block_size: u32) {
+                let y = x.sqrt().mul_add(x, 1.0).clamp(0.0, x.ln());
+            }
+        };
+        let kernel = lower_kernel(&item).unwrap();
+        let float = |value| Expr::Literal(Literal::Float(value, None));
+        let x = || Expr::Load(Place::Param(0));
+        let fma = Expr::Call(
+            Function::Fma,
+            vec![Expr::Call(Function::Sqrt, vec![x()]), x(), float(1.0)],
+        );
+        assert_eq!(
+            kernel.body[0],
+            Stmt::Let {
+                local: LocalId(0),
+                init: Some(Expr::Call(
+                    Function::Clamp,
+                    vec![fma, float(0.0), Expr::Call(Function::Log, vec![x()])],
+                )),
+            }
+        );
+
+        let item: ItemFn = parse_quote! {
+            fn k(x: f32, num_thread_blocks: u32, thread_block_size: u32) {
+                let y = x.powf();
+            }
+        };
+        assert!(lower_kernel(&item).is_err());
+    }
+
+    #[test]
+    fn test_lower_slices() {
+        let item: ItemFn = parse_quote! {
+            fn k(x: &[f32], y: &mut [f32], num_thread_blocks: u32, thread_block_size: u32) {
+                let n = y.len();
+            }
+        };
+        let kernel = lower_kernel(&item).unwrap();
+        let slice = Type::Slice(Box::new(Type::Scalar(ScalarType::F32)));
+        assert_eq!(
+            (&kernel.params[0].ty, kernel.params[0].mutable),
+            (&slice, false)
+        );
+        assert_eq!(
+            (&kernel.params[1].ty, kernel.params[1].mutable),
+            (&slice, true)
+        );
+        assert_eq!(
+            kernel.body[0],
+            Stmt::Let {
+                local: LocalId(0),
+                init: Some(Expr::Len(Place::Param(1))),
+            }
+        );
+    }
+
+    #[test]
+    fn test_lower_rejects_return_value() {
+        let item: ItemFn = parse_quote! {
+            fn k(a: i32, num_thread_blocks: u32, thread_block_size: u32) -> i32 { a }
+        };
+        assert!(lower_kernel(&item).is_err());
+    }
+
+    #[test]
+    fn test_lower_shadowing() {
+        let item: ItemFn = parse_quote! {
+            fn k(num_thread_blocks: u32, thread_block_size: u32) {
+                let x = 1;
+                let x = x + 1;
+            }
+        };
+        let kernel = lower_kernel(&item).unwrap();
+        assert_eq!(
+            kernel.body[1],
+            Stmt::Let {
+                local: LocalId(1),
+                init: Some(Expr::Binary(
+                    BinaryOp::Add,
+                    Box::new(Expr::Load(Place::Local(LocalId(0)))),
+                    Box::new(Expr::Literal(Literal::Int(1, None))),
+                )),
+            }
+        );
+    }
+
+    #[test]
+    fn test_lower_vectors() {
+        let item: ItemFn = parse_quote! {
+            fn k(mut out: [Vec3; 4], num_thread_blocks: u32, thread_block_size: u32) {
+                let v = Vec3::new(1.0, 2.0, 3.0);
+                out[0] = (v + Vec3::ONE).zyx();
+                out[1].x = v.dot(v) + (v * 2.0).y;
+            }
+        };
+        let kernel = lower_kernel(&item).unwrap();
+        let vec3 = Type::Vector(ScalarType::F32, 3);
+        let float = |value| Expr::Literal(Literal::Float(value, None));
+        let v = || Expr::Load(Place::Local(LocalId(0)));
+        assert_eq!(
+            kernel.body[0],
+            Stmt::Let {
+                local: LocalId(0),
+                init: Some(Expr::Construct(
+                    vec3.clone(),
+                    vec![float(1.0), float(2.0), float(3.0)]
+                )),
+            }
+        );
+        let Stmt::Assign { value, .. } = &kernel.body[1] else {
+            panic!("expected an assignment");
+        };
+        let one = Expr::Construct(
+            vec3,
+            vec![Expr::Literal(Literal::Float(1.0, Some(ScalarType::F32)))],
+        );
+        assert_eq!(
+            *value,
+            Expr::Swizzle(
+                Box::new(Expr::Binary(BinaryOp::Add, Box::new(v()), Box::new(one))),
+                vec![2, 1, 0],
+            )
+        );
+        let Stmt::Assign { place, value } = &kernel.body[2] else {
+            panic!("expected an assignment");
+        };
+        assert!(matches!(place, Place::Field(_, name) if name == "x"));
+        assert_eq!(
+            *value,
+            Expr::Binary(
+                BinaryOp::Add,
+                Box::new(Expr::Call(Function::Dot, vec![v(), v()])),
+                Box::new(Expr::Swizzle(
+                    Box::new(Expr::Binary(
+                        BinaryOp::Mul,
+                        Box::new(v()),
+                        Box::new(float(2.0))
+                    )),
+                    vec![1],
+                )),
+            )
+        );
+    }
+}
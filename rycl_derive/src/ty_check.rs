@@ -1,29 +1,84 @@
 use std::collections::HashSet;
 
-use syn::Type;
+use syn::{GenericArgument, PathArguments, Type};
 
 pub(crate) type GenericParamSet = HashSet<String>;
 
-pub(crate) static ALLOWED_PRIMITIVE_TYPES: [&str; 3] = ["u32", "i32", "f32"];
+pub(crate) static ALLOWED_PRIMITIVE_TYPES: [&str; 12] = [
+    "bool", "u8", "i8", "u16", "i16", "u32", "i32", "u64", "i64", "f16", "f32", "f64",
+];
+
+// Rust primitives without a device representation
+static UNSUPPORTED_PRIMITIVE_TYPES: [&str; 6] = ["u128", "i128", "usize", "isize", "char", "str"];
+
+// Standard library types that own or borrow host memory, and so have no
+// device representation even behind a path
+static HOST_TYPES: [&str; 17] = [
+    "Vec",
+    "VecDeque",
+    "String",
+    "Box",
+    "Rc",
+    "Arc",
+    "Cell",
+    "RefCell",
+    "Mutex",
+    "RwLock",
+    "Option",
+    "Result",
+    "HashMap",
+    "HashSet",
+    "BTreeMap",
+    "BTreeSet",
+    "PhantomData",
+];
 
 pub(crate) fn is_valid_type(ty: &Type, generic_param_set: &GenericParamSet) -> bool {
     match ty {
-        Type::Path(type_path) => {
+        Type::Path(type_path) if type_path.qself.is_none() => {
             if let Some(segment) = type_path.path.segments.last() {
                 let ident = segment.ident.to_string();
                 // we dont check the generic type here as the kernel function is enforced to have a trait bound of KernelStructMarker for the generic type
-                return ALLOWED_PRIMITIVE_TYPES.contains(&ident.as_str())
-                    || generic_param_set.contains(&ident);
+                if ALLOWED_PRIMITIVE_TYPES.contains(&ident.as_str())
+                    || generic_param_set.contains(&ident)
+                {
+                    return true;
+                }
+                if UNSUPPORTED_PRIMITIVE_TYPES.contains(&ident.as_str())
+                    || HOST_TYPES.contains(&ident.as_str())
+                {
+                    return false;
+                }
+                // Any other path names a kernel struct, which the generated code
+                // requires to implement `KernelType`
+                return match &segment.arguments {
+                    PathArguments::None => true,
+                    PathArguments::AngleBracketed(args) => args.args.iter().all(|arg| match arg {
+                        GenericArgument::Type(ty) => is_valid_type(ty, generic_param_set),
+                        GenericArgument::Const(_) => true,
+                        _ => false,
+                    }),
+                    PathArguments::Parenthesized(_) => false,
+                };
             }
             false
         }
-        Type::Array(arr) => {
-            return is_valid_type(&*arr.elem, generic_param_set);
-        }
+        Type::Array(arr) => is_valid_type(&arr.elem, generic_param_set),
         _ => false,
     }
 }
 
+// Kernel arguments may also be `&[T]` or `&mut [T]`, passed as runtime-sized buffers
+pub(crate) fn is_valid_arg_type(ty: &Type, generic_param_set: &GenericParamSet) -> bool {
+    match ty {
+        Type::Reference(reference) => match &*reference.elem {
+            Type::Slice(slice) => is_valid_type(&slice.elem, generic_param_set),
+            _ => false,
+        },
+        ty => is_valid_type(ty, generic_param_set),
+    }
+}
+
 // Helper function to check if the argument type is `u32`
 pub(crate) fn is_u32(ty: &Type) -> bool {
     if let Type::Path(type_path) = ty {
@@ -35,6 +90,7 @@ pub(crate) fn is_u32(ty: &Type) -> bool {
     false
 }
 
+#[cfg(test)]
 mod test {
     use syn::parse_quote;
     #[test]
@@ -42,8 +98,31 @@ mod test {
         let generic_param_set = std::collections::HashSet::new();
         use super::is_valid_type;
         let valid_type = parse_quote! { u32 };
-        let invalid_type = parse_quote! { u64 };
-        assert_eq!(is_valid_type(&valid_type, &generic_param_set), true);
-        assert_eq!(is_valid_type(&invalid_type, &generic_param_set), false);
+        let invalid_type = parse_quote! { u128 };
+        assert!(is_valid_type(&valid_type, &generic_param_set));
+        assert!(!is_valid_type(&invalid_type, &generic_param_set));
+        let nested = parse_quote! { [particles::Particle<f32>; 4] };
+        let invalid_nested = parse_quote! { Particle<usize> };
+        let reference = parse_quote! { &Particle };
+        assert!(is_valid_type(&nested, &generic_param_set));
+        assert!(!is_valid_type(&invalid_nested, &generic_param_set));
+        assert!(!is_valid_type(&reference, &generic_param_set));
+        let container = parse_quote! { std::vec::Vec<f32> };
+        let nested_container = parse_quote! { [Particle<Option<f32>>; 2] };
+        let vector = parse_quote! { [Vec3; 2] };
+        assert!(!is_valid_type(&container, &generic_param_set));
+        assert!(!is_valid_type(&nested_container, &generic_param_set));
+        assert!(is_valid_type(&vector, &generic_param_set));
+    }
+
+    #[test]
+    fn test_is_valid_arg_type() {
+        let generic_param_set = std::collections::HashSet::new();
+        use super::{is_valid_arg_type, is_valid_type};
+        let slice = parse_quote! { &mut [f32] };
+        let reference = parse_quote! { &f32 };
+        assert!(is_valid_arg_type(&slice, &generic_param_set));
+        assert!(!is_valid_type(&slice, &generic_param_set));
+        assert!(!is_valid_arg_type(&reference, &generic_param_set));
     }
 }
@@ -5,8 +5,13 @@ struct Test {
 }
 #[kernel_fn]
 fn test_kernel_func(a: u32, b: i32, t: Test, num_thread_blocks: u32, thread_block_size: u32) {
-    println!("Hello from kernel function");
+    let c = a as i32 + b;
 }
 
 fn main() {
-}
\ No newline at end of file
+}
+
+#[kernel_fn]
+fn test_host_args(v: Vec<f32>, r: &u32, num_thread_blocks: u32, thread_block_size: u32) {
+    let _ = (v, r);
+}
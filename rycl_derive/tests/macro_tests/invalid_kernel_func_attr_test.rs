@@ -0,0 +1,12 @@
+use rycl_derive::kernel_fn;
+
+#[kernel_fn(workgroup_size = 0)]
+fn empty_blocks(a: u32, num_thread_blocks: u32, thread_block_size: u32) {}
+
+#[kernel_fn(threads = 64)]
+fn unknown_argument(a: u32, num_thread_blocks: u32, thread_block_size: u32) {}
+
+#[kernel_fn(spirv)]
+fn any_block_size(a: u32, num_thread_blocks: u32, thread_block_size: u32) {}
+
+fn main() {}
@@ -0,0 +1,14 @@
+use rycl_derive::kernel_fn;
+
+#[kernel_fn]
+fn test_kernel_func(a: u32, b: i32, num_thread_blocks: u32, thread_block_size: u32) {
+    println!("Hello from kernel function");
+}
+
+#[kernel_fn]
+fn test_kernel_func_return(a: u32, num_thread_blocks: u32, thread_block_size: u32) -> u32 {
+    a
+}
+
+fn main() {
+}
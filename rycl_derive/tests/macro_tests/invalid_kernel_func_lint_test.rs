@@ -0,0 +1,21 @@
+#![deny(unused)]
+use rycl_derive::kernel_fn;
+
+// The launch configuration arguments and the writes to `mut` arrays are
+// exempt from lints, the rest of the function is not.
+#[kernel_fn]
+fn scale(factor: f32, mut out: [f32; 4], num_thread_blocks: u32, thread_block_size: u32) {
+    for i in 0..4 {
+        out[i] = out[i] * factor;
+    }
+}
+
+#[kernel_fn]
+fn forgotten(factor: f32, mut out: [f32; 4], num_thread_blocks: u32, thread_block_size: u32) {
+    let doubled = factor * 2.0;
+    out[0] = factor;
+}
+
+fn main() {
+    let _ = (ScaleKernel, ForgottenKernel);
+}
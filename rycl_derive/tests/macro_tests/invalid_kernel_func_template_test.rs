@@ -5,7 +5,7 @@ struct Test {
 }
 #[kernel_fn]
 fn test_kernel_func<T>(a: u32, b: i32, t: T, num_thread_blocks: u32, thread_block_size: u32) {
-    println!("Hello from kernel function");
+    let c = a as i32 + b;
 }
 
 fn main() {
@@ -6,9 +6,10 @@ pub struct Test {
     pub a: u32,
     pub b: i32,
     pub c: f32,
-    pub d: f64,
+    pub d: u128,
 }
 
-fn main() {
-    
-}
\ No newline at end of file
+#[kernel_struct]
+pub struct Pair(u32, u32);
+
+fn main() {}
@@ -0,0 +1,44 @@
+use rycl_derive::kernel_fn;
+use shared_type::ir::{typeck, ScalarType, Type};
+use shared_type::KernelFn;
+
+#[kernel_fn]
+fn step(
+    mut positions: [f32; 12],
+    mut masses: [f32; 4],
+    scale: f32,
+    num_thread_blocks: u32,
+    thread_block_size: u32,
+) {
+    let steps = num_thread_blocks * thread_block_size;
+    for i in 0..4 {
+        let mut j = 0;
+        while j < 3 {
+            positions[i * 3 + j] += scale * masses[i];
+            j += 1;
+        }
+        if steps > 1 {
+            masses[i] = if scale > 0.0 { scale } else { -scale };
+        }
+    }
+}
+
+fn main() {
+    let mut kernel = step_ir();
+    assert_eq!(StepKernel.entry_point(), "step");
+    assert_eq!(StepKernel.args()[1].binding, 1);
+    assert_eq!(StepKernel.workgroup_size(), None);
+    assert_eq!(std::mem::size_of::<StepKernel>(), 0);
+    assert_eq!(kernel.name, "step");
+    assert_eq!(kernel.params.len(), 3);
+    assert!(kernel.params[0].mutable);
+    assert!(!kernel.params[2].mutable);
+    assert_eq!(
+        kernel.params[0].ty,
+        Type::Array(Box::new(Type::Scalar(ScalarType::F32)), 12)
+    );
+    typeck::check(&mut kernel).unwrap();
+    assert_eq!(StepKernel.module(), Ok(kernel.clone()));
+    assert_eq!(kernel.locals[0].ty, Some(Type::Scalar(ScalarType::U32)));
+    assert_eq!(kernel.locals[2].ty, Some(Type::Scalar(ScalarType::U32)));
+}
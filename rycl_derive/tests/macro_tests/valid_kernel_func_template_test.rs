@@ -1,5 +1,6 @@
 use rycl_derive::{kernel_fn, kernel_struct};
-use shared_type::DeviceStructMarker;
+use shared_type::ir::{ScalarType, Type};
+use shared_type::{Access, DeviceStructMarker, KernelFn, Primitive};
 
 #[kernel_struct]
 struct Test {
@@ -7,9 +8,20 @@ struct Test {
 }
 #[kernel_fn]
 fn test_kernel_func<T>(a: u32, b: i32, t: T, num_thread_blocks: u32, thread_block_size: u32) {
-    println!("Hello from kernel function");
+    let c = a as i32 + b;
+}
+
+#[kernel_fn]
+fn test_primitive_func<P: Primitive>(p: P, q: &[P], num_thread_blocks: u32, thread_block_size: u32) {
+    let n = num_thread_blocks * thread_block_size;
 }
 
 fn main() {
     test_kernel_func::<Test>(1, 2, Test { a: 3.0 }, 4, 5);
-}
\ No newline at end of file
+    test_primitive_func::<f32>(1.0, &[2.0], 4, 5);
+    let _ = test_kernel_func_ir::<Test>();
+    let handle = TestPrimitiveFuncKernel::<i32>::new();
+    assert_eq!(handle.entry_point(), "test_primitive_func");
+    assert_eq!(handle.args()[0].ty, Type::Scalar(ScalarType::I32));
+    assert_eq!(handle.args()[1].access, Access::ReadOnly);
+}
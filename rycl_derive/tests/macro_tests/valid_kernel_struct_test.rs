@@ -1,12 +1,55 @@
 use rycl_derive::kernel_struct;
-use shared_type::DeviceStructMarker;
+use shared_type::{DeviceCopy, DeviceStructMarker, KernelType};
 
-    #[kernel_struct]
-    pub struct Test {
-        pub a: u32,
-        pub b: i32,
-        pub c: f32,
-    }
+#[kernel_struct]
+pub struct Test {
+    pub a: u32,
+    pub b: i32,
+    pub c: f32,
+    pub d: f64,
+    pub e: u8,
+    pub f: bool,
+}
+
+#[kernel_struct]
+pub struct Particle {
+    pub position: [f32; 3],
+    pub mass: f32,
+}
+
+#[kernel_struct]
+pub struct Cloud {
+    pub particles: [Particle; 2],
+    pub center: Particle,
+    pub count: u32,
+}
 
 fn main() {
-}
\ No newline at end of file
+    assert_eq!(Test::STD430_LAYOUT.offsets, [0, 4, 8, 16, 24, 28]);
+    assert_eq!((Test::STD430.size, Test::STD430.align), (32, 8));
+    assert_eq!((Test::STD140.size, Test::STD140.align), (32, 16));
+
+    // std140 rounds the stride of the array up to 16 bytes
+    assert_eq!(Particle::STD430_LAYOUT.offsets, [0, 12]);
+    assert_eq!(Particle::STD140_LAYOUT.offsets, [0, 48]);
+
+    assert_eq!(Cloud::STD430_LAYOUT.offsets, [0, 32, 48]);
+    assert_eq!(Cloud::STD430.size, 52);
+    assert_eq!(Cloud::STD140_LAYOUT.offsets, [0, 128, 192]);
+    assert_eq!(Cloud::STD140.size, 208);
+
+    let test = Test {
+        a: 1,
+        b: -2,
+        c: 3.0,
+        d: 4.5,
+        e: 5,
+        f: true,
+    };
+    let mut bytes = vec![0xff; Test::STD430.size as usize];
+    test.write_bytes(&mut bytes);
+    assert_eq!(bytes[16..24], 4.5f64.to_ne_bytes());
+    assert_eq!(bytes[28..32], 1u32.to_ne_bytes());
+    let copy = Test::read_bytes(&bytes);
+    assert_eq!((copy.a, copy.b, copy.d, copy.e, copy.f), (1, -2, 4.5, 5, true));
+}
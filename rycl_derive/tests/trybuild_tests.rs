@@ -7,4 +7,8 @@ fn test_kernels() {
     t.compile_fail("tests/macro_tests/invalid_kernel_func_arg_test.rs");
     t.pass("tests/macro_tests/valid_kernel_func_template_test.rs");
     t.compile_fail("tests/macro_tests/invalid_kernel_func_template_test.rs");
+    t.compile_fail("tests/macro_tests/invalid_kernel_func_body_test.rs");
+    t.pass("tests/macro_tests/valid_kernel_func_ir_test.rs");
+    t.compile_fail("tests/macro_tests/invalid_kernel_func_attr_test.rs");
+    t.compile_fail("tests/macro_tests/invalid_kernel_func_lint_test.rs");
 }
@@ -0,0 +1,355 @@
+//! Thread-index, shared memory, synchronization, atomic and subgroup
+//! intrinsics for kernel functions.
+//!
+//! Kernel functions also call the math methods of `f32`, such as `sqrt`,
+//! `ln`, `powf` and `mul_add`, and [`Mix::mix`].
+//!
+//! Inside `#[kernel_fn]` bodies, calls to these functions are recognized by
+//! name and lowered to device builtins. When a kernel function is called
+//! directly on the host, the thread-index intrinsics read the index set by
+//! [`with_thread_index`], which defaults to a launch of a single thread.
+use std::cell::Cell;
+use std::ops::{BitAnd, BitOr, BitXor};
+use std::sync::atomic::{self, Ordering};
+
+/// The position of the current thread in a launch.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ThreadIndex {
+    pub block_id: u32,
+    pub local_id: u32,
+    pub block_dim: u32,
+    pub grid_dim: u32,
+}
+
+impl Default for ThreadIndex {
+    fn default() -> Self {
+        Self {
+            block_id: 0,
+            local_id: 0,
+            block_dim: 1,
+            grid_dim: 1,
+        }
+    }
+}
+
+thread_local! {
+    static THREAD_INDEX: Cell<ThreadIndex> = Cell::new(ThreadIndex::default());
+}
+
+/// Runs `f` with the intrinsics of the calling host thread returning `index`.
+pub fn with_thread_index<R>(index: ThreadIndex, f: impl FnOnce() -> R) -> R {
+    struct Restore(ThreadIndex);
+
+    impl Drop for Restore {
+        fn drop(&mut self) {
+            THREAD_INDEX.with(|current| current.set(self.0));
+        }
+    }
+
+    let _restore = Restore(THREAD_INDEX.with(|current| current.replace(index)));
+    f()
+}
+
+fn current() -> ThreadIndex {
+    THREAD_INDEX.with(Cell::get)
+}
+
+/// Index of the thread among all threads of the launch. Like the 32-bit
+/// id on a device, it wraps around in launches of more than `u32::MAX`
+/// threads.
+pub fn global_id() -> u32 {
+    let index = current();
+    index
+        .block_id
+        .wrapping_mul(index.block_dim)
+        .wrapping_add(index.local_id)
+}
+
+/// Index of the thread within its thread block.
+pub fn local_id() -> u32 {
+    current().local_id
+}
+
+/// Index of the thread's block.
+pub fn block_id() -> u32 {
+    current().block_id
+}
+
+/// Number of threads in a thread block.
+pub fn block_dim() -> u32 {
+    current().block_dim
+}
+
+/// Number of thread blocks in the launch.
+pub fn grid_dim() -> u32 {
+    current().grid_dim
+}
+
+/// Number of threads in a subgroup, the threads of a block that the device
+/// runs together. Subgroups are made of consecutive [`local_id`]s.
+///
+/// On the host, every thread is a subgroup of its own.
+pub fn subgroup_size() -> u32 {
+    1
+}
+
+/// Index of the thread's subgroup within its thread block.
+pub fn subgroup_id() -> u32 {
+    current().local_id
+}
+
+/// Index of the thread within its subgroup, its lane.
+pub fn subgroup_local_id() -> u32 {
+    0
+}
+
+/// An array shared by every thread of the thread block, for use as
+/// `let mut tile = shared_array::<T, N>();` in a kernel function. Its
+/// contents are unspecified until written.
+///
+/// On the host, each call returns a fresh array of default values, which is
+/// only shared with the calling thread.
+pub fn shared_array<T: Copy + Default, const N: usize>() -> [T; N] {
+    [T::default(); N]
+}
+
+/// Waits until every thread of the block has reached the barrier, after
+/// which the writes they made to shared arrays and buffers before it are
+/// visible. Every thread of the block must reach the same barrier, so it
+/// cannot be called in control flow that only some threads take.
+///
+/// On the host, the calling thread is the whole block and does not wait.
+pub fn block_barrier() {}
+
+/// Orders the memory accesses of the thread as seen by the other threads of
+/// its block, without waiting for them.
+pub fn memory_fence_block() {
+    atomic::fence(Ordering::SeqCst);
+}
+
+/// Orders the memory accesses of the thread as seen by every thread of the
+/// launch, without waiting for them.
+pub fn memory_fence_device() {
+    atomic::fence(Ordering::SeqCst);
+}
+
+/// Sum of `value` over the threads of the subgroup, which must all call
+/// it. Like the other subgroup operations, it takes a `u32`, `i32` or `f32`
+/// value.
+pub fn subgroup_reduce_add<T: Copy>(value: T) -> T {
+    value
+}
+
+/// Minimum of `value` over the threads of the subgroup.
+pub fn subgroup_reduce_min<T: Copy>(value: T) -> T {
+    value
+}
+
+/// Maximum of `value` over the threads of the subgroup.
+pub fn subgroup_reduce_max<T: Copy>(value: T) -> T {
+    value
+}
+
+/// Sum of `value` over the threads of the subgroup up to and including the
+/// calling thread, by lane.
+pub fn subgroup_inclusive_add<T: Copy>(value: T) -> T {
+    value
+}
+
+/// Sum of `value` over the threads of the subgroup before the calling
+/// thread, by lane, which is zero in lane 0.
+pub fn subgroup_exclusive_add<T: Copy + Default>(_value: T) -> T {
+    T::default()
+}
+
+/// The `value` of the thread in `lane`, which must be the same for every
+/// thread of the subgroup. The value may be a scalar or a vector.
+pub fn subgroup_broadcast<T: Copy>(value: T, _lane: u32) -> T {
+    value
+}
+
+/// The `value` of the thread in `lane`, which may differ between threads.
+pub fn subgroup_shuffle<T: Copy>(value: T, _lane: u32) -> T {
+    value
+}
+
+/// A mask whose bit `i` is set if `predicate` is true in lane `i`. Lanes
+/// beyond the 32nd are not reported.
+pub fn subgroup_ballot(predicate: bool) -> u32 {
+    u32::from(predicate)
+}
+
+/// Linear interpolation, which kernel functions call with method syntax.
+pub trait Mix {
+    /// `self * (1 - t) + other * t`.
+    fn mix(self, other: Self, t: Self) -> Self;
+}
+
+impl Mix for f32 {
+    fn mix(self, other: f32, t: f32) -> f32 {
+        self * (1.0 - t) + other * t
+    }
+}
+
+mod sealed {
+    pub trait Sealed {}
+
+    impl Sealed for u32 {}
+    impl Sealed for i32 {}
+    impl Sealed for f32 {}
+}
+
+/// Values that kernel functions can update atomically: `u32`, `i32` and,
+/// for [`atomic_add`] and [`atomic_exchange`] on devices that support it,
+/// `f32`.
+pub trait AtomicValue: Copy + PartialEq + sealed::Sealed {
+    /// Sum of `self` and `value`, wrapping around on integer overflow.
+    fn atomic_sum(self, value: Self) -> Self;
+}
+
+impl AtomicValue for u32 {
+    fn atomic_sum(self, value: Self) -> Self {
+        self.wrapping_add(value)
+    }
+}
+
+impl AtomicValue for i32 {
+    fn atomic_sum(self, value: Self) -> Self {
+        self.wrapping_add(value)
+    }
+}
+
+impl AtomicValue for f32 {
+    fn atomic_sum(self, value: Self) -> Self {
+        self + value
+    }
+}
+
+/// Integer values, which support every atomic operation.
+pub trait AtomicInt:
+    AtomicValue + Ord + BitAnd<Output = Self> + BitOr<Output = Self> + BitXor<Output = Self>
+{
+}
+
+impl AtomicInt for u32 {}
+impl AtomicInt for i32 {}
+
+/// Atomically adds `value` to `*target`, returning its previous value.
+/// Integer additions wrap around on overflow.
+///
+/// In a kernel function, `target` is written `&mut` followed by an element
+/// of a `&mut` slice argument or of a shared array. On the host, the call
+/// is not atomic: the exclusive borrow already rules out other accesses.
+pub fn atomic_add<T: AtomicValue>(target: &mut T, value: T) -> T {
+    std::mem::replace(target, target.atomic_sum(value))
+}
+
+/// Atomically replaces `*target` with the minimum of it and `value`,
+/// returning its previous value.
+pub fn atomic_min<T: AtomicInt>(target: &mut T, value: T) -> T {
+    std::mem::replace(target, (*target).min(value))
+}
+
+/// Atomically replaces `*target` with the maximum of it and `value`,
+/// returning its previous value.
+pub fn atomic_max<T: AtomicInt>(target: &mut T, value: T) -> T {
+    std::mem::replace(target, (*target).max(value))
+}
+
+/// Atomically replaces `*target` with its bitwise and with `value`,
+/// returning its previous value.
+pub fn atomic_and<T: AtomicInt>(target: &mut T, value: T) -> T {
+    std::mem::replace(target, *target & value)
+}
+
+/// Atomically replaces `*target` with its bitwise or with `value`,
+/// returning its previous value.
+pub fn atomic_or<T: AtomicInt>(target: &mut T, value: T) -> T {
+    std::mem::replace(target, *target | value)
+}
+
+/// Atomically replaces `*target` with its bitwise exclusive or with
+/// `value`, returning its previous value.
+pub fn atomic_xor<T: AtomicInt>(target: &mut T, value: T) -> T {
+    std::mem::replace(target, *target ^ value)
+}
+
+/// Atomically replaces `*target` with `value`, returning its previous
+/// value.
+pub fn atomic_exchange<T: AtomicValue>(target: &mut T, value: T) -> T {
+    std::mem::replace(target, value)
+}
+
+/// Atomically replaces `*target` with `new` if it equals `current`,
+/// returning its previous value either way. The exchange took place if the
+/// returned value equals `current`.
+pub fn atomic_compare_exchange<T: AtomicInt>(target: &mut T, current: T, new: T) -> T {
+    let previous = *target;
+    if previous == current {
+        *target = new;
+    }
+    previous
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_host_fallback() {
+        assert_eq!((global_id(), block_dim(), grid_dim()), (0, 1, 1));
+        let index = ThreadIndex {
+            block_id: 2,
+            local_id: 3,
+            block_dim: 8,
+            grid_dim: 4,
+        };
+        let ids = with_thread_index(index, || (global_id(), local_id(), block_id()));
+        assert_eq!(ids, (19, 3, 2));
+        assert_eq!(global_id(), 0);
+        let index = ThreadIndex {
+            block_id: u32::MAX,
+            local_id: 1,
+            block_dim: 2,
+            grid_dim: u32::MAX,
+        };
+        assert_eq!(with_thread_index(index, global_id), u32::MAX);
+    }
+
+    #[test]
+    fn test_host_subgroups() {
+        let index = ThreadIndex {
+            block_id: 1,
+            local_id: 3,
+            block_dim: 4,
+            grid_dim: 2,
+        };
+        let ids = with_thread_index(index, || (subgroup_id(), subgroup_local_id()));
+        assert_eq!(ids, (3, 0));
+        assert_eq!(subgroup_reduce_max(5), 5);
+        assert_eq!(subgroup_exclusive_add(5.0f32), 0.0);
+        assert_eq!(subgroup_shuffle(7u32, 0), 7);
+        assert_eq!(subgroup_ballot(true), 1);
+    }
+
+    #[test]
+    fn test_host_mix() {
+        assert_eq!(2.0f32.mix(4.0, 0.25), 2.5);
+    }
+
+    #[test]
+    fn test_host_atomics() {
+        let mut value = u32::MAX;
+        assert_eq!(atomic_add(&mut value, 2), u32::MAX);
+        assert_eq!(value, 1);
+        assert_eq!(atomic_max(&mut value, 5), 1);
+        assert_eq!(atomic_min(&mut value, 3), 5);
+        assert_eq!(atomic_xor(&mut value, 1), 3);
+        assert_eq!(atomic_compare_exchange(&mut value, 3, 7), 2);
+        assert_eq!(atomic_compare_exchange(&mut value, 2, 7), 2);
+        assert_eq!(value, 7);
+        let mut sum = 1.5f32;
+        assert_eq!(atomic_add(&mut sum, 2.0), 1.5);
+        assert_eq!(atomic_exchange(&mut sum, -1.0), 3.5);
+    }
+}
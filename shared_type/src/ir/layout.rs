@@ -0,0 +1,282 @@
+//! Memory layout of kernel types in device buffers.
+//!
+//! Buffers use the std430 rules: scalars are aligned to their size, arrays
+//! are tightly packed, and structs are aligned to their most aligned member.
+//! The std140 rules, used by uniform buffers, additionally round the
+//! alignment of arrays and structs, and the stride of arrays, up to 16 bytes.
+//! Under both, vectors are aligned to twice their component size when they
+//! have two components and to four times otherwise, and matrices are laid
+//! out as arrays of their columns.
+//!
+//! The layout functions are `const`, so that `#[kernel_struct]` can expose
+//! the layout of a struct as an associated constant.
+use super::{ScalarType, StructType, Type};
+
+/// Which set of layout rules to apply.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Rules {
+    Std430,
+    Std140,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Layout {
+    pub size: u32,
+    pub align: u32,
+}
+
+impl Layout {
+    pub const fn scalar(scalar: ScalarType) -> Layout {
+        Layout {
+            size: scalar.size(),
+            align: scalar.size(),
+        }
+    }
+
+    /// Layout of a vector of `len` components of `scalar`.
+    pub const fn vector(scalar: ScalarType, len: u32) -> Layout {
+        Layout {
+            size: scalar.size() * len,
+            align: scalar.size() * if len == 2 { 2 } else { 4 },
+        }
+    }
+
+    /// Layout of an array of `len` elements laid out as `elem`.
+    pub const fn array(elem: Layout, len: u32, rules: Rules) -> Layout {
+        Layout {
+            size: elem.stride(rules) * len,
+            align: aggregate_align(elem.align, rules),
+        }
+    }
+
+    /// Distance in bytes between consecutive array elements of this layout.
+    pub const fn stride(self, rules: Rules) -> u32 {
+        round_up(self.size, aggregate_align(self.align, rules))
+    }
+}
+
+/// Field offsets and overall layout of a struct with `N` fields.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct StructLayout<const N: usize> {
+    pub offsets: [u32; N],
+    pub size: u32,
+    pub align: u32,
+}
+
+impl<const N: usize> StructLayout<N> {
+    /// Places fields laid out as `fields` in declaration order.
+    pub const fn new(fields: [Layout; N], rules: Rules) -> Self {
+        let mut offsets = [0; N];
+        let mut end = 0;
+        let mut align = 1;
+        let mut i = 0;
+        while i < N {
+            offsets[i] = round_up(end, fields[i].align);
+            end = offsets[i] + fields[i].size;
+            if fields[i].align > align {
+                align = fields[i].align;
+            }
+            i += 1;
+        }
+        let align = aggregate_align(align, rules);
+        Self {
+            offsets,
+            size: round_up(end, align),
+            align,
+        }
+    }
+
+    pub const fn layout(&self) -> Layout {
+        Layout {
+            size: self.size,
+            align: self.align,
+        }
+    }
+}
+
+/// std430 layout of `ty`.
+pub fn std430(ty: &Type) -> Layout {
+    of(ty, Rules::Std430)
+}
+
+/// std140 layout of `ty`.
+pub fn std140(ty: &Type) -> Layout {
+    of(ty, Rules::Std140)
+}
+
+/// Layout of `ty` under `rules`.
+pub fn of(ty: &Type, rules: Rules) -> Layout {
+    match ty {
+        Type::Scalar(scalar) => Layout::scalar(*scalar),
+        Type::Array(elem, len) => Layout::array(of(elem, rules), *len, rules),
+        // Slices are runtime-sized and only ever bound as a whole buffer, so
+        // they take no static space.
+        Type::Slice(elem) => Layout {
+            size: 0,
+            align: aggregate_align(of(elem, rules).align, rules),
+        },
+        Type::Struct(s) => struct_layout(s, rules).1,
+        Type::Vector(scalar, len) => Layout::vector(*scalar, *len),
+        Type::Matrix(scalar, len) => Layout::array(Layout::vector(*scalar, *len), *len, rules),
+        Type::Named(name) => panic!("layout of unresolved type `{name}`"),
+    }
+}
+
+/// Distance in bytes between consecutive elements of an array of `elem`.
+pub fn array_stride(elem: &Type) -> u32 {
+    std430(elem).stride(Rules::Std430)
+}
+
+/// Byte offset of every field of `s`.
+pub fn struct_offsets(s: &StructType) -> Vec<u32> {
+    struct_layout(s, Rules::Std430).0
+}
+
+/// Field offsets and layout of `s`, placed as [`StructLayout::new`] does.
+fn struct_layout(s: &StructType, rules: Rules) -> (Vec<u32>, Layout) {
+    let mut end = 0;
+    let mut align = 1;
+    let offsets = s
+        .fields
+        .iter()
+        .map(|field| {
+            let layout = of(&field.ty, rules);
+            let offset = round_up(end, layout.align);
+            end = offset + layout.size;
+            align = align.max(layout.align);
+            offset
+        })
+        .collect();
+    let align = aggregate_align(align, rules);
+    (
+        offsets,
+        Layout {
+            size: round_up(end, align),
+            align,
+        },
+    )
+}
+
+/// Alignment of an array or struct whose members are aligned to `align`.
+const fn aggregate_align(align: u32, rules: Rules) -> u32 {
+    match rules {
+        Rules::Std430 => align,
+        Rules::Std140 => round_up(align, 16),
+    }
+}
+
+const fn round_up(value: u32, align: u32) -> u32 {
+    value.div_ceil(align) * align
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::ir::Field;
+
+    fn field(name: &str, ty: Type) -> Field {
+        Field {
+            name: name.into(),
+            ty,
+        }
+    }
+
+    #[test]
+    fn test_std430_and_std140() {
+        let f32_ty = Type::Scalar(ScalarType::F32);
+        let vec3 = Type::Array(Box::new(f32_ty.clone()), 3);
+        let s = StructType {
+            name: "S".into(),
+            fields: vec![
+                field("flag", Type::Scalar(ScalarType::U8)),
+                field("pos", vec3.clone()),
+                field("weight", Type::Scalar(ScalarType::F64)),
+            ],
+        };
+        let ty = Type::Struct(s.clone());
+
+        assert_eq!(struct_offsets(&s), [0, 4, 16]);
+        assert_eq!(std430(&ty), Layout { size: 24, align: 8 });
+        assert_eq!(struct_layout(&s, Rules::Std140).0, [0, 16, 64]);
+        assert_eq!(
+            std140(&ty),
+            Layout {
+                size: 80,
+                align: 16
+            }
+        );
+        assert_eq!(array_stride(&f32_ty), 4);
+        assert_eq!(std140(&vec3).size, 48);
+    }
+
+    #[test]
+    fn test_vectors_and_matrices() {
+        let vec2 = Type::Vector(ScalarType::F32, 2);
+        let vec3 = Type::Vector(ScalarType::F32, 3);
+        assert_eq!(std430(&vec2), Layout { size: 8, align: 8 });
+        assert_eq!(
+            std430(&vec3),
+            Layout {
+                size: 12,
+                align: 16
+            }
+        );
+        assert_eq!(array_stride(&vec3), 16);
+
+        // A scalar fits in the padding after a 3-component vector.
+        let s = StructType {
+            name: "S".into(),
+            fields: vec![
+                field("position", vec3),
+                field("mass", Type::Scalar(ScalarType::F32)),
+                field("velocity", vec2),
+            ],
+        };
+        assert_eq!(struct_offsets(&s), [0, 12, 16]);
+
+        let mat2 = Type::Matrix(ScalarType::F32, 2);
+        let mat3 = Type::Matrix(ScalarType::F32, 3);
+        assert_eq!(std430(&mat2), Layout { size: 16, align: 8 });
+        assert_eq!(
+            std140(&mat2),
+            Layout {
+                size: 32,
+                align: 16
+            }
+        );
+        assert_eq!(
+            std430(&mat3),
+            Layout {
+                size: 48,
+                align: 16
+            }
+        );
+    }
+
+    #[test]
+    fn test_const_layout_matches() {
+        let s = StructType {
+            name: "S".into(),
+            fields: vec![
+                field("a", Type::Scalar(ScalarType::U16)),
+                field(
+                    "b",
+                    Type::Array(Box::new(Type::Scalar(ScalarType::Bool)), 2),
+                ),
+                field("c", Type::Scalar(ScalarType::I64)),
+            ],
+        };
+        for rules in [Rules::Std430, Rules::Std140] {
+            let fields = [
+                Layout::scalar(ScalarType::U16),
+                Layout::array(Layout::scalar(ScalarType::Bool), 2, rules),
+                Layout::scalar(ScalarType::I64),
+            ];
+            let layout = StructLayout::new(fields, rules);
+            assert_eq!(
+                (layout.offsets.to_vec(), layout.layout()),
+                struct_layout(&s, rules)
+            );
+        }
+    }
+}
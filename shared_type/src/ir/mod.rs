@@ -0,0 +1,511 @@
+//! Kernel intermediate representation.
+//!
+//! `#[kernel_fn]` lowers the body of a kernel function into this IR, and the
+//! `compiler` crate consumes it to generate device code. The IR mirrors the
+//! subset of Rust accepted in kernels: scalar, vector and matrix arithmetic,
+//! `let` bindings, `if`, `while` and `for` over ranges, and indexing into
+//! arrays and slices.
+//!
+//! Types that the macro cannot see (user structs and generic parameters) are
+//! resolved through [`KernelType`](crate::KernelType) when the generated code
+//! builds the IR, so a [`Kernel`] handed to the compiler only contains
+//! concrete types. Literal and local types are filled in by [`typeck`].
+pub mod layout;
+pub mod typeck;
+
+/// Scalar types supported on the device.
+///
+/// `usize` and `isize` are lowered to `U32` and `I32`: the device has no
+/// pointer-sized integer, and indices are 32 bits wide. Types other than
+/// `bool` and the 32-bit ones need an optional device feature, see
+/// [`ScalarType::is_optional`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum ScalarType {
+    Bool,
+    U8,
+    I8,
+    U16,
+    I16,
+    U32,
+    I32,
+    U64,
+    I64,
+    F16,
+    F32,
+    F64,
+}
+
+impl ScalarType {
+    /// Size of the scalar in bytes, as stored in a device buffer. Booleans
+    /// are stored as 32-bit integers.
+    pub const fn size(self) -> u32 {
+        match self {
+            ScalarType::U8 | ScalarType::I8 => 1,
+            ScalarType::U16 | ScalarType::I16 | ScalarType::F16 => 2,
+            ScalarType::Bool | ScalarType::U32 | ScalarType::I32 | ScalarType::F32 => 4,
+            ScalarType::U64 | ScalarType::I64 | ScalarType::F64 => 8,
+        }
+    }
+
+    pub fn is_int(self) -> bool {
+        !matches!(self, ScalarType::Bool) && !self.is_float()
+    }
+
+    pub fn is_signed(self) -> bool {
+        matches!(
+            self,
+            ScalarType::I8 | ScalarType::I16 | ScalarType::I32 | ScalarType::I64
+        )
+    }
+
+    pub fn is_float(self) -> bool {
+        matches!(self, ScalarType::F16 | ScalarType::F32 | ScalarType::F64)
+    }
+
+    /// Whether devices may lack support for the type.
+    pub fn is_optional(self) -> bool {
+        self != ScalarType::Bool && self.size() != 4
+    }
+
+    /// Maps a Rust primitive type name to its device scalar type.
+    pub fn from_rust_name(name: &str) -> Option<Self> {
+        Some(match name {
+            "bool" => ScalarType::Bool,
+            "u8" => ScalarType::U8,
+            "i8" => ScalarType::I8,
+            "u16" => ScalarType::U16,
+            "i16" => ScalarType::I16,
+            "u32" | "usize" => ScalarType::U32,
+            "i32" | "isize" => ScalarType::I32,
+            "u64" => ScalarType::U64,
+            "i64" => ScalarType::I64,
+            "f16" => ScalarType::F16,
+            "f32" => ScalarType::F32,
+            "f64" => ScalarType::F64,
+            _ => return None,
+        })
+    }
+
+    /// The Rust name of the type, for error messages.
+    pub fn rust_name(self) -> &'static str {
+        match self {
+            ScalarType::Bool => "bool",
+            ScalarType::U8 => "u8",
+            ScalarType::I8 => "i8",
+            ScalarType::U16 => "u16",
+            ScalarType::I16 => "i16",
+            ScalarType::U32 => "u32",
+            ScalarType::I32 => "i32",
+            ScalarType::U64 => "u64",
+            ScalarType::I64 => "i64",
+            ScalarType::F16 => "f16",
+            ScalarType::F32 => "f32",
+            ScalarType::F64 => "f64",
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Type {
+    Scalar(ScalarType),
+    /// Fixed-size array `[T; N]`.
+    Array(Box<Type>, u32),
+    /// Slice `[T]` whose length is only known at launch. Only kernel
+    /// arguments can be slices; they are passed as `&[T]` or `&mut [T]`.
+    Slice(Box<Type>),
+    Struct(StructType),
+    /// Vector of 2 to 4 components, such as [`Vec3`](crate::Vec3).
+    Vector(ScalarType, u32),
+    /// Square column-major matrix with as many columns as each column has
+    /// components, such as [`Mat3`](crate::Mat3).
+    Matrix(ScalarType, u32),
+    /// A type the macro could not resolve on its own, such as a generic
+    /// parameter or a `#[kernel_struct]`. Only appears in IR that has not
+    /// been through [`KernelType`](crate::KernelType) resolution.
+    Named(String),
+}
+
+impl Type {
+    pub fn as_scalar(&self) -> Option<ScalarType> {
+        match self {
+            Type::Scalar(s) => Some(*s),
+            _ => None,
+        }
+    }
+
+    /// The scalar type of a scalar, or of the components of a vector or
+    /// matrix.
+    pub fn component_type(&self) -> Option<ScalarType> {
+        match self {
+            Type::Scalar(s) | Type::Vector(s, _) | Type::Matrix(s, _) => Some(*s),
+            _ => None,
+        }
+    }
+
+    /// Index and type of the member accessed as `.name`: a struct field, a
+    /// vector component `x`, `y`, `z` or `w`, or a matrix column `x_axis`
+    /// to `w_axis`.
+    pub fn member(&self, name: &str) -> Option<(usize, Type)> {
+        match self {
+            Type::Struct(s) => s.field(name).map(|(i, f)| (i, f.ty.clone())),
+            Type::Vector(scalar, len) => vector_component(name)
+                .filter(|&i| i < *len)
+                .map(|i| (i as usize, Type::Scalar(*scalar))),
+            Type::Matrix(scalar, len) => ["x_axis", "y_axis", "z_axis", "w_axis"][..*len as usize]
+                .iter()
+                .position(|axis| *axis == name)
+                .map(|i| (i, Type::Vector(*scalar, *len))),
+            _ => None,
+        }
+    }
+
+    /// Whether the type contains no [`Type::Named`], so that it is known
+    /// without [`KernelType`](crate::KernelType) resolution.
+    pub fn is_resolved(&self) -> bool {
+        match self {
+            Type::Array(elem, _) | Type::Slice(elem) => elem.is_resolved(),
+            Type::Struct(s) => s.fields.iter().all(|f| f.ty.is_resolved()),
+            Type::Named(_) => false,
+            Type::Scalar(_) | Type::Vector(..) | Type::Matrix(..) => true,
+        }
+    }
+}
+
+/// Index of vector component `x`, `y`, `z` or `w`.
+pub fn vector_component(name: &str) -> Option<u32> {
+    Some(match name {
+        "x" => 0,
+        "y" => 1,
+        "z" => 2,
+        "w" => 3,
+        _ => return None,
+    })
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct StructType {
+    pub name: String,
+    pub fields: Vec<Field>,
+}
+
+impl StructType {
+    pub fn field(&self, name: &str) -> Option<(usize, &Field)> {
+        self.fields.iter().enumerate().find(|(_, f)| f.name == name)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Field {
+    pub name: String,
+    pub ty: Type,
+}
+
+/// A lowered kernel function.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Kernel {
+    pub name: String,
+    /// Kernel arguments, excluding the launch configuration
+    /// (`num_thread_blocks` and `thread_block_size`), which are lowered to
+    /// [`Builtin`]s. Every parameter is bound to its own device buffer, in
+    /// order.
+    pub params: Vec<Param>,
+    pub locals: Vec<Local>,
+    /// Arrays shared by the threads of a thread block.
+    pub shared: Vec<Shared>,
+    pub body: Block,
+}
+
+impl Kernel {
+    pub fn local(&self, id: LocalId) -> &Local {
+        &self.locals[id.0 as usize]
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Param {
+    pub name: String,
+    pub ty: Type,
+    /// Whether the kernel writes to the argument. Arguments live in device
+    /// buffers, so writes are visible to the host after the launch.
+    pub mutable: bool,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Local {
+    pub name: String,
+    /// Declared type, or the inferred one once the kernel is type checked.
+    pub ty: Option<Type>,
+}
+
+/// An array declared with `shared_array`, which every thread of a thread
+/// block reads and writes. Its contents are unspecified until written, and
+/// do not outlive the thread block.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Shared {
+    pub name: String,
+    pub ty: Type,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct LocalId(pub u32);
+
+pub type Block = Vec<Stmt>;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Stmt {
+    Let {
+        local: LocalId,
+        init: Option<Expr>,
+    },
+    Assign {
+        place: Place,
+        value: Expr,
+    },
+    If {
+        cond: Expr,
+        then_block: Block,
+        else_block: Block,
+    },
+    /// `for var in start..end` (or `start..=end` when `inclusive`).
+    For {
+        var: LocalId,
+        start: Expr,
+        end: Expr,
+        inclusive: bool,
+        body: Block,
+    },
+    While {
+        cond: Expr,
+        body: Block,
+    },
+    Break,
+    Continue,
+    Return,
+    Sync(SyncOp),
+    /// An expression evaluated for its side effects.
+    Expr(Expr),
+}
+
+/// A memory location that can be read or assigned.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Place {
+    Param(usize),
+    Local(LocalId),
+    /// An entry of [`Kernel::shared`].
+    Shared(usize),
+    Index(Box<Place>, Box<Expr>),
+    Field(Box<Place>, String),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Expr {
+    Literal(Literal),
+    Load(Place),
+    Builtin(Builtin),
+    Unary(UnaryOp, Box<Expr>),
+    /// Vectors and matrices are combined component-wise, and a scalar
+    /// operand applies to every component, except that `*` with a matrix
+    /// on the left is the matrix product. `==` and `!=` compare whole
+    /// vectors.
+    Binary(BinaryOp, Box<Expr>, Box<Expr>),
+    Cast(Box<Expr>, ScalarType),
+    /// `if cond { a } else { b }` used as a value. Both operands are
+    /// evaluated, so they must be [`Expr::is_speculatable`].
+    Select(Box<Expr>, Box<Expr>, Box<Expr>),
+    /// `place.len()` on an array or slice, as a `u32`.
+    Len(Place),
+    /// A vector built from scalars and smaller vectors whose components add
+    /// up to its length, or from a single scalar copied to every component.
+    /// A matrix is built from its columns.
+    Construct(Type, Vec<Expr>),
+    /// The vector components at the given indices, such as `v.zyx()`, or a
+    /// scalar when there is a single index.
+    Swizzle(Box<Expr>, Vec<u32>),
+    Call(Function, Vec<Expr>),
+    /// Atomically updates the `u32`, `i32` or `f32` element of a buffer or
+    /// shared array at the place with the operands, and evaluates to the
+    /// value it held before.
+    Atomic(AtomicOp, Place, Vec<Expr>),
+    /// Combines the operands of the threads of the subgroup, which must all
+    /// evaluate it.
+    Subgroup(SubgroupOp, Vec<Expr>),
+}
+
+/// Read-modify-write operations of [`Expr::Atomic`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum AtomicOp {
+    /// The only operation on `f32`, along with `Exchange`.
+    Add,
+    Min,
+    Max,
+    And,
+    Or,
+    Xor,
+    Exchange,
+    /// Stores the second operand if the place holds the first.
+    CompareExchange,
+}
+
+/// Operations across the threads of a subgroup, of [`Expr::Subgroup`].
+///
+/// Reductions and scans take a `u32`, `i32` or `f32` value.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum SubgroupOp {
+    ReduceAdd,
+    ReduceMin,
+    ReduceMax,
+    /// Sum of the values of the thread and of the lower subgroup lanes.
+    InclusiveAdd,
+    /// Sum of the values of the lower subgroup lanes only.
+    ExclusiveAdd,
+    /// The value of the lane given by the second operand, which must be the
+    /// same for every thread.
+    Broadcast,
+    /// The value of the lane given by the second operand, which may differ
+    /// between threads.
+    Shuffle,
+    /// A `u32` whose bit `i` is set if the `bool` of lane `i` is true.
+    Ballot,
+}
+
+/// Vector, matrix and math functions, called with method syntax in kernels.
+///
+/// The math functions take `f32` values, except `Min`, `Max` and `Clamp`,
+/// which also take `u32` and `i32` values, and `Abs`, which also takes
+/// `i32` values. Every operand has the type of the first.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Function {
+    Dot,
+    /// Cross product of two 3-component vectors.
+    Cross,
+    Length,
+    Normalize,
+    Transpose,
+    Sqrt,
+    Exp,
+    /// Natural logarithm.
+    Log,
+    Pow,
+    Sin,
+    Cos,
+    Tan,
+    Floor,
+    Ceil,
+    /// Rounds to the nearest integer. Which way halves round depends on the
+    /// device.
+    Round,
+    /// `a * b + c`, possibly with a single rounding.
+    Fma,
+    Min,
+    Max,
+    /// The first operand clamped to the range given by the other two.
+    Clamp,
+    Abs,
+    /// Linear interpolation `a * (1 - t) + b * t` of `a` and `b`.
+    Mix,
+}
+
+impl Function {
+    /// Whether the function is a math function rather than a vector or
+    /// matrix one.
+    pub fn is_math(self) -> bool {
+        !matches!(
+            self,
+            Function::Dot
+                | Function::Cross
+                | Function::Length
+                | Function::Normalize
+                | Function::Transpose
+        )
+    }
+}
+
+/// A literal. Numeric literals carry their suffix type, or `None` until
+/// type checking infers it from context.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Literal {
+    Bool(bool),
+    Int(u64, Option<ScalarType>),
+    Float(f64, Option<ScalarType>),
+}
+
+/// Values provided by the launch rather than by kernel arguments.
+///
+/// Launches are one-dimensional, so every builtin is a single `u32`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Builtin {
+    NumThreadBlocks,
+    ThreadBlockSize,
+    /// Index of the thread among all threads of the launch.
+    GlobalId,
+    /// Index of the thread within its thread block.
+    LocalId,
+    /// Index of the thread's block.
+    BlockId,
+    /// Number of threads in a subgroup.
+    SubgroupSize,
+    /// Index of the thread's subgroup within its thread block.
+    SubgroupId,
+    /// Index of the thread within its subgroup, its lane.
+    SubgroupLocalId,
+}
+
+/// Synchronization between threads. Fences order the memory accesses of the
+/// thread issuing them, as seen by the other threads of its block or of the
+/// launch; they do not wait for anything.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum SyncOp {
+    /// Waits until every thread of the block reaches the barrier. Writes to
+    /// shared arrays and buffers made before it are visible to the block
+    /// after it. Every thread of the block must reach the same barrier.
+    BlockBarrier,
+    BlockFence,
+    DeviceFence,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum UnaryOp {
+    Neg,
+    Not,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum BinaryOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Rem,
+    BitAnd,
+    BitOr,
+    BitXor,
+    Shl,
+    Shr,
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    /// Short-circuiting `&&`.
+    And,
+    /// Short-circuiting `||`.
+    Or,
+}
+
+impl BinaryOp {
+    pub fn is_comparison(self) -> bool {
+        matches!(
+            self,
+            BinaryOp::Eq | BinaryOp::Ne | BinaryOp::Lt | BinaryOp::Le | BinaryOp::Gt | BinaryOp::Ge
+        )
+    }
+
+    pub fn is_logical(self) -> bool {
+        matches!(self, BinaryOp::And | BinaryOp::Or)
+    }
+
+    pub fn is_shift(self) -> bool {
+        matches!(self, BinaryOp::Shl | BinaryOp::Shr)
+    }
+}
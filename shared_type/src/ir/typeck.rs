@@ -0,0 +1,1349 @@
+//! Type inference for kernel IR.
+//!
+//! The kernel body has already been accepted by rustc, so this pass does not
+//! try to reject ill-typed programs. It recovers the types rustc inferred for
+//! unannotated `let` bindings and unsuffixed literals, and reports constructs
+//! the device cannot represent.
+use std::fmt;
+
+use super::{
+    AtomicOp, BinaryOp, Block, Builtin, Expr, Function, Kernel, Literal, LocalId, Place,
+    ScalarType, Stmt, SubgroupOp, Type, UnaryOp,
+};
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TypeError(pub String);
+
+impl fmt::Display for TypeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "kernel type error: {}", self.0)
+    }
+}
+
+impl std::error::Error for TypeError {}
+
+type Result<T> = std::result::Result<T, TypeError>;
+
+/// Infers the type of every local and literal in `kernel`.
+///
+/// On success, every [`Local::ty`](super::Local::ty) and every numeric
+/// [`Literal`] carries a concrete type.
+pub fn check(kernel: &mut Kernel) -> Result<()> {
+    for param in &kernel.params {
+        match &param.ty {
+            Type::Slice(elem) => ensure_sized(elem)?,
+            ty => ensure_sized(ty)?,
+        }
+    }
+    for shared in &kernel.shared {
+        ensure_sized(&shared.ty)?;
+    }
+    let mut infer = Infer::new(kernel)?;
+    infer.block(&kernel.body)?;
+
+    let local_types = (0..kernel.locals.len())
+        .map(|i| {
+            let name = &kernel.locals[i].name;
+            infer
+                .resolve(infer.locals[i].clone())
+                .ok_or_else(|| TypeError(format!("cannot infer the type of `{name}`")))
+        })
+        .collect::<Result<Vec<_>>>()?;
+    let literal_types = std::mem::take(&mut infer.literals)
+        .into_iter()
+        .map(|var| match infer.resolve(Ty::Var(var)) {
+            Some(Type::Scalar(s)) => Ok(s),
+            _ => Err(TypeError("cannot infer the type of a literal".into())),
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    for (local, ty) in kernel.locals.iter().zip(&local_types) {
+        if let Type::Slice(_) = ty {
+            return Err(TypeError(format!(
+                "`{}` cannot hold a slice, only kernel arguments can be slices",
+                local.name
+            )));
+        }
+    }
+    for (local, ty) in kernel.locals.iter_mut().zip(local_types) {
+        local.ty = Some(ty);
+    }
+    let mut literal_types = literal_types.into_iter();
+    for_each_literal_in_block(&mut kernel.body, &mut |lit| {
+        if let Literal::Int(_, ty @ None) | Literal::Float(_, ty @ None) = lit {
+            *ty = literal_types.next();
+        }
+    });
+    Ok(())
+}
+
+fn ensure_resolved(ty: &Type) -> Result<()> {
+    match ty {
+        Type::Scalar(_) | Type::Vector(..) | Type::Matrix(..) => Ok(()),
+        Type::Array(elem, _) | Type::Slice(elem) => ensure_resolved(elem),
+        Type::Struct(s) => s.fields.iter().try_for_each(|f| ensure_resolved(&f.ty)),
+        Type::Named(name) => Err(TypeError(format!("unresolved type `{name}`"))),
+    }
+}
+
+/// Like [`ensure_resolved`], and also checks that `ty` has a size known at
+/// compile time.
+fn ensure_sized(ty: &Type) -> Result<()> {
+    match ty {
+        Type::Scalar(_) | Type::Vector(..) | Type::Matrix(..) => Ok(()),
+        Type::Array(elem, _) => ensure_sized(elem),
+        Type::Slice(_) => Err(TypeError(
+            "slices can only be kernel arguments, not elements or fields".into(),
+        )),
+        Type::Struct(s) => s.fields.iter().try_for_each(|f| ensure_sized(&f.ty)),
+        Type::Named(name) => Err(TypeError(format!("unresolved type `{name}`"))),
+    }
+}
+
+impl Kernel {
+    /// Type of `expr`. Only meaningful once the kernel has been [`check`]ed.
+    pub fn expr_type(&self, expr: &Expr) -> Type {
+        match expr {
+            Expr::Literal(Literal::Bool(_)) => Type::Scalar(ScalarType::Bool),
+            Expr::Literal(Literal::Int(_, ty)) => Type::Scalar(ty.unwrap_or(ScalarType::I32)),
+            Expr::Literal(Literal::Float(_, ty)) => Type::Scalar(ty.unwrap_or(ScalarType::F32)),
+            Expr::Load(place) => self.place_type(place),
+            Expr::Builtin(_) => Type::Scalar(ScalarType::U32),
+            Expr::Unary(_, operand) => self.expr_type(operand),
+            Expr::Binary(op, lhs, rhs) => {
+                if op.is_comparison() || op.is_logical() {
+                    Type::Scalar(ScalarType::Bool)
+                } else if op.is_shift() {
+                    self.expr_type(lhs)
+                } else {
+                    match (self.expr_type(lhs), self.expr_type(rhs)) {
+                        (Type::Matrix(..), rhs @ Type::Vector(..)) | (Type::Scalar(_), rhs) => rhs,
+                        (lhs, _) => lhs,
+                    }
+                }
+            }
+            Expr::Cast(_, ty) => Type::Scalar(*ty),
+            Expr::Select(_, then_expr, _) => self.expr_type(then_expr),
+            Expr::Len(_) => Type::Scalar(ScalarType::U32),
+            Expr::Construct(ty, _) => ty.clone(),
+            Expr::Swizzle(operand, indices) => {
+                let Type::Vector(scalar, _) = self.expr_type(operand) else {
+                    panic!("swizzle of a non-vector value");
+                };
+                match indices.len() {
+                    1 => Type::Scalar(scalar),
+                    len => Type::Vector(scalar, len as u32),
+                }
+            }
+            Expr::Call(Function::Dot | Function::Length, args) => Type::Scalar(
+                self.expr_type(&args[0])
+                    .component_type()
+                    .expect("vector function called on a non-vector value"),
+            ),
+            Expr::Call(_, args) => self.expr_type(&args[0]),
+            Expr::Atomic(_, place, _) => self.place_type(place),
+            Expr::Subgroup(SubgroupOp::Ballot, _) => Type::Scalar(ScalarType::U32),
+            Expr::Subgroup(_, args) => self.expr_type(&args[0]),
+        }
+    }
+
+    /// Type of the value stored at `place`. Only meaningful once the kernel
+    /// has been [`check`]ed.
+    pub fn place_type(&self, place: &Place) -> Type {
+        match place {
+            Place::Param(i) => self.params[*i].ty.clone(),
+            Place::Local(id) => self
+                .local(*id)
+                .ty
+                .clone()
+                .expect("kernel has not been type checked"),
+            Place::Shared(i) => self.shared[*i].ty.clone(),
+            Place::Index(base, _) => match self.place_type(base) {
+                Type::Array(elem, _) | Type::Slice(elem) => *elem,
+                Type::Vector(scalar, _) => Type::Scalar(scalar),
+                Type::Matrix(scalar, len) => Type::Vector(scalar, len),
+                ty => panic!("indexing into non-array type {ty:?}"),
+            },
+            Place::Field(base, name) => match self.place_type(base).member(name) {
+                Some((_, ty)) => ty,
+                None => panic!("unknown field `{name}`"),
+            },
+        }
+    }
+
+    /// Every scalar type the kernel stores or computes with, in first-use
+    /// order. Only meaningful once the kernel has been [`check`]ed.
+    pub fn scalar_types(&self) -> Vec<ScalarType> {
+        let mut scalars = Vec::new();
+        let types = self
+            .params
+            .iter()
+            .map(|p| &p.ty)
+            .chain(self.shared.iter().map(|s| &s.ty))
+            .chain(self.locals.iter().filter_map(|l| l.ty.as_ref()));
+        for ty in types {
+            scalar_types_of(ty, &mut scalars);
+        }
+        for_each_expr_in_block(&self.body, &mut |expr| {
+            let ty = match expr {
+                Expr::Literal(_) | Expr::Cast(..) => self.expr_type(expr),
+                _ => return,
+            };
+            scalar_types_of(&ty, &mut scalars);
+        });
+        scalars
+    }
+
+    /// Whether the kernel atomically adds to `f32` values, which devices
+    /// support optionally. Only meaningful once the kernel has been
+    /// [`check`]ed.
+    pub fn uses_float_atomics(&self) -> bool {
+        let mut found = false;
+        for_each_expr_in_block(&self.body, &mut |expr| {
+            if let Expr::Atomic(_, place, _) = expr {
+                found |= self.place_type(place) == Type::Scalar(ScalarType::F32);
+            }
+        });
+        found
+    }
+
+    /// Whether the kernel uses subgroup builtins or operations, which
+    /// devices support optionally.
+    pub fn uses_subgroups(&self) -> bool {
+        let mut found = false;
+        for_each_expr_in_block(&self.body, &mut |expr| {
+            found |= matches!(
+                expr,
+                Expr::Subgroup(..)
+                    | Expr::Builtin(
+                        Builtin::SubgroupSize | Builtin::SubgroupId | Builtin::SubgroupLocalId
+                    )
+            );
+        });
+        found
+    }
+}
+
+impl Expr {
+    /// Whether evaluating the expression can neither trap nor have side
+    /// effects, so that it can be evaluated even where Rust would not: it
+    /// indexes nothing, divides by nothing and performs no atomic or
+    /// subgroup operation.
+    pub fn is_speculatable(&self) -> bool {
+        fn indexes(place: &Place) -> bool {
+            match place {
+                Place::Param(_) | Place::Local(_) | Place::Shared(_) => false,
+                Place::Index(..) => true,
+                Place::Field(base, _) => indexes(base),
+            }
+        }
+        let mut speculatable = true;
+        for_each_expr(self, &mut |expr| {
+            speculatable &= match expr {
+                Expr::Load(place) | Expr::Len(place) => !indexes(place),
+                Expr::Binary(op, ..) => !matches!(op, BinaryOp::Div | BinaryOp::Rem),
+                Expr::Atomic(..) | Expr::Subgroup(..) => false,
+                _ => true,
+            };
+        });
+        speculatable
+    }
+}
+
+fn scalar_types_of(ty: &Type, scalars: &mut Vec<ScalarType>) {
+    match ty {
+        Type::Scalar(scalar) => {
+            if !scalars.contains(scalar) {
+                scalars.push(*scalar);
+            }
+        }
+        Type::Array(elem, _) | Type::Slice(elem) => scalar_types_of(elem, scalars),
+        Type::Struct(s) => s
+            .fields
+            .iter()
+            .for_each(|f| scalar_types_of(&f.ty, scalars)),
+        Type::Vector(scalar, _) | Type::Matrix(scalar, _) => {
+            scalar_types_of(&Type::Scalar(*scalar), scalars)
+        }
+        Type::Named(name) => panic!("unresolved type `{name}`"),
+    }
+}
+
+/// Calls `f` on every expression of `block`, subexpressions included.
+fn for_each_expr_in_block(block: &Block, f: &mut impl FnMut(&Expr)) {
+    for stmt in block {
+        match stmt {
+            Stmt::Let { init, .. } => {
+                if let Some(init) = init {
+                    for_each_expr(init, f);
+                }
+            }
+            Stmt::Assign { place, value } => {
+                for_each_expr_in_place(place, f);
+                for_each_expr(value, f);
+            }
+            Stmt::If {
+                cond,
+                then_block,
+                else_block,
+            } => {
+                for_each_expr(cond, f);
+                for_each_expr_in_block(then_block, f);
+                for_each_expr_in_block(else_block, f);
+            }
+            Stmt::For {
+                start, end, body, ..
+            } => {
+                for_each_expr(start, f);
+                for_each_expr(end, f);
+                for_each_expr_in_block(body, f);
+            }
+            Stmt::While { cond, body } => {
+                for_each_expr(cond, f);
+                for_each_expr_in_block(body, f);
+            }
+            Stmt::Break | Stmt::Continue | Stmt::Return | Stmt::Sync(_) => {}
+            Stmt::Expr(expr) => for_each_expr(expr, f),
+        }
+    }
+}
+
+fn for_each_expr(expr: &Expr, f: &mut impl FnMut(&Expr)) {
+    f(expr);
+    match expr {
+        Expr::Literal(_) | Expr::Builtin(_) => {}
+        Expr::Load(place) | Expr::Len(place) => for_each_expr_in_place(place, f),
+        Expr::Unary(_, operand) | Expr::Cast(operand, _) => for_each_expr(operand, f),
+        Expr::Binary(_, lhs, rhs) => {
+            for_each_expr(lhs, f);
+            for_each_expr(rhs, f);
+        }
+        Expr::Select(cond, then_expr, else_expr) => {
+            for_each_expr(cond, f);
+            for_each_expr(then_expr, f);
+            for_each_expr(else_expr, f);
+        }
+        Expr::Construct(_, args) | Expr::Call(_, args) | Expr::Subgroup(_, args) => {
+            args.iter().for_each(|arg| for_each_expr(arg, f))
+        }
+        Expr::Swizzle(operand, _) => for_each_expr(operand, f),
+        Expr::Atomic(_, place, args) => {
+            for_each_expr_in_place(place, f);
+            args.iter().for_each(|arg| for_each_expr(arg, f));
+        }
+    }
+}
+
+fn for_each_expr_in_place(place: &Place, f: &mut impl FnMut(&Expr)) {
+    match place {
+        Place::Param(_) | Place::Local(_) | Place::Shared(_) => {}
+        Place::Index(base, index) => {
+            for_each_expr_in_place(base, f);
+            for_each_expr(index, f);
+        }
+        Place::Field(base, _) => for_each_expr_in_place(base, f),
+    }
+}
+
+/// A type during inference: either known, or an inference variable.
+#[derive(Clone, Debug)]
+enum Ty {
+    Known(Type),
+    Var(usize),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum VarKind {
+    Any,
+    Int,
+    Float,
+}
+
+struct Var {
+    parent: usize,
+    kind: VarKind,
+    bound: Option<Type>,
+}
+
+struct Infer<'k> {
+    kernel: &'k Kernel,
+    vars: Vec<Var>,
+    locals: Vec<Ty>,
+    /// Inference variables of unsuffixed literals, in visiting order.
+    literals: Vec<usize>,
+}
+
+impl<'k> Infer<'k> {
+    fn new(kernel: &'k Kernel) -> Result<Self> {
+        let mut infer = Infer {
+            kernel,
+            vars: Vec::new(),
+            locals: Vec::new(),
+            literals: Vec::new(),
+        };
+        for local in &kernel.locals {
+            let ty = match &local.ty {
+                Some(ty) => {
+                    ensure_resolved(ty)?;
+                    Ty::Known(ty.clone())
+                }
+                None => infer.fresh(VarKind::Any),
+            };
+            infer.locals.push(ty);
+        }
+        Ok(infer)
+    }
+
+    fn fresh(&mut self, kind: VarKind) -> Ty {
+        let id = self.vars.len();
+        self.vars.push(Var {
+            parent: id,
+            kind,
+            bound: None,
+        });
+        Ty::Var(id)
+    }
+
+    fn root(&mut self, mut var: usize) -> usize {
+        while self.vars[var].parent != var {
+            let grandparent = self.vars[self.vars[var].parent].parent;
+            self.vars[var].parent = grandparent;
+            var = grandparent;
+        }
+        var
+    }
+
+    /// Resolves `ty` to a concrete type, applying literal defaults.
+    fn resolve(&mut self, ty: Ty) -> Option<Type> {
+        match ty {
+            Ty::Known(ty) => Some(ty),
+            Ty::Var(var) => {
+                let root = self.root(var);
+                let var = &self.vars[root];
+                match (&var.bound, var.kind) {
+                    (Some(ty), _) => Some(ty.clone()),
+                    (None, VarKind::Int) => Some(Type::Scalar(ScalarType::I32)),
+                    // Rust would default to `f64`, which the device does not
+                    // support; `f32` is the only float type kernels can use.
+                    (None, VarKind::Float) => Some(Type::Scalar(ScalarType::F32)),
+                    (None, VarKind::Any) => None,
+                }
+            }
+        }
+    }
+
+    /// Resolves `ty` without applying defaults, for places where the
+    /// structure of the type must already be known.
+    fn known(&mut self, ty: &Ty) -> Option<Type> {
+        match ty {
+            Ty::Known(ty) => Some(ty.clone()),
+            Ty::Var(var) => {
+                let root = self.root(*var);
+                self.vars[root].bound.clone()
+            }
+        }
+    }
+
+    fn unify(&mut self, a: Ty, b: Ty) -> Result<Ty> {
+        match (a, b) {
+            (Ty::Known(a), Ty::Known(b)) => {
+                if a == b {
+                    Ok(Ty::Known(a))
+                } else {
+                    Err(TypeError(format!("mismatched types {a:?} and {b:?}")))
+                }
+            }
+            (Ty::Var(var), Ty::Known(ty)) | (Ty::Known(ty), Ty::Var(var)) => {
+                let root = self.root(var);
+                if let Some(bound) = self.vars[root].bound.clone() {
+                    return self.unify(Ty::Known(bound), Ty::Known(ty));
+                }
+                check_kind(self.vars[root].kind, &ty)?;
+                self.vars[root].bound = Some(ty);
+                Ok(Ty::Var(root))
+            }
+            (Ty::Var(a), Ty::Var(b)) => {
+                let (a, b) = (self.root(a), self.root(b));
+                if a == b {
+                    return Ok(Ty::Var(a));
+                }
+                let kind = match (self.vars[a].kind, self.vars[b].kind) {
+                    (VarKind::Any, kind) | (kind, VarKind::Any) => kind,
+                    (x, y) if x == y => x,
+                    _ => return Err(TypeError("mismatched integer and float types".into())),
+                };
+                let bound_b = self.vars[b].bound.take();
+                self.vars[b].parent = a;
+                self.vars[a].kind = kind;
+                match (self.vars[a].bound.clone(), bound_b) {
+                    (Some(x), Some(y)) => {
+                        self.unify(Ty::Known(x), Ty::Known(y))?;
+                    }
+                    (None, Some(ty)) | (Some(ty), None) => {
+                        check_kind(kind, &ty)?;
+                        self.vars[a].bound = Some(ty);
+                    }
+                    (None, None) => {}
+                }
+                Ok(Ty::Var(a))
+            }
+        }
+    }
+
+    fn block(&mut self, block: &Block) -> Result<()> {
+        block.iter().try_for_each(|stmt| self.stmt(stmt))
+    }
+
+    fn stmt(&mut self, stmt: &Stmt) -> Result<()> {
+        match stmt {
+            Stmt::Let { local, init } => {
+                if let Some(init) = init {
+                    let ty = self.expr(init)?;
+                    self.unify(self.local(*local), ty)?;
+                }
+            }
+            Stmt::Assign { place, value } => {
+                let place_ty = self.place(place)?;
+                if let Some(Type::Slice(_)) = self.known(&place_ty) {
+                    return Err(TypeError("cannot assign to a whole slice".into()));
+                }
+                let value_ty = self.expr(value)?;
+                self.unify(place_ty, value_ty)?;
+            }
+            Stmt::If {
+                cond,
+                then_block,
+                else_block,
+            } => {
+                self.expect_bool(cond)?;
+                self.block(then_block)?;
+                self.block(else_block)?;
+            }
+            Stmt::For {
+                var,
+                start,
+                end,
+                body,
+                ..
+            } => {
+                let start = self.expr(start)?;
+                let end = self.expr(end)?;
+                let bound = self.unify(start, end)?;
+                let int = self.fresh(VarKind::Int);
+                let bound = self.unify(bound, int)?;
+                self.unify(self.local(*var), bound)?;
+                self.block(body)?;
+            }
+            Stmt::While { cond, body } => {
+                self.expect_bool(cond)?;
+                self.block(body)?;
+            }
+            Stmt::Break | Stmt::Continue | Stmt::Return | Stmt::Sync(_) => {}
+            Stmt::Expr(expr) => {
+                self.expr(expr)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn local(&self, id: LocalId) -> Ty {
+        self.locals[id.0 as usize].clone()
+    }
+
+    fn expect_bool(&mut self, expr: &Expr) -> Result<()> {
+        let ty = self.expr(expr)?;
+        self.unify(ty, Ty::Known(Type::Scalar(ScalarType::Bool)))?;
+        Ok(())
+    }
+
+    fn expr(&mut self, expr: &Expr) -> Result<Ty> {
+        Ok(match expr {
+            Expr::Literal(Literal::Bool(_)) => Ty::Known(Type::Scalar(ScalarType::Bool)),
+            Expr::Literal(Literal::Int(_, Some(ty)) | Literal::Float(_, Some(ty))) => {
+                Ty::Known(Type::Scalar(*ty))
+            }
+            Expr::Literal(Literal::Int(_, None)) => self.literal(VarKind::Int),
+            Expr::Literal(Literal::Float(_, None)) => self.literal(VarKind::Float),
+            Expr::Load(place) => self.place(place)?,
+            Expr::Builtin(_) => Ty::Known(Type::Scalar(ScalarType::U32)),
+            Expr::Unary(UnaryOp::Neg | UnaryOp::Not, operand) => self.expr(operand)?,
+            Expr::Binary(op, lhs, rhs) => {
+                let lhs = self.expr(lhs)?;
+                let rhs = self.expr(rhs)?;
+                if op.is_logical() {
+                    let bool_ty = Ty::Known(Type::Scalar(ScalarType::Bool));
+                    self.unify(lhs, bool_ty.clone())?;
+                    self.unify(rhs, bool_ty.clone())?;
+                    bool_ty
+                } else if op.is_shift() {
+                    // Rust allows shifting by any integer type.
+                    lhs
+                } else if op.is_comparison() {
+                    self.unify(lhs, rhs)?;
+                    Ty::Known(Type::Scalar(ScalarType::Bool))
+                } else {
+                    self.arith(*op, lhs, rhs)?
+                }
+            }
+            Expr::Cast(operand, ty) => {
+                self.expr(operand)?;
+                Ty::Known(Type::Scalar(*ty))
+            }
+            Expr::Select(cond, then_expr, else_expr) => {
+                self.expect_bool(cond)?;
+                let then_ty = self.expr(then_expr)?;
+                let else_ty = self.expr(else_expr)?;
+                self.unify(then_ty, else_ty)?
+            }
+            Expr::Len(place) => {
+                let ty = self.place(place)?;
+                match self.known(&ty) {
+                    Some(Type::Array(..) | Type::Slice(_)) => {}
+                    _ => return Err(TypeError("`len` called on a non-array value".into())),
+                }
+                Ty::Known(Type::Scalar(ScalarType::U32))
+            }
+            Expr::Construct(ty, args) => {
+                let mut components = 0;
+                for arg in args {
+                    let arg_ty = self.expr(arg)?;
+                    components += match (ty, self.known(&arg_ty)) {
+                        (Type::Vector(scalar, _), Some(Type::Vector(s, len))) if s == *scalar => {
+                            len
+                        }
+                        (Type::Vector(scalar, _), _) => {
+                            self.unify(arg_ty, Ty::Known(Type::Scalar(*scalar)))?;
+                            1
+                        }
+                        (Type::Matrix(scalar, len), _) => {
+                            self.unify(arg_ty, Ty::Known(Type::Vector(*scalar, *len)))?;
+                            1
+                        }
+                        (ty, _) => return Err(TypeError(format!("cannot construct {ty:?}"))),
+                    };
+                }
+                let splat = matches!(ty, Type::Vector(..)) && args.len() == 1 && components == 1;
+                if !splat && Some(components) != vector_len(ty) {
+                    return Err(TypeError(format!(
+                        "{ty:?} built from {components} components"
+                    )));
+                }
+                Ty::Known(ty.clone())
+            }
+            Expr::Swizzle(operand, indices) => {
+                let ty = self.expr(operand)?;
+                match self.known(&ty) {
+                    Some(Type::Vector(scalar, len)) if indices.iter().all(|&i| i < len) => {
+                        Ty::Known(match indices.len() {
+                            1 => Type::Scalar(scalar),
+                            len => Type::Vector(scalar, len as u32),
+                        })
+                    }
+                    _ => return Err(TypeError("invalid vector swizzle".into())),
+                }
+            }
+            Expr::Call(function, args) => {
+                let mut arg_types = Vec::with_capacity(args.len());
+                for arg in args {
+                    arg_types.push(self.expr(arg)?);
+                }
+                let first = arg_types[0].clone();
+                for ty in arg_types.drain(1..) {
+                    self.unify(first.clone(), ty)?;
+                }
+                match (function, self.known(&first)) {
+                    (Function::Dot | Function::Length, Some(Type::Vector(scalar, _))) => {
+                        Ty::Known(Type::Scalar(scalar))
+                    }
+                    (Function::Cross, Some(Type::Vector(_, 3)))
+                    | (Function::Normalize, Some(Type::Vector(..)))
+                    | (Function::Transpose, Some(Type::Matrix(..))) => first,
+                    (
+                        Function::Min | Function::Max | Function::Clamp,
+                        Some(Type::Scalar(ScalarType::F32 | ScalarType::U32 | ScalarType::I32)),
+                    )
+                    | (Function::Abs, Some(Type::Scalar(ScalarType::F32 | ScalarType::I32))) => {
+                        first
+                    }
+                    (function, Some(Type::Scalar(ScalarType::F32))) if function.is_math() => first,
+                    _ => {
+                        return Err(TypeError(format!(
+                            "`{function:?}` called on an unsupported value"
+                        )))
+                    }
+                }
+            }
+            Expr::Atomic(op, place, args) => {
+                match atomic_root(place) {
+                    Place::Param(i) if !self.kernel.params[*i].mutable => {
+                        return Err(TypeError(format!(
+                            "atomics cannot update read-only argument `{}`",
+                            self.kernel.params[*i].name
+                        )))
+                    }
+                    Place::Param(_) | Place::Shared(_) => {}
+                    _ => {
+                        return Err(TypeError(
+                            "atomics can only update elements of buffers and shared arrays".into(),
+                        ))
+                    }
+                }
+                let ty = self.place(place)?;
+                match (op, self.known(&ty)) {
+                    (_, Some(Type::Scalar(ScalarType::U32 | ScalarType::I32)))
+                    | (AtomicOp::Add | AtomicOp::Exchange, Some(Type::Scalar(ScalarType::F32))) => {
+                    }
+                    (_, Some(Type::Scalar(ScalarType::F32))) => {
+                        return Err(TypeError(format!(
+                            "atomic `{op:?}` is not supported on `f32` values"
+                        )))
+                    }
+                    (_, ty) => {
+                        return Err(TypeError(format!(
+                            "atomics can only update `u32`, `i32` or `f32` values, found {ty:?}"
+                        )))
+                    }
+                }
+                for arg in args {
+                    let arg_ty = self.expr(arg)?;
+                    self.unify(arg_ty, ty.clone())?;
+                }
+                ty
+            }
+            Expr::Subgroup(op, args) => self.subgroup(*op, args)?,
+        })
+    }
+
+    fn subgroup(&mut self, op: SubgroupOp, args: &[Expr]) -> Result<Ty> {
+        let ty = self.expr(&args[0])?;
+        match op {
+            SubgroupOp::Ballot => {
+                self.unify(ty, Ty::Known(Type::Scalar(ScalarType::Bool)))?;
+                return Ok(Ty::Known(Type::Scalar(ScalarType::U32)));
+            }
+            SubgroupOp::Broadcast | SubgroupOp::Shuffle => {
+                let lane = self.expr(&args[1])?;
+                self.unify(lane, Ty::Known(Type::Scalar(ScalarType::U32)))?;
+                let known = self.known(&ty);
+                if let Some(ty) =
+                    known.filter(|ty| !matches!(ty, Type::Scalar(_) | Type::Vector(..)))
+                {
+                    return Err(TypeError(format!(
+                        "subgroup `{op:?}` of {ty:?}, which is not a scalar or vector"
+                    )));
+                }
+            }
+            _ => {
+                let numeric = match (self.known(&ty), &ty) {
+                    (Some(known), _) => matches!(
+                        known,
+                        Type::Scalar(ScalarType::U32 | ScalarType::I32 | ScalarType::F32)
+                    ),
+                    // An integer or float literal, which defaults to `i32`
+                    // or `f32`.
+                    (None, Ty::Var(var)) => {
+                        let root = self.root(*var);
+                        self.vars[root].kind != VarKind::Any
+                    }
+                    (None, Ty::Known(_)) => unreachable!(),
+                };
+                if !numeric {
+                    return Err(TypeError(format!(
+                        "subgroup `{op:?}` can only combine `u32`, `i32` or `f32` values"
+                    )));
+                }
+            }
+        }
+        Ok(ty)
+    }
+
+    /// Type of arithmetic `lhs op rhs`. A vector or matrix combines with a
+    /// value of its own type or with its component type, and a matrix also
+    /// multiplies vectors of its size.
+    fn arith(&mut self, op: BinaryOp, lhs: Ty, rhs: Ty) -> Result<Ty> {
+        let composite = |ty: &Option<Type>| match ty {
+            Some(ty @ (Type::Vector(..) | Type::Matrix(..))) => ty.component_type(),
+            _ => None,
+        };
+        let (lhs_known, rhs_known) = (self.known(&lhs), self.known(&rhs));
+        match (&lhs_known, &rhs_known) {
+            (Some(Type::Matrix(scalar, len)), Some(Type::Vector(..))) if op == BinaryOp::Mul => {
+                self.unify(rhs, Ty::Known(Type::Vector(*scalar, *len)))
+            }
+            (Some(Type::Vector(..)), Some(Type::Vector(..)))
+            | (Some(Type::Matrix(..)), Some(Type::Matrix(..))) => self.unify(lhs, rhs),
+            _ => match (composite(&lhs_known), composite(&rhs_known)) {
+                (Some(scalar), None) => {
+                    self.unify(rhs, Ty::Known(Type::Scalar(scalar)))?;
+                    Ok(lhs)
+                }
+                (None, Some(scalar)) => {
+                    self.unify(lhs, Ty::Known(Type::Scalar(scalar)))?;
+                    Ok(rhs)
+                }
+                _ => self.unify(lhs, rhs),
+            },
+        }
+    }
+
+    fn literal(&mut self, kind: VarKind) -> Ty {
+        let ty = self.fresh(kind);
+        if let Ty::Var(var) = ty {
+            self.literals.push(var);
+        }
+        ty
+    }
+
+    fn place(&mut self, place: &Place) -> Result<Ty> {
+        Ok(match place {
+            Place::Param(i) => Ty::Known(self.kernel.params[*i].ty.clone()),
+            Place::Local(id) => self.local(*id),
+            Place::Shared(i) => Ty::Known(self.kernel.shared[*i].ty.clone()),
+            Place::Index(base, index) => {
+                let base = self.place(base)?;
+                let index = self.expr(index)?;
+                self.unify(index, Ty::Known(Type::Scalar(ScalarType::U32)))?;
+                match self.known(&base) {
+                    Some(Type::Array(elem, _) | Type::Slice(elem)) => Ty::Known(*elem),
+                    Some(Type::Vector(scalar, _)) => Ty::Known(Type::Scalar(scalar)),
+                    Some(Type::Matrix(scalar, len)) => Ty::Known(Type::Vector(scalar, len)),
+                    Some(ty) => return Err(TypeError(format!("cannot index into {ty:?}"))),
+                    None => return Err(TypeError("cannot index a value of unknown type".into())),
+                }
+            }
+            Place::Field(base, name) => {
+                let base = self.place(base)?;
+                let base = self.known(&base);
+                match base.as_ref().and_then(|ty| ty.member(name)) {
+                    Some((_, ty)) => Ty::Known(ty),
+                    None => {
+                        return Err(TypeError(match base {
+                            Some(Type::Struct(s)) => {
+                                format!("struct `{}` has no field `{name}`", s.name)
+                            }
+                            Some(ty) => format!("{ty:?} has no field `{name}`"),
+                            None => format!("field `{name}` accessed on a value of unknown type"),
+                        }))
+                    }
+                }
+            }
+        })
+    }
+}
+
+/// The argument or shared array an atomic updates an element of.
+fn atomic_root(place: &Place) -> &Place {
+    match place {
+        Place::Index(base, _) | Place::Field(base, _) => atomic_root(base),
+        root => root,
+    }
+}
+
+/// Number of components of a vector, or of columns of a matrix.
+fn vector_len(ty: &Type) -> Option<u32> {
+    match ty {
+        Type::Vector(_, len) | Type::Matrix(_, len) => Some(*len),
+        _ => None,
+    }
+}
+
+fn check_kind(kind: VarKind, ty: &Type) -> Result<()> {
+    let ok = match kind {
+        VarKind::Any => true,
+        VarKind::Int => ty.as_scalar().is_some_and(ScalarType::is_int),
+        VarKind::Float => ty.as_scalar().is_some_and(ScalarType::is_float),
+    };
+    if ok {
+        Ok(())
+    } else {
+        Err(TypeError(format!("numeric literal used as {ty:?}")))
+    }
+}
+
+// Visits literals in the same order as `Infer`, so the n-th unsuffixed literal
+// here is the n-th entry of `Infer::literals`.
+fn for_each_literal_in_block(block: &mut Block, f: &mut impl FnMut(&mut Literal)) {
+    for stmt in block {
+        match stmt {
+            Stmt::Let { init, .. } => {
+                if let Some(init) = init {
+                    for_each_literal(init, f);
+                }
+            }
+            Stmt::Assign { place, value } => {
+                for_each_literal_in_place(place, f);
+                for_each_literal(value, f);
+            }
+            Stmt::If {
+                cond,
+                then_block,
+                else_block,
+            } => {
+                for_each_literal(cond, f);
+                for_each_literal_in_block(then_block, f);
+                for_each_literal_in_block(else_block, f);
+            }
+            Stmt::For {
+                start, end, body, ..
+            } => {
+                for_each_literal(start, f);
+                for_each_literal(end, f);
+                for_each_literal_in_block(body, f);
+            }
+            Stmt::While { cond, body } => {
+                for_each_literal(cond, f);
+                for_each_literal_in_block(body, f);
+            }
+            Stmt::Break | Stmt::Continue | Stmt::Return | Stmt::Sync(_) => {}
+            Stmt::Expr(expr) => for_each_literal(expr, f),
+        }
+    }
+}
+
+fn for_each_literal(expr: &mut Expr, f: &mut impl FnMut(&mut Literal)) {
+    match expr {
+        Expr::Literal(lit) => f(lit),
+        Expr::Load(place) | Expr::Len(place) => for_each_literal_in_place(place, f),
+        Expr::Builtin(_) => {}
+        Expr::Unary(_, operand) | Expr::Cast(operand, _) => for_each_literal(operand, f),
+        Expr::Binary(_, lhs, rhs) => {
+            for_each_literal(lhs, f);
+            for_each_literal(rhs, f);
+        }
+        Expr::Select(cond, then_expr, else_expr) => {
+            for_each_literal(cond, f);
+            for_each_literal(then_expr, f);
+            for_each_literal(else_expr, f);
+        }
+        Expr::Construct(_, args) | Expr::Call(_, args) | Expr::Subgroup(_, args) => {
+            args.iter_mut().for_each(|arg| for_each_literal(arg, f))
+        }
+        Expr::Swizzle(operand, _) => for_each_literal(operand, f),
+        Expr::Atomic(_, place, args) => {
+            for_each_literal_in_place(place, f);
+            args.iter_mut().for_each(|arg| for_each_literal(arg, f));
+        }
+    }
+}
+
+fn for_each_literal_in_place(place: &mut Place, f: &mut impl FnMut(&mut Literal)) {
+    match place {
+        Place::Param(_) | Place::Local(_) | Place::Shared(_) => {}
+        Place::Index(base, index) => {
+            for_each_literal_in_place(base, f);
+            for_each_literal(index, f);
+        }
+        Place::Field(base, _) => for_each_literal_in_place(base, f),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::ir::{BinaryOp, Local, Param};
+
+    fn u32_ty() -> Type {
+        Type::Scalar(ScalarType::U32)
+    }
+
+    #[test]
+    fn test_infers_literal_from_param() {
+        // let x = 1; out[x] = x + a;
+        let mut kernel = Kernel {
+            name: "k".into(),
+            params: vec![
+                Param {
+                    name: "a".into(),
+                    ty: u32_ty(),
+                    mutable: false,
+                },
+                Param {
+                    name: "out".into(),
+                    ty: Type::Array(Box::new(u32_ty()), 4),
+                    mutable: true,
+                },
+            ],
+            locals: vec![Local {
+                name: "x".into(),
+                ty: None,
+            }],
+            shared: vec![],
+            body: vec![
+                Stmt::Let {
+                    local: LocalId(0),
+                    init: Some(Expr::Literal(Literal::Int(1, None))),
+                },
+                Stmt::Assign {
+                    place: Place::Index(
+                        Box::new(Place::Param(1)),
+                        Box::new(Expr::Load(Place::Local(LocalId(0)))),
+                    ),
+                    value: Expr::Binary(
+                        BinaryOp::Add,
+                        Box::new(Expr::Load(Place::Local(LocalId(0)))),
+                        Box::new(Expr::Load(Place::Param(0))),
+                    ),
+                },
+            ],
+        };
+        check(&mut kernel).unwrap();
+        assert_eq!(kernel.locals[0].ty, Some(u32_ty()));
+        assert_eq!(
+            kernel.body[0],
+            Stmt::Let {
+                local: LocalId(0),
+                init: Some(Expr::Literal(Literal::Int(1, Some(ScalarType::U32)))),
+            }
+        );
+    }
+
+    #[test]
+    fn test_defaults_unconstrained_literals() {
+        let mut kernel = Kernel {
+            name: "k".into(),
+            params: vec![],
+            locals: vec![
+                Local {
+                    name: "i".into(),
+                    ty: None,
+                },
+                Local {
+                    name: "f".into(),
+                    ty: None,
+                },
+            ],
+            shared: vec![],
+            body: vec![
+                Stmt::Let {
+                    local: LocalId(0),
+                    init: Some(Expr::Literal(Literal::Int(3, None))),
+                },
+                Stmt::Let {
+                    local: LocalId(1),
+                    init: Some(Expr::Literal(Literal::Float(0.5, None))),
+                },
+            ],
+        };
+        check(&mut kernel).unwrap();
+        assert_eq!(kernel.locals[0].ty, Some(Type::Scalar(ScalarType::I32)));
+        assert_eq!(kernel.locals[1].ty, Some(Type::Scalar(ScalarType::F32)));
+    }
+
+    #[test]
+    fn test_rejects_unresolved_param() {
+        let mut kernel = Kernel {
+            name: "k".into(),
+            params: vec![Param {
+                name: "t".into(),
+                ty: Type::Named("T".into()),
+                mutable: false,
+            }],
+            locals: vec![],
+            shared: vec![],
+            body: vec![],
+        };
+        assert!(check(&mut kernel).is_err());
+    }
+
+    #[test]
+    fn test_slices_are_only_params() {
+        let slice = || Type::Slice(Box::new(u32_ty()));
+        // let n = out.len(); let copy = out;
+        let mut kernel = Kernel {
+            name: "k".into(),
+            params: vec![Param {
+                name: "out".into(),
+                ty: slice(),
+                mutable: true,
+            }],
+            locals: vec![Local {
+                name: "n".into(),
+                ty: None,
+            }],
+            shared: vec![],
+            body: vec![Stmt::Let {
+                local: LocalId(0),
+                init: Some(Expr::Len(Place::Param(0))),
+            }],
+        };
+        check(&mut kernel.clone()).unwrap();
+        kernel.locals.push(Local {
+            name: "copy".into(),
+            ty: None,
+        });
+        kernel.body.push(Stmt::Let {
+            local: LocalId(1),
+            init: Some(Expr::Load(Place::Param(0))),
+        });
+        assert!(check(&mut kernel).is_err());
+
+        let mut nested = Kernel {
+            name: "k".into(),
+            params: vec![Param {
+                name: "t".into(),
+                ty: Type::Slice(Box::new(slice())),
+                mutable: false,
+            }],
+            locals: vec![],
+            shared: vec![],
+            body: vec![],
+        };
+        assert!(check(&mut nested).is_err());
+    }
+
+    #[test]
+    fn test_atomics() {
+        let slice = |scalar| Type::Slice(Box::new(Type::Scalar(scalar)));
+        let element = |param| {
+            Place::Index(
+                Box::new(Place::Param(param)),
+                Box::new(Expr::Literal(Literal::Int(0, None))),
+            )
+        };
+        // atomic_add(&mut counts[0], 1); atomic_add(&mut sums[0], 1.0);
+        let kernel = Kernel {
+            name: "k".into(),
+            params: vec![
+                Param {
+                    name: "counts".into(),
+                    ty: slice(ScalarType::U32),
+                    mutable: true,
+                },
+                Param {
+                    name: "sums".into(),
+                    ty: slice(ScalarType::F32),
+                    mutable: true,
+                },
+                Param {
+                    name: "input".into(),
+                    ty: slice(ScalarType::I32),
+                    mutable: false,
+                },
+            ],
+            locals: vec![],
+            shared: vec![],
+            body: vec![
+                Stmt::Expr(Expr::Atomic(
+                    AtomicOp::Add,
+                    element(0),
+                    vec![Expr::Literal(Literal::Int(1, None))],
+                )),
+                Stmt::Expr(Expr::Atomic(
+                    AtomicOp::Add,
+                    element(1),
+                    vec![Expr::Literal(Literal::Float(1.0, None))],
+                )),
+            ],
+        };
+        let mut checked = kernel.clone();
+        check(&mut checked).unwrap();
+        assert!(checked.uses_float_atomics());
+        let Stmt::Expr(Expr::Atomic(_, _, args)) = &checked.body[0] else {
+            unreachable!()
+        };
+        assert_eq!(
+            args[0],
+            Expr::Literal(Literal::Int(1, Some(ScalarType::U32)))
+        );
+
+        let rejected = |op, param, arg| {
+            let mut kernel = kernel.clone();
+            kernel.body = vec![Stmt::Expr(Expr::Atomic(op, element(param), vec![arg]))];
+            check(&mut kernel).unwrap_err().0
+        };
+        let one = || Expr::Literal(Literal::Int(1, None));
+        let err = rejected(AtomicOp::Max, 1, Expr::Literal(Literal::Float(1.0, None)));
+        assert!(err.contains("`f32`"), "{err}");
+        assert!(rejected(AtomicOp::Add, 2, one()).contains("read-only"));
+        let err = rejected(AtomicOp::Add, 0, Expr::Literal(Literal::Bool(true)));
+        assert!(err.contains("mismatched"), "{err}");
+    }
+
+    #[test]
+    fn test_subgroups() {
+        // let x = subgroup_op(...);
+        let check_op = |op, args| {
+            let mut kernel = Kernel {
+                name: "k".into(),
+                params: vec![],
+                locals: vec![Local {
+                    name: "x".into(),
+                    ty: None,
+                }],
+                shared: vec![],
+                body: vec![Stmt::Let {
+                    local: LocalId(0),
+                    init: Some(Expr::Subgroup(op, args)),
+                }],
+            };
+            check(&mut kernel).map(|()| kernel.locals[0].ty.clone().unwrap())
+        };
+        let float = || Expr::Literal(Literal::Float(1.0, None));
+        let int = || Expr::Literal(Literal::Int(1, None));
+        let bool = || Expr::Literal(Literal::Bool(true));
+        let f32_ty = Type::Scalar(ScalarType::F32);
+        assert_eq!(
+            check_op(SubgroupOp::ReduceMin, vec![float()]),
+            Ok(f32_ty.clone())
+        );
+        assert_eq!(check_op(SubgroupOp::Ballot, vec![bool()]), Ok(u32_ty()));
+        assert_eq!(
+            check_op(SubgroupOp::Shuffle, vec![float(), int()]),
+            Ok(f32_ty)
+        );
+        assert!(check_op(SubgroupOp::ReduceAdd, vec![bool()]).is_err());
+        assert!(check_op(SubgroupOp::Ballot, vec![int()]).is_err());
+        assert!(check_op(SubgroupOp::Broadcast, vec![int(), float()]).is_err());
+    }
+
+    #[test]
+    fn test_math_functions() {
+        // let x = <function>(args);
+        let check_call = |function, args| {
+            let mut kernel = Kernel {
+                name: "k".into(),
+                params: vec![],
+                locals: vec![Local {
+                    name: "x".into(),
+                    ty: None,
+                }],
+                shared: vec![],
+                body: vec![Stmt::Let {
+                    local: LocalId(0),
+                    init: Some(Expr::Call(function, args)),
+                }],
+            };
+            check(&mut kernel).map(|()| kernel.locals[0].ty.clone().unwrap())
+        };
+        let float = || Expr::Literal(Literal::Float(1.0, None));
+        let int = || Expr::Literal(Literal::Int(1, None));
+        let x = || Expr::Literal(Literal::Float(2.0, Some(ScalarType::F32)));
+        let u = || Expr::Literal(Literal::Int(5, Some(ScalarType::U32)));
+        let i = || Expr::Literal(Literal::Int(5, Some(ScalarType::I32)));
+        let f32_ty = Type::Scalar(ScalarType::F32);
+        assert_eq!(
+            check_call(Function::Pow, vec![x(), float()]),
+            Ok(f32_ty.clone())
+        );
+        assert_eq!(
+            check_call(Function::Mix, vec![x(), float(), float()]),
+            Ok(f32_ty)
+        );
+        assert_eq!(
+            check_call(Function::Clamp, vec![u(), int(), int()]),
+            Ok(u32_ty())
+        );
+        assert_eq!(
+            check_call(Function::Abs, vec![i()]),
+            Ok(Type::Scalar(ScalarType::I32))
+        );
+        assert!(check_call(Function::Sqrt, vec![u()]).is_err());
+        assert!(check_call(Function::Abs, vec![u()]).is_err());
+        assert!(check_call(Function::Min, vec![x(), int()]).is_err());
+        // The receiver's type must be known, as in Rust.
+        assert!(check_call(Function::Sqrt, vec![float()]).is_err());
+    }
+
+    #[test]
+    fn test_scalar_types() {
+        // let x = 1u8 as f64; out[0] = x > 2.0;
+        let mut kernel = Kernel {
+            name: "k".into(),
+            params: vec![Param {
+                name: "out".into(),
+                ty: Type::Array(Box::new(Type::Scalar(ScalarType::Bool)), 1),
+                mutable: true,
+            }],
+            locals: vec![Local {
+                name: "x".into(),
+                ty: None,
+            }],
+            shared: vec![],
+            body: vec![
+                Stmt::Let {
+                    local: LocalId(0),
+                    init: Some(Expr::Cast(
+                        Box::new(Expr::Literal(Literal::Int(1, Some(ScalarType::U8)))),
+                        ScalarType::F64,
+                    )),
+                },
+                Stmt::Assign {
+                    place: Place::Index(
+                        Box::new(Place::Param(0)),
+                        Box::new(Expr::Literal(Literal::Int(0, None))),
+                    ),
+                    value: Expr::Binary(
+                        BinaryOp::Gt,
+                        Box::new(Expr::Load(Place::Local(LocalId(0)))),
+                        Box::new(Expr::Literal(Literal::Float(2.0, None))),
+                    ),
+                },
+            ],
+        };
+        check(&mut kernel).unwrap();
+        assert_eq!(
+            kernel.scalar_types(),
+            [
+                ScalarType::Bool,
+                ScalarType::F64,
+                ScalarType::U8,
+                ScalarType::U32
+            ]
+        );
+    }
+
+    #[test]
+    fn test_vector_arith() {
+        // let v = m * Vec3::new(1.0, 2.0, 3.0) * 2.0; let x = v.dot(v);
+        let vec3 = Type::Vector(ScalarType::F32, 3);
+        let float = |value| Expr::Literal(Literal::Float(value, None));
+        let construct = |args| Expr::Construct(Type::Vector(ScalarType::F32, 3), args);
+        let local = |name: &str| Local {
+            name: name.into(),
+            ty: None,
+        };
+        let mut kernel = Kernel {
+            name: "k".into(),
+            params: vec![Param {
+                name: "m".into(),
+                ty: Type::Matrix(ScalarType::F32, 3),
+                mutable: false,
+            }],
+            locals: vec![local("v"), local("x")],
+            shared: vec![],
+            body: vec![
+                Stmt::Let {
+                    local: LocalId(0),
+                    init: Some(Expr::Binary(
+                        BinaryOp::Mul,
+                        Box::new(Expr::Binary(
+                            BinaryOp::Mul,
+                            Box::new(Expr::Load(Place::Param(0))),
+                            Box::new(construct(vec![float(1.0), float(2.0), float(3.0)])),
+                        )),
+                        Box::new(float(2.0)),
+                    )),
+                },
+                Stmt::Let {
+                    local: LocalId(1),
+                    init: Some(Expr::Call(
+                        Function::Dot,
+                        vec![
+                            Expr::Load(Place::Local(LocalId(0))),
+                            Expr::Load(Place::Local(LocalId(0))),
+                        ],
+                    )),
+                },
+            ],
+        };
+        check(&mut kernel).unwrap();
+        assert_eq!(kernel.locals[0].ty, Some(vec3));
+        assert_eq!(kernel.locals[1].ty, Some(Type::Scalar(ScalarType::F32)));
+
+        kernel.body[0] = Stmt::Let {
+            local: LocalId(0),
+            init: Some(construct(vec![float(1.0), float(2.0)])),
+        };
+        assert!(check(&mut kernel).is_err());
+    }
+}
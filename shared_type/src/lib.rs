@@ -1,23 +1,209 @@
+pub mod intrinsics;
+pub mod ir;
+pub mod matrix;
+pub mod vector;
+
+use ir::layout::{Layout, Rules};
+pub use matrix::{Mat2, Mat3, Mat4};
+pub use vector::{Vec2, Vec3, Vec4};
+
+/// Describes how a host type is represented on the device.
+/// Implemented for the primitive types, arrays, vectors and matrices, and generated by `#[kernel_struct]`.
+pub trait KernelType {
+    /// Layout of the type in storage buffers.
+    const STD430: Layout;
+    /// Layout of the type in uniform buffers.
+    const STD140: Layout;
+
+    fn kernel_type() -> ir::Type;
+}
+
 /// Marker trait for kernel functions, user should not implement this trait manually
 /// This trait is used to check if the customize type is valid in kernel functions
 #[allow(dead_code)]
-pub trait DeviceStructMarker {}
+pub trait DeviceStructMarker: KernelType {}
 
 /// Primitive trait is used to restrict the generic type of device struct
 #[allow(dead_code)]
-pub trait Primitive {}
+pub trait Primitive: KernelType {}
 
-impl Primitive for u32 {}
-impl Primitive for i32 {}
-impl Primitive for f32 {}
+/// Half-precision float, usable as the `f16` kernel type.
+pub use half::f16;
 
-#[allow(dead_code)]
+macro_rules! scalar_kernel_type {
+    ($($ty:ty => $scalar:ident),*) => {
+        $(impl Primitive for $ty {}
+
+        impl KernelType for $ty {
+            const STD430: Layout = Layout::scalar(ir::ScalarType::$scalar);
+            const STD140: Layout = Layout::scalar(ir::ScalarType::$scalar);
+
+            fn kernel_type() -> ir::Type {
+                ir::Type::Scalar(ir::ScalarType::$scalar)
+            }
+        })*
+    };
+}
+
+scalar_kernel_type!(
+    bool => Bool,
+    u8 => U8,
+    i8 => I8,
+    u16 => U16,
+    i16 => I16,
+    u32 => U32,
+    i32 => I32,
+    u64 => U64,
+    i64 => I64,
+    f16 => F16,
+    f32 => F32,
+    f64 => F64
+);
+
+impl<T: KernelType, const N: usize> KernelType for [T; N] {
+    const STD430: Layout = Layout::array(T::STD430, N as u32, Rules::Std430);
+    const STD140: Layout = Layout::array(T::STD140, N as u32, Rules::Std140);
+
+    fn kernel_type() -> ir::Type {
+        ir::Type::Array(Box::new(T::kernel_type()), N as u32)
+    }
+}
+
+/// Types that can be copied between host memory and device buffers.
+///
+/// Values are stored in their std430 layout, which matches the host
+/// representation for numeric scalars, 2- and 4-component vectors and
+/// arrays of them. `#[kernel_struct]`
+/// implements it for structs, writing each field at its std430 offset and
+/// leaving the padding zeroed.
+pub trait DeviceCopy: KernelType + Sized {
+    /// Writes `self` to the start of `out`.
+    fn write_bytes(&self, out: &mut [u8]);
+    /// Reads a value from the start of `bytes`.
+    fn read_bytes(bytes: &[u8]) -> Self;
+}
+
+macro_rules! scalar_device_copy {
+    ($($ty:ty),*) => {
+        $(impl DeviceCopy for $ty {
+            fn write_bytes(&self, out: &mut [u8]) {
+                out[..std::mem::size_of::<$ty>()].copy_from_slice(&self.to_ne_bytes());
+            }
+
+            fn read_bytes(bytes: &[u8]) -> Self {
+                let mut raw = [0; std::mem::size_of::<$ty>()];
+                raw.copy_from_slice(&bytes[..std::mem::size_of::<$ty>()]);
+                <$ty>::from_ne_bytes(raw)
+            }
+        })*
+    };
+}
+
+scalar_device_copy!(u8, i8, u16, i16, u32, i32, u64, i64, f16, f32, f64);
+
+// Booleans are stored as 32-bit integers, as in SPIR-V buffers.
+impl DeviceCopy for bool {
+    fn write_bytes(&self, out: &mut [u8]) {
+        u32::from(*self).write_bytes(out);
+    }
+
+    fn read_bytes(bytes: &[u8]) -> Self {
+        u32::read_bytes(bytes) != 0
+    }
+}
+
+impl<T: DeviceCopy, const N: usize> DeviceCopy for [T; N] {
+    fn write_bytes(&self, out: &mut [u8]) {
+        let stride = T::STD430.stride(Rules::Std430) as usize;
+        for (i, value) in self.iter().enumerate() {
+            value.write_bytes(&mut out[i * stride..]);
+        }
+    }
+
+    fn read_bytes(bytes: &[u8]) -> Self {
+        let stride = T::STD430.stride(Rules::Std430) as usize;
+        std::array::from_fn(|i| T::read_bytes(&bytes[i * stride..]))
+    }
+}
+
+/// How a kernel accesses one of its arguments.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Access {
+    ReadOnly,
+    /// Arguments declared `mut`, whose writes are visible to the host after
+    /// the launch.
+    ReadWrite,
+}
+
+/// Describes a kernel argument to the host.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct ArgDesc {
+    pub name: String,
+    pub ty: ir::Type,
+    pub access: Access,
+    /// Binding of the storage buffer the argument is passed in, in descriptor
+    /// set 0.
+    pub binding: u32,
+}
+
+/// A kernel that can be launched on a device.
+///
+/// `#[kernel_fn]` implements it for a zero-sized handle type generated next to
+/// each kernel function, named after the function in `CamelCase` with a
+/// `Kernel` suffix. It is also implemented for lowered [`ir::Kernel`]s.
 pub trait KernelFn {
-    fn execute(&self);
+    /// Name of the entry point of the kernel's module, which is the name of
+    /// the kernel function.
+    fn entry_point(&self) -> &str;
+
+    /// Kernel arguments, excluding the launch configuration, in declaration
+    /// order.
+    fn args(&self) -> Vec<ArgDesc>;
+
+    /// Thread block size the kernel must be launched with, if it declares
+    /// one with `#[kernel_fn(workgroup_size = N)]`.
+    fn workgroup_size(&self) -> Option<u32> {
+        None
+    }
+
+    /// The kernel lowered to type-checked IR, with the types of generic
+    /// parameters resolved. Backends generate device code from it.
+    fn module(&self) -> Result<ir::Kernel, ir::typeck::TypeError>;
+
+    /// SPIR-V words of the kernel's module, generated when the crate was
+    /// built, for thread blocks of [`KernelFn::workgroup_size`] threads.
+    /// `None` when the module is generated at launch time.
+    fn spirv(&self) -> Option<&'static [u32]> {
+        None
+    }
 }
 
-impl KernelFn for fn() {
-    fn execute(&self) {
-        (self)(); // Call the function
+impl KernelFn for ir::Kernel {
+    fn entry_point(&self) -> &str {
+        &self.name
+    }
+
+    // Every argument is bound to its own buffer, in order.
+    fn args(&self) -> Vec<ArgDesc> {
+        self.params
+            .iter()
+            .enumerate()
+            .map(|(binding, param)| ArgDesc {
+                name: param.name.clone(),
+                ty: param.ty.clone(),
+                access: if param.mutable {
+                    Access::ReadWrite
+                } else {
+                    Access::ReadOnly
+                },
+                binding: binding as u32,
+            })
+            .collect()
+    }
+
+    fn module(&self) -> Result<ir::Kernel, ir::typeck::TypeError> {
+        let mut kernel = self.clone();
+        ir::typeck::check(&mut kernel)?;
+        Ok(kernel)
     }
 }
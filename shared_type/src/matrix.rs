@@ -0,0 +1,236 @@
+//! Square matrix types for kernel functions.
+//!
+//! [`Mat2`], [`Mat3`] and [`Mat4`] are stored column-major, each column
+//! being a vector, and are lowered to device matrices. Like vectors, their
+//! constructors, constants, operators, columns and methods are recognized by
+//! name inside `#[kernel_fn]` bodies.
+use std::ops::{Add, AddAssign, Index, IndexMut, Mul, MulAssign, Neg, Sub, SubAssign};
+
+use crate::ir::layout::{Layout, Rules};
+use crate::vector::{Vec2, Vec3, Vec4};
+use crate::{ir, DeviceCopy, DeviceStructMarker, KernelType, Primitive};
+
+macro_rules! matrix {
+    ($name:ident, $vec:ident, $len:literal, $doc:literal, $($index:literal => $axis:ident: $component:ident),+) => {
+        #[doc = $doc]
+        #[derive(Clone, Copy, Debug, PartialEq)]
+        pub struct $name {
+            $(pub $axis: $vec),+
+        }
+
+        impl $name {
+            pub const ZERO: Self = Self { $($axis: $vec::ZERO),+ };
+            pub const IDENTITY: Self = Self {
+                $($axis: {
+                    let mut column = $vec::ZERO;
+                    column.$component = 1.0;
+                    column
+                }),+
+            };
+
+            pub const fn from_cols($($axis: $vec),+) -> Self {
+                Self { $($axis),+ }
+            }
+
+            pub fn from_cols_array_2d(columns: [[f32; $len]; $len]) -> Self {
+                let [$($axis),+] = columns;
+                Self { $($axis: $vec::from($axis)),+ }
+            }
+
+            pub fn to_cols_array_2d(self) -> [[f32; $len]; $len] {
+                [$(self.$axis.to_array()),+]
+            }
+
+            pub fn transpose(self) -> Self {
+                let columns = self.to_cols_array_2d();
+                Self::from_cols_array_2d(std::array::from_fn(|column| {
+                    std::array::from_fn(|row| columns[row][column])
+                }))
+            }
+        }
+
+        /// The identity matrix.
+        impl Default for $name {
+            fn default() -> Self {
+                Self::IDENTITY
+            }
+        }
+
+        /// Columns by index.
+        impl Index<usize> for $name {
+            type Output = $vec;
+
+            fn index(&self, index: usize) -> &$vec {
+                match index {
+                    $($index => &self.$axis,)+
+                    _ => panic!("index out of bounds: the len is {} but the index is {index}", $len),
+                }
+            }
+        }
+
+        impl IndexMut<usize> for $name {
+            fn index_mut(&mut self, index: usize) -> &mut $vec {
+                match index {
+                    $($index => &mut self.$axis,)+
+                    _ => panic!("index out of bounds: the len is {} but the index is {index}", $len),
+                }
+            }
+        }
+
+        impl Neg for $name {
+            type Output = Self;
+
+            fn neg(self) -> Self {
+                Self { $($axis: -self.$axis),+ }
+            }
+        }
+
+        impl Add for $name {
+            type Output = Self;
+
+            fn add(self, rhs: Self) -> Self {
+                Self { $($axis: self.$axis + rhs.$axis),+ }
+            }
+        }
+
+        impl Sub for $name {
+            type Output = Self;
+
+            fn sub(self, rhs: Self) -> Self {
+                Self { $($axis: self.$axis - rhs.$axis),+ }
+            }
+        }
+
+        impl Mul<$vec> for $name {
+            type Output = $vec;
+
+            fn mul(self, rhs: $vec) -> $vec {
+                $vec::ZERO $(+ self.$axis * rhs.$component)+
+            }
+        }
+
+        impl Mul for $name {
+            type Output = Self;
+
+            fn mul(self, rhs: Self) -> Self {
+                Self { $($axis: self * rhs.$axis),+ }
+            }
+        }
+
+        impl Mul<f32> for $name {
+            type Output = Self;
+
+            fn mul(self, rhs: f32) -> Self {
+                Self { $($axis: self.$axis * rhs),+ }
+            }
+        }
+
+        impl Mul<$name> for f32 {
+            type Output = $name;
+
+            fn mul(self, rhs: $name) -> $name {
+                rhs * self
+            }
+        }
+
+        impl AddAssign for $name {
+            fn add_assign(&mut self, rhs: Self) {
+                *self = *self + rhs;
+            }
+        }
+
+        impl SubAssign for $name {
+            fn sub_assign(&mut self, rhs: Self) {
+                *self = *self - rhs;
+            }
+        }
+
+        impl MulAssign for $name {
+            fn mul_assign(&mut self, rhs: Self) {
+                *self = *self * rhs;
+            }
+        }
+
+        impl MulAssign<f32> for $name {
+            fn mul_assign(&mut self, rhs: f32) {
+                *self = *self * rhs;
+            }
+        }
+
+        impl KernelType for $name {
+            const STD430: Layout = Layout::array($vec::STD430, $len, Rules::Std430);
+            const STD140: Layout = Layout::array($vec::STD140, $len, Rules::Std140);
+
+            fn kernel_type() -> ir::Type {
+                ir::Type::Matrix(ir::ScalarType::F32, $len)
+            }
+        }
+
+        impl DeviceStructMarker for $name {}
+
+        impl Primitive for $name {}
+
+        // Columns are laid out as an array of vectors.
+        impl DeviceCopy for $name {
+            fn write_bytes(&self, out: &mut [u8]) {
+                [$(self.$axis),+].write_bytes(out);
+            }
+
+            fn read_bytes(bytes: &[u8]) -> Self {
+                let [$($axis),+] = <[$vec; $len]>::read_bytes(bytes);
+                Self { $($axis),+ }
+            }
+        }
+    };
+}
+
+matrix!(Mat2, Vec2, 2, "A 2x2 column-major matrix.", 0 => x_axis: x, 1 => y_axis: y);
+matrix!(
+    Mat3,
+    Vec3,
+    3,
+    "A 3x3 column-major matrix.",
+    0 => x_axis: x,
+    1 => y_axis: y,
+    2 => z_axis: z
+);
+matrix!(
+    Mat4,
+    Vec4,
+    4,
+    "A 4x4 column-major matrix.",
+    0 => x_axis: x,
+    1 => y_axis: y,
+    2 => z_axis: z,
+    3 => w_axis: w
+);
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_matrix_ops() {
+        // A quarter turn around z.
+        let m = Mat3::from_cols(
+            Vec3::new(0.0, 1.0, 0.0),
+            Vec3::new(-1.0, 0.0, 0.0),
+            Vec3::new(0.0, 0.0, 1.0),
+        );
+        assert_eq!(m * Vec3::new(1.0, 2.0, 3.0), Vec3::new(-2.0, 1.0, 3.0));
+        assert_eq!(m * m.transpose(), Mat3::IDENTITY);
+        assert_eq!(m[1], m.y_axis);
+        assert_eq!((m * 2.0 - m).to_cols_array_2d(), m.to_cols_array_2d());
+    }
+
+    #[test]
+    fn test_matrix_layout() {
+        // Columns of a `Mat3` are padded to 16 bytes.
+        assert_eq!(Mat3::STD430.size, 48);
+        let m = Mat3::from_cols_array_2d([[1.0, 2.0, 3.0], [4.0, 5.0, 6.0], [7.0, 8.0, 9.0]]);
+        let mut bytes = [0; 48];
+        m.write_bytes(&mut bytes);
+        assert_eq!(f32::read_bytes(&bytes[16..]), 4.0);
+        assert_eq!(Mat3::read_bytes(&bytes), m);
+    }
+}
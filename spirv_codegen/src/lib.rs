@@ -0,0 +1,1722 @@
+//! SPIR-V code generation for kernel IR.
+//!
+//! Kept apart from the host runtime, so that `#[kernel_fn(spirv)]` can
+//! embed modules at expansion time without building a device API.
+//!
+//! Every kernel argument is bound to its own storage buffer in descriptor
+//! set 0, at the binding matching its position. The buffer holds a single
+//! block member with the argument's value, laid out per std430. Slice
+//! arguments are runtime arrays spanning the whole buffer.
+//!
+//! SPIR-V booleans have no size, so buffers store them as 32-bit integers
+//! and loads and stores convert between the two.
+//!
+//! Vectors and matrices map to their SPIR-V counterparts. Matrices are
+//! column-major, and the vector and math functions that SPIR-V lacks use
+//! the `GLSL.std.450` extended instructions.
+use std::collections::{HashMap, HashSet};
+
+use rspirv::binary::Assemble;
+use rspirv::dr::{self, Builder, Operand};
+use rspirv::spirv::{self, Word};
+use shared_type::f16;
+use shared_type::ir::{
+    layout, AtomicOp, BinaryOp, Block, Builtin, Expr, Function, Kernel, Literal, LocalId, Place,
+    ScalarType, Stmt, SubgroupOp, SyncOp, Type, UnaryOp,
+};
+
+pub type BuildResult<T> = Result<T, dr::Error>;
+
+/// Generates the SPIR-V module of a type-checked `kernel`, for thread blocks
+/// of `thread_block_size` threads. The entry point is named after the kernel.
+pub fn build_spirv(kernel: &Kernel, thread_block_size: u32) -> BuildResult<Vec<u32>> {
+    build_module(kernel, &kernel.name, thread_block_size)
+}
+
+/// Generates a `GLCompute` module for a type-checked `kernel`, with a
+/// workgroup of `local_size` threads along x.
+pub fn build_module(kernel: &Kernel, entry_point: &str, local_size: u32) -> BuildResult<Vec<u32>> {
+    let mut codegen = Codegen {
+        b: Builder::new(),
+        kernel,
+        local_size,
+        types: HashMap::new(),
+        capabilities: HashSet::new(),
+        constants: HashMap::new(),
+        builtins: HashMap::new(),
+        glsl: None,
+        params: Vec::new(),
+        shared: Vec::new(),
+        locals: Vec::new(),
+        counters: HashMap::new(),
+        loops: Vec::new(),
+        current_block: 0,
+        terminated: false,
+    };
+    codegen.module(entry_point)?;
+    Ok(codegen.b.module().assemble())
+}
+
+/// Input variables declared by every module, in interface order.
+const BUILTINS: [spirv::BuiltIn; 4] = [
+    spirv::BuiltIn::NumWorkgroups,
+    spirv::BuiltIn::WorkgroupId,
+    spirv::BuiltIn::LocalInvocationId,
+    spirv::BuiltIn::GlobalInvocationId,
+];
+
+/// Scalar input variables declared by modules that use subgroups, after
+/// [`BUILTINS`].
+const SUBGROUP_BUILTINS: [spirv::BuiltIn; 3] = [
+    spirv::BuiltIn::SubgroupSize,
+    spirv::BuiltIn::SubgroupId,
+    spirv::BuiltIn::SubgroupLocalInvocationId,
+];
+
+struct Loop {
+    merge: Word,
+    continue_target: Word,
+}
+
+struct Codegen<'k> {
+    b: Builder,
+    kernel: &'k Kernel,
+    local_size: u32,
+    /// Type ids by type and whether the type is laid out for a buffer.
+    types: HashMap<(Type, bool), Word>,
+    capabilities: HashSet<spirv::Capability>,
+    /// Constants by type id and bit pattern.
+    constants: HashMap<(Word, u64), Word>,
+    builtins: HashMap<spirv::BuiltIn, Word>,
+    /// The `GLSL.std.450` import, once an instruction needs it.
+    glsl: Option<Word>,
+    params: Vec<Word>,
+    shared: Vec<Word>,
+    locals: Vec<Word>,
+    /// Hidden counter of each `for` loop, by loop variable.
+    counters: HashMap<LocalId, Word>,
+    loops: Vec<Loop>,
+    current_block: Word,
+    /// Whether the current block already ends in a branch or return.
+    terminated: bool,
+}
+
+impl<'k> Codegen<'k> {
+    fn module(&mut self, entry_point: &str) -> BuildResult<()> {
+        self.b.set_version(1, 0);
+        self.b.capability(spirv::Capability::Shader);
+        self.b.extension("SPV_KHR_storage_buffer_storage_class");
+        self.b
+            .memory_model(spirv::AddressingModel::Logical, spirv::MemoryModel::GLSL450);
+
+        for builtin in BUILTINS {
+            let u32_ty = self.type_id(&Type::Scalar(ScalarType::U32));
+            let uvec3 = self.b.type_vector(u32_ty, 3);
+            let ptr = self.b.type_pointer(None, spirv::StorageClass::Input, uvec3);
+            let var = self.b.variable(ptr, None, spirv::StorageClass::Input, None);
+            self.b
+                .decorate(var, spirv::Decoration::BuiltIn, [Operand::BuiltIn(builtin)]);
+            self.builtins.insert(builtin, var);
+        }
+        // Subgroup operations are core from SPIR-V 1.3.
+        if self.kernel.uses_subgroups() {
+            self.b.set_version(1, 3);
+            self.require_subgroups(spirv::Capability::GroupNonUniform);
+            for builtin in SUBGROUP_BUILTINS {
+                let u32_ty = self.type_id(&Type::Scalar(ScalarType::U32));
+                let ptr = self
+                    .b
+                    .type_pointer(None, spirv::StorageClass::Input, u32_ty);
+                let var = self.b.variable(ptr, None, spirv::StorageClass::Input, None);
+                self.b
+                    .decorate(var, spirv::Decoration::BuiltIn, [Operand::BuiltIn(builtin)]);
+                self.builtins.insert(builtin, var);
+            }
+        }
+
+        for (binding, param) in self.kernel.params.iter().enumerate() {
+            let value_ty = self.type_id_in(&param.ty, true);
+            let block = self.b.id();
+            self.b.type_struct_id(Some(block), [value_ty]);
+            self.b.decorate(block, spirv::Decoration::Block, []);
+            self.b.member_decorate(
+                block,
+                0,
+                spirv::Decoration::Offset,
+                [Operand::LiteralBit32(0)],
+            );
+            self.decorate_matrix_member(block, 0, &param.ty);
+            if !param.mutable {
+                self.b
+                    .member_decorate(block, 0, spirv::Decoration::NonWritable, []);
+            }
+            let ptr = self
+                .b
+                .type_pointer(None, spirv::StorageClass::StorageBuffer, block);
+            let var = self
+                .b
+                .variable(ptr, None, spirv::StorageClass::StorageBuffer, None);
+            self.b.decorate(
+                var,
+                spirv::Decoration::DescriptorSet,
+                [Operand::LiteralBit32(0)],
+            );
+            self.b.decorate(
+                var,
+                spirv::Decoration::Binding,
+                [Operand::LiteralBit32(binding as u32)],
+            );
+            self.b.name(var, param.name.clone());
+            self.params.push(var);
+        }
+
+        for shared in &self.kernel.shared {
+            let ptr = self.pointer_type(spirv::StorageClass::Workgroup, &shared.ty);
+            let var = self
+                .b
+                .variable(ptr, None, spirv::StorageClass::Workgroup, None);
+            self.b.name(var, shared.name.clone());
+            self.shared.push(var);
+        }
+
+        let void = self.b.type_void();
+        let void_fn = self.b.type_function(void, vec![]);
+        let fun = self
+            .b
+            .begin_function(void, None, spirv::FunctionControl::NONE, void_fn)?;
+        self.current_block = self.b.begin_block(None)?;
+        // Function variables must come first in the entry block.
+        for local in &self.kernel.locals {
+            let ty = local.ty.as_ref().expect("kernel has not been type checked");
+            let ptr = self.pointer_type(spirv::StorageClass::Function, ty);
+            let var = self
+                .b
+                .variable(ptr, None, spirv::StorageClass::Function, None);
+            self.b.name(var, local.name.clone());
+            self.locals.push(var);
+        }
+        let mut loop_vars = Vec::new();
+        for_loop_vars(&self.kernel.body, &mut loop_vars);
+        for var in loop_vars {
+            let local = self.kernel.local(var);
+            let ty = local.ty.as_ref().expect("kernel has not been type checked");
+            let ptr = self.pointer_type(spirv::StorageClass::Function, ty);
+            let counter = self
+                .b
+                .variable(ptr, None, spirv::StorageClass::Function, None);
+            self.b.name(counter, format!("{}_counter", local.name));
+            self.counters.insert(var, counter);
+        }
+        self.block(&self.kernel.body)?;
+        if !self.terminated {
+            self.b.ret()?;
+        }
+        self.b.end_function()?;
+
+        let interface: Vec<Word> = BUILTINS
+            .iter()
+            .chain(&SUBGROUP_BUILTINS)
+            .filter_map(|b| self.builtins.get(b).copied())
+            .collect();
+        self.b.entry_point(
+            spirv::ExecutionModel::GLCompute,
+            fun,
+            entry_point,
+            interface,
+        );
+        self.b.execution_mode(
+            fun,
+            spirv::ExecutionMode::LocalSize,
+            [self.local_size, 1, 1],
+        );
+        Ok(())
+    }
+
+    fn type_id(&mut self, ty: &Type) -> Word {
+        self.type_id_in(ty, false)
+    }
+
+    /// Id of `ty` as laid out in a buffer if `in_buffer`, where booleans are
+    /// 32-bit integers, or as a value otherwise.
+    fn type_id_in(&mut self, ty: &Type, in_buffer: bool) -> Word {
+        let in_buffer = in_buffer && contains_bool(ty);
+        if let Some(&id) = self.types.get(&(ty.clone(), in_buffer)) {
+            return id;
+        }
+        let id = match ty {
+            Type::Scalar(ScalarType::Bool) if in_buffer => {
+                self.type_id(&Type::Scalar(ScalarType::U32))
+            }
+            Type::Scalar(ScalarType::Bool) => self.b.type_bool(),
+            Type::Scalar(scalar) => {
+                self.require_capabilities(*scalar);
+                let width = scalar.size() * 8;
+                if scalar.is_float() {
+                    self.b.type_float(width)
+                } else {
+                    self.b.type_int(width, u32::from(scalar.is_signed()))
+                }
+            }
+            Type::Array(elem, len) => {
+                let elem_id = self.type_id_in(elem, in_buffer);
+                let len_id = self.constant(ScalarType::U32, u64::from(*len));
+                let id = self.b.type_array(elem_id, len_id);
+                self.b.decorate(
+                    id,
+                    spirv::Decoration::ArrayStride,
+                    [Operand::LiteralBit32(layout::array_stride(elem))],
+                );
+                id
+            }
+            Type::Slice(elem) => {
+                let elem_id = self.type_id_in(elem, in_buffer);
+                let id = self.b.type_runtime_array(elem_id);
+                self.b.decorate(
+                    id,
+                    spirv::Decoration::ArrayStride,
+                    [Operand::LiteralBit32(layout::array_stride(elem))],
+                );
+                id
+            }
+            Type::Vector(scalar, len) => {
+                let component = self.type_id_in(&Type::Scalar(*scalar), in_buffer);
+                self.b.type_vector(component, *len)
+            }
+            Type::Matrix(scalar, len) => {
+                let column = self.type_id(&Type::Vector(*scalar, *len));
+                self.b.type_matrix(column, *len)
+            }
+            Type::Struct(s) => {
+                let members: Vec<Word> = s
+                    .fields
+                    .iter()
+                    .map(|f| self.type_id_in(&f.ty, in_buffer))
+                    .collect();
+                // Structs are never deduplicated, so that member decorations
+                // stay attached to a single type.
+                let id = self.b.id();
+                self.b.type_struct_id(Some(id), members);
+                self.b.name(id, s.name.clone());
+                for (i, (field, offset)) in
+                    s.fields.iter().zip(layout::struct_offsets(s)).enumerate()
+                {
+                    self.b.member_name(id, i as u32, field.name.clone());
+                    self.b.member_decorate(
+                        id,
+                        i as u32,
+                        spirv::Decoration::Offset,
+                        [Operand::LiteralBit32(offset)],
+                    );
+                    self.decorate_matrix_member(id, i as u32, &field.ty);
+                }
+                id
+            }
+            Type::Named(name) => panic!("unresolved kernel type `{name}`"),
+        };
+        self.types.insert((ty.clone(), in_buffer), id);
+        id
+    }
+
+    /// Matrices in buffers need their layout declared on the struct member
+    /// holding them, or holding arrays of them.
+    fn decorate_matrix_member(&mut self, id: Word, member: u32, ty: &Type) {
+        let mut ty = ty;
+        while let Type::Array(elem, _) | Type::Slice(elem) = ty {
+            ty = elem;
+        }
+        if let Type::Matrix(scalar, len) = *ty {
+            let stride = layout::array_stride(&Type::Vector(scalar, len));
+            self.b
+                .member_decorate(id, member, spirv::Decoration::ColMajor, []);
+            self.b.member_decorate(
+                id,
+                member,
+                spirv::Decoration::MatrixStride,
+                [Operand::LiteralBit32(stride)],
+            );
+        }
+    }
+
+    /// Declares what the module needs to use `scalar` in values and buffers.
+    fn require_capabilities(&mut self, scalar: ScalarType) {
+        use spirv::Capability::*;
+        let (capability, storage) = match scalar {
+            ScalarType::U8 | ScalarType::I8 => (
+                Int8,
+                Some((StorageBuffer8BitAccess, "SPV_KHR_8bit_storage")),
+            ),
+            ScalarType::U16 | ScalarType::I16 => (
+                Int16,
+                Some((StorageBuffer16BitAccess, "SPV_KHR_16bit_storage")),
+            ),
+            ScalarType::F16 => (
+                Float16,
+                Some((StorageBuffer16BitAccess, "SPV_KHR_16bit_storage")),
+            ),
+            ScalarType::U64 | ScalarType::I64 => (Int64, None),
+            ScalarType::F64 => (Float64, None),
+            ScalarType::Bool | ScalarType::U32 | ScalarType::I32 | ScalarType::F32 => return,
+        };
+        if self.capabilities.insert(capability) {
+            self.b.capability(capability);
+        }
+        if let Some((capability, extension)) = storage {
+            if self.capabilities.insert(capability) {
+                self.b.capability(capability);
+                self.b.extension(extension);
+            }
+        }
+    }
+
+    fn pointer_type(&mut self, class: spirv::StorageClass, ty: &Type) -> Word {
+        let pointee = self.type_id_in(ty, class == spirv::StorageClass::StorageBuffer);
+        self.b.type_pointer(None, class, pointee)
+    }
+
+    fn constant(&mut self, scalar: ScalarType, bits: u64) -> Word {
+        let ty = self.type_id(&Type::Scalar(scalar));
+        if let Some(&id) = self.constants.get(&(ty, bits)) {
+            return id;
+        }
+        let id = match scalar {
+            ScalarType::Bool if bits != 0 => self.b.constant_true(ty),
+            ScalarType::Bool => self.b.constant_false(ty),
+            s if s.size() == 8 => self.b.constant_bit64(ty, bits),
+            // Narrower signed constants are sign-extended to a whole word.
+            ScalarType::I8 => self.b.constant_bit32(ty, bits as i8 as u32),
+            ScalarType::I16 => self.b.constant_bit32(ty, bits as i16 as u32),
+            _ => self.b.constant_bit32(ty, bits as u32),
+        };
+        self.constants.insert((ty, bits), id);
+        id
+    }
+
+    fn literal(&mut self, lit: &Literal) -> Word {
+        match *lit {
+            Literal::Bool(b) => self.constant(ScalarType::Bool, u64::from(b)),
+            Literal::Int(value, ty) => self.constant(ty.unwrap_or(ScalarType::I32), value),
+            Literal::Float(value, ty) => {
+                let ty = ty.unwrap_or(ScalarType::F32);
+                self.constant(ty, float_bits(value, ty))
+            }
+        }
+    }
+
+    /// The constant 1 of `scalar`.
+    fn one(&mut self, scalar: ScalarType) -> Word {
+        let bits = if scalar.is_float() {
+            float_bits(1.0, scalar)
+        } else {
+            1
+        };
+        self.constant(scalar, bits)
+    }
+
+    fn begin_block(&mut self, label: Word) -> BuildResult<()> {
+        self.b.begin_block(Some(label))?;
+        self.current_block = label;
+        self.terminated = false;
+        Ok(())
+    }
+
+    fn block(&mut self, block: &Block) -> BuildResult<()> {
+        for stmt in block {
+            // Anything after a `break`, `continue` or `return` is dead.
+            if self.terminated {
+                break;
+            }
+            self.stmt(stmt)?;
+        }
+        Ok(())
+    }
+
+    fn stmt(&mut self, stmt: &Stmt) -> BuildResult<()> {
+        match stmt {
+            Stmt::Let { local, init } => {
+                if let Some(init) = init {
+                    let value = self.expr(init)?;
+                    self.b
+                        .store(self.locals[local.0 as usize], value, None, [])?;
+                }
+            }
+            Stmt::Assign { place, value } => {
+                let mut value = self.expr(value)?;
+                let (ptr, class) = self.place_ptr(place)?;
+                if class == spirv::StorageClass::StorageBuffer {
+                    value = self.store_converted(value, &self.kernel.place_type(place))?;
+                }
+                self.b.store(ptr, value, None, [])?;
+            }
+            Stmt::If {
+                cond,
+                then_block,
+                else_block,
+            } => {
+                let cond = self.expr(cond)?;
+                let then_label = self.b.id();
+                let merge = self.b.id();
+                let else_label = if else_block.is_empty() {
+                    merge
+                } else {
+                    self.b.id()
+                };
+                self.b
+                    .selection_merge(merge, spirv::SelectionControl::NONE)?;
+                self.b
+                    .branch_conditional(cond, then_label, else_label, [])?;
+
+                self.begin_block(then_label)?;
+                self.block(then_block)?;
+                let then_falls_through = !self.terminated;
+                if then_falls_through {
+                    self.b.branch(merge)?;
+                }
+                let mut else_falls_through = true;
+                if !else_block.is_empty() {
+                    self.begin_block(else_label)?;
+                    self.block(else_block)?;
+                    else_falls_through = !self.terminated;
+                    if else_falls_through {
+                        self.b.branch(merge)?;
+                    }
+                }
+
+                self.begin_block(merge)?;
+                if !then_falls_through && !else_falls_through {
+                    self.b.unreachable()?;
+                    self.terminated = true;
+                }
+            }
+            Stmt::For {
+                var,
+                start,
+                end,
+                inclusive,
+                body,
+            } => {
+                // The loop runs on a hidden counter, copied into the loop
+                // variable at the top of every iteration, so that the body
+                // can assign the variable without changing the iteration.
+                let var_ptr = self.locals[var.0 as usize];
+                let counter = self.counters[var];
+                let scalar = self
+                    .kernel
+                    .local(*var)
+                    .ty
+                    .as_ref()
+                    .and_then(Type::as_scalar)
+                    .expect("loop variables are integers");
+                let ty = self.type_id(&Type::Scalar(scalar));
+                let start = self.expr(start)?;
+                // The range is evaluated once, before the loop.
+                let end = self.expr(end)?;
+                self.b.store(counter, start, None, [])?;
+
+                self.emit_loop(
+                    |this| {
+                        let current = this.b.load(ty, None, counter, None, [])?;
+                        this.b.store(var_ptr, current, None, [])?;
+                        let bool_ty = this.type_id(&Type::Scalar(ScalarType::Bool));
+                        match (scalar.is_signed(), inclusive) {
+                            (true, false) => this.b.s_less_than(bool_ty, None, current, end),
+                            (true, true) => this.b.s_less_than_equal(bool_ty, None, current, end),
+                            (false, false) => this.b.u_less_than(bool_ty, None, current, end),
+                            (false, true) => this.b.u_less_than_equal(bool_ty, None, current, end),
+                        }
+                    },
+                    body,
+                    |this| {
+                        let current = this.b.load(ty, None, counter, None, [])?;
+                        let one = this.one(scalar);
+                        let next = this.b.i_add(ty, None, current, one)?;
+                        this.b.store(counter, next, None, [])?;
+                        if !inclusive {
+                            return Ok(None);
+                        }
+                        // `start..=end` must stop at `end` even when it is
+                        // the largest value of its type, where the counter
+                        // wraps around.
+                        let bool_ty = this.type_id(&Type::Scalar(ScalarType::Bool));
+                        Ok(Some(this.b.i_not_equal(bool_ty, None, current, end)?))
+                    },
+                )?;
+            }
+            Stmt::While { cond, body } => {
+                self.emit_loop(|this| this.expr(cond), body, |_| Ok(None))?;
+            }
+            Stmt::Break => {
+                let merge = self.loops.last().expect("`break` outside of a loop").merge;
+                self.b.branch(merge)?;
+                self.terminated = true;
+            }
+            Stmt::Continue => {
+                let target = self
+                    .loops
+                    .last()
+                    .expect("`continue` outside of a loop")
+                    .continue_target;
+                self.b.branch(target)?;
+                self.terminated = true;
+            }
+            Stmt::Return => {
+                self.b.ret()?;
+                self.terminated = true;
+            }
+            Stmt::Sync(op) => self.sync(*op)?,
+            Stmt::Expr(expr) => {
+                self.expr(expr)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Emits a barrier or fence. Both order accesses to shared arrays and to
+    /// buffers, which are `Uniform` memory as far as semantics go.
+    fn sync(&mut self, op: SyncOp) -> BuildResult<()> {
+        use spirv::MemorySemantics as Semantics;
+        let semantics = Semantics::ACQUIRE_RELEASE | Semantics::UNIFORM_MEMORY;
+        let (memory, semantics) = match op {
+            SyncOp::BlockBarrier | SyncOp::BlockFence => (
+                spirv::Scope::Workgroup,
+                semantics | Semantics::WORKGROUP_MEMORY,
+            ),
+            SyncOp::DeviceFence => (spirv::Scope::Device, semantics),
+        };
+        let memory = self.constant(ScalarType::U32, memory as u64);
+        let semantics = self.constant(ScalarType::U32, u64::from(semantics.bits()));
+        match op {
+            SyncOp::BlockBarrier => {
+                let execution = self.constant(ScalarType::U32, spirv::Scope::Workgroup as u64);
+                self.b.control_barrier(execution, memory, semantics)
+            }
+            SyncOp::BlockFence | SyncOp::DeviceFence => self.b.memory_barrier(memory, semantics),
+        }
+    }
+
+    /// Emits a structured loop. `cond` is evaluated at the top of every
+    /// iteration, and `step` in the continue block. The loop also exits
+    /// after `step` if it returns a condition that is false.
+    fn emit_loop(
+        &mut self,
+        cond: impl FnOnce(&mut Self) -> BuildResult<Word>,
+        body: &Block,
+        step: impl FnOnce(&mut Self) -> BuildResult<Option<Word>>,
+    ) -> BuildResult<()> {
+        let header = self.b.id();
+        let cond_label = self.b.id();
+        let body_label = self.b.id();
+        let continue_target = self.b.id();
+        let merge = self.b.id();
+
+        self.b.branch(header)?;
+        self.begin_block(header)?;
+        self.b
+            .loop_merge(merge, continue_target, spirv::LoopControl::NONE, [])?;
+        self.b.branch(cond_label)?;
+
+        self.begin_block(cond_label)?;
+        let cond = cond(self)?;
+        self.b.branch_conditional(cond, body_label, merge, [])?;
+
+        self.begin_block(body_label)?;
+        self.loops.push(Loop {
+            merge,
+            continue_target,
+        });
+        self.block(body)?;
+        self.loops.pop();
+        if !self.terminated {
+            self.b.branch(continue_target)?;
+        }
+
+        self.begin_block(continue_target)?;
+        match step(self)? {
+            Some(again) => self.b.branch_conditional(again, header, merge, [])?,
+            None => self.b.branch(header)?,
+        }
+
+        self.begin_block(merge)
+    }
+
+    /// Pointer to `place`, and the storage class it points into.
+    fn place_ptr(&mut self, place: &Place) -> BuildResult<(Word, spirv::StorageClass)> {
+        let (base, class, indices) = self.access_path(place)?;
+        if indices.is_empty() {
+            return Ok((base, class));
+        }
+        let ty = self.kernel.place_type(place);
+        let ptr_ty = self.pointer_type(class, &ty);
+        Ok((self.b.access_chain(ptr_ty, None, base, indices)?, class))
+    }
+
+    /// Converts `value` of type `ty` from its buffer layout.
+    fn load_converted(&mut self, value: Word, ty: &Type) -> BuildResult<Word> {
+        self.convert_bools(value, ty, true)
+    }
+
+    /// Converts `value` of type `ty` to its buffer layout.
+    fn store_converted(&mut self, value: Word, ty: &Type) -> BuildResult<Word> {
+        self.convert_bools(value, ty, false)
+    }
+
+    /// Rebuilds `value` with its booleans converted from or to integers,
+    /// member by member.
+    fn convert_bools(&mut self, value: Word, ty: &Type, from_buffer: bool) -> BuildResult<Word> {
+        if !contains_bool(ty) {
+            return Ok(value);
+        }
+        let members: Vec<Type> = match ty {
+            Type::Scalar(_) => {
+                let bool_ty = self.type_id(ty);
+                let u32_ty = self.type_id(&Type::Scalar(ScalarType::U32));
+                let zero = self.constant(ScalarType::U32, 0);
+                return if from_buffer {
+                    self.b.i_not_equal(bool_ty, None, value, zero)
+                } else {
+                    let one = self.constant(ScalarType::U32, 1);
+                    self.b.select(u32_ty, None, value, one, zero)
+                };
+            }
+            Type::Array(elem, len) => vec![(**elem).clone(); *len as usize],
+            Type::Vector(scalar, len) => vec![Type::Scalar(*scalar); *len as usize],
+            Type::Struct(s) => s.fields.iter().map(|f| f.ty.clone()).collect(),
+            ty => panic!("{ty:?} is never loaded or stored whole"),
+        };
+        let mut converted = Vec::with_capacity(members.len());
+        for (i, member) in members.iter().enumerate() {
+            let member_ty = self.type_id_in(member, from_buffer);
+            let item = self
+                .b
+                .composite_extract(member_ty, None, value, [i as u32])?;
+            converted.push(self.convert_bools(item, member, from_buffer)?);
+        }
+        let result_ty = self.type_id_in(ty, !from_buffer);
+        self.b.composite_construct(result_ty, None, converted)
+    }
+
+    /// Base variable, storage class and access chain indices of `place`.
+    fn access_path(
+        &mut self,
+        place: &Place,
+    ) -> BuildResult<(Word, spirv::StorageClass, Vec<Word>)> {
+        Ok(match place {
+            Place::Param(i) => {
+                let member = self.constant(ScalarType::U32, 0);
+                (
+                    self.params[*i],
+                    spirv::StorageClass::StorageBuffer,
+                    vec![member],
+                )
+            }
+            Place::Local(id) => (
+                self.locals[id.0 as usize],
+                spirv::StorageClass::Function,
+                Vec::new(),
+            ),
+            Place::Shared(i) => (self.shared[*i], spirv::StorageClass::Workgroup, Vec::new()),
+            Place::Index(base, index) => {
+                let (var, class, mut indices) = self.access_path(base)?;
+                indices.push(self.expr(index)?);
+                (var, class, indices)
+            }
+            Place::Field(base, name) => {
+                let (field, _) = self
+                    .kernel
+                    .place_type(base)
+                    .member(name)
+                    .expect("unknown field");
+                let (var, class, mut indices) = self.access_path(base)?;
+                indices.push(self.constant(ScalarType::U32, field as u64));
+                (var, class, indices)
+            }
+        })
+    }
+
+    /// Emits an atomic update of the scalar at `place`. Atomics are relaxed,
+    /// ordering nothing but the update itself.
+    fn atomic(&mut self, op: AtomicOp, place: &Place, args: &[Expr]) -> BuildResult<Word> {
+        let ty = self.kernel.place_type(place);
+        let Type::Scalar(scalar) = ty else {
+            panic!("atomic update of {ty:?}");
+        };
+        let (ptr, class) = self.place_ptr(place)?;
+        let args = args
+            .iter()
+            .map(|arg| self.expr(arg))
+            .collect::<BuildResult<Vec<_>>>()?;
+        let scope = match class {
+            spirv::StorageClass::Workgroup => spirv::Scope::Workgroup,
+            _ => spirv::Scope::Device,
+        };
+        let scope = self.constant(ScalarType::U32, scope as u64);
+        let relaxed = self.constant(ScalarType::U32, 0);
+        let ty = self.type_id(&ty);
+        let signed = scalar.is_signed();
+        match op {
+            AtomicOp::Add if scalar.is_float() => {
+                let capability = spirv::Capability::AtomicFloat32AddEXT;
+                if self.capabilities.insert(capability) {
+                    self.b.capability(capability);
+                    self.b.extension("SPV_EXT_shader_atomic_float_add");
+                }
+                self.b
+                    .atomic_f_add_ext(ty, None, ptr, scope, relaxed, args[0])
+            }
+            AtomicOp::Add => self.b.atomic_i_add(ty, None, ptr, scope, relaxed, args[0]),
+            AtomicOp::Min if signed => self.b.atomic_s_min(ty, None, ptr, scope, relaxed, args[0]),
+            AtomicOp::Min => self.b.atomic_u_min(ty, None, ptr, scope, relaxed, args[0]),
+            AtomicOp::Max if signed => self.b.atomic_s_max(ty, None, ptr, scope, relaxed, args[0]),
+            AtomicOp::Max => self.b.atomic_u_max(ty, None, ptr, scope, relaxed, args[0]),
+            AtomicOp::And => self.b.atomic_and(ty, None, ptr, scope, relaxed, args[0]),
+            AtomicOp::Or => self.b.atomic_or(ty, None, ptr, scope, relaxed, args[0]),
+            AtomicOp::Xor => self.b.atomic_xor(ty, None, ptr, scope, relaxed, args[0]),
+            AtomicOp::Exchange => self
+                .b
+                .atomic_exchange(ty, None, ptr, scope, relaxed, args[0]),
+            AtomicOp::CompareExchange => self
+                .b
+                .atomic_compare_exchange(ty, None, ptr, scope, relaxed, relaxed, args[1], args[0]),
+        }
+    }
+
+    fn require_subgroups(&mut self, capability: spirv::Capability) {
+        if self.capabilities.insert(capability) {
+            self.b.capability(capability);
+        }
+    }
+
+    fn subgroup(&mut self, op: SubgroupOp, args: &[Expr]) -> BuildResult<Word> {
+        use spirv::Capability::*;
+        let ty = self.kernel.expr_type(&args[0]);
+        let ty_id = self.type_id(&ty);
+        let value = self.expr(&args[0])?;
+        let scope = self.constant(ScalarType::U32, spirv::Scope::Subgroup as u64);
+        let operation = match op {
+            SubgroupOp::InclusiveAdd => spirv::GroupOperation::InclusiveScan,
+            SubgroupOp::ExclusiveAdd => spirv::GroupOperation::ExclusiveScan,
+            _ => spirv::GroupOperation::Reduce,
+        };
+        let scalar = ty.component_type();
+        let float = scalar.is_some_and(ScalarType::is_float);
+        let signed = scalar.is_some_and(ScalarType::is_signed);
+        match op {
+            SubgroupOp::ReduceAdd | SubgroupOp::InclusiveAdd | SubgroupOp::ExclusiveAdd => {
+                self.require_subgroups(GroupNonUniformArithmetic);
+                if float {
+                    self.b
+                        .group_non_uniform_f_add(ty_id, None, scope, operation, value, None)
+                } else {
+                    self.b
+                        .group_non_uniform_i_add(ty_id, None, scope, operation, value, None)
+                }
+            }
+            SubgroupOp::ReduceMin => {
+                self.require_subgroups(GroupNonUniformArithmetic);
+                let b = &mut self.b;
+                match (float, signed) {
+                    (true, _) => {
+                        b.group_non_uniform_f_min(ty_id, None, scope, operation, value, None)
+                    }
+                    (_, true) => {
+                        b.group_non_uniform_s_min(ty_id, None, scope, operation, value, None)
+                    }
+                    _ => b.group_non_uniform_u_min(ty_id, None, scope, operation, value, None),
+                }
+            }
+            SubgroupOp::ReduceMax => {
+                self.require_subgroups(GroupNonUniformArithmetic);
+                let b = &mut self.b;
+                match (float, signed) {
+                    (true, _) => {
+                        b.group_non_uniform_f_max(ty_id, None, scope, operation, value, None)
+                    }
+                    (_, true) => {
+                        b.group_non_uniform_s_max(ty_id, None, scope, operation, value, None)
+                    }
+                    _ => b.group_non_uniform_u_max(ty_id, None, scope, operation, value, None),
+                }
+            }
+            // Before SPIR-V 1.5, the lane of a broadcast must be a constant.
+            // Shuffling from the same lane in every thread is equivalent.
+            SubgroupOp::Broadcast if matches!(args[1], Expr::Literal(_)) => {
+                self.require_subgroups(GroupNonUniformBallot);
+                let lane = self.expr(&args[1])?;
+                self.b
+                    .group_non_uniform_broadcast(ty_id, None, scope, value, lane)
+            }
+            SubgroupOp::Broadcast | SubgroupOp::Shuffle => {
+                self.require_subgroups(GroupNonUniformShuffle);
+                let lane = self.expr(&args[1])?;
+                self.b
+                    .group_non_uniform_shuffle(ty_id, None, scope, value, lane)
+            }
+            // Only the lanes of the first word of the mask are reported.
+            SubgroupOp::Ballot => {
+                self.require_subgroups(GroupNonUniformBallot);
+                let u32_ty = self.type_id(&Type::Scalar(ScalarType::U32));
+                let uvec4 = self.b.type_vector(u32_ty, 4);
+                let mask = self.b.group_non_uniform_ballot(uvec4, None, scope, value)?;
+                self.b.composite_extract(u32_ty, None, mask, [0])
+            }
+        }
+    }
+
+    fn subgroup_builtin(&mut self, builtin: spirv::BuiltIn) -> BuildResult<Word> {
+        let u32_ty = self.type_id(&Type::Scalar(ScalarType::U32));
+        self.b.load(u32_ty, None, self.builtins[&builtin], None, [])
+    }
+
+    fn builtin_component(&mut self, builtin: spirv::BuiltIn) -> BuildResult<Word> {
+        let u32_ty = self.type_id(&Type::Scalar(ScalarType::U32));
+        let uvec3 = self.b.type_vector(u32_ty, 3);
+        let vector = self
+            .b
+            .load(uvec3, None, self.builtins[&builtin], None, [])?;
+        self.b.composite_extract(u32_ty, None, vector, [0])
+    }
+
+    fn expr(&mut self, expr: &Expr) -> BuildResult<Word> {
+        match expr {
+            Expr::Literal(lit) => Ok(self.literal(lit)),
+            Expr::Load(place) => {
+                let ty = self.kernel.place_type(place);
+                let (ptr, class) = self.place_ptr(place)?;
+                let in_buffer = class == spirv::StorageClass::StorageBuffer;
+                let ty_id = self.type_id_in(&ty, in_buffer);
+                let value = self.b.load(ty_id, None, ptr, None, [])?;
+                if in_buffer {
+                    self.load_converted(value, &ty)
+                } else {
+                    Ok(value)
+                }
+            }
+            Expr::Len(place) => match (self.kernel.place_type(place), place) {
+                (Type::Array(_, len), _) => Ok(self.constant(ScalarType::U32, u64::from(len))),
+                // Slices are always whole arguments, the runtime array
+                // being the only member of the buffer block.
+                (Type::Slice(_), Place::Param(i)) => {
+                    let u32_ty = self.type_id(&Type::Scalar(ScalarType::U32));
+                    self.b.array_length(u32_ty, None, self.params[*i], 0)
+                }
+                _ => panic!("length of a value that is not an array or slice argument"),
+            },
+            Expr::Builtin(Builtin::NumThreadBlocks) => {
+                self.builtin_component(spirv::BuiltIn::NumWorkgroups)
+            }
+            Expr::Builtin(Builtin::ThreadBlockSize) => {
+                Ok(self.constant(ScalarType::U32, u64::from(self.local_size)))
+            }
+            Expr::Builtin(Builtin::GlobalId) => {
+                self.builtin_component(spirv::BuiltIn::GlobalInvocationId)
+            }
+            Expr::Builtin(Builtin::LocalId) => {
+                self.builtin_component(spirv::BuiltIn::LocalInvocationId)
+            }
+            Expr::Builtin(Builtin::BlockId) => self.builtin_component(spirv::BuiltIn::WorkgroupId),
+            Expr::Builtin(Builtin::SubgroupSize) => {
+                self.subgroup_builtin(spirv::BuiltIn::SubgroupSize)
+            }
+            Expr::Builtin(Builtin::SubgroupId) => self.subgroup_builtin(spirv::BuiltIn::SubgroupId),
+            Expr::Builtin(Builtin::SubgroupLocalId) => {
+                self.subgroup_builtin(spirv::BuiltIn::SubgroupLocalInvocationId)
+            }
+            Expr::Unary(op, operand) => {
+                let ty = self.kernel.expr_type(operand);
+                let value = self.expr(operand)?;
+                self.unary(*op, &ty, value)
+            }
+            Expr::Binary(op, lhs, rhs) if op.is_logical() => self.short_circuit(*op, lhs, rhs),
+            Expr::Binary(op, lhs, rhs) => {
+                let lhs_ty = self.kernel.expr_type(lhs);
+                let rhs_ty = self.kernel.expr_type(rhs);
+                let lhs = self.expr(lhs)?;
+                let rhs = self.expr(rhs)?;
+                self.composite_binary(*op, &lhs_ty, lhs, &rhs_ty, rhs)
+            }
+            Expr::Cast(operand, to) => {
+                let from = self.scalar_type_of(operand);
+                let value = self.expr(operand)?;
+                self.cast(value, from, *to)
+            }
+            Expr::Select(cond, then_expr, else_expr) => {
+                let ty = self.kernel.expr_type(then_expr);
+                let cond = self.expr(cond)?;
+                let then_value = self.expr(then_expr)?;
+                let else_value = self.expr(else_expr)?;
+                self.select(&ty, cond, then_value, else_value)
+            }
+            Expr::Construct(ty, args) => {
+                let mut components = Vec::with_capacity(args.len());
+                for arg in args {
+                    components.push(self.expr(arg)?);
+                }
+                match (ty, components.as_slice()) {
+                    (Type::Vector(scalar, len), &[value])
+                        if self.kernel.expr_type(&args[0]) == Type::Scalar(*scalar) =>
+                    {
+                        self.splat(value, *scalar, *len)
+                    }
+                    _ => {
+                        let ty = self.type_id(ty);
+                        self.b.composite_construct(ty, None, components)
+                    }
+                }
+            }
+            Expr::Swizzle(operand, indices) => {
+                let ty = self.kernel.expr_type(expr);
+                let ty = self.type_id(&ty);
+                let value = self.expr(operand)?;
+                match indices.as_slice() {
+                    &[index] => self.b.composite_extract(ty, None, value, [index]),
+                    _ => self
+                        .b
+                        .vector_shuffle(ty, None, value, value, indices.iter().copied()),
+                }
+            }
+            Expr::Atomic(op, place, args) => self.atomic(*op, place, args),
+            Expr::Subgroup(op, args) => self.subgroup(*op, args),
+            Expr::Call(function, args) => {
+                let ty = self.kernel.expr_type(expr);
+                let ty = self.type_id(&ty);
+                let mut values = Vec::with_capacity(args.len());
+                for arg in args {
+                    values.push(self.expr(arg)?);
+                }
+                match function {
+                    Function::Dot => self.b.dot(ty, None, values[0], values[1]),
+                    Function::Transpose => self.b.transpose(ty, None, values[0]),
+                    Function::Cross => self.glsl_inst(ty, GLSL_CROSS, &values),
+                    Function::Length => self.glsl_inst(ty, GLSL_LENGTH, &values),
+                    Function::Normalize => self.glsl_inst(ty, GLSL_NORMALIZE, &values),
+                    &function => {
+                        let scalar = self.kernel.expr_type(&args[0]).component_type();
+                        let inst = glsl_math(function, scalar.expect("math on a scalar"));
+                        self.glsl_inst(ty, inst, &values)
+                    }
+                }
+            }
+        }
+    }
+
+    /// Applies `GLSL.std.450` instruction `inst` to `args`.
+    fn glsl_inst(&mut self, ty: Word, inst: u32, args: &[Word]) -> BuildResult<Word> {
+        let glsl = match self.glsl {
+            Some(glsl) => glsl,
+            None => *self.glsl.insert(self.b.ext_inst_import("GLSL.std.450")),
+        };
+        let operands = args.iter().map(|&arg| Operand::IdRef(arg));
+        self.b.ext_inst(ty, None, glsl, inst, operands)
+    }
+
+    /// A vector of `len` copies of `value`.
+    fn splat(&mut self, value: Word, scalar: ScalarType, len: u32) -> BuildResult<Word> {
+        let ty = self.type_id(&Type::Vector(scalar, len));
+        self.b
+            .composite_construct(ty, None, vec![value; len as usize])
+    }
+
+    /// `OpSelect` only picks between scalars and vectors, by a condition
+    /// with one boolean per component, so matrices are selected by column.
+    fn select(&mut self, ty: &Type, cond: Word, lhs: Word, rhs: Word) -> BuildResult<Word> {
+        match *ty {
+            Type::Vector(_, len) => {
+                let cond = self.splat(cond, ScalarType::Bool, len)?;
+                let ty = self.type_id(ty);
+                self.b.select(ty, None, cond, lhs, rhs)
+            }
+            Type::Matrix(scalar, len) => {
+                self.by_column(scalar, len, lhs, rhs, |this, column, lhs, rhs| {
+                    this.select(column, cond, lhs, rhs)
+                })
+            }
+            _ => {
+                let ty = self.type_id(ty);
+                self.b.select(ty, None, cond, lhs, rhs)
+            }
+        }
+    }
+
+    /// Builds a matrix from `f` applied to the matching columns of `lhs` and
+    /// `rhs`.
+    fn by_column(
+        &mut self,
+        scalar: ScalarType,
+        len: u32,
+        lhs: Word,
+        rhs: Word,
+        mut f: impl FnMut(&mut Self, &Type, Word, Word) -> BuildResult<Word>,
+    ) -> BuildResult<Word> {
+        let column = Type::Vector(scalar, len);
+        let column_ty = self.type_id(&column);
+        let mut columns = Vec::with_capacity(len as usize);
+        for i in 0..len {
+            let lhs = self.b.composite_extract(column_ty, None, lhs, [i])?;
+            let rhs = self.b.composite_extract(column_ty, None, rhs, [i])?;
+            columns.push(f(self, &column, lhs, rhs)?);
+        }
+        let ty = self.type_id(&Type::Matrix(scalar, len));
+        self.b.composite_construct(ty, None, columns)
+    }
+
+    fn unary(&mut self, op: UnaryOp, ty: &Type, value: Word) -> BuildResult<Word> {
+        if let Type::Matrix(scalar, len) = *ty {
+            return self.by_column(scalar, len, value, value, |this, column, value, _| {
+                this.unary(op, column, value)
+            });
+        }
+        let scalar = ty
+            .component_type()
+            .expect("operators only apply to scalars, vectors and matrices");
+        let ty = self.type_id(ty);
+        match (op, scalar) {
+            (UnaryOp::Neg, s) if s.is_float() => self.b.f_negate(ty, None, value),
+            (UnaryOp::Neg, _) => self.b.s_negate(ty, None, value),
+            (UnaryOp::Not, ScalarType::Bool) => self.b.logical_not(ty, None, value),
+            (UnaryOp::Not, _) => self.b.not(ty, None, value),
+        }
+    }
+
+    /// `lhs op rhs` on any mix of scalars, vectors and matrices that
+    /// type checks.
+    fn composite_binary(
+        &mut self,
+        op: BinaryOp,
+        lhs_ty: &Type,
+        lhs: Word,
+        rhs_ty: &Type,
+        rhs: Word,
+    ) -> BuildResult<Word> {
+        let float = lhs_ty.component_type().is_some_and(ScalarType::is_float);
+        match (lhs_ty, rhs_ty) {
+            _ if op.is_shift() => self.binary(op, lhs_ty, lhs, rhs),
+            (Type::Matrix(..), Type::Vector(..)) if op == BinaryOp::Mul => {
+                let ty = self.type_id(rhs_ty);
+                self.b.matrix_times_vector(ty, None, lhs, rhs)
+            }
+            (Type::Matrix(..), Type::Matrix(..)) if op == BinaryOp::Mul => {
+                let ty = self.type_id(lhs_ty);
+                self.b.matrix_times_matrix(ty, None, lhs, rhs)
+            }
+            (Type::Matrix(..), Type::Scalar(_)) if op == BinaryOp::Mul => {
+                let ty = self.type_id(lhs_ty);
+                self.b.matrix_times_scalar(ty, None, lhs, rhs)
+            }
+            (Type::Scalar(_), Type::Matrix(..)) if op == BinaryOp::Mul => {
+                self.composite_binary(op, rhs_ty, rhs, lhs_ty, lhs)
+            }
+            (Type::Vector(..), Type::Scalar(_)) if op == BinaryOp::Mul && float => {
+                let ty = self.type_id(lhs_ty);
+                self.b.vector_times_scalar(ty, None, lhs, rhs)
+            }
+            (Type::Scalar(_), Type::Vector(..)) if op == BinaryOp::Mul && float => {
+                self.composite_binary(op, rhs_ty, rhs, lhs_ty, lhs)
+            }
+            (&Type::Vector(scalar, len), Type::Scalar(_)) => {
+                let rhs = self.splat(rhs, scalar, len)?;
+                self.composite_binary(op, lhs_ty, lhs, lhs_ty, rhs)
+            }
+            (Type::Scalar(_), &Type::Vector(scalar, len)) => {
+                let lhs = self.splat(lhs, scalar, len)?;
+                self.composite_binary(op, rhs_ty, lhs, rhs_ty, rhs)
+            }
+            // Whole vectors are equal when all their components are.
+            (Type::Vector(..), Type::Vector(..)) if op.is_comparison() => {
+                let lanes = self.binary(op, lhs_ty, lhs, rhs)?;
+                let bool_ty = self.type_id(&Type::Scalar(ScalarType::Bool));
+                if op == BinaryOp::Ne {
+                    self.b.any(bool_ty, None, lanes)
+                } else {
+                    self.b.all(bool_ty, None, lanes)
+                }
+            }
+            (&Type::Matrix(scalar, len), Type::Matrix(..)) if op.is_comparison() => {
+                let column = Type::Vector(scalar, len);
+                let column_ty = self.type_id(&column);
+                let bool_ty = self.type_id(&Type::Scalar(ScalarType::Bool));
+                let mut result = None;
+                for i in 0..len {
+                    let l = self.b.composite_extract(column_ty, None, lhs, [i])?;
+                    let r = self.b.composite_extract(column_ty, None, rhs, [i])?;
+                    let equal = self.composite_binary(op, &column, l, &column, r)?;
+                    result = Some(match result {
+                        None => equal,
+                        Some(acc) if op == BinaryOp::Ne => {
+                            self.b.logical_or(bool_ty, None, acc, equal)?
+                        }
+                        Some(acc) => self.b.logical_and(bool_ty, None, acc, equal)?,
+                    });
+                }
+                Ok(result.expect("matrices have columns"))
+            }
+            // Other matrix arithmetic applies column by column.
+            (&Type::Matrix(scalar, len), Type::Matrix(..)) => {
+                self.by_column(scalar, len, lhs, rhs, |this, column, lhs, rhs| {
+                    this.composite_binary(op, column, lhs, column, rhs)
+                })
+            }
+            (&Type::Matrix(scalar, len), Type::Scalar(_)) => {
+                self.by_column(scalar, len, lhs, lhs, |this, column, lhs, _| {
+                    this.composite_binary(op, column, lhs, rhs_ty, rhs)
+                })
+            }
+            (Type::Scalar(_), &Type::Matrix(scalar, len)) => {
+                self.by_column(scalar, len, rhs, rhs, |this, column, rhs, _| {
+                    this.composite_binary(op, lhs_ty, lhs, column, rhs)
+                })
+            }
+            _ => self.binary(op, lhs_ty, lhs, rhs),
+        }
+    }
+
+    fn scalar_type_of(&self, expr: &Expr) -> ScalarType {
+        self.kernel
+            .expr_type(expr)
+            .as_scalar()
+            .expect("operators only apply to scalars")
+    }
+
+    // `&&` and `||` only evaluate their right operand when needed.
+    fn short_circuit(&mut self, op: BinaryOp, lhs: &Expr, rhs: &Expr) -> BuildResult<Word> {
+        let bool_ty = self.type_id(&Type::Scalar(ScalarType::Bool));
+        let lhs = self.expr(lhs)?;
+        let lhs_block = self.current_block;
+        let rhs_label = self.b.id();
+        let merge = self.b.id();
+        self.b
+            .selection_merge(merge, spirv::SelectionControl::NONE)?;
+        if op == BinaryOp::And {
+            self.b.branch_conditional(lhs, rhs_label, merge, [])?;
+        } else {
+            self.b.branch_conditional(lhs, merge, rhs_label, [])?;
+        }
+        self.begin_block(rhs_label)?;
+        let rhs = self.expr(rhs)?;
+        let rhs_block = self.current_block;
+        self.b.branch(merge)?;
+        self.begin_block(merge)?;
+        self.b
+            .phi(bool_ty, None, [(lhs, lhs_block), (rhs, rhs_block)])
+    }
+
+    /// `lhs op rhs` on two scalars, or component-wise on two vectors of
+    /// type `ty`.
+    fn binary(&mut self, op: BinaryOp, ty: &Type, lhs: Word, rhs: Word) -> BuildResult<Word> {
+        let scalar = ty
+            .component_type()
+            .expect("operators only apply to scalars and vectors");
+        let bool_ty = match *ty {
+            Type::Vector(_, len) => self.type_id(&Type::Vector(ScalarType::Bool, len)),
+            _ => self.type_id(&Type::Scalar(ScalarType::Bool)),
+        };
+        let ty = self.type_id(ty);
+        let b = &mut self.b;
+        let float = scalar.is_float();
+        let signed = scalar.is_signed();
+        match op {
+            BinaryOp::Add if float => b.f_add(ty, None, lhs, rhs),
+            BinaryOp::Add => b.i_add(ty, None, lhs, rhs),
+            BinaryOp::Sub if float => b.f_sub(ty, None, lhs, rhs),
+            BinaryOp::Sub => b.i_sub(ty, None, lhs, rhs),
+            BinaryOp::Mul if float => b.f_mul(ty, None, lhs, rhs),
+            BinaryOp::Mul => b.i_mul(ty, None, lhs, rhs),
+            BinaryOp::Div if float => b.f_div(ty, None, lhs, rhs),
+            BinaryOp::Div if signed => b.s_div(ty, None, lhs, rhs),
+            BinaryOp::Div => b.u_div(ty, None, lhs, rhs),
+            // Rust's `%` takes the sign of the dividend, like `OpFRem` and `OpSRem`.
+            BinaryOp::Rem if float => b.f_rem(ty, None, lhs, rhs),
+            BinaryOp::Rem if signed => b.s_rem(ty, None, lhs, rhs),
+            BinaryOp::Rem => b.u_mod(ty, None, lhs, rhs),
+            BinaryOp::BitAnd if scalar == ScalarType::Bool => b.logical_and(ty, None, lhs, rhs),
+            BinaryOp::BitAnd => b.bitwise_and(ty, None, lhs, rhs),
+            BinaryOp::BitOr if scalar == ScalarType::Bool => b.logical_or(ty, None, lhs, rhs),
+            BinaryOp::BitOr => b.bitwise_or(ty, None, lhs, rhs),
+            BinaryOp::BitXor if scalar == ScalarType::Bool => {
+                b.logical_not_equal(ty, None, lhs, rhs)
+            }
+            BinaryOp::BitXor => b.bitwise_xor(ty, None, lhs, rhs),
+            BinaryOp::Shl => b.shift_left_logical(ty, None, lhs, rhs),
+            BinaryOp::Shr if signed => b.shift_right_arithmetic(ty, None, lhs, rhs),
+            BinaryOp::Shr => b.shift_right_logical(ty, None, lhs, rhs),
+            BinaryOp::Eq if float => b.f_ord_equal(bool_ty, None, lhs, rhs),
+            BinaryOp::Eq if scalar == ScalarType::Bool => b.logical_equal(bool_ty, None, lhs, rhs),
+            BinaryOp::Eq => b.i_equal(bool_ty, None, lhs, rhs),
+            // NaN compares unequal to everything, itself included.
+            BinaryOp::Ne if float => b.f_unord_not_equal(bool_ty, None, lhs, rhs),
+            BinaryOp::Ne if scalar == ScalarType::Bool => {
+                b.logical_not_equal(bool_ty, None, lhs, rhs)
+            }
+            BinaryOp::Ne => b.i_not_equal(bool_ty, None, lhs, rhs),
+            BinaryOp::Lt if float => b.f_ord_less_than(bool_ty, None, lhs, rhs),
+            BinaryOp::Lt if signed => b.s_less_than(bool_ty, None, lhs, rhs),
+            BinaryOp::Lt => b.u_less_than(bool_ty, None, lhs, rhs),
+            BinaryOp::Le if float => b.f_ord_less_than_equal(bool_ty, None, lhs, rhs),
+            BinaryOp::Le if signed => b.s_less_than_equal(bool_ty, None, lhs, rhs),
+            BinaryOp::Le => b.u_less_than_equal(bool_ty, None, lhs, rhs),
+            BinaryOp::Gt if float => b.f_ord_greater_than(bool_ty, None, lhs, rhs),
+            BinaryOp::Gt if signed => b.s_greater_than(bool_ty, None, lhs, rhs),
+            BinaryOp::Gt => b.u_greater_than(bool_ty, None, lhs, rhs),
+            BinaryOp::Ge if float => b.f_ord_greater_than_equal(bool_ty, None, lhs, rhs),
+            BinaryOp::Ge if signed => b.s_greater_than_equal(bool_ty, None, lhs, rhs),
+            BinaryOp::Ge => b.u_greater_than_equal(bool_ty, None, lhs, rhs),
+            BinaryOp::And | BinaryOp::Or => unreachable!("handled by short_circuit"),
+        }
+    }
+
+    fn cast(&mut self, value: Word, from: ScalarType, to: ScalarType) -> BuildResult<Word> {
+        if from == to {
+            return Ok(value);
+        }
+        let ty = self.type_id(&Type::Scalar(to));
+        match (from, to) {
+            (ScalarType::Bool, _) => {
+                let one = self.one(to);
+                let zero = self.constant(to, 0);
+                self.b.select(ty, None, value, one, zero)
+            }
+            (from, to) if from.is_int() && to.is_int() && from.size() == to.size() => {
+                self.b.bitcast(ty, None, value)
+            }
+            // Changes the width first, extending the sign of signed values as
+            // `as` does, then reinterprets the bits with the target sign.
+            (from, to) if from.is_int() && to.is_int() => {
+                let resized = int_type(to.size(), from.is_signed());
+                let resized_ty = self.type_id(&Type::Scalar(resized));
+                let value = if from.is_signed() {
+                    self.b.s_convert(resized_ty, None, value)?
+                } else {
+                    self.b.u_convert(resized_ty, None, value)?
+                };
+                self.cast(value, resized, to)
+            }
+            (from, to) if from.is_float() && to.is_float() => self.b.f_convert(ty, None, value),
+            (from, to) if from.is_int() && to.is_float() => {
+                if from.is_signed() {
+                    self.b.convert_s_to_f(ty, None, value)
+                } else {
+                    self.b.convert_u_to_f(ty, None, value)
+                }
+            }
+            (from, to) if from.is_float() && to.is_int() => {
+                if to.is_signed() {
+                    self.b.convert_f_to_s(ty, None, value)
+                } else {
+                    self.b.convert_f_to_u(ty, None, value)
+                }
+            }
+            (from, to) => panic!("unsupported cast from {from:?} to {to:?}"),
+        }
+    }
+}
+
+/// Appends the variables of the `for` loops in `block`, nested ones included.
+fn for_loop_vars(block: &Block, vars: &mut Vec<LocalId>) {
+    for stmt in block {
+        match stmt {
+            Stmt::If {
+                then_block,
+                else_block,
+                ..
+            } => {
+                for_loop_vars(then_block, vars);
+                for_loop_vars(else_block, vars);
+            }
+            Stmt::For { var, body, .. } => {
+                vars.push(*var);
+                for_loop_vars(body, vars);
+            }
+            Stmt::While { body, .. } => for_loop_vars(body, vars),
+            _ => {}
+        }
+    }
+}
+
+fn contains_bool(ty: &Type) -> bool {
+    match ty {
+        Type::Scalar(scalar) => *scalar == ScalarType::Bool,
+        Type::Array(elem, _) | Type::Slice(elem) => contains_bool(elem),
+        Type::Struct(s) => s.fields.iter().any(|f| contains_bool(&f.ty)),
+        Type::Vector(scalar, _) | Type::Matrix(scalar, _) => *scalar == ScalarType::Bool,
+        Type::Named(name) => panic!("unresolved kernel type `{name}`"),
+    }
+}
+
+/// `GLSL.std.450` extended instruction numbers.
+const GLSL_ROUND: u32 = 1;
+const GLSL_FABS: u32 = 4;
+const GLSL_SABS: u32 = 5;
+const GLSL_FLOOR: u32 = 8;
+const GLSL_CEIL: u32 = 9;
+const GLSL_SIN: u32 = 13;
+const GLSL_COS: u32 = 14;
+const GLSL_TAN: u32 = 15;
+const GLSL_POW: u32 = 26;
+const GLSL_EXP: u32 = 27;
+const GLSL_LOG: u32 = 28;
+const GLSL_SQRT: u32 = 31;
+const GLSL_FMIN: u32 = 37;
+const GLSL_UMIN: u32 = 38;
+const GLSL_SMIN: u32 = 39;
+const GLSL_FMAX: u32 = 40;
+const GLSL_UMAX: u32 = 41;
+const GLSL_SMAX: u32 = 42;
+const GLSL_FCLAMP: u32 = 43;
+const GLSL_UCLAMP: u32 = 44;
+const GLSL_SCLAMP: u32 = 45;
+const GLSL_FMIX: u32 = 46;
+const GLSL_FMA: u32 = 50;
+const GLSL_LENGTH: u32 = 66;
+const GLSL_CROSS: u32 = 68;
+const GLSL_NORMALIZE: u32 = 69;
+
+/// The `GLSL.std.450` instruction of math `function` on `scalar` values.
+fn glsl_math(function: Function, scalar: ScalarType) -> u32 {
+    // Picks the float, unsigned or signed form of an instruction.
+    let by_type = |float, unsigned, signed| match scalar {
+        _ if scalar.is_float() => float,
+        _ if scalar.is_signed() => signed,
+        _ => unsigned,
+    };
+    match function {
+        Function::Sqrt => GLSL_SQRT,
+        Function::Exp => GLSL_EXP,
+        Function::Log => GLSL_LOG,
+        Function::Pow => GLSL_POW,
+        Function::Sin => GLSL_SIN,
+        Function::Cos => GLSL_COS,
+        Function::Tan => GLSL_TAN,
+        Function::Floor => GLSL_FLOOR,
+        Function::Ceil => GLSL_CEIL,
+        Function::Round => GLSL_ROUND,
+        Function::Fma => GLSL_FMA,
+        Function::Mix => GLSL_FMIX,
+        Function::Min => by_type(GLSL_FMIN, GLSL_UMIN, GLSL_SMIN),
+        Function::Max => by_type(GLSL_FMAX, GLSL_UMAX, GLSL_SMAX),
+        Function::Clamp => by_type(GLSL_FCLAMP, GLSL_UCLAMP, GLSL_SCLAMP),
+        Function::Abs => by_type(GLSL_FABS, GLSL_SABS, GLSL_SABS),
+        function => unreachable!("{function:?} is not a math function"),
+    }
+}
+
+/// Bit pattern of `value` rounded to the float type `scalar`.
+fn float_bits(value: f64, scalar: ScalarType) -> u64 {
+    match scalar {
+        ScalarType::F16 => u64::from(f16::from_f64(value).to_bits()),
+        ScalarType::F64 => value.to_bits(),
+        _ => u64::from((value as f32).to_bits()),
+    }
+}
+
+/// The integer type of `size` bytes and signedness `signed`.
+fn int_type(size: u32, signed: bool) -> ScalarType {
+    match (size, signed) {
+        (1, false) => ScalarType::U8,
+        (1, true) => ScalarType::I8,
+        (2, false) => ScalarType::U16,
+        (2, true) => ScalarType::I16,
+        (4, false) => ScalarType::U32,
+        (4, true) => ScalarType::I32,
+        (8, false) => ScalarType::U64,
+        (8, true) => ScalarType::I64,
+        _ => unreachable!("no {size}-byte integer type"),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use rspirv::dr::load_words;
+    use shared_type::ir::{typeck, Local, LocalId, Param};
+
+    fn scale_kernel() -> Kernel {
+        // for i in 0..4 { out[i] = out[i] * factor; }
+        let index = Box::new(Expr::Load(Place::Local(LocalId(0))));
+        let element = Place::Index(Box::new(Place::Param(1)), index);
+        Kernel {
+            name: "scale".into(),
+            params: vec![
+                Param {
+                    name: "factor".into(),
+                    ty: Type::Scalar(ScalarType::F32),
+                    mutable: false,
+                },
+                Param {
+                    name: "out".into(),
+                    ty: Type::Array(Box::new(Type::Scalar(ScalarType::F32)), 4),
+                    mutable: true,
+                },
+            ],
+            locals: vec![Local {
+                name: "i".into(),
+                ty: None,
+            }],
+            shared: vec![],
+            body: vec![Stmt::For {
+                var: LocalId(0),
+                start: Expr::Literal(Literal::Int(0, None)),
+                end: Expr::Literal(Literal::Int(4, None)),
+                inclusive: false,
+                body: vec![Stmt::Assign {
+                    place: element.clone(),
+                    value: Expr::Binary(
+                        BinaryOp::Mul,
+                        Box::new(Expr::Load(element)),
+                        Box::new(Expr::Load(Place::Param(0))),
+                    ),
+                }],
+            }],
+        }
+    }
+
+    #[test]
+    fn test_build_compute_module() {
+        let mut kernel = scale_kernel();
+        typeck::check(&mut kernel).unwrap();
+        let words = build_module(&kernel, "main", 64).unwrap();
+        let module = load_words(&words).unwrap();
+
+        let entry = &module.entry_points[0];
+        assert_eq!(
+            entry.operands[0],
+            Operand::ExecutionModel(spirv::ExecutionModel::GLCompute)
+        );
+        assert_eq!(entry.operands[2], Operand::LiteralString("main".into()));
+        let mode = &module.execution_modes[0];
+        assert_eq!(
+            mode.operands[1..],
+            [
+                Operand::ExecutionMode(spirv::ExecutionMode::LocalSize),
+                Operand::LiteralBit32(64),
+                Operand::LiteralBit32(1),
+                Operand::LiteralBit32(1),
+            ]
+        );
+
+        let bindings: Vec<_> = module
+            .annotations
+            .iter()
+            .filter(|inst| {
+                inst.operands.get(1) == Some(&Operand::Decoration(spirv::Decoration::Binding))
+            })
+            .map(|inst| inst.operands[2].clone())
+            .collect();
+        assert_eq!(
+            bindings,
+            [Operand::LiteralBit32(0), Operand::LiteralBit32(1)]
+        );
+
+        let function = &module.functions[0];
+        let opcodes: Vec<_> = function
+            .blocks
+            .iter()
+            .flat_map(|block| &block.instructions)
+            .map(|inst| inst.class.opcode)
+            .collect();
+        assert!(opcodes.contains(&spirv::Op::LoopMerge));
+        assert!(opcodes.contains(&spirv::Op::FMul));
+        assert!(opcodes.contains(&spirv::Op::ULessThan));
+    }
+
+    #[test]
+    fn test_wide_scalar_capabilities() {
+        let capabilities = |kernel: &mut Kernel| {
+            typeck::check(kernel).unwrap();
+            let module = load_words(build_module(kernel, "main", 1).unwrap()).unwrap();
+            let capabilities: Vec<_> = module
+                .capabilities
+                .iter()
+                .map(|inst| inst.operands[0].unwrap_capability())
+                .collect();
+            let extensions: Vec<_> = module
+                .extensions
+                .iter()
+                .map(|inst| inst.operands[0].unwrap_literal_string().to_string())
+                .collect();
+            (capabilities, extensions)
+        };
+        assert_eq!(
+            capabilities(&mut scale_kernel()).0,
+            [spirv::Capability::Shader]
+        );
+
+        let mut kernel = scale_kernel();
+        kernel.params[0].ty = Type::Scalar(ScalarType::F64);
+        kernel.params[1].ty = Type::Array(Box::new(Type::Scalar(ScalarType::U8)), 4);
+        kernel.locals.clear();
+        kernel.body = vec![Stmt::Assign {
+            place: Place::Index(
+                Box::new(Place::Param(1)),
+                Box::new(Expr::Literal(Literal::Int(0, None))),
+            ),
+            value: Expr::Cast(Box::new(Expr::Load(Place::Param(0))), ScalarType::U8),
+        }];
+        let (capabilities, extensions) = capabilities(&mut kernel);
+        for capability in [
+            spirv::Capability::Float64,
+            spirv::Capability::Int8,
+            spirv::Capability::StorageBuffer8BitAccess,
+        ] {
+            assert!(capabilities.contains(&capability), "{capability:?}");
+        }
+        assert!(!capabilities.contains(&spirv::Capability::Int64));
+        assert!(extensions.contains(&"SPV_KHR_8bit_storage".to_string()));
+    }
+
+    #[test]
+    fn test_matrix_layout() {
+        let mut kernel = scale_kernel();
+        kernel.params[0].ty = Type::Matrix(ScalarType::F32, 3);
+        kernel.params[1].ty = Type::Array(Box::new(Type::Vector(ScalarType::F32, 3)), 4);
+        kernel.locals.clear();
+        kernel.body = vec![Stmt::Assign {
+            place: Place::Index(
+                Box::new(Place::Param(1)),
+                Box::new(Expr::Literal(Literal::Int(0, None))),
+            ),
+            value: Expr::Call(
+                Function::Normalize,
+                vec![Expr::Load(Place::Field(
+                    Box::new(Place::Param(0)),
+                    "y_axis".into(),
+                ))],
+            ),
+        }];
+        typeck::check(&mut kernel).unwrap();
+        let module = load_words(build_module(&kernel, "main", 1).unwrap()).unwrap();
+        // The matrix member of the first argument's block is column-major,
+        // with vec3 columns 16 bytes apart.
+        let decorations: Vec<_> = module
+            .annotations
+            .iter()
+            .filter(|inst| inst.class.opcode == spirv::Op::MemberDecorate)
+            .map(|inst| inst.operands[2..].to_vec())
+            .collect();
+        assert!(decorations.contains(&vec![Operand::Decoration(spirv::Decoration::ColMajor)]));
+        assert!(decorations.contains(&vec![
+            Operand::Decoration(spirv::Decoration::MatrixStride),
+            Operand::LiteralBit32(16),
+        ]));
+        let imports: Vec<_> = module
+            .ext_inst_imports
+            .iter()
+            .map(|inst| inst.operands[0].unwrap_literal_string())
+            .collect();
+        assert_eq!(imports, ["GLSL.std.450"]);
+
+        // Modules that do not need the extended instructions do not import
+        // them.
+        let mut kernel = scale_kernel();
+        typeck::check(&mut kernel).unwrap();
+        let module = load_words(build_module(&kernel, "main", 1).unwrap()).unwrap();
+        assert!(module.ext_inst_imports.is_empty());
+    }
+
+    #[test]
+    fn test_subgroup_module() {
+        // let mask = subgroup_ballot(subgroup_local_id() == 0);
+        let mut kernel = Kernel {
+            name: "ballot".into(),
+            params: vec![],
+            locals: vec![Local {
+                name: "mask".into(),
+                ty: None,
+            }],
+            shared: vec![],
+            body: vec![Stmt::Let {
+                local: LocalId(0),
+                init: Some(Expr::Subgroup(
+                    SubgroupOp::Ballot,
+                    vec![Expr::Binary(
+                        BinaryOp::Eq,
+                        Box::new(Expr::Builtin(Builtin::SubgroupLocalId)),
+                        Box::new(Expr::Literal(Literal::Int(0, None))),
+                    )],
+                )),
+            }],
+        };
+        typeck::check(&mut kernel).unwrap();
+        let module = load_words(build_module(&kernel, "main", 8).unwrap()).unwrap();
+        assert_eq!(module.header.as_ref().unwrap().version(), (1, 3));
+        let capabilities: Vec<_> = module
+            .capabilities
+            .iter()
+            .map(|inst| inst.operands[0].unwrap_capability())
+            .collect();
+        assert!(capabilities.contains(&spirv::Capability::GroupNonUniform));
+        assert!(capabilities.contains(&spirv::Capability::GroupNonUniformBallot));
+        assert!(!capabilities.contains(&spirv::Capability::GroupNonUniformArithmetic));
+        // The subgroup builtins follow the others in the interface.
+        let entry_point = &module.entry_points[0];
+        assert_eq!(entry_point.operands.len(), 3 + BUILTINS.len() + 3);
+
+        let mut scale = scale_kernel();
+        typeck::check(&mut scale).unwrap();
+        let module = load_words(build_module(&scale, "main", 1).unwrap()).unwrap();
+        assert_eq!(module.header.unwrap().version(), (1, 0));
+    }
+
+    #[test]
+    fn test_barrier_scopes() {
+        let kernel = Kernel {
+            name: "sync".into(),
+            params: vec![],
+            locals: vec![],
+            shared: vec![],
+            body: vec![
+                Stmt::Sync(SyncOp::BlockBarrier),
+                Stmt::Sync(SyncOp::BlockFence),
+                Stmt::Sync(SyncOp::DeviceFence),
+            ],
+        };
+        let module = load_words(build_module(&kernel, "main", 8).unwrap()).unwrap();
+        let constants: HashMap<Word, u32> = module
+            .types_global_values
+            .iter()
+            .filter(|inst| inst.class.opcode == spirv::Op::Constant)
+            .map(|inst| {
+                (
+                    inst.result_id.unwrap(),
+                    inst.operands[0].unwrap_literal_bit32(),
+                )
+            })
+            .collect();
+        let barriers: Vec<_> = module.functions[0]
+            .blocks
+            .iter()
+            .flat_map(|block| &block.instructions)
+            .filter(|inst| {
+                matches!(
+                    inst.class.opcode,
+                    spirv::Op::ControlBarrier | spirv::Op::MemoryBarrier
+                )
+            })
+            .map(|inst| {
+                let operands: Vec<_> = inst
+                    .operands
+                    .iter()
+                    .map(|operand| match operand {
+                        Operand::IdScope(id) | Operand::IdMemorySemantics(id) => constants[id],
+                        operand => panic!("unexpected barrier operand {operand:?}"),
+                    })
+                    .collect();
+                (inst.class.opcode, operands)
+            })
+            .collect();
+
+        use spirv::MemorySemantics as Semantics;
+        let workgroup = spirv::Scope::Workgroup as u32;
+        let device = spirv::Scope::Device as u32;
+        let buffers = Semantics::ACQUIRE_RELEASE | Semantics::UNIFORM_MEMORY;
+        let shared = (buffers | Semantics::WORKGROUP_MEMORY).bits();
+        assert_eq!(
+            barriers,
+            [
+                (
+                    spirv::Op::ControlBarrier,
+                    vec![workgroup, workgroup, shared]
+                ),
+                (spirv::Op::MemoryBarrier, vec![workgroup, shared]),
+                (spirv::Op::MemoryBarrier, vec![device, buffers.bits()]),
+            ]
+        );
+    }
+}
@@ -0,0 +1,898 @@
+use compiler::{Context, Cpu, DeviceBuffer, KernelArg, Queue, RyclError};
+use rycl_derive::{kernel_fn, kernel_struct};
+use shared_type::intrinsics::{
+    atomic_add, atomic_compare_exchange, atomic_max, atomic_min, block_barrier, block_dim,
+    block_id, global_id, grid_dim, local_id, memory_fence_block, shared_array, subgroup_ballot,
+    subgroup_broadcast, subgroup_exclusive_add, subgroup_local_id, subgroup_reduce_add,
+    subgroup_reduce_max, subgroup_reduce_min, subgroup_shuffle, subgroup_size, Mix,
+};
+use shared_type::ir::{ScalarType, Type};
+use shared_type::{
+    Access, ArgDesc, DeviceStructMarker, KernelFn, KernelType, Mat3, Primitive, Vec2, Vec3,
+};
+use std::ops::{Add, Mul};
+use std::sync::Arc;
+
+#[kernel_fn]
+fn add(a: i32, b: i32, mut c: [i32; 1], num_thread_blocks: u32, thread_block_size: u32) {
+    if num_thread_blocks * thread_block_size == 1 {
+        c[0] = a + b;
+    }
+}
+
+#[kernel_fn]
+fn sum(values: [u32; 8], mut total: [u32; 1], num_thread_blocks: u32, thread_block_size: u32) {
+    let mut acc = 0u32;
+    let mut i = 0;
+    while i < 8 {
+        let value = values[i];
+        i += 1;
+        if value & 1 == 0 && value != 6 {
+            continue;
+        }
+        acc += value;
+    }
+    if num_thread_blocks * thread_block_size > 0 {
+        total[0] = acc;
+    }
+}
+
+#[kernel_fn]
+fn index(
+    values: [i32; 2],
+    i: u32,
+    mut out: [i32; 1],
+    num_thread_blocks: u32,
+    thread_block_size: u32,
+) {
+    if num_thread_blocks * thread_block_size == 1 {
+        out[0] = values[i as usize];
+    }
+}
+
+#[kernel_fn(workgroup_size = 4, spirv)]
+fn thread_ids(
+    mut ids: [u32; 8],
+    mut dims: [u32; 2],
+    num_thread_blocks: u32,
+    thread_block_size: u32,
+) {
+    ids[global_id() as usize] = block_id() * 10 + local_id();
+    if global_id() == 0 {
+        dims[0] = grid_dim();
+        dims[1] = block_dim();
+    }
+}
+
+#[kernel_fn(workgroup_size = 4, spirv)]
+fn stage(x: &[f32], y: &mut [f32], num_thread_blocks: u32, thread_block_size: u32) {
+    // Without a barrier, each thread only reads back the element it wrote.
+    let mut tile = shared_array::<f32, 4>();
+    let lid = local_id() as usize;
+    tile[lid] = x[global_id() as usize] * 2.0;
+    y[global_id() as usize] = tile[lid] + 1.0;
+}
+
+#[kernel_fn(workgroup_size = 8, spirv)]
+fn reverse_blocks(x: &[u32], y: &mut [u32], num_thread_blocks: u32, thread_block_size: u32) {
+    let mut tile = shared_array::<u32, 8>();
+    let lid = local_id() as usize;
+    tile[lid] = x[global_id() as usize];
+    memory_fence_block();
+    block_barrier();
+    y[global_id() as usize] = tile[7 - lid];
+}
+
+#[kernel_fn(workgroup_size = 64, spirv)]
+fn histogram(values: &[u32], bins: &mut [u32], num_thread_blocks: u32, thread_block_size: u32) {
+    let mut counts = shared_array::<u32, 4>();
+    let lid = local_id() as usize;
+    if lid < 4 {
+        counts[lid] = 0;
+    }
+    block_barrier();
+    atomic_add(&mut counts[(values[global_id() as usize] % 4) as usize], 1);
+    block_barrier();
+    if lid < 4 {
+        atomic_add(&mut bins[lid], counts[lid]);
+    }
+}
+
+#[kernel_fn]
+fn atomic_ops(
+    values: &[i32],
+    extremes: &mut [i32],
+    winner: &mut [u32],
+    total: &mut [f32],
+    num_thread_blocks: u32,
+    thread_block_size: u32,
+) {
+    let value = values[global_id() as usize];
+    atomic_min(&mut extremes[0], value);
+    atomic_max(&mut extremes[1], value);
+    atomic_compare_exchange(&mut winner[0], 0, global_id() + 1);
+    atomic_add(&mut total[0], value as f32);
+}
+
+#[kernel_fn]
+fn claim_slots(
+    next: &mut [u32],
+    slots: &mut [u32],
+    num_thread_blocks: u32,
+    thread_block_size: u32,
+) {
+    slots[atomic_add(&mut next[0], 1) as usize] += global_id() + 1;
+}
+
+#[kernel_fn(workgroup_size = 64, spirv)]
+fn subgroup_sums(
+    x: &[u32],
+    sums: &mut [u32],
+    prefix: &mut [u32],
+    num_thread_blocks: u32,
+    thread_block_size: u32,
+) {
+    let gid = global_id() as usize;
+    prefix[gid] = subgroup_exclusive_add(x[gid]);
+    let total = subgroup_reduce_add(x[gid]);
+    if subgroup_local_id() == 0 {
+        sums[gid / subgroup_size() as usize] = total;
+    }
+}
+
+#[kernel_fn]
+fn subgroup_lanes(
+    x: &[i32],
+    rotated: &mut [i32],
+    spread: &mut [i32],
+    masks: &mut [u32],
+    num_thread_blocks: u32,
+    thread_block_size: u32,
+) {
+    let gid = global_id() as usize;
+    let value = x[gid];
+    rotated[gid] = subgroup_shuffle(value, (subgroup_local_id() + 1) % subgroup_size());
+    spread[gid] =
+        subgroup_reduce_max(value) - subgroup_reduce_min(value) + subgroup_broadcast(value, 0);
+    masks[gid] = subgroup_ballot(value < 0);
+}
+
+#[kernel_fn]
+fn divergent_subgroups(
+    x: &[u32],
+    sums: &mut [u32],
+    firsts: &mut [u32],
+    num_thread_blocks: u32,
+    thread_block_size: u32,
+) {
+    let gid = global_id() as usize;
+    let lane = subgroup_local_id();
+    if lane < 2 {
+        sums[gid] = subgroup_reduce_add(x[gid]);
+    } else {
+        sums[gid] = subgroup_reduce_add(x[gid] * 10);
+    }
+    if lane == 0 {
+        firsts[gid / 4] = subgroup_broadcast(x[gid], 0);
+    }
+    block_barrier();
+}
+
+#[kernel_fn(workgroup_size = 4, spirv)]
+fn math(
+    x: &[f32],
+    y: &mut [f32],
+    z: &mut [f32],
+    n: &mut [i32],
+    num_thread_blocks: u32,
+    thread_block_size: u32,
+) {
+    let i = global_id() as usize;
+    let v = x[i];
+    let wave = v.sin().powf(2.0) + v.cos() * v.cos();
+    y[i] = v.abs().sqrt().mul_add(wave, v.exp().ln()) + v.tan().clamp(-1.0, 1.0);
+    z[i] = (v.floor() + v.ceil() + v.round()).mix(v.max(-0.5), 0.25);
+    n[i] = (i as i32 - 4).abs().min(3) + (i as i32 - 6).max(0);
+}
+
+#[kernel_fn]
+fn guarded(x: &[u32], y: &mut [u32], num_thread_blocks: u32, thread_block_size: u32) {
+    let i = global_id() as usize;
+    // Threads past the end of `x` must not read it.
+    let value = if i < x.len() { x[i] } else { 0 };
+    let mut steps = 0;
+    while steps < 3 && (if i < x.len() { x[i] } else { 0 }) > steps {
+        steps += 1;
+    }
+    y[i] = value * 10 + steps;
+}
+
+#[kernel_fn]
+fn early_exit(mut count: [u32; 1], num_thread_blocks: u32, thread_block_size: u32) {
+    if local_id() % 2 == 1 {
+        return;
+    }
+    block_barrier();
+    if local_id() == 0 {
+        count[0] = block_dim();
+    }
+}
+
+#[kernel_fn]
+fn saxpy(a: f32, x: &[f32], y: &mut [f32], num_thread_blocks: u32, thread_block_size: u32) {
+    let i = global_id() as usize;
+    if i < y.len() {
+        y[i] += a * x[i];
+    }
+}
+
+#[kernel_fn]
+fn widen(
+    bytes: &[u8],
+    mut halves: [u8; 4],
+    mut scaled: [f64; 4],
+    mut big: [bool; 4],
+    mut total: [i64; 1],
+    num_thread_blocks: u32,
+    thread_block_size: u32,
+) {
+    let i = global_id() as usize;
+    halves[i] = bytes[i] / 2;
+    scaled[i] = bytes[i] as f64 * 0.1;
+    big[i] = bytes[i] > 100;
+    if i == 0 {
+        let mut acc = 0i64;
+        let mut j = 0;
+        while j < bytes.len() {
+            acc = acc * 1000 - bytes[j] as i64;
+            j += 1;
+        }
+        total[0] = acc;
+    }
+}
+
+#[kernel_struct]
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Particle {
+    alive: bool,
+    id: u8,
+    mass: f64,
+    position: [f32; 3],
+}
+
+#[kernel_struct]
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Segment {
+    ends: [Particle; 2],
+    length: f32,
+}
+
+#[kernel_fn]
+fn measure(segments: &mut [Segment], num_thread_blocks: u32, thread_block_size: u32) {
+    let i = global_id() as usize;
+    if i < segments.len() {
+        let dx = segments[i].ends[1].position[0] - segments[i].ends[0].position[0];
+        segments[i].length = dx * dx;
+        segments[i].ends[0].alive = segments[i].ends[1].mass > 1.0;
+    }
+}
+
+#[kernel_struct]
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Body {
+    position: Vec3,
+    velocity: Vec3,
+    speed: f32,
+}
+
+#[kernel_fn]
+fn turn(
+    rotation: Mat3,
+    dt: f32,
+    bodies: &mut [Body],
+    num_thread_blocks: u32,
+    thread_block_size: u32,
+) {
+    let i = global_id() as usize;
+    if i < bodies.len() {
+        let velocity = rotation * bodies[i].velocity;
+        bodies[i].position += velocity * dt;
+        bodies[i].velocity = velocity;
+        bodies[i].speed = velocity.length();
+        bodies[i].position.z = bodies[i].position.xy().dot(Vec2::ONE);
+    }
+}
+
+#[kernel_fn(workgroup_size = 4, spirv)]
+fn copy_all<T: Copy>(src: &[T], dst: &mut [T], num_thread_blocks: u32, thread_block_size: u32) {
+    let i = global_id() as usize;
+    if i < dst.len() {
+        dst[i] = src[i];
+    }
+}
+
+#[kernel_fn]
+fn axpy<T: Primitive + Copy + Add<Output = T> + Mul<Output = T>>(
+    a: T,
+    x: &[T],
+    y: &mut [T],
+    num_thread_blocks: u32,
+    thread_block_size: u32,
+) {
+    let i = global_id() as usize;
+    if i < y.len() {
+        y[i] = a * x[i] + y[i];
+    }
+}
+
+#[test]
+fn test_add() {
+    let mut c = [0];
+    Queue::new(&Context::cpu())
+        .launch(
+            &AddKernel,
+            [
+                KernelArg::input(&[1]),
+                KernelArg::input(&[2]),
+                KernelArg::output(&mut c),
+            ],
+            (1, 1),
+        )
+        .unwrap();
+    assert_eq!(c, [3]);
+}
+
+#[test]
+fn test_loop_on_device_buffer() {
+    let context = Context::cpu();
+    let values = [1u32, 2, 3, 4, 5, 6, 7, 8];
+    let input = DeviceBuffer::from_slice(&context, &values, Default::default()).unwrap();
+    let total = DeviceBuffer::from_slice(&context, &[0u32], Default::default()).unwrap();
+    Queue::new(&context)
+        .launch(
+            &SumKernel,
+            [KernelArg::buffer(&input), KernelArg::buffer(&total)],
+            (4, 8),
+        )
+        .unwrap();
+    assert_eq!(total.to_vec().unwrap(), [1 + 3 + 5 + 6 + 7]);
+}
+
+#[test]
+fn test_out_of_bounds_index_fails_launch() {
+    let mut out = [0];
+    let err = Queue::new(&Context::cpu())
+        .launch(
+            &IndexKernel,
+            [
+                KernelArg::input(&[10, 20]),
+                KernelArg::input(&[2u32]),
+                KernelArg::output(&mut out),
+            ],
+            (1, 1),
+        )
+        .unwrap_err();
+    assert!(matches!(err, RyclError::DispatchFailed(_)), "{err}");
+}
+
+#[test]
+fn test_thread_ids() {
+    let mut ids = [0u32; 8];
+    let mut dims = [0u32; 2];
+    Queue::new(&Context::cpu())
+        .launch(
+            &ThreadIdsKernel,
+            [KernelArg::output(&mut ids), KernelArg::output(&mut dims)],
+            (2, 4),
+        )
+        .unwrap();
+    assert_eq!(ids, [0, 1, 2, 3, 10, 11, 12, 13]);
+    assert_eq!(dims, [2, 4]);
+}
+
+#[test]
+fn test_shared_arrays() {
+    let kernel = StageKernel.module().unwrap();
+    assert_eq!(
+        kernel.shared[0].ty,
+        Type::Array(Box::new(Type::Scalar(ScalarType::F32)), 4)
+    );
+    assert!(StageKernel.spirv().is_some());
+
+    let x: Vec<f32> = (0..8).map(|i| i as f32).collect();
+    let mut y = vec![0f32; 8];
+    Queue::new(&Context::cpu())
+        .launch(
+            &StageKernel,
+            [KernelArg::input(&x), KernelArg::output(&mut y)],
+            (2, 4),
+        )
+        .unwrap();
+    assert_eq!(y, [1., 3., 5., 7., 9., 11., 13., 15.]);
+}
+
+#[test]
+fn test_block_barrier() {
+    let x: Vec<u32> = (0..32).collect();
+    let mut y = vec![0u32; 32];
+    Queue::new(&Context::cpu())
+        .launch(
+            &ReverseBlocksKernel,
+            [KernelArg::input(&x), KernelArg::output(&mut y)],
+            (4, 8),
+        )
+        .unwrap();
+    let expected: Vec<u32> = x
+        .chunks(8)
+        .flat_map(|block| block.iter().rev())
+        .copied()
+        .collect();
+    assert_eq!(y, expected);
+}
+
+#[test]
+fn test_atomic_histogram() {
+    let values: Vec<u32> = (0..256).map(|i| i * 7 % 10).collect();
+    let mut bins = [0u32; 4];
+    Queue::new(&Context::cpu())
+        .launch(
+            &HistogramKernel,
+            [KernelArg::input(&values), KernelArg::output(&mut bins)],
+            (4, 64),
+        )
+        .unwrap();
+    let mut expected = [0u32; 4];
+    for value in &values {
+        expected[(value % 4) as usize] += 1;
+    }
+    assert_eq!(bins, expected);
+}
+
+#[test]
+fn test_atomic_ops() {
+    let values: Vec<i32> = (0..128).map(|i| (i * 37 % 101) - 50).collect();
+    let mut extremes = [i32::MAX, i32::MIN];
+    let mut winner = [0u32];
+    let mut total = [0.5f32];
+    Queue::new(&Context::cpu())
+        .launch(
+            &AtomicOpsKernel,
+            [
+                KernelArg::input(&values),
+                KernelArg::output(&mut extremes),
+                KernelArg::output(&mut winner),
+                KernelArg::output(&mut total),
+            ],
+            (4, 32),
+        )
+        .unwrap();
+    let min = *values.iter().min().unwrap();
+    let max = *values.iter().max().unwrap();
+    assert_eq!(extremes, [min, max]);
+    // Exactly one thread swapped in its id.
+    assert!((1..=128).contains(&winner[0]));
+    let sum: i32 = values.iter().sum();
+    assert_eq!(total[0], sum as f32 + 0.5);
+}
+
+#[test]
+fn test_compound_assign_with_atomic_index() {
+    let mut next = [0u32];
+    let mut slots = [0u32; 64];
+    Queue::new(&Context::cpu())
+        .launch(
+            &ClaimSlotsKernel,
+            [KernelArg::output(&mut next), KernelArg::output(&mut slots)],
+            (2, 32),
+        )
+        .unwrap();
+    // Each thread claimed one slot, and added to the slot it claimed.
+    assert_eq!(next, [64]);
+    slots.sort();
+    assert_eq!(slots, std::array::from_fn(|i| i as u32 + 1));
+}
+
+#[test]
+fn test_subgroup_sums() {
+    let x: Vec<u32> = (0..128).map(|i| i * 3 % 7).collect();
+    for subgroup_size in [8, 32] {
+        let context = Context::from_backend(Arc::new(
+            Cpu::new().with_subgroup_size(subgroup_size).unwrap(),
+        ));
+        assert_eq!(context.backend().limits().subgroup_size, subgroup_size);
+        let mut sums = vec![0u32; 128 / subgroup_size as usize];
+        let mut prefix = vec![0u32; 128];
+        Queue::new(&context)
+            .launch(
+                &SubgroupSumsKernel,
+                [
+                    KernelArg::input(&x),
+                    KernelArg::output(&mut sums),
+                    KernelArg::output(&mut prefix),
+                ],
+                (2, 64),
+            )
+            .unwrap();
+        for (i, subgroup) in x.chunks(subgroup_size as usize).enumerate() {
+            assert_eq!(sums[i], subgroup.iter().sum::<u32>());
+            let mut running = 0;
+            for (j, value) in subgroup.iter().enumerate() {
+                assert_eq!(prefix[i * subgroup_size as usize + j], running);
+                running += value;
+            }
+        }
+    }
+}
+
+#[test]
+fn test_invalid_subgroup_size() {
+    for subgroup_size in [0, 12, 64] {
+        let result = Cpu::new().with_subgroup_size(subgroup_size);
+        assert!(matches!(result, Err(RyclError::InvalidArgument(_))));
+    }
+}
+
+#[test]
+fn test_divergent_subgroups() {
+    let x: Vec<u32> = (1..=16).collect();
+    let mut sums = [0u32; 16];
+    let mut firsts = [0u32; 4];
+    let context = Context::from_backend(Arc::new(Cpu::new().with_subgroup_size(4).unwrap()));
+    Queue::new(&context)
+        .launch(
+            &DivergentSubgroupsKernel,
+            [
+                KernelArg::input(&x),
+                KernelArg::output(&mut sums),
+                KernelArg::output(&mut firsts),
+            ],
+            (2, 8),
+        )
+        .unwrap();
+    // Each branch only reduces the values of the lanes that took it.
+    for (i, subgroup) in x.chunks(4).enumerate() {
+        let low = subgroup[0] + subgroup[1];
+        let high = (subgroup[2] + subgroup[3]) * 10;
+        assert_eq!(sums[i * 4..i * 4 + 4], [low, low, high, high]);
+        assert_eq!(firsts[i], subgroup[0]);
+    }
+}
+
+#[test]
+fn test_subgroup_lanes() {
+    let x: Vec<i32> = (0..16).map(|i| if i % 5 == 0 { -i } else { i }).collect();
+    let mut rotated = [0i32; 16];
+    let mut spread = [0i32; 16];
+    let mut masks = [0u32; 16];
+    let context = Context::from_backend(Arc::new(Cpu::new().with_subgroup_size(4).unwrap()));
+    Queue::new(&context)
+        .launch(
+            &SubgroupLanesKernel,
+            [
+                KernelArg::input(&x),
+                KernelArg::output(&mut rotated),
+                KernelArg::output(&mut spread),
+                KernelArg::output(&mut masks),
+            ],
+            (2, 8),
+        )
+        .unwrap();
+    for (i, subgroup) in x.chunks(4).enumerate() {
+        let max = subgroup.iter().max().unwrap();
+        let min = subgroup.iter().min().unwrap();
+        let negative = subgroup
+            .iter()
+            .enumerate()
+            .filter(|(_, value)| **value < 0)
+            .fold(0, |mask, (lane, _)| mask | 1 << lane);
+        for lane in 0..4 {
+            let gid = i * 4 + lane;
+            assert_eq!(rotated[gid], subgroup[(lane + 1) % 4]);
+            assert_eq!(spread[gid], max - min + subgroup[0]);
+            assert_eq!(masks[gid], negative);
+        }
+    }
+}
+
+#[test]
+fn test_math_methods() {
+    assert!(MathKernel.spirv().is_some());
+
+    let x = [-2.5f32, -1.25, -0.5, 0.0, 0.5, 1.25, 2.5, 3.75];
+    let mut y = [0f32; 8];
+    let mut z = [0f32; 8];
+    let mut n = [0i32; 8];
+    Queue::new(&Context::cpu())
+        .launch(
+            &MathKernel,
+            [
+                KernelArg::input(&x),
+                KernelArg::output(&mut y),
+                KernelArg::output(&mut z),
+                KernelArg::output(&mut n),
+            ],
+            (2, 4),
+        )
+        .unwrap();
+    for (i, v) in x.into_iter().enumerate() {
+        let wave = v.sin().powf(2.0) + v.cos() * v.cos();
+        let expected = v.abs().sqrt().mul_add(wave, v.exp().ln()) + v.tan().clamp(-1.0, 1.0);
+        assert!((y[i] - expected).abs() < 1e-5, "{} != {expected}", y[i]);
+        let rounded = v.floor() + v.ceil() + v.round();
+        assert_eq!(z[i], rounded.mix(v.max(-0.5), 0.25));
+    }
+    assert_eq!(n, [3, 3, 2, 1, 0, 1, 2, 4]);
+}
+
+#[test]
+fn test_barrier_after_early_return() {
+    // Undefined on devices, but threads that returned must not hang the
+    // rest of their block.
+    let mut count = [0u32];
+    Queue::new(&Context::cpu())
+        .launch(&EarlyExitKernel, [KernelArg::output(&mut count)], (2, 4))
+        .unwrap();
+    assert_eq!(count, [4]);
+}
+
+#[test]
+fn test_slice_arguments() {
+    let x: Vec<f32> = (0..10).map(|i| i as f32).collect();
+    let mut y = vec![1.0f32; 10];
+    Queue::new(&Context::cpu())
+        .launch(
+            &SaxpyKernel,
+            [
+                KernelArg::input(&[2.0f32]),
+                KernelArg::input(&x),
+                KernelArg::output(&mut y),
+            ],
+            (3, 4),
+        )
+        .unwrap();
+    let expected: Vec<f32> = x.iter().map(|x| 2.0 * x + 1.0).collect();
+    assert_eq!(y, expected);
+}
+
+#[test]
+fn test_guarded_index() {
+    let mut y = [u32::MAX; 6];
+    Queue::new(&Context::cpu())
+        .launch(
+            &GuardedKernel,
+            [KernelArg::input(&[5u32, 1, 2]), KernelArg::output(&mut y)],
+            (2, 3),
+        )
+        .unwrap();
+    assert_eq!(y, [53, 11, 22, 0, 0, 0]);
+}
+
+#[test]
+fn test_kernel_metadata() {
+    fn describe(kernel: &impl KernelFn) -> (String, Vec<(String, Access, u32)>) {
+        let args = kernel.args().into_iter();
+        let args = args.map(|arg| (arg.name, arg.access, arg.binding));
+        (kernel.entry_point().to_string(), args.collect())
+    }
+    let f32_ty = Type::Scalar(ScalarType::F32);
+    assert_eq!(
+        SaxpyKernel.args()[1],
+        ArgDesc {
+            name: "x".into(),
+            ty: Type::Slice(Box::new(f32_ty)),
+            access: Access::ReadOnly,
+            binding: 1,
+        }
+    );
+    assert_eq!(
+        describe(&AxpyKernel::<i32>::new()).1,
+        describe(&SaxpyKernel).1
+    );
+    assert_eq!(
+        describe(&ThreadIdsKernel),
+        (
+            "thread_ids".into(),
+            vec![
+                ("ids".into(), Access::ReadWrite, 0),
+                ("dims".into(), Access::ReadWrite, 1)
+            ]
+        )
+    );
+    assert_eq!(SaxpyKernel.workgroup_size(), None);
+    assert_eq!(ThreadIdsKernel.workgroup_size(), Some(4));
+    assert!(SaxpyKernel.module().is_ok());
+
+    // Only kernels whose types are known when they are expanded embed their
+    // SPIR-V.
+    let module = ThreadIdsKernel.module().unwrap();
+    let spirv = compiler::build_spirv(&module, 4).unwrap();
+    assert_eq!(ThreadIdsKernel.spirv(), Some(&spirv[..]));
+    assert_eq!(SaxpyKernel.spirv(), None);
+    assert_eq!(CopyAllKernel::<Particle>::new().spirv(), None);
+
+    // Kernels declaring a workgroup size only run with that size.
+    let mut ids = [0u32; 8];
+    let mut dims = [0u32; 2];
+    let result = Queue::new(&Context::cpu()).launch(
+        &ThreadIdsKernel,
+        [KernelArg::output(&mut ids), KernelArg::output(&mut dims)],
+        (4, 2),
+    );
+    assert!(matches!(result, Err(RyclError::InvalidArgument(msg)) if msg.contains("4 threads")));
+}
+
+#[test]
+fn test_wide_scalars() {
+    let bytes = [7u8, 101, 200, 255];
+    let mut halves = [0u8; 4];
+    let mut scaled = [0f64; 4];
+    let mut big = [false; 4];
+    let mut total = [0i64];
+    Queue::new(&Context::cpu())
+        .launch(
+            &WidenKernel,
+            [
+                KernelArg::input(&bytes),
+                KernelArg::output(&mut halves),
+                KernelArg::output(&mut scaled),
+                KernelArg::output(&mut big),
+                KernelArg::output(&mut total),
+            ],
+            (1, 4),
+        )
+        .unwrap();
+    assert_eq!(halves, [3, 50, 100, 127]);
+    assert_eq!(scaled, bytes.map(|b| b as f64 * 0.1));
+    assert_eq!(big, [false, true, true, true]);
+    assert_eq!(total, [-7_101_200_255]);
+}
+
+#[test]
+fn test_struct_arguments() {
+    let particles: Vec<Particle> = (0..5)
+        .map(|i| Particle {
+            alive: i % 2 == 0,
+            id: i as u8,
+            mass: i as f64 * 0.5,
+            position: [i as f32, 1.0, -2.0],
+        })
+        .collect();
+    assert_eq!(Particle::STD430_LAYOUT.offsets, [0, 4, 8, 16]);
+    assert_eq!(Particle::STD430.size, 32);
+
+    let context = Context::cpu();
+    let src = DeviceBuffer::from_slice(&context, &particles, Default::default()).unwrap();
+    let mut dst = vec![particles[0]; 5];
+    dst.iter_mut().for_each(|p| p.alive = false);
+    Queue::new(&context)
+        .launch(
+            &CopyAllKernel::<Particle>::new(),
+            [KernelArg::buffer(&src), KernelArg::output(&mut dst)],
+            (2, 4),
+        )
+        .unwrap();
+    assert_eq!(dst, particles);
+}
+
+#[test]
+fn test_generic_instantiations() {
+    let context = Context::cpu();
+    let queue = Queue::new(&context);
+    let mut floats = [1.0f32, 2.0, 3.0];
+    let mut ints = [1i32, 2, 3];
+    for _ in 0..2 {
+        queue
+            .launch(
+                &AxpyKernel::<f32>::new(),
+                [
+                    KernelArg::input(&[0.5f32]),
+                    KernelArg::input(&[2.0f32, 4.0, 6.0]),
+                    KernelArg::output(&mut floats),
+                ],
+                (1, 4),
+            )
+            .unwrap();
+        queue
+            .launch(
+                &AxpyKernel::<i32>::new(),
+                [
+                    KernelArg::input(&[-3]),
+                    KernelArg::input(&[1, 2, 3]),
+                    KernelArg::output(&mut ints),
+                ],
+                (1, 4),
+            )
+            .unwrap();
+    }
+    assert_eq!(floats, [3.0, 6.0, 9.0]);
+    assert_eq!(ints, [-5, -10, -15]);
+    // An instantiation only accepts arguments of its own type.
+    let result = queue.launch(
+        &AxpyKernel::<i32>::new(),
+        [
+            KernelArg::input(&[1.0f32]),
+            KernelArg::input(&[1.0f32]),
+            KernelArg::output(&mut floats[..1]),
+        ],
+        (1, 1),
+    );
+    assert!(matches!(result, Err(RyclError::InvalidArgument(_))));
+}
+
+#[test]
+fn test_nested_struct_fields() {
+    let particle = |x: f32, mass: f64| Particle {
+        alive: true,
+        id: 1,
+        mass,
+        position: [x, 0.0, 0.0],
+    };
+    let mut segments = [
+        Segment {
+            ends: [particle(1.0, 1.0), particle(4.0, 2.0)],
+            length: 0.0,
+        },
+        Segment {
+            ends: [particle(2.0, 1.0), particle(-1.0, 0.5)],
+            length: 0.0,
+        },
+    ];
+    assert_eq!(Segment::STD430_LAYOUT.offsets, [0, 64]);
+    assert_eq!(Segment::STD430.size, 72);
+    Queue::new(&Context::cpu())
+        .launch(&MeasureKernel, [KernelArg::output(&mut segments)], (1, 2))
+        .unwrap();
+    assert_eq!(segments.map(|s| s.length), [9.0, 9.0]);
+    assert_eq!(segments.map(|s| s.ends[0].alive), [true, false]);
+    assert_eq!(segments[1].ends[1], particle(-1.0, 0.5));
+}
+
+#[test]
+fn test_vectors_and_matrices() {
+    let body = |x: f32, vx: f32, vy: f32| Body {
+        position: Vec3::new(x, 0.0, 0.0),
+        velocity: Vec3::new(vx, vy, 0.0),
+        speed: 0.0,
+    };
+    let mut bodies = [
+        body(1.0, 3.0, 4.0),
+        body(-2.0, 0.0, 1.0),
+        body(0.0, 0.0, 0.0),
+    ];
+    assert_eq!(Body::STD430_LAYOUT.offsets, [0, 16, 28]);
+    assert_eq!(Body::STD430.size, 32);
+    // A quarter turn around z.
+    let rotation = Mat3::from_cols(
+        Vec3::new(0.0, 1.0, 0.0),
+        Vec3::new(-1.0, 0.0, 0.0),
+        Vec3::new(0.0, 0.0, 1.0),
+    );
+    Queue::new(&Context::cpu())
+        .launch(
+            &TurnKernel,
+            [
+                KernelArg::input(&[rotation]),
+                KernelArg::input(&[0.5f32]),
+                KernelArg::output(&mut bodies),
+            ],
+            (1, 4),
+        )
+        .unwrap();
+    assert_eq!(
+        bodies.map(|b| b.velocity),
+        [
+            Vec3::new(-4.0, 3.0, 0.0),
+            Vec3::new(-1.0, 0.0, 0.0),
+            Vec3::ZERO,
+        ]
+    );
+    assert_eq!(
+        bodies.map(|b| b.position),
+        [
+            Vec3::new(-1.0, 1.5, 0.5),
+            Vec3::new(-2.5, 0.0, -2.5),
+            Vec3::ZERO,
+        ]
+    );
+    assert_eq!(bodies.map(|b| b.speed), [5.0, 1.0, 0.0]);
+}